@@ -0,0 +1,69 @@
+//! Sequential vs parallel Merkle root construction.
+//!
+//! ```bash
+//! cargo bench --bench merkle
+//! ```
+//!
+//! Builds trees over synthetic txids at block sizes around
+//! `PARALLEL_MERKLE_THRESHOLD` and well above it, and prints the median
+//! time of each path. Both paths must agree on the root.
+
+use btpc_quantum_resistant_chain::blockchain::merkle::{MerkleTree, PARALLEL_MERKLE_THRESHOLD};
+use sha2::{Digest, Sha512};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZES: [usize; 5] = [1_000, 4_096, 10_000, 50_000, 100_000];
+const RUNS: usize = 15;
+
+fn leaves(count: usize) -> Vec<[u8; 64]> {
+    (0..count as u64)
+        .map(|i| {
+            let mut leaf = [0u8; 64];
+            leaf.copy_from_slice(&Sha512::digest(i.to_le_bytes()));
+            leaf
+        })
+        .collect()
+}
+
+/// Median wall time of `RUNS` tree builds.
+fn median(leaves: &[[u8; 64]], threshold: usize) -> (Duration, [u8; 64]) {
+    let mut root = [0u8; 64];
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let tree = MerkleTree::with_parallel_threshold(black_box(leaves), threshold)
+                .expect("non-empty");
+            let elapsed = start.elapsed();
+            root = tree.root();
+            elapsed
+        })
+        .collect();
+    times.sort();
+    (times[RUNS / 2], root)
+}
+
+fn main() {
+    println!(
+        "parallel threshold: {} nodes, {} rayon threads",
+        PARALLEL_MERKLE_THRESHOLD,
+        rayon::current_num_threads()
+    );
+    println!(
+        "{:>8} {:>14} {:>14} {:>8}",
+        "leaves", "sequential", "parallel", "speedup"
+    );
+    for count in SIZES {
+        let leaves = leaves(count);
+        let (sequential, sequential_root) = median(&leaves, usize::MAX);
+        let (parallel, parallel_root) = median(&leaves, 0);
+        assert_eq!(sequential_root, parallel_root, "roots differ at {}", count);
+        println!(
+            "{:>8} {:>14?} {:>14?} {:>7.2}x",
+            count,
+            sequential,
+            parallel,
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+}
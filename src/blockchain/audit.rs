@@ -0,0 +1,351 @@
+//! Supply audit: checks the UTXO set against the emission schedule.
+//!
+//! Every coin in the UTXO set, plus every coin provably burned, was issued
+//! by the schedule. Burns come in two kinds: outputs in the set that can
+//! never be spent (`TxOutput::is_unspendable`), found by walking the set,
+//! and subsidy and fees a coinbase left unclaimed, which never reached the
+//! set and are recorded per height in a `BurnLedger` by `connect_block`.
+//! So at the tip, the spendable value plus everything burned can never
+//! exceed `EmissionSchedule::total_supply(tip)` (what
+//! `calculate_total_supply` reports on mainnet); it only falls short if the
+//! ledger missed blocks. The same bound holds for every height `h`: outputs
+//! created at or before `h` can only hold coins issued by then. Walking the
+//! set in fixed height buckets locates where any excess first shows up.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::reward::EmissionSchedule;
+use crate::database::utxo_set::{UTXOError, UTXOSet};
+
+/// Blocks per bucket when locating discrepancies, if the caller has no
+/// preference.
+pub const DEFAULT_AUDIT_BUCKET_BLOCKS: u64 = 1_000;
+
+/// Value burned at each height without ever entering the UTXO set: the
+/// subsidy and fees each block's coinbase left unclaimed. Kept current by
+/// `mining::connect_block`.
+pub type BurnLedger = BTreeMap<u64, u64>;
+
+/// Heights over which the coins held exceeded the coins issued.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SupplyDiscrepancy {
+    pub start_height: u64,
+    pub end_height: u64,
+    /// Largest excess of held over issued coins seen in the range.
+    pub max_excess: u64,
+}
+
+/// Result of `audit_supply`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SupplyAudit {
+    pub height: u64,
+    /// Coins issued by blocks `0..=height` according to the schedule.
+    pub expected: u64,
+    /// `UTXOStats::unspent_value` of the audited set.
+    pub unspent_value: u64,
+    /// Part of `unspent_value` held by unspendable outputs.
+    pub unspendable: u64,
+    /// Unspendable outputs plus the unclaimed rewards in the `BurnLedger`.
+    pub burned: u64,
+    /// Height ranges where outputs created so far held more than had been
+    /// issued. Empty for a sound chain.
+    pub discrepancies: Vec<SupplyDiscrepancy>,
+}
+
+impl SupplyAudit {
+    /// Coins accounted for: spendable plus burned.
+    pub fn accounted(&self) -> u64 {
+        self.unspent_value
+            .saturating_sub(self.unspendable)
+            .saturating_add(self.burned)
+    }
+
+    /// Coins accounted for beyond the schedule: inflation.
+    pub fn excess(&self) -> u64 {
+        self.accounted().saturating_sub(self.expected)
+    }
+
+    /// Scheduled coins not accounted for: blocks missing from the ledger.
+    pub fn shortfall(&self) -> u64 {
+        self.expected.saturating_sub(self.accounted())
+    }
+
+    /// Whether the set is within the schedule at every audited height.
+    pub fn is_sound(&self) -> bool {
+        self.excess() == 0 && self.discrepancies.is_empty()
+    }
+}
+
+impl fmt::Display for SupplyAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Supply audit at height {}", self.height)?;
+        writeln!(f, "  expected:  {}", self.expected)?;
+        writeln!(f, "  unspent:   {}", self.unspent_value)?;
+        writeln!(f, "  burned:    {}", self.burned)?;
+        writeln!(f, "  excess:    {}", self.excess())?;
+        writeln!(f, "  shortfall: {}", self.shortfall())?;
+        for d in &self.discrepancies {
+            writeln!(
+                f,
+                "  DISCREPANCY heights {}..={}: up to {} over issued supply",
+                d.start_height, d.end_height, d.max_excess
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Audit `utxos` as of chain height `height`.
+///
+/// Totals come from `UTXOSet::stats`, the unclaimed rewards from `burns`;
+/// unspendable outputs are found while walking the set. The per-height
+/// bound is checked in buckets of `bucket_blocks` blocks, with outputs
+/// dated by the height that created them. Outputs or burns dated past
+/// `height` count towards the last bucket.
+pub fn audit_supply(
+    utxos: &UTXOSet,
+    height: u64,
+    emission: &EmissionSchedule,
+    burns: &BurnLedger,
+    bucket_blocks: u64,
+) -> Result<SupplyAudit, UTXOError> {
+    let bucket_blocks = bucket_blocks.max(1);
+    let stats = utxos.stats()?;
+
+    let unspent = utxos.unspent()?;
+    let unspendable = unspent
+        .iter()
+        .filter(|record| record.output.is_unspendable())
+        .fold(0u64, |sum, record| sum.saturating_add(record.output.value));
+    let unclaimed = burns.values().fold(0u64, |sum, v| sum.saturating_add(*v));
+
+    let mut held: BTreeMap<u64, u64> = BTreeMap::new();
+    let dated = unspent
+        .into_iter()
+        .map(|record| (record.block_height, record.output.value))
+        .chain(burns.iter().map(|(&h, &value)| (h, value)));
+    for (created, value) in dated {
+        let bucket = held.entry(created.min(height) / bucket_blocks).or_default();
+        *bucket = bucket.saturating_add(value);
+    }
+
+    let mut discrepancies: Vec<SupplyDiscrepancy> = Vec::new();
+    let mut cumulative: u64 = 0;
+    for bucket in 0..=height / bucket_blocks {
+        cumulative = cumulative.saturating_add(held.get(&bucket).copied().unwrap_or(0));
+        let start = bucket * bucket_blocks;
+        let end = (start + bucket_blocks - 1).min(height);
+        let excess = cumulative.saturating_sub(emission.total_supply(end));
+        if excess == 0 {
+            continue;
+        }
+        match discrepancies.last_mut() {
+            Some(last) if last.end_height + 1 == start => {
+                last.end_height = end;
+                last.max_excess = last.max_excess.max(excess);
+            }
+            _ => discrepancies.push(SupplyDiscrepancy {
+                start_height: start,
+                end_height: end,
+                max_excess: excess,
+            }),
+        }
+    }
+
+    Ok(SupplyAudit {
+        height,
+        expected: emission.total_supply(height),
+        unspent_value: stats.unspent_value,
+        unspendable,
+        burned: unspendable.saturating_add(unclaimed),
+        discrepancies,
+    })
+}
+
+/// One row of `inflation_table`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InflationRow {
+    pub year: u64,
+    pub height: u64,
+    pub supply: u64,
+    /// Annual inflation in percent (see `EmissionSchedule::inflation_rate`).
+    pub rate: f64,
+}
+
+impl fmt::Display for InflationRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>4} {:>10} {:>22} {:>8.3}%",
+            self.year, self.height, self.supply, self.rate
+        )
+    }
+}
+
+/// Supply and inflation at the start of each year from 0 to `years`.
+pub fn inflation_table(emission: &EmissionSchedule, years: u64) -> Vec<InflationRow> {
+    (0..=years)
+        .map(|year| {
+            let height = year.saturating_mul(emission.blocks_per_year);
+            let supply = emission.total_supply(height);
+            InflationRow {
+                year,
+                height,
+                supply,
+                rate: emission.inflation_rate(height, supply),
+            }
+        })
+        .collect()
+}
+
+/// Years in `inflation_table` that cover the decay period and the first
+/// tail-emission year.
+pub fn inflation_table_years(emission: &EmissionSchedule) -> u64 {
+    emission
+        .decay_period_blocks
+        .div_ceil(emission.blocks_per_year.max(1))
+        + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::reward::calculate_inflation_rate;
+    use crate::database::utxo_set::{
+        create_outpoint, hash_transaction, MemoryUTXOStorage, TxOutput, UTXOStorage, OP_RETURN,
+    };
+    use crate::mining::{coinbase_transaction, connect_block, BlockSubmission};
+    use crate::network::protocol::{BlockHeader, Hash};
+
+    fn schedule() -> EmissionSchedule {
+        EmissionSchedule {
+            initial_reward: 1_000,
+            tail_reward: 10,
+            decay_period_blocks: 100,
+            blocks_per_year: 50,
+        }
+    }
+
+    /// A set holding each block's full reward in one output.
+    fn fully_claimed(emission: &EmissionSchedule, height: u64) -> UTXOSet {
+        let mut set = UTXOSet::new(Box::new(MemoryUTXOStorage::new()));
+        for h in 0..=height {
+            let outpoint = create_outpoint(Hash(hash_transaction(&h.to_le_bytes())), 0);
+            let output = TxOutput {
+                value: emission.block_reward(h),
+                script_pubkey: vec![],
+            };
+            set.add(outpoint, output, h, true).unwrap();
+        }
+        set
+    }
+
+    #[test]
+    fn sound_chain_matches_schedule() {
+        let emission = schedule();
+        let set = fully_claimed(&emission, 120);
+
+        let audit = audit_supply(&set, 120, &emission, &BurnLedger::new(), 10).unwrap();
+        assert!(audit.is_sound(), "{}", audit);
+        assert_eq!(audit.accounted(), emission.total_supply(120));
+        assert_eq!(audit.shortfall(), 0);
+    }
+
+    #[test]
+    fn counts_unspendable_outputs_and_unclaimed_rewards() {
+        let emission = schedule();
+        let mut storage = MemoryUTXOStorage::new();
+        let mut burns = BurnLedger::new();
+        let mut coinbases = Vec::new();
+        // connect_block leaves header checks to check_block.
+        let header = BlockHeader {
+            version: 1,
+            prev_block: Hash::zero(),
+            merkle_root: Hash::zero(),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+            utxo_root: Hash::zero(),
+        };
+        for height in 0..=20u64 {
+            // Block 3's miner leaves 100 of the subsidy unclaimed.
+            let claimed = emission.block_reward(height) - if height == 3 { 100 } else { 0 };
+            let coinbase = coinbase_transaction(height, "miner", claimed);
+            let block = BlockSubmission {
+                header: header.clone(),
+                transactions: vec![coinbase.clone()],
+            };
+            connect_block(&block, height, &mut storage, &emission, 0, &mut burns).unwrap();
+            coinbases.push(coinbase);
+        }
+        assert_eq!(burns, BurnLedger::from([(3, 100)]));
+
+        // Block 1's reward is moved into an output nobody can spend.
+        let (outpoint, value) = coinbases[1].outputs[0].clone();
+        storage.spend_output(&outpoint, Hash::zero()).unwrap();
+        let burn = TxOutput {
+            value,
+            script_pubkey: vec![OP_RETURN, 0x00],
+        };
+        let burn_outpoint = create_outpoint(Hash(hash_transaction(b"burn")), 0);
+        storage.add_output(burn_outpoint, burn, 15, false).unwrap();
+
+        let set = UTXOSet::new(Box::new(storage));
+        let audit = audit_supply(&set, 20, &emission, &burns, 10).unwrap();
+        assert!(audit.is_sound(), "{}", audit);
+        assert_eq!(audit.unspendable, value);
+        assert_eq!(audit.burned, value + 100);
+        assert_eq!(audit.accounted(), emission.total_supply(20));
+        assert_eq!(audit.shortfall(), 0);
+    }
+
+    #[test]
+    fn locates_inflation_by_height() {
+        let emission = schedule();
+        let mut set = fully_claimed(&emission, 120);
+        // An output at height 45 worth more than was ever issued by then.
+        let minted = create_outpoint(Hash(hash_transaction(b"minted")), 0);
+        let output = TxOutput {
+            value: 500,
+            script_pubkey: vec![],
+        };
+        set.add(minted, output, 45, false).unwrap();
+
+        let audit = audit_supply(&set, 120, &emission, &BurnLedger::new(), 10).unwrap();
+        assert!(!audit.is_sound());
+        assert_eq!(audit.excess(), 500);
+        assert_eq!(
+            audit.discrepancies,
+            vec![SupplyDiscrepancy {
+                start_height: 40,
+                end_height: 120,
+                max_excess: 500,
+            }]
+        );
+
+        // Blocks missing from the ledger show up as a shortfall, not a
+        // discrepancy.
+        let empty = UTXOSet::new(Box::new(MemoryUTXOStorage::new()));
+        let audit = audit_supply(&empty, 120, &emission, &BurnLedger::new(), 10).unwrap();
+        assert!(audit.is_sound());
+        assert_eq!(audit.shortfall(), emission.total_supply(120));
+    }
+
+    #[test]
+    fn inflation_table_follows_the_schedule() {
+        let mainnet = EmissionSchedule::mainnet();
+        let years = inflation_table_years(&mainnet);
+        assert_eq!(years, 25);
+
+        let table = inflation_table(&mainnet, years);
+        assert_eq!(table.len(), 26);
+        assert_eq!(table[0].supply, mainnet.initial_reward);
+        for row in &table {
+            assert_eq!(row.height, row.year * mainnet.blocks_per_year);
+            assert_eq!(row.rate, calculate_inflation_rate(row.height, row.supply));
+        }
+        assert!(table.windows(2).all(|w| w[1].rate < w[0].rate));
+    }
+}
@@ -87,25 +87,4 @@ impl QuantumResistantBlockchain {
         }
         Ok(())
     }
-
-    /// Enhanced reward calculation with halving events
-    pub fn calculate_block_reward(height: u64) -> u64 {
-        const BLOCKS_PER_ERA: u64 = 210_000; // Bitcoin-like halving every 210k blocks
-        const INITIAL_REWARD: u64 = 32_375_000_000; // 32.375 BTP in credits
-        const FINAL_ERA: u64 = 24 * 52_560 / 210_000; // 24 years in eras
-
-        let era = height / BLOCKS_PER_ERA;
-
-        if era < FINAL_ERA {
-            // Linear decay within each era
-            let blocks_in_era = height % BLOCKS_PER_ERA;
-            let era_reward = INITIAL_REWARD * (FINAL_ERA - era) / FINAL_ERA;
-            let decay_per_block = era_reward / BLOCKS_PER_ERA;
-
-            era_reward - (decay_per_block * blocks_in_era)
-        } else {
-            // Tail emission: 0.5 BTP
-            50_000_000 // 0.5 BTP in credits
-        }
-    }
 }
\ No newline at end of file
@@ -1,88 +1,223 @@
 //! Merkle tree utilities for SHA-512 (64-byte) leaf hashes.
 //!
+//! This is the consensus tree: block headers commit to
+//! `MerkleTree::new(txids).root()`.
+//!
 //! - Parents are computed as `SHA512(left || right)`.
 //! - When a level has an odd number of nodes, the last is duplicated (Bitcoin-style).
 //! - Leaves are treated as already-hashed 64-byte values.
 //! - Root returned by value as `[u8; 64]`.
+//!
+//! `MerkleTree::proof` produces an inclusion proof for one leaf, which
+//! `MerkleProof::verify` checks against a root alone, so a light client
+//! holding only headers can confirm a txid is in a block.
+//!
+//! Duplicating the odd node means `[a, b, c]` and `[a, b, c, c]` share a
+//! root (CVE-2012-2459), so a valid block's header also fits a mutated,
+//! invalid transaction list. As in Bitcoin, the tree records whether any
+//! level hashed two identical nodes as a pair; `is_mutated` lists must be
+//! rejected rather than cached as invalid under that header's hash.
+//!
+//! Levels with at least `PARALLEL_MERKLE_THRESHOLD` nodes are hashed on the
+//! rayon pool; the pairing is the same, so roots do not depend on which
+//! path built them (`benches/merkle.rs` compares the two).
 
 use core::fmt;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use crate::network::protocol::Hash;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MerkleError {
     Empty,
+    /// A proof was requested for a leaf index the tree does not have.
+    IndexOutOfRange(usize),
 }
 
 impl fmt::Display for MerkleError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             MerkleError::Empty => write!(f, "merkle tree requires at least one leaf"),
+            MerkleError::IndexOutOfRange(index) => {
+                write!(f, "merkle tree has no leaf at index {}", index)
+            }
         }
     }
 }
 
 impl std::error::Error for MerkleError {}
 
+/// Smallest level, in nodes, hashed in parallel. Below this the work is
+/// too short to repay handing it to the thread pool.
+pub const PARALLEL_MERKLE_THRESHOLD: usize = 4096;
+
+/// `SHA512(left || right)`.
+fn hash_pair(left: &[u8; 64], right: &[u8; 64]) -> [u8; 64] {
+    // Concatenate into 128-byte stack buffer to avoid allocs
+    let mut buf = [0u8; 128];
+    buf[..64].copy_from_slice(left);
+    buf[64..].copy_from_slice(right);
+
+    let parent = Sha512::digest(buf);
+    let mut parent_arr = [0u8; 64];
+    parent_arr.copy_from_slice(&parent);
+    parent_arr
+}
+
+/// Parent of one `chunks(2)` slice of a level.
+fn hash_chunk(pair: &[[u8; 64]]) -> [u8; 64] {
+    match pair {
+        [left, right] => hash_pair(left, right),
+        // If odd, duplicate last
+        [last] => hash_pair(last, last),
+        _ => unreachable!("chunks(2) yields one or two nodes"),
+    }
+}
+
 pub struct MerkleTree {
-    root: [u8; 64],
+    /// `levels[0]` are the leaves, the last level is the root alone. Odd
+    /// levels are stored without their duplicated last node.
+    levels: Vec<Vec<[u8; 64]>>,
+    /// Some level paired two equal nodes (not counting odd duplication).
+    mutated: bool,
 }
 
 impl MerkleTree {
     /// Build a Merkle tree from pre-hashed 64-byte leaves.
     pub fn new(leaves: &[[u8; 64]]) -> Result<Self, MerkleError> {
+        Self::with_parallel_threshold(leaves, PARALLEL_MERKLE_THRESHOLD)
+    }
+
+    /// Like `new`, hashing levels of at least `threshold` nodes in
+    /// parallel (`usize::MAX` never does, `0` always does).
+    pub fn with_parallel_threshold(
+        leaves: &[[u8; 64]],
+        threshold: usize,
+    ) -> Result<Self, MerkleError> {
         if leaves.is_empty() {
             return Err(MerkleError::Empty);
         }
-        if leaves.len() == 1 {
-            return Ok(Self { root: leaves[0] });
-        }
-
-        use sha2::{Digest, Sha512};
 
-        // Work buffer: start with the leaves
-        let mut level: Vec<[u8; 64]> = leaves.to_vec();
+        let mut levels = vec![leaves.to_vec()];
+        let mut mutated = false;
 
         // Reduce until one node remains
-        while level.len() > 1 {
-            // If odd, duplicate last
-            if level.len() % 2 == 1 {
-                let last = *level.last().expect("non-empty");
-                level.push(last);
-            }
+        while levels[levels.len() - 1].len() > 1 {
+            let level = &levels[levels.len() - 1];
+            mutated |= level.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+            let next: Vec<[u8; 64]> = if level.len() >= threshold {
+                level.par_chunks(2).map(hash_chunk).collect()
+            } else {
+                level.chunks(2).map(hash_chunk).collect()
+            };
+            levels.push(next);
+        }
 
-            let mut next = Vec::with_capacity(level.len() / 2);
+        Ok(Self { levels, mutated })
+    }
+
+    /// Return the Merkle root by value.
+    #[inline]
+    pub fn root(&self) -> [u8; 64] {
+        self.levels[self.levels.len() - 1][0]
+    }
 
-            // Hash pairs (left || right)
-            for pair in level.chunks_exact(2) {
-                let (left, right) = (&pair[0], &pair[1]);
+    pub fn leaves(&self) -> &[[u8; 64]] {
+        &self.levels[0]
+    }
 
-                // Concatenate into 128-byte stack buffer to avoid allocs
-                let mut buf = [0u8; 128];
-                buf[..64].copy_from_slice(left);
-                buf[64..].copy_from_slice(right);
+    /// Whether the leaves are a mutated list: some level paired two
+    /// identical nodes, so a shorter list without the repeat has the same
+    /// root. Lists with distinct leaves are never mutated.
+    pub fn is_mutated(&self) -> bool {
+        self.mutated
+    }
 
-                let parent = Sha512::digest(buf);
-                let mut parent_arr = [0u8; 64];
-                parent_arr.copy_from_slice(&parent);
-                next.push(parent_arr);
-            }
+    /// Inclusion proof for the leaf at `index`.
+    pub fn proof(&self, index: usize) -> Result<MerkleProof, MerkleError> {
+        let leaves = self.leaves();
+        if index >= leaves.len() {
+            return Err(MerkleError::IndexOutOfRange(index));
+        }
 
-            level = next;
+        let mut siblings = Vec::with_capacity(self.levels.len() - 1);
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            // A last node without a partner was paired with itself.
+            let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+            siblings.push(Hash(*sibling));
+            position /= 2;
         }
 
-        Ok(Self { root: level[0] })
+        Ok(MerkleProof {
+            leaf: Hash(leaves[index]),
+            index: index as u64,
+            siblings,
+        })
     }
 
-    /// Return the Merkle root by value.
-    #[inline]
+    /// Inclusion proof for the first leaf equal to `leaf`, if present.
+    pub fn proof_for(&self, leaf: &[u8; 64]) -> Option<MerkleProof> {
+        let index = self.leaves().iter().position(|l| l == leaf)?;
+        self.proof(index).ok()
+    }
+}
+
+/// Path from one leaf to the root: the sibling at each level, bottom up.
+/// Bit `i` of `index` says whether the path node at level `i` is a right
+/// child.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub leaf: Hash,
+    pub index: u64,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// The root this proof leads to.
     pub fn root(&self) -> [u8; 64] {
-        self.root
+        let mut node = self.leaf.into_bytes();
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if (self.index >> level) & 1 == 0 {
+                hash_pair(&node, sibling.as_bytes())
+            } else {
+                hash_pair(sibling.as_bytes(), &node)
+            };
+        }
+        node
+    }
+
+    /// Whether this proof places `leaf` under `root`. Index bits beyond the
+    /// proof's depth are rejected so each leaf has exactly one valid index.
+    ///
+    /// A right child whose sibling equals it can only be the duplicate of
+    /// an odd last node (an honest tree never pairs two equal nodes), so
+    /// such a step is rejected: otherwise the last leaf of `[a, b, c]`
+    /// would also verify at the phantom index 3.
+    pub fn verify(&self, root: &[u8; 64]) -> bool {
+        let depth = self.siblings.len();
+        if depth < 64 && self.index >> depth != 0 {
+            return false;
+        }
+        let mut node = self.leaf.into_bytes();
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            node = if (self.index >> level) & 1 == 0 {
+                hash_pair(&node, sibling.as_bytes())
+            } else if sibling.as_bytes() == &node {
+                return false;
+            } else {
+                hash_pair(sibling.as_bytes(), &node)
+            };
+        }
+        node == *root
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sha2::{Digest, Sha512};
 
     fn h(b: &[u8]) -> [u8; 64] {
         let x = Sha512::digest(b);
@@ -119,5 +254,132 @@ mod tests {
         let r1 = t.root();
         let r2 = MerkleTree::new(&leaves).unwrap().root();
         assert_eq!(r1, r2);
+
+        let ab = hash_pair(&leaves[0], &leaves[1]);
+        let cc = hash_pair(&leaves[2], &leaves[2]);
+        assert_eq!(r1, hash_pair(&ab, &cc));
+    }
+
+    #[test]
+    fn merkle_empty_is_an_error() {
+        assert_eq!(MerkleTree::new(&[]).err(), Some(MerkleError::Empty));
+    }
+
+    #[test]
+    fn proofs_verify_for_every_leaf() {
+        for count in 1..=9usize {
+            let leaves: Vec<[u8; 64]> = (0..count).map(|i| h(&[i as u8])).collect();
+            let tree = MerkleTree::new(&leaves).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert_eq!(&proof.leaf.into_bytes(), leaf);
+                assert!(proof.verify(&tree.root()), "{} of {}", index, count);
+                assert_eq!(tree.proof_for(leaf), Some(proof));
+            }
+            assert_eq!(
+                tree.proof(count).err(),
+                Some(MerkleError::IndexOutOfRange(count))
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_proofs_fail() {
+        let leaves: Vec<[u8; 64]> = (0..5u8).map(|i| h(&[i])).collect();
+        let tree = MerkleTree::new(&leaves).unwrap();
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
+
+        let mut wrong_leaf = proof.clone();
+        wrong_leaf.leaf = Hash(h(b"wrong_data"));
+        assert!(!wrong_leaf.verify(&root));
+
+        let mut wrong_index = proof.clone();
+        wrong_index.index = 3;
+        assert!(!wrong_index.verify(&root));
+
+        let mut aliased_index = proof.clone();
+        aliased_index.index += 1 << proof.siblings.len();
+        assert!(!aliased_index.verify(&root));
+
+        let mut wrong_sibling = proof.clone();
+        wrong_sibling.siblings[1] = Hash(h(b"x"));
+        assert!(!wrong_sibling.verify(&root));
+
+        assert!(tree.proof_for(&h(b"absent")).is_none());
+    }
+
+    #[test]
+    fn odd_last_leaf_only_verifies_at_its_own_index() {
+        let leaves = [h(b"a"), h(b"b"), h(b"c")];
+        let tree = MerkleTree::new(&leaves).unwrap();
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
+        assert!(proof.verify(&root));
+
+        // c is paired with itself, so index 3 reaches the same root.
+        let phantom = MerkleProof {
+            index: 3,
+            ..proof.clone()
+        };
+        assert_eq!(phantom.root(), root);
+        assert!(!phantom.verify(&root));
+
+        // The same one level up: [ab, cc] is the tree's right half too.
+        let five: Vec<[u8; 64]> = (0..5u8).map(|i| h(&[i])).collect();
+        let tree = MerkleTree::new(&five).unwrap();
+        let proof = tree.proof(4).unwrap();
+        assert!(proof.verify(&tree.root()));
+        for index in 5..8 {
+            let phantom = MerkleProof {
+                index,
+                ..proof.clone()
+            };
+            assert!(!phantom.verify(&tree.root()), "index {}", index);
+        }
+    }
+
+    #[test]
+    fn duplicated_tail_is_detected_as_mutation() {
+        let leaf = |i: u8| h(&[i]);
+
+        // CVE-2012-2459: repeating the odd last leaf keeps the root.
+        let honest = MerkleTree::new(&[leaf(1), leaf(2), leaf(3)]).unwrap();
+        let mutated = MerkleTree::new(&[leaf(1), leaf(2), leaf(3), leaf(3)]).unwrap();
+        assert_eq!(honest.root(), mutated.root());
+        assert!(!honest.is_mutated());
+        assert!(mutated.is_mutated());
+
+        // The same trick one level up: repeat the last two leaves of six.
+        let six: Vec<[u8; 64]> = (1..=6).map(leaf).collect();
+        let mut eight = six.clone();
+        eight.extend_from_slice(&six[4..]);
+        let honest = MerkleTree::new(&six).unwrap();
+        let mutated = MerkleTree::new(&eight).unwrap();
+        assert_eq!(honest.root(), mutated.root());
+        assert!(!honest.is_mutated());
+        assert!(mutated.is_mutated());
+
+        let distinct: Vec<[u8; 64]> = (1..=7).map(leaf).collect();
+        assert!(!MerkleTree::new(&distinct).unwrap().is_mutated());
+    }
+
+    #[test]
+    fn parallel_and_sequential_roots_match() {
+        for count in [1usize, 2, 3, 7, 64, 1_001, 5_000] {
+            let leaves: Vec<[u8; 64]> = (0..count as u32).map(|i| h(&i.to_le_bytes())).collect();
+            let sequential = MerkleTree::with_parallel_threshold(&leaves, usize::MAX).unwrap();
+            let parallel = MerkleTree::with_parallel_threshold(&leaves, 0).unwrap();
+            let default = MerkleTree::new(&leaves).unwrap();
+            assert_eq!(sequential.root(), parallel.root(), "{} leaves", count);
+            assert_eq!(sequential.root(), default.root(), "{} leaves", count);
+            assert_eq!(sequential.levels, parallel.levels);
+        }
+
+        let mut mutated: Vec<[u8; 64]> = (0..5u8).map(|i| h(&[i])).collect();
+        mutated.push(mutated[4]);
+        assert!(MerkleTree::with_parallel_threshold(&mutated, 0)
+            .unwrap()
+            .is_mutated());
     }
 }
@@ -1,3 +1,4 @@
+pub mod audit;
 pub mod block;
 pub mod merkle;
 pub mod reward;
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::config::{ConfigError, NetworkType};
+
 // ======================================================================
 // Linear-decay economic model constants
 // ======================================================================
@@ -12,11 +15,11 @@ const BLOCKS_PER_YEAR: u64 = 52_560;
 /// 1 BTP = 100,000,000 base units
 const COIN: u64 = 100_000_000;
 
-/// 32.375 BTP initial block reward
-const INITIAL_REWARD: f64 = 32.375;
+/// 32.375 BTP initial block reward, in base units
+const INITIAL_REWARD: u64 = 3_237_500_000;
 
-/// 0.5 BTP tail emission reward
-const FINAL_REWARD: f64 = 0.5;
+/// 0.5 BTP tail emission reward, in base units
+const FINAL_REWARD: u64 = 50_000_000;
 
 /// 24 years decay period
 const DECAY_PERIOD_YEARS: u64 = 24;
@@ -24,6 +27,11 @@ const DECAY_PERIOD_YEARS: u64 = 24;
 /// Total blocks in the decay period
 const DECAY_PERIOD_BLOCKS: u64 = BLOCKS_PER_YEAR * DECAY_PERIOD_YEARS;
 
+/// Target block time in seconds, when network parameters don't say
+const TARGET_BLOCK_TIME: u64 = 600;
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
 // ======================================================================
 // Reward data model
 // ======================================================================
@@ -48,7 +56,7 @@ impl Reward {
             .expect("Time went backwards")
             .as_secs();
 
-        let reward_amount = calculate_block_reward(block_height as f64);
+        let reward_amount = calculate_block_reward(block_height);
 
         Reward {
             recipient,
@@ -72,7 +80,7 @@ impl Reward {
             .expect("Time went backwards")
             .as_secs();
 
-        let reward_amount = calculate_block_reward(block_height as f64);
+        let reward_amount = calculate_block_reward(block_height);
 
         Reward {
             recipient,
@@ -99,7 +107,7 @@ impl Reward {
             return false;
         }
 
-        if self.block_height == 0 && self.amount != calculate_block_reward(0.0) {
+        if self.block_height == 0 && self.amount != calculate_block_reward(0) {
             return false;
         }
 
@@ -157,48 +165,233 @@ impl fmt::Display for Reward {
 //
 // ======================================================================
 
-/// Linear-decay block reward: 32.375 → 0.5 BTP over 24 years, then tail 0.5 BTP.
-pub fn calculate_block_reward(block_height: f64) -> u64 {
-    if block_height >= DECAY_PERIOD_BLOCKS as f64 {
-        // Tail emission: constant 0.5 BTP after decay period
-        return (FINAL_REWARD * COIN as f64) as u64;
+/// Parameters of a linear-decay emission: the reward falls linearly from
+/// `initial_reward` to `tail_reward` over `decay_period_blocks`, then stays
+/// at `tail_reward` forever. Amounts are in base units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmissionSchedule {
+    pub initial_reward: u64,
+    pub tail_reward: u64,
+    pub decay_period_blocks: u64,
+    /// Blocks per year at the target block time, for inflation figures.
+    pub blocks_per_year: u64,
+}
+
+impl Default for EmissionSchedule {
+    fn default() -> Self {
+        Self::mainnet()
     }
+}
 
-    // reward = initial - (initial - final) * (block_height / total_decay_blocks)
-    let progress = block_height / DECAY_PERIOD_BLOCKS as f64;
-    let reward_btp = INITIAL_REWARD - (INITIAL_REWARD - FINAL_REWARD) * progress;
+impl EmissionSchedule {
+    /// 32.375 → 0.5 BTP over 24 years of 10-minute blocks.
+    pub const fn mainnet() -> Self {
+        Self {
+            initial_reward: INITIAL_REWARD,
+            tail_reward: FINAL_REWARD,
+            decay_period_blocks: DECAY_PERIOD_BLOCKS,
+            blocks_per_year: BLOCKS_PER_YEAR,
+        }
+    }
 
-    // Convert to base units and ensure we don't go below final reward due to floating point issues
-    let reward_base_units = (reward_btp * COIN as f64) as u64;
-    let min_reward = (FINAL_REWARD * COIN as f64) as u64;
+    /// Built-in schedule of `network`, for when its parameter file
+    /// (`NetworkType::params_file`) cannot be read; matches the shipped
+    /// `config/<network>.toml`.
+    pub fn for_network(network: &NetworkType) -> Self {
+        match network {
+            NetworkType::Mainnet => Self::mainnet(),
+            // Mainnet amounts over 24 years of 5-minute blocks.
+            NetworkType::Testnet => Self::from_parts(INITIAL_REWARD, FINAL_REWARD, 24, 300),
+            // 1000 → 5 BTP over 100 years of 1-minute blocks.
+            NetworkType::Regtest => Self::from_parts(1_000 * COIN, 5 * COIN, 100, 60),
+        }
+    }
 
-    reward_base_units.max(min_reward)
-}
+    /// Schedule for a decay period given in years of `block_time`-second
+    /// blocks.
+    pub fn from_parts(
+        initial_reward: u64,
+        tail_reward: u64,
+        decay_period_years: u64,
+        block_time: u64,
+    ) -> Self {
+        let blocks_per_year = SECONDS_PER_YEAR / block_time.max(1);
+        Self {
+            initial_reward,
+            tail_reward,
+            decay_period_blocks: decay_period_years.saturating_mul(blocks_per_year),
+            blocks_per_year,
+        }
+    }
 
-/// Returns the total supply at a given block height.
-pub fn calculate_total_supply(block_height: u64) -> u64 {
-    if block_height == 0 {
-        return calculate_block_reward(0.0);
+    /// Read the `[rewards]` table (and `[consensus] target_block_time`) of a
+    /// network parameter file such as `config/mainnet.toml`.
+    pub fn from_network_params(params: &str) -> Result<Self, ConfigError> {
+        let params: toml::Value =
+            toml::from_str(params).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+        let field = |table: &str, key: &str| -> Result<Option<u64>, ConfigError> {
+            match params.get(table).and_then(|t| t.get(key)) {
+                None => Ok(None),
+                Some(value) => value
+                    .as_integer()
+                    .and_then(|v| u64::try_from(v).ok())
+                    .map(Some)
+                    .ok_or_else(|| {
+                        ConfigError::ValidationError(format!(
+                            "[{}] {} must be a non-negative integer",
+                            table, key
+                        ))
+                    }),
+            }
+        };
+        let required = |key: &str| {
+            field("rewards", key)?.ok_or_else(|| {
+                ConfigError::ValidationError(format!("[rewards] {} is missing", key))
+            })
+        };
+
+        let block_time = field("consensus", "target_block_time")?.unwrap_or(TARGET_BLOCK_TIME);
+        let schedule = Self::from_parts(
+            required("initial_reward")?,
+            required("tail_reward")?,
+            required("decay_period_years")?,
+            block_time,
+        );
+        schedule.validate()?;
+        Ok(schedule)
     }
 
-    let mut total_supply = 0u64;
+    /// Load `from_network_params` from a file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let params =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::IoError(e.to_string()))?;
+        Self::from_network_params(&params)
+    }
 
-    // Sum rewards from block 0 to block_height (inclusive)
-    for height in 0..=block_height {
-        total_supply += calculate_block_reward(height as f64);
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.tail_reward > self.initial_reward {
+            return Err(ConfigError::ValidationError(
+                "tail_reward exceeds initial_reward".to_string(),
+            ));
+        }
+        if self.decay_period_blocks == 0 || self.blocks_per_year == 0 {
+            return Err(ConfigError::ValidationError(
+                "decay period must be at least one block".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Subsidy of the block at `block_height`.
+    ///
+    /// Integer-only so every node agrees to the base unit: the exact linear
+    /// value `initial - (initial - tail) * height / decay_blocks`, rounded
+    /// down.
+    pub fn block_reward(&self, block_height: u64) -> u64 {
+        if block_height >= self.decay_period_blocks {
+            return self.tail_reward;
+        }
+
+        let decayed = (u128::from(self.reward_decay()) * u128::from(block_height))
+            .div_ceil(u128::from(self.decay_period_blocks));
+        self.initial_reward - decayed as u64
     }
 
-    total_supply
+    /// Total coins issued by blocks `0..=block_height`, without iterating.
+    pub fn total_supply(&self, block_height: u64) -> u64 {
+        let blocks = u128::from(block_height) + 1;
+        let decay_blocks = blocks.min(u128::from(self.decay_period_blocks));
+        let tail_blocks = blocks - decay_blocks;
+
+        // Each decay-period reward is initial - ceil(decay * h / period); the
+        // ceilings are summed as floor((decay * h + period - 1) / period).
+        let decayed = match self.decay_period_blocks {
+            0 => 0,
+            period => {
+                let period = u128::from(period);
+                floor_sum(
+                    decay_blocks,
+                    period,
+                    u128::from(self.reward_decay()),
+                    period - 1,
+                )
+            }
+        };
+        let supply = decay_blocks * u128::from(self.initial_reward) - decayed
+            + tail_blocks * u128::from(self.tail_reward);
+
+        u64::try_from(supply).unwrap_or(u64::MAX)
+    }
+
+    /// Estimated annual inflation, in percent, of `current_supply` at the
+    /// reward paid at `block_height`.
+    pub fn inflation_rate(&self, block_height: u64, current_supply: u64) -> f64 {
+        if current_supply == 0 {
+            return 0.0;
+        }
+
+        let annual_reward = self
+            .block_reward(block_height)
+            .saturating_mul(self.blocks_per_year);
+        (annual_reward as f64 / current_supply as f64) * 100.0
+    }
+
+    /// Whether `block_height` is past the decay period.
+    pub fn is_tail_emission(&self, block_height: u64) -> bool {
+        block_height >= self.decay_period_blocks
+    }
+
+    fn reward_decay(&self) -> u64 {
+        self.initial_reward.saturating_sub(self.tail_reward)
+    }
 }
 
-/// Returns the estimated annual inflation rate at a given block height.
-pub fn calculate_inflation_rate(block_height: u64, current_supply: u64) -> f64 {
-    if current_supply == 0 {
-        return 0.0;
+/// Linear-decay block reward on the mainnet schedule: 32.375 → 0.5 BTP over
+/// 24 years, then tail 0.5 BTP. See `EmissionSchedule::block_reward`.
+pub fn calculate_block_reward(block_height: u64) -> u64 {
+    EmissionSchedule::mainnet().block_reward(block_height)
+}
+
+/// Total mainnet supply after `block_height`; see
+/// `EmissionSchedule::total_supply`.
+pub fn total_supply(block_height: u64) -> u64 {
+    EmissionSchedule::mainnet().total_supply(block_height)
+}
+
+/// Returns the total supply at a given block height; see `total_supply`.
+pub fn calculate_total_supply(block_height: u64) -> u64 {
+    total_supply(block_height)
+}
+
+/// `sum(floor((a * i + b) / m) for i in 0..n)` in O(log m) steps.
+fn floor_sum(mut n: u128, mut m: u128, mut a: u128, mut b: u128) -> u128 {
+    let mut total = 0;
+    if n == 0 {
+        return 0;
+    }
+    loop {
+        if a >= m {
+            total += n * (n - 1) / 2 * (a / m);
+            a %= m;
+        }
+        if b >= m {
+            total += n * (b / m);
+            b %= m;
+        }
+        let y_max = a * n + b;
+        if y_max < m {
+            return total;
+        }
+        n = y_max / m;
+        b = y_max % m;
+        std::mem::swap(&mut m, &mut a);
     }
+}
 
-    let annual_reward = calculate_block_reward(block_height as f64) * BLOCKS_PER_YEAR;
-    (annual_reward as f64 / current_supply as f64) * 100.0
+/// Returns the estimated annual inflation rate at a given block height on
+/// the mainnet schedule.
+pub fn calculate_inflation_rate(block_height: u64, current_supply: u64) -> f64 {
+    EmissionSchedule::mainnet().inflation_rate(block_height, current_supply)
 }
 
 // ======================================================================
@@ -247,7 +440,7 @@ pub struct LinearDecayRewardCalculator;
 impl LinearDecayRewardCalculator {
     /// Calculates total reward for a block (block reward + fees).
     pub fn calculate_total_reward(params: &RewardParameters) -> u64 {
-        let block_reward = calculate_block_reward(params.block_height as f64);
+        let block_reward = calculate_block_reward(params.block_height);
         block_reward + params.transaction_fees
     }
 
@@ -276,35 +469,33 @@ mod tests {
 
     #[test]
     fn test_calculate_block_reward() {
-        // Test genesis block reward
-        assert_eq!(
-            calculate_block_reward(0.0),
-            (INITIAL_REWARD * COIN as f64) as u64
-        );
+        // Genesis
+        assert_eq!(calculate_block_reward(0), 3_237_500_000);
+        assert_eq!(calculate_block_reward(1), 3_237_497_473);
 
-        // Test middle of decay period
-        let middle_block = (DECAY_PERIOD_BLOCKS / 2) as f64;
-        let expected_middle =
-            (INITIAL_REWARD - (INITIAL_REWARD - FINAL_REWARD) * 0.5) * COIN as f64;
-        assert_eq!(calculate_block_reward(middle_block), expected_middle as u64);
-
-        // Test final decay block
-        let final_block = (DECAY_PERIOD_BLOCKS - 1) as f64;
-        let expected_final = (INITIAL_REWARD
-            - (INITIAL_REWARD - FINAL_REWARD)
-                * ((DECAY_PERIOD_BLOCKS - 1) as f64 / DECAY_PERIOD_BLOCKS as f64))
-            * COIN as f64;
-        assert_eq!(calculate_block_reward(final_block), expected_final as u64);
-
-        // Test tail emission
+        // Middle of decay period: exactly halfway between initial and final
         assert_eq!(
-            calculate_block_reward(DECAY_PERIOD_BLOCKS as f64),
-            (FINAL_REWARD * COIN as f64) as u64
-        );
-        assert_eq!(
-            calculate_block_reward((DECAY_PERIOD_BLOCKS + 1000) as f64),
-            (FINAL_REWARD * COIN as f64) as u64
+            calculate_block_reward(DECAY_PERIOD_BLOCKS / 2),
+            1_643_750_000
         );
+
+        // Final decay block
+        assert_eq!(calculate_block_reward(DECAY_PERIOD_BLOCKS - 1), 50_002_526);
+
+        // Tail emission
+        assert_eq!(calculate_block_reward(DECAY_PERIOD_BLOCKS), FINAL_REWARD);
+        assert_eq!(calculate_block_reward(u64::MAX), FINAL_REWARD);
+    }
+
+    #[test]
+    fn test_reward_never_increases() {
+        let mut previous = calculate_block_reward(0);
+        for height in (0..DECAY_PERIOD_BLOCKS + 10).step_by(997) {
+            let reward = calculate_block_reward(height);
+            assert!(reward <= previous);
+            assert!(reward >= FINAL_REWARD);
+            previous = reward;
+        }
     }
 
     #[test]
@@ -313,7 +504,7 @@ mod tests {
 
         assert_eq!(reward.recipient, "miner123");
         assert_eq!(reward.block_height, 1000);
-        assert_eq!(reward.amount, calculate_block_reward(1000.0));
+        assert_eq!(reward.amount, calculate_block_reward(1000));
         assert!(reward.is_valid());
 
         // Display / ToString path
@@ -324,13 +515,73 @@ mod tests {
 
     #[test]
     fn test_total_supply_calculation() {
-        // Test early supply
-        let early_supply = calculate_total_supply(10);
-        assert!(early_supply > 0);
+        assert_eq!(total_supply(0), INITIAL_REWARD);
+        assert_eq!(total_supply(1), 3_237_500_000 + 3_237_497_473);
+
+        // Closed form matches summing every block, across the tail boundary.
+        let mut summed = 0u64;
+        for height in 0..DECAY_PERIOD_BLOCKS + 100 {
+            summed += calculate_block_reward(height);
+            if height % 50_000 == 0 || height + 2 >= DECAY_PERIOD_BLOCKS {
+                assert_eq!(total_supply(height), summed, "height {}", height);
+            }
+        }
+
+        assert_eq!(total_supply(DECAY_PERIOD_BLOCKS - 1), 2_073_493_593_119_520);
+        assert_eq!(
+            total_supply(DECAY_PERIOD_BLOCKS + 99),
+            total_supply(DECAY_PERIOD_BLOCKS - 1) + 100 * FINAL_REWARD
+        );
+        assert_eq!(calculate_total_supply(20), total_supply(20));
+        assert_eq!(total_supply(u64::MAX), u64::MAX);
+    }
 
-        // Test that supply increases
-        let later_supply = calculate_total_supply(20);
-        assert!(later_supply > early_supply);
+    #[test]
+    fn test_schedules_match_network_params() {
+        for (network, params) in [
+            (
+                NetworkType::Mainnet,
+                include_str!("../../config/mainnet.toml"),
+            ),
+            (
+                NetworkType::Testnet,
+                include_str!("../../config/testnet.toml"),
+            ),
+            (
+                NetworkType::Regtest,
+                include_str!("../../config/regtest.toml"),
+            ),
+        ] {
+            assert_eq!(
+                EmissionSchedule::from_network_params(params).unwrap(),
+                EmissionSchedule::for_network(&network),
+                "{:?}",
+                network
+            );
+        }
+
+        let regtest = EmissionSchedule::for_network(&NetworkType::Regtest);
+        assert_eq!(regtest.block_reward(0), 1_000 * COIN);
+        assert_eq!(regtest.decay_period_blocks, 52_560_000);
+        assert_eq!(regtest.block_reward(regtest.decay_period_blocks), 5 * COIN);
+        assert_eq!(
+            regtest.total_supply(1),
+            1_000 * COIN + regtest.block_reward(1)
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_network_params() {
+        let missing = "[rewards]\ninitial_reward = 10\ntail_reward = 1\n";
+        assert!(matches!(
+            EmissionSchedule::from_network_params(missing),
+            Err(ConfigError::ValidationError(_))
+        ));
+        let inverted = "[rewards]\ninitial_reward = 1\ntail_reward = 10\ndecay_period_years = 1\n";
+        assert!(EmissionSchedule::from_network_params(inverted).is_err());
+        let negative = "[rewards]\ninitial_reward = -1\ntail_reward = 0\ndecay_period_years = 1\n";
+        assert!(EmissionSchedule::from_network_params(negative).is_err());
+        assert!(EmissionSchedule::from_network_params("[rewards").is_err());
     }
 
     #[test]
@@ -366,9 +617,9 @@ mod tests {
     #[test]
     fn test_reward_amount_in_btp() {
         let reward = Reward::new("test".to_string(), 0, "test".to_string());
-        assert_eq!(reward.amount_in_btp(), INITIAL_REWARD);
+        assert_eq!(reward.amount_in_btp(), 32.375);
 
         let tail_reward = Reward::new("test".to_string(), DECAY_PERIOD_BLOCKS, "test".to_string());
-        assert_eq!(tail_reward.amount_in_btp(), FINAL_REWARD);
+        assert_eq!(tail_reward.amount_in_btp(), 0.5);
     }
 }
@@ -3,6 +3,8 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use crate::blockchain::reward::EmissionSchedule;
+
 /// Preferred way to configure bincode going forward (replaces deprecated `bincode::config`).
 #[allow(dead_code)]
 pub fn bincode_options() -> impl bincode::Options {
@@ -26,11 +28,14 @@ impl NetworkType {
         }
     }
 
+    /// Message start bytes (sent little-endian). Distinct per network and
+    /// from Bitcoin's, so a node never takes another chain's traffic for
+    /// its own.
     pub fn magic_bytes(&self) -> u32 {
         match self {
-            NetworkType::Mainnet => 0xD9B4_BEF9,
-            NetworkType::Testnet => 0x0709_110B,
-            NetworkType::Regtest => 0xDAB5_BFFA,
+            NetworkType::Mainnet => 0xC3B7_D0F2,
+            NetworkType::Testnet => 0xC3B7_D10B,
+            NetworkType::Regtest => 0xC3B7_D2FA,
         }
     }
 
@@ -48,21 +53,35 @@ impl NetworkType {
             NetworkType::Testnet | NetworkType::Regtest => 1_296_688_602,
         }
     }
-}
 
-/* ------------------------- Linear Decay Model Constants -------------------------
-   These mirror the economics used in `blockchain::reward`:
+    /// Lower-case name, as in `config/<name>.toml`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetworkType::Mainnet => "mainnet",
+            NetworkType::Testnet => "testnet",
+            NetworkType::Regtest => "regtest",
+        }
+    }
 
-   - 10-minute blocks => 52_560 blocks/year
-   - Linear decay: 32.375 BTP -> 0.5 BTP over 24 years
-   - Tail emission afterwards at 0.5 BTP
------------------------------------------------------------------------------ */
-const BLOCKS_PER_YEAR: u64 = 52_560;
-const DECAY_PERIOD_YEARS: u64 = 24;
-const DECAY_PERIOD_BLOCKS: u64 = BLOCKS_PER_YEAR * DECAY_PERIOD_YEARS; // 1_261_440
+    /// The network parameter file shipped in `config/`, relative to the
+    /// working directory.
+    pub fn params_file(&self) -> PathBuf {
+        PathBuf::from("config").join(format!("{}.toml", self.name()))
+    }
 
-// Initial reward in base units (no float math at runtime): 32.375 * 100_000_000
-const INITIAL_BLOCK_REWARD_SATS: u64 = 3_237_500_000;
+    /// DNS seeds queried for peer addresses; regtest has none.
+    pub fn dns_seeds(&self) -> &'static [&'static str] {
+        match self {
+            NetworkType::Mainnet => &[
+                "seed1.btpc.network",
+                "seed2.btpc.network",
+                "seed3.btpc.network",
+            ],
+            NetworkType::Testnet => &["testnet-seed1.btpc.network", "testnet-seed2.btpc.network"],
+            NetworkType::Regtest => &[],
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
@@ -114,6 +133,10 @@ pub struct NetworkConfig {
     pub message_timeout: Duration,
     pub peer_discovery_interval: Duration,
     pub dns_seeds: Vec<String>,
+    /// Peers always added to the address manager, as multiaddrs
+    /// (`/ip4/1.2.3.4/tcp/8333/p2p/<id>`) or `ip:port`.
+    #[serde(default)]
+    pub bootnodes: Vec<String>,
     pub enable_upnp: bool,
     pub ban_threshold: u32,
     pub ban_duration: Duration,
@@ -128,13 +151,12 @@ impl Default for NetworkConfig {
             connection_timeout: Duration::from_secs(30),
             message_timeout: Duration::from_secs(120),
             peer_discovery_interval: Duration::from_secs(300),
-            dns_seeds: vec![
-                "seed.bitcoin.sipa.be".to_string(),
-                "dnsseed.bitcoin.dashjr.org".to_string(),
-                "seed.bitcoinstats.com".to_string(),
-                "seed.bitcoin.jonasschnelli.ch".to_string(),
-                "seed.btc.petertodd.org".to_string(),
-            ],
+            dns_seeds: NetworkType::Mainnet
+                .dns_seeds()
+                .iter()
+                .map(|seed| seed.to_string())
+                .collect(),
+            bootnodes: Vec::new(),
             enable_upnp: true,
             ban_threshold: 100,
             ban_duration: Duration::from_secs(86_400), // 24 hours
@@ -149,13 +171,17 @@ pub struct MiningConfig {
     pub difficulty_target: u32,
     pub block_size_limit: usize,
     pub transaction_fee: u64,
-    /// For linear model this is the **initial** block reward in base units.
-    pub block_reward: u64,
-    /// For linear model, this field is used as **decay period (blocks)**.
-    /// (Kept name for backward compatibility.)
-    pub halving_interval: u64,
+    /// Block subsidy schedule; `Config::new` loads the network's from its
+    /// parameter file.
+    pub emission: EmissionSchedule,
     pub coinbase_maturity: u32,
     pub pow_algorithm: String,
+    /// Produce blocks even when the mempool has nothing to include.
+    pub mine_empty_blocks: bool,
+    /// Address the coinbase pays; mining refuses to start while empty.
+    pub mining_address: String,
+    /// Upper bound on summed transaction sigops per block.
+    pub max_block_sigops: usize,
 }
 
 impl Default for MiningConfig {
@@ -166,10 +192,12 @@ impl Default for MiningConfig {
             difficulty_target: 0x1f00_ffff, // initial PoW difficulty
             block_size_limit: 4 * 1024 * 1024, // 4MB
             transaction_fee: 1_000,         // base units
-            block_reward: INITIAL_BLOCK_REWARD_SATS, // 32.375 BTP in base units
-            halving_interval: DECAY_PERIOD_BLOCKS, // used as decay period blocks (1_261_440)
+            emission: EmissionSchedule::mainnet(),
             coinbase_maturity: 100,
             pow_algorithm: "sha512".to_string(),
+            mine_empty_blocks: false,
+            mining_address: String::new(),
+            max_block_sigops: 80_000,
         }
     }
 }
@@ -339,6 +367,24 @@ impl Config {
             config.data_dir = dir;
         }
 
+        // Built-in defaults, then whatever the network parameter file says.
+        config.mining.emission = EmissionSchedule::for_network(&config.network);
+        let params_file = config.network.params_file();
+        match std::fs::read_to_string(&params_file) {
+            Ok(params) => {
+                if let Err(e) = config.apply_network_params(&params) {
+                    log::warn!("Ignoring invalid {}: {}", params_file.display(), e);
+                }
+            }
+            Err(e) => log::debug!("No network parameters at {}: {}", params_file.display(), e),
+        }
+        config.network_config.dns_seeds = config
+            .network
+            .dns_seeds()
+            .iter()
+            .map(|seed| seed.to_string())
+            .collect();
+
         // Adjust network-specific settings
         match config.network {
             NetworkType::Testnet => {
@@ -350,8 +396,6 @@ impl Config {
                     .parse()
                     .unwrap();
 
-                // Keep linear model defaults, but you can lower reward here if desired.
-                config.mining.block_reward = INITIAL_BLOCK_REWARD_SATS;
                 config.enable_testnet_faucet = true;
             }
             NetworkType::Regtest => {
@@ -363,10 +407,10 @@ impl Config {
                     .parse()
                     .unwrap();
 
-                // Very low difficulty for regtest; keep reward matching linear model start.
+                // Very low difficulty for regtest.
                 config.mining.difficulty_target = 0x207f_ffff;
-                config.mining.block_reward = INITIAL_BLOCK_REWARD_SATS;
                 config.mining.enabled = true; // Enable mining by default on regtest
+                config.mining.mine_empty_blocks = true;
             }
             NetworkType::Mainnet => { /* defaults */ }
         }
@@ -374,6 +418,33 @@ impl Config {
         config
     }
 
+    /// Take the consensus settings of a network parameter file such as
+    /// `config/regtest.toml`: the `[rewards]` emission schedule and the
+    /// `[network] bootnodes`. Nothing changes if the file is invalid.
+    pub fn apply_network_params(&mut self, params: &str) -> Result<(), ConfigError> {
+        let emission = EmissionSchedule::from_network_params(params)?;
+        let bootnodes = Self::parse_bootnodes(params)?;
+        self.mining.emission = emission;
+        self.network_config.bootnodes = bootnodes;
+        Ok(())
+    }
+
+    fn parse_bootnodes(params: &str) -> Result<Vec<String>, ConfigError> {
+        let params: toml::Value =
+            toml::from_str(params).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+        let Some(bootnodes) = params.get("network").and_then(|n| n.get("bootnodes")) else {
+            return Ok(Vec::new());
+        };
+        let invalid =
+            || ConfigError::ValidationError("[network] bootnodes must be a list of strings".into());
+        bootnodes
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|bootnode| bootnode.as_str().map(str::to_string).ok_or_else(invalid))
+            .collect()
+    }
+
     pub fn from_file(path: &PathBuf) -> Result<Self, ConfigError> {
         let content =
             std::fs::read_to_string(path).map_err(|e| ConfigError::IoError(e.to_string()))?;
@@ -433,6 +504,14 @@ impl Config {
         self.data_dir.join("peers.json")
     }
 
+    pub fn get_banlist_file(&self) -> PathBuf {
+        self.data_dir.join(crate::network::ban::BANLIST_FILE)
+    }
+
+    pub fn get_mempool_file(&self) -> PathBuf {
+        self.data_dir.join(crate::mempool::MEMPOOL_FILE)
+    }
+
     pub fn get_genesis_timestamp(&self) -> u64 {
         self.network.genesis_timestamp()
     }
@@ -508,7 +587,17 @@ mod tests {
         assert_eq!(NetworkType::Mainnet.default_port(), 8333);
         assert_eq!(NetworkType::Testnet.default_port(), 18333);
         assert_eq!(NetworkType::Regtest.default_port(), 18444);
-        assert_eq!(NetworkType::Mainnet.magic_bytes(), 0xD9B4_BEF9);
+        assert_eq!(NetworkType::Mainnet.magic_bytes(), 0xC3B7_D0F2);
+
+        // Bitcoin's mainnet, testnet3 and regtest magics.
+        let bitcoin = [0xD9B4_BEF9, 0x0709_110B, 0xDAB5_BFFA];
+        let ours = [
+            NetworkType::Mainnet.magic_bytes(),
+            NetworkType::Testnet.magic_bytes(),
+            NetworkType::Regtest.magic_bytes(),
+        ];
+        assert!(ours.iter().all(|magic| !bitcoin.contains(magic)));
+        assert!(ours[0] != ours[1] && ours[1] != ours[2] && ours[0] != ours[2]);
     }
 
     #[test]
@@ -534,5 +623,63 @@ mod tests {
         let regtest_config = Config::new(NetworkType::Regtest, None);
         assert_eq!(regtest_config.network, NetworkType::Regtest);
         assert!(regtest_config.mining.enabled);
+        assert_eq!(
+            regtest_config.mining.emission,
+            EmissionSchedule::for_network(&NetworkType::Regtest)
+        );
+        assert_eq!(
+            Config::default().mining.emission,
+            EmissionSchedule::mainnet()
+        );
+        assert!(regtest_config.network_config.dns_seeds.is_empty());
+
+        // The schedule comes from the network parameter file.
+        assert_eq!(
+            regtest_config.mining.emission,
+            EmissionSchedule::from_network_params(include_str!("../config/regtest.toml")).unwrap()
+        );
+        let mut edited = Config::new(NetworkType::Regtest, None);
+        let params = include_str!("../config/regtest.toml").replace(
+            "initial_reward = 100000000000",
+            "initial_reward = 200000000000",
+        );
+        edited.apply_network_params(&params).unwrap();
+        assert_eq!(edited.mining.emission.block_reward(0), 200_000_000_000);
+        assert!(edited.apply_network_params("[rewards").is_err());
+        assert_eq!(edited.mining.emission.block_reward(0), 200_000_000_000);
+        assert_eq!(
+            testnet_config.network_config.dns_seeds,
+            NetworkType::Testnet.dns_seeds()
+        );
+    }
+
+    #[test]
+    fn test_bootnodes_come_from_network_params() {
+        for (network, params) in [
+            (NetworkType::Mainnet, include_str!("../config/mainnet.toml")),
+            (NetworkType::Testnet, include_str!("../config/testnet.toml")),
+            (NetworkType::Regtest, include_str!("../config/regtest.toml")),
+        ] {
+            let params: toml::Value = toml::from_str(params).unwrap();
+            let bootnodes: Vec<&str> = params["network"]["bootnodes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|bootnode| bootnode.as_str().unwrap())
+                .collect();
+            assert_eq!(
+                Config::new(network.clone(), None).network_config.bootnodes,
+                bootnodes,
+                "{:?}",
+                network
+            );
+        }
+
+        let mut config = Config::new(NetworkType::Mainnet, None);
+        let before = config.network_config.bootnodes.clone();
+        let params = include_str!("../config/mainnet.toml")
+            .replace("\"/ip4/18.144.1.23/tcp/8333/p2p/12D3KooWMainNode1\"", "18");
+        assert!(config.apply_network_params(&params).is_err());
+        assert_eq!(config.network_config.bootnodes, before);
     }
 }
@@ -3,6 +3,7 @@ pub mod pow;
 
 // Re-export for easier access
 pub use difficulty::{DifficultyManager, DifficultyParams, CompactDifficulty};
+#[allow(deprecated)]
 pub use pow::{PowSolution, PowMiner, PowValidator, PowParams, PowAlgorithm};
 
 /// Consensus configuration
@@ -110,6 +110,13 @@ impl PowSolution {
 }
 
 /// Proof of Work miner
+///
+/// Superseded by `crate::mining::Miner`, which mines the consensus header
+/// hash over disjoint extra-nonce spaces, drops its work on a new tip and
+/// reports its hashrate through `Miner::hashrate`. This single-threaded
+/// loop hashes a different preimage, never notices a new tip, and is kept
+/// only until the consensus module is retired.
+#[deprecated(note = "use crate::mining::Miner, which this loop predates")]
 pub struct PowMiner {
     params: PowParams,
     current_nonce: u64,
@@ -118,6 +125,7 @@ pub struct PowMiner {
     start_time: u64,
 }
 
+#[allow(deprecated)]
 impl PowMiner {
     /// Creates a new PoW miner
     pub fn new(params: PowParams) -> Self {
@@ -241,6 +249,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_solution_validation() {
         let block_header = b"test block header";
         let target = DifficultyManager::difficulty_to_target(1000); // Easy target
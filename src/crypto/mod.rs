@@ -1,20 +1,38 @@
-use std::fmt;
+//! Cryptographic building blocks.
+//!
+//! The Merkle tree lives with the consensus code in `blockchain::merkle`;
+//! it is re-exported here so there is a single implementation.
 
-#[derive(Debug)]
-pub enum MerkleError {
-    EmptyInput,
-    InvalidInput,
-    // ... other variants
+pub use crate::blockchain::merkle::{MerkleError, MerkleProof, MerkleTree};
+
+use sha2::{Digest, Sha512};
+
+/// Length of a wallet address: the hex encoding of a SHA-512 digest.
+pub const ADDRESS_LENGTH: usize = 128;
+
+/// Wallet address for `public_key`: lowercase hex of its SHA-512 digest.
+pub fn address_from_public_key(public_key: &[u8]) -> String {
+    hex::encode(Sha512::digest(public_key))
 }
 
-impl fmt::Display for MerkleError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            MerkleError::EmptyInput => write!(f, "Empty input provided to Merkle tree"),
-            MerkleError::InvalidInput => write!(f, "Invalid input provided to Merkle tree"),
-            // ... other variants
-        }
-    }
+/// Whether `address` has the form `address_from_public_key` produces.
+pub fn is_valid_address(address: &str) -> bool {
+    address.len() == ADDRESS_LENGTH
+        && address
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
 }
 
-impl std::error::Error for MerkleError {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_lowercase_sha512_hex() {
+        let address = address_from_public_key(b"public key");
+        assert!(is_valid_address(&address));
+        assert!(!is_valid_address(&address.to_uppercase()));
+        assert!(!is_valid_address(&address[1..]));
+        assert!(!is_valid_address("alice"));
+    }
+}
@@ -6,17 +6,19 @@
 //! - `DatabaseConfig` here is a lightweight config for the database module
 //!   (distinct from `crate::config::DatabaseConfig`).
 //! - UTXO storage is injected via `Box<dyn UTXOStorage + Send + Sync>` so you
-//!   can use `MemoryUTXOStorage` (in-memory) or a persistent backend later.
+//!   can use `MemoryUTXOStorage` (in-memory) or `FileUTXOStorage` (persistent).
 
+pub mod utxo_commitment;
 pub mod utxo_set;
 
 use serde::de::DeserializeOwned;
 use sha2::{Digest, Sha512};
 use std::path::PathBuf;
 
+pub use utxo_commitment::{utxo_key, CommittedUTXOStorage, UtxoCommitment, UtxoProof};
 pub use utxo_set::{
-    create_outpoint, hash_transaction, MemoryUTXOStorage, OutPoint, TxOutput, UTXOError,
-    UTXORecord, UTXOSet, UTXOStats, UTXOStorage,
+    create_outpoint, hash_transaction, is_spendable_at, FileUTXOStorage, MemoryUTXOStorage,
+    OutPoint, TxOutput, UTXOError, UTXORecord, UTXOSet, UTXOStats, UTXOStorage, UTXO_FILE,
 };
 
 /// Simple config local to the database module.
@@ -43,7 +45,7 @@ impl Default for DatabaseConfig {
 /// High-level manager that owns a concrete UTXO storage.
 ///
 /// You can pass `Box::new(MemoryUTXOStorage::new())` for in-memory usage, or
-/// swap in a persistent backend in the future.
+/// a `FileUTXOStorage` to keep the set across restarts.
 #[derive(Debug)]
 pub struct DatabaseManager {
     storage: Box<dyn UTXOStorage + Send + Sync>,
@@ -0,0 +1,525 @@
+//! Authenticated commitment to the UTXO set.
+//!
+//! `UtxoCommitment` is a sparse Merkle tree over `OutPoint → TxOutput`,
+//! keyed by `utxo_key` (SHA-512 of the bincode outpoint) and walked from the
+//! key's most significant bit. Empty subtrees hash to zero, and a subtree
+//! holding a single output is stored as that leaf rather than as a path of
+//! 512 nodes, so the tree is only as deep as it needs to be to tell its keys
+//! apart:
+//!
+//! - leaf: `SHA-512(0x00 || key || SHA-512(bincode(output)))`
+//! - node: `SHA-512(0x01 || left || right)`
+//!
+//! The shape depends only on the set, not on the order of updates, so every
+//! node derives the same `root` for the same UTXO set. Headers from
+//! `UTXO_COMMITMENT_VERSION` on carry it as `utxo_root`.
+//!
+//! `UtxoProof`s show that an outpoint is unspent with a given output, or that
+//! it is not in the set at all, against a root alone.
+//! `CommittedUTXOStorage` wraps any `UTXOStorage` and keeps a commitment in
+//! step with it.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+use super::utxo_set::{OutPoint, TxOutput, UTXOError, UTXORecord, UTXOStats, UTXOStorage};
+use crate::network::protocol::Hash;
+
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+/// Bits in a key, and so the deepest a proof can go.
+const KEY_BITS: usize = 512;
+
+/// Position of `outpoint` in the tree.
+pub fn utxo_key(outpoint: &OutPoint) -> Hash {
+    let encoded = bincode::serialize(outpoint).expect("outpoint serialize");
+    Hash(sha512(&[&encoded]))
+}
+
+/// Hash of `output` as committed in its leaf.
+pub fn utxo_value_hash(output: &TxOutput) -> Hash {
+    let encoded = bincode::serialize(output).expect("output serialize");
+    Hash(sha512(&[&encoded]))
+}
+
+fn sha512(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut out = [0u8; 64];
+    out.copy_from_slice(&hasher.finalize());
+    out
+}
+
+fn leaf_hash(key: &Hash, value_hash: &Hash) -> Hash {
+    Hash(sha512(&[
+        &[LEAF_TAG],
+        key.as_bytes(),
+        value_hash.as_bytes(),
+    ]))
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Hash(sha512(&[&[NODE_TAG], left.as_bytes(), right.as_bytes()]))
+}
+
+/// Bit `depth` of `key`, most significant first; `true` goes right.
+fn bit(key: &Hash, depth: usize) -> bool {
+    key.as_bytes()[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+fn common_prefix_bits(a: &Hash, b: &Hash) -> usize {
+    (0..KEY_BITS)
+        .find(|&depth| bit(a, depth) != bit(b, depth))
+        .unwrap_or(KEY_BITS)
+}
+
+#[derive(Debug, Clone, Default)]
+enum Node {
+    #[default]
+    Empty,
+    Leaf {
+        key: Hash,
+        value_hash: Hash,
+        hash: Hash,
+    },
+    Internal {
+        left: Box<Node>,
+        right: Box<Node>,
+        hash: Hash,
+    },
+}
+
+impl Node {
+    fn leaf(key: Hash, value_hash: Hash) -> Node {
+        Node::Leaf {
+            hash: leaf_hash(&key, &value_hash),
+            key,
+            value_hash,
+        }
+    }
+
+    /// Join two subtrees, collapsing to a lone leaf (or nothing) so the
+    /// shape stays canonical after removals.
+    fn join(left: Node, right: Node) -> Node {
+        match (left, right) {
+            (Node::Empty, Node::Empty) => Node::Empty,
+            (leaf @ Node::Leaf { .. }, Node::Empty) | (Node::Empty, leaf @ Node::Leaf { .. }) => {
+                leaf
+            }
+            (left, right) => Node::Internal {
+                hash: node_hash(&left.hash(), &right.hash()),
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+        }
+    }
+
+    fn hash(&self) -> Hash {
+        match self {
+            Node::Empty => Hash::zero(),
+            Node::Leaf { hash, .. } | Node::Internal { hash, .. } => *hash,
+        }
+    }
+
+    fn insert(self, depth: usize, key: Hash, value_hash: Hash) -> Node {
+        match self {
+            Node::Empty => Node::leaf(key, value_hash),
+            Node::Leaf { key: existing, .. } if existing == key => Node::leaf(key, value_hash),
+            leaf @ Node::Leaf { .. } => Node::split(leaf, Node::leaf(key, value_hash), depth),
+            Node::Internal { left, right, .. } => {
+                if bit(&key, depth) {
+                    Node::join(*left, right.insert(depth + 1, key, value_hash))
+                } else {
+                    Node::join(left.insert(depth + 1, key, value_hash), *right)
+                }
+            }
+        }
+    }
+
+    /// The subtree at `depth` holding two leaves with distinct keys.
+    fn split(a: Node, b: Node, depth: usize) -> Node {
+        let (Node::Leaf { key: key_a, .. }, Node::Leaf { key: key_b, .. }) = (&a, &b) else {
+            unreachable!("split joins two leaves");
+        };
+        match (bit(key_a, depth), bit(key_b, depth)) {
+            (false, false) => Node::join(Node::split(a, b, depth + 1), Node::Empty),
+            (true, true) => Node::join(Node::Empty, Node::split(a, b, depth + 1)),
+            (false, true) => Node::join(a, b),
+            (true, false) => Node::join(b, a),
+        }
+    }
+
+    /// Returns the new subtree and whether `key` was present.
+    fn remove(self, depth: usize, key: &Hash) -> (Node, bool) {
+        match self {
+            Node::Leaf { key: existing, .. } if existing == *key => (Node::Empty, true),
+            Node::Internal { left, right, hash } => {
+                let (left, right, removed) = if bit(key, depth) {
+                    let (right, removed) = right.remove(depth + 1, key);
+                    (*left, right, removed)
+                } else {
+                    let (left, removed) = left.remove(depth + 1, key);
+                    (left, *right, removed)
+                };
+                if removed {
+                    (Node::join(left, right), true)
+                } else {
+                    let node = Node::Internal {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        hash,
+                    };
+                    (node, false)
+                }
+            }
+            other => (other, false),
+        }
+    }
+}
+
+/// Sparse Merkle tree over the UTXO set; see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoCommitment {
+    root: Node,
+    len: usize,
+}
+
+impl UtxoCommitment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Commitment to every record in `records`.
+    pub fn from_records<'a>(records: impl IntoIterator<Item = &'a UTXORecord>) -> Self {
+        let mut commitment = Self::new();
+        for record in records {
+            commitment.insert(&record.outpoint, &record.output);
+        }
+        commitment
+    }
+
+    /// Root hash; zero for an empty set.
+    pub fn root(&self) -> Hash {
+        self.root.hash()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add `outpoint`, or replace its output if it is already present.
+    pub fn insert(&mut self, outpoint: &OutPoint, output: &TxOutput) {
+        let key = utxo_key(outpoint);
+        if !self.contains_key(&key) {
+            self.len += 1;
+        }
+        self.root = std::mem::take(&mut self.root).insert(0, key, utxo_value_hash(output));
+    }
+
+    /// Remove `outpoint`; returns whether it was present.
+    pub fn remove(&mut self, outpoint: &OutPoint) -> bool {
+        let (root, removed) = std::mem::take(&mut self.root).remove(0, &utxo_key(outpoint));
+        self.root = root;
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.contains_key(&utxo_key(outpoint))
+    }
+
+    fn contains_key(&self, key: &Hash) -> bool {
+        matches!(self.prove_key(key).leaf, Some((found, _)) if found == *key)
+    }
+
+    /// Proof of membership for an unspent `outpoint`, or of non-membership
+    /// otherwise.
+    pub fn prove(&self, outpoint: &OutPoint) -> UtxoProof {
+        self.prove_key(&utxo_key(outpoint))
+    }
+
+    fn prove_key(&self, key: &Hash) -> UtxoProof {
+        let mut siblings = Vec::new();
+        let mut node = &self.root;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Empty => {
+                    return UtxoProof {
+                        siblings,
+                        leaf: None,
+                    }
+                }
+                Node::Leaf {
+                    key, value_hash, ..
+                } => {
+                    return UtxoProof {
+                        siblings,
+                        leaf: Some((*key, *value_hash)),
+                    }
+                }
+                Node::Internal { left, right, .. } => {
+                    let (next, sibling) = if bit(key, depth) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push(sibling.hash());
+                    node = next;
+                    depth += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Path from the root towards an outpoint's key.
+///
+/// `siblings` run from the root down. The path ends in `leaf`, the key and
+/// value hash of the single output stored there, or in an empty subtree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoProof {
+    pub siblings: Vec<Hash>,
+    pub leaf: Option<(Hash, Hash)>,
+}
+
+impl UtxoProof {
+    /// Root implied by following `key`'s bits up from the end of the path.
+    fn root_for(&self, key: &Hash) -> Option<Hash> {
+        if self.siblings.len() > KEY_BITS {
+            return None;
+        }
+        let mut hash = match &self.leaf {
+            Some((leaf_key, value_hash)) => leaf_hash(leaf_key, value_hash),
+            None => Hash::zero(),
+        };
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if bit(key, depth) {
+                node_hash(sibling, &hash)
+            } else {
+                node_hash(&hash, sibling)
+            };
+        }
+        Some(hash)
+    }
+
+    /// Whether this proves `outpoint` is unspent with `output` under `root`.
+    pub fn verify_membership(&self, root: &Hash, outpoint: &OutPoint, output: &TxOutput) -> bool {
+        let key = utxo_key(outpoint);
+        self.leaf == Some((key, utxo_value_hash(output))) && self.root_for(&key) == Some(*root)
+    }
+
+    /// Whether this proves `outpoint` is not in the set under `root`.
+    ///
+    /// The path must end in an empty subtree, or in another output's leaf
+    /// that sits where `outpoint` would have to be.
+    pub fn verify_non_membership(&self, root: &Hash, outpoint: &OutPoint) -> bool {
+        let key = utxo_key(outpoint);
+        let diverges = match &self.leaf {
+            None => true,
+            Some((leaf_key, _)) => {
+                *leaf_key != key && common_prefix_bits(leaf_key, &key) >= self.siblings.len()
+            }
+        };
+        diverges && self.root_for(&key) == Some(*root)
+    }
+}
+
+/// A `UTXOStorage` that keeps a `UtxoCommitment` to its contents.
+#[derive(Debug)]
+pub struct CommittedUTXOStorage<S> {
+    inner: S,
+    commitment: UtxoCommitment,
+}
+
+impl<S: UTXOStorage> CommittedUTXOStorage<S> {
+    /// Wrap `inner`, committing to whatever it already holds.
+    pub fn new(inner: S) -> Result<Self, UTXOError> {
+        let commitment = UtxoCommitment::from_records(&inner.get_unspent_outputs()?);
+        Ok(Self { inner, commitment })
+    }
+
+    pub fn commitment(&self) -> &UtxoCommitment {
+        &self.commitment
+    }
+
+    /// See `UtxoCommitment::root`.
+    pub fn root(&self) -> Hash {
+        self.commitment.root()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: UTXOStorage> UTXOStorage for CommittedUTXOStorage<S> {
+    fn add_output(
+        &mut self,
+        outpoint: OutPoint,
+        output: TxOutput,
+        block_height: u64,
+        is_coinbase: bool,
+    ) -> Result<(), UTXOError> {
+        let (key, value) = (outpoint.clone(), output.clone());
+        self.inner
+            .add_output(outpoint, output, block_height, is_coinbase)?;
+        self.commitment.insert(&key, &value);
+        Ok(())
+    }
+
+    fn spend_output(
+        &mut self,
+        outpoint: &OutPoint,
+        spending_tx_hash: Hash,
+    ) -> Result<(), UTXOError> {
+        self.inner.spend_output(outpoint, spending_tx_hash)?;
+        self.commitment.remove(outpoint);
+        Ok(())
+    }
+
+    fn get_output(&self, outpoint: &OutPoint) -> Result<Option<(TxOutput, u64, bool)>, UTXOError> {
+        self.inner.get_output(outpoint)
+    }
+
+    fn get_unspent_outputs(&self) -> Result<Vec<UTXORecord>, UTXOError> {
+        self.inner.get_unspent_outputs()
+    }
+
+    fn get_stats(&self) -> Result<UTXOStats, UTXOError> {
+        self.inner.get_stats()
+    }
+
+    fn clear(&mut self) -> Result<(), UTXOError> {
+        self.inner.clear()?;
+        self.commitment = UtxoCommitment::new();
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.inner.is_persistent()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::{create_outpoint, hash_transaction, MemoryUTXOStorage};
+
+    fn op(i: u32) -> OutPoint {
+        create_outpoint(Hash(hash_transaction(&i.to_le_bytes())), i % 3)
+    }
+
+    fn out(value: u64) -> TxOutput {
+        TxOutput {
+            value,
+            script_pubkey: vec![0x51],
+        }
+    }
+
+    #[test]
+    fn root_depends_only_on_contents() {
+        let mut forward = UtxoCommitment::new();
+        assert_eq!(forward.root(), Hash::zero());
+        for i in 0..64 {
+            forward.insert(&op(i), &out(u64::from(i)));
+        }
+        let mut backward = UtxoCommitment::new();
+        for i in (0..80).rev() {
+            backward.insert(&op(i), &out(u64::from(i)));
+        }
+        for i in 64..80 {
+            assert!(backward.remove(&op(i)));
+        }
+        assert!(!backward.remove(&op(64)));
+        assert_eq!(forward.len(), 64);
+        assert_eq!(backward.len(), 64);
+        assert_eq!(forward.root(), backward.root());
+
+        // Outputs are committed, not just outpoints.
+        backward.insert(&op(0), &out(1));
+        assert_eq!(backward.len(), 64);
+        assert_ne!(forward.root(), backward.root());
+
+        for i in 0..64 {
+            forward.remove(&op(i));
+        }
+        assert!(forward.is_empty());
+        assert_eq!(forward.root(), Hash::zero());
+    }
+
+    #[test]
+    fn proves_membership_and_non_membership() {
+        let empty = UtxoCommitment::new();
+        assert!(empty
+            .prove(&op(0))
+            .verify_non_membership(&empty.root(), &op(0)));
+
+        let mut tree = UtxoCommitment::new();
+        for i in 0..50 {
+            tree.insert(&op(i), &out(u64::from(i)));
+        }
+        let root = tree.root();
+        for i in 0..50 {
+            let proof = tree.prove(&op(i));
+            assert!(proof.verify_membership(&root, &op(i), &out(u64::from(i))));
+            assert!(!proof.verify_membership(&root, &op(i), &out(999)));
+            assert!(!proof.verify_non_membership(&root, &op(i)));
+        }
+        for i in 50..100 {
+            let proof = tree.prove(&op(i));
+            assert!(proof.verify_non_membership(&root, &op(i)), "{}", i);
+            assert!(!proof.verify_membership(&root, &op(i), &out(u64::from(i))));
+        }
+
+        // Proofs are bound to the root and to their depth.
+        let member = tree.prove(&op(0));
+        let stale_root = {
+            let mut other = tree.clone();
+            other.remove(&op(0));
+            other.root()
+        };
+        assert!(!member.verify_membership(&stale_root, &op(0), &out(0)));
+        let mut forged = member.clone();
+        forged.siblings.pop();
+        assert!(!forged.verify_membership(&root, &op(0), &out(0)));
+    }
+
+    #[test]
+    fn committed_storage_tracks_its_backend() {
+        let mut backend = MemoryUTXOStorage::new();
+        backend.add_output(op(1), out(1), 1, true).unwrap();
+        let mut storage = CommittedUTXOStorage::new(backend).unwrap();
+        storage.add_output(op(2), out(2), 2, false).unwrap();
+        // A refused add leaves the commitment alone.
+        assert!(storage.add_output(op(2), out(5), 3, false).is_err());
+
+        let mut expected = UtxoCommitment::new();
+        expected.insert(&op(1), &out(1));
+        expected.insert(&op(2), &out(2));
+        assert_eq!(storage.root(), expected.root());
+
+        assert!(storage.spend_output(&op(3), Hash::zero()).is_err());
+        storage.spend_output(&op(1), Hash::zero()).unwrap();
+        expected.remove(&op(1));
+        assert_eq!(storage.root(), expected.root());
+        assert!(storage.commitment().prove(&op(2)).verify_membership(
+            &storage.root(),
+            &op(2),
+            &out(2)
+        ));
+
+        storage.clear().unwrap();
+        assert_eq!(storage.root(), Hash::zero());
+        assert!(storage.into_inner().outputs.is_empty());
+    }
+}
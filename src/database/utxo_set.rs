@@ -1,4 +1,4 @@
-//! UTXO storage abstraction + in-memory implementation.
+//! UTXO storage abstraction + in-memory and file-backed implementations.
 //! Switched raw `[u8; 64]` fields to the `Hash` newtype for clean serde,
 //! added `Default` where Clippy suggested, and small type aliases.
 
@@ -6,7 +6,11 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
 use std::collections::HashMap;
 use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
+use crate::database::DatabaseManager;
 use crate::network::protocol::Hash;
 
 /// A reference to a previous transaction output.
@@ -26,6 +30,17 @@ pub struct TxOutput {
     pub script_pubkey: Vec<u8>,
 }
 
+/// Script opcode marking an output as provably unspendable.
+pub const OP_RETURN: u8 = 0x6a;
+
+impl TxOutput {
+    /// Whether no input can ever spend this output: its script starts with
+    /// `OP_RETURN`. Its value is burned.
+    pub fn is_unspendable(&self) -> bool {
+        self.script_pubkey.first() == Some(&OP_RETURN)
+    }
+}
+
 /// An unspent output record exposed by `get_unspent_outputs`.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct UTXORecord {
@@ -35,6 +50,18 @@ pub struct UTXORecord {
     pub is_coinbase: bool,
 }
 
+impl UTXORecord {
+    /// See `is_spendable_at`.
+    pub fn is_spendable_at(&self, spend_height: u64, coinbase_maturity: u32) -> bool {
+        is_spendable_at(
+            self.is_coinbase,
+            self.block_height,
+            spend_height,
+            coinbase_maturity,
+        )
+    }
+}
+
 /// Accumulated statistics over the UTXO set.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct UTXOStats {
@@ -51,6 +78,7 @@ pub enum UTXOError {
     NotFound,
     AlreadySpent,
     InvalidInput,
+    IoError(String),
 }
 
 impl fmt::Display for UTXOError {
@@ -60,6 +88,7 @@ impl fmt::Display for UTXOError {
             UTXOError::NotFound => write!(f, "UTXO not found"),
             UTXOError::AlreadySpent => write!(f, "UTXO already spent"),
             UTXOError::InvalidInput => write!(f, "Invalid input"),
+            UTXOError::IoError(msg) => write!(f, "IO error: {msg}"),
         }
     }
 }
@@ -90,6 +119,12 @@ pub trait UTXOStorage: fmt::Debug + Send + Sync {
     fn get_stats(&self) -> Result<UTXOStats, UTXOError>;
 
     fn clear(&mut self) -> Result<(), UTXOError>;
+
+    /// Whether the set survives a restart. Data validated against it (a
+    /// reloaded mempool, ...) is only meaningful if it does.
+    fn is_persistent(&self) -> bool {
+        false
+    }
 }
 
 /// Utility: SHA-512 hash of arbitrary bytes, returned as a 64-byte array.
@@ -102,6 +137,18 @@ pub fn hash_transaction(data: &[u8]) -> [u8; 64] {
     out
 }
 
+/// Whether an output created at `created_height` may be spent by a
+/// transaction in the block at `spend_height`. Coinbase outputs need
+/// `coinbase_maturity` confirmations first; other outputs are always spendable.
+pub fn is_spendable_at(
+    is_coinbase: bool,
+    created_height: u64,
+    spend_height: u64,
+    coinbase_maturity: u32,
+) -> bool {
+    !is_coinbase || spend_height >= created_height.saturating_add(u64::from(coinbase_maturity))
+}
+
 /// Utility constructor for OutPoint.
 pub fn create_outpoint(tx_hash: Hash, index: u32) -> OutPoint {
     OutPoint { tx_hash, index }
@@ -188,6 +235,104 @@ impl UTXOStorage for MemoryUTXOStorage {
     }
 }
 
+/// File name of the `FileUTXOStorage` snapshot inside the chainstate directory.
+pub const UTXO_FILE: &str = "utxos.dat";
+
+/// A `MemoryUTXOStorage` mirrored to a checksummed snapshot file (see
+/// `DatabaseManager::serialize_with_checksum`).
+///
+/// Every write rewrites the snapshot next to `path` and renames it into
+/// place, so the set survives a restart or a crash at any point. That makes
+/// each write cost the size of the set: fine for small chains, not a
+/// replacement for an indexed on-disk backend.
+#[derive(Debug)]
+pub struct FileUTXOStorage {
+    path: PathBuf,
+    memory: MemoryUTXOStorage,
+}
+
+impl FileUTXOStorage {
+    /// Open the snapshot at `path`, starting from an empty set if there is
+    /// none yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, UTXOError> {
+        let path = path.into();
+        let outputs = match fs::read(&path) {
+            Ok(bytes) => {
+                let payload = DatabaseManager::verify_and_strip_checksum(&bytes)?;
+                let entries: Vec<(OutPoint, UTXOEntry)> =
+                    DatabaseManager::deserialize_with_checksum(&payload)?;
+                entries.into_iter().collect()
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(UTXOError::IoError(e.to_string())),
+        };
+        Ok(Self {
+            path,
+            memory: MemoryUTXOStorage { outputs },
+        })
+    }
+
+    /// Where the snapshot lives.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn save(&self) -> Result<(), UTXOError> {
+        let entries: Vec<(&OutPoint, &UTXOEntry)> = self.memory.outputs.iter().collect();
+        let bytes = DatabaseManager::serialize_with_checksum(&entries)?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| UTXOError::IoError(e.to_string()))?;
+        }
+        let tmp = self.path.with_extension("dat.new");
+        fs::write(&tmp, &bytes).map_err(|e| UTXOError::IoError(e.to_string()))?;
+        fs::rename(&tmp, &self.path).map_err(|e| UTXOError::IoError(e.to_string()))
+    }
+}
+
+impl UTXOStorage for FileUTXOStorage {
+    fn add_output(
+        &mut self,
+        outpoint: OutPoint,
+        output: TxOutput,
+        block_height: u64,
+        is_coinbase: bool,
+    ) -> Result<(), UTXOError> {
+        self.memory
+            .add_output(outpoint, output, block_height, is_coinbase)?;
+        self.save()
+    }
+
+    fn spend_output(
+        &mut self,
+        outpoint: &OutPoint,
+        spending_tx_hash: Hash,
+    ) -> Result<(), UTXOError> {
+        self.memory.spend_output(outpoint, spending_tx_hash)?;
+        self.save()
+    }
+
+    fn get_output(&self, outpoint: &OutPoint) -> Result<Option<(TxOutput, u64, bool)>, UTXOError> {
+        self.memory.get_output(outpoint)
+    }
+
+    fn get_unspent_outputs(&self) -> Result<Vec<UTXORecord>, UTXOError> {
+        self.memory.get_unspent_outputs()
+    }
+
+    fn get_stats(&self) -> Result<UTXOStats, UTXOError> {
+        self.memory.get_stats()
+    }
+
+    fn clear(&mut self) -> Result<(), UTXOError> {
+        self.memory.clear()?;
+        self.save()
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
 /// A façade over a `UTXOStorage` backend.
 #[derive(Debug)]
 pub struct UTXOSet {
@@ -229,6 +374,15 @@ impl UTXOSet {
     pub fn clear(&mut self) -> Result<(), UTXOError> {
         self.storage.clear()
     }
+
+    /// The backend itself, for code written against `UTXOStorage`.
+    pub fn storage(&self) -> &dyn UTXOStorage {
+        &*self.storage
+    }
+
+    pub fn storage_mut(&mut self) -> &mut dyn UTXOStorage {
+        &mut *self.storage
+    }
 }
 
 #[cfg(test)]
@@ -265,6 +419,38 @@ mod tests {
         assert!(store.get_output(&op).unwrap().is_none());
     }
 
+    #[test]
+    fn file_utxo_storage_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chainstate").join(UTXO_FILE);
+        let kept = create_outpoint(Hash(hash_transaction(b"kept")), 0);
+        let spent = create_outpoint(Hash(hash_transaction(b"spent")), 0);
+        let out = TxOutput {
+            value: 7,
+            script_pubkey: vec![],
+        };
+
+        let mut store = FileUTXOStorage::open(&path).unwrap();
+        assert!(store.is_persistent());
+        store
+            .add_output(kept.clone(), out.clone(), 3, true)
+            .unwrap();
+        store
+            .add_output(spent.clone(), out.clone(), 3, false)
+            .unwrap();
+        store.spend_output(&spent, Hash::zero()).unwrap();
+        drop(store);
+
+        let store = FileUTXOStorage::open(&path).unwrap();
+        assert_eq!(store.get_output(&kept).unwrap(), Some((out, 3, true)));
+        assert!(store.get_output(&spent).unwrap().is_none());
+
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&path, bytes).unwrap();
+        assert!(FileUTXOStorage::open(&path).is_err());
+    }
+
     #[test]
     fn utxoset_facade() {
         let mut set = UTXOSet::new(Box::new(MemoryUTXOStorage::new()));
@@ -280,4 +466,24 @@ mod tests {
         set.spend(&op, Hash(hash_transaction(b"spend-2"))).unwrap();
         assert!(set.get(&op).unwrap().is_none());
     }
+
+    #[test]
+    fn coinbase_outputs_wait_for_maturity() {
+        assert!(is_spendable_at(false, 10, 10, 100));
+        assert!(!is_spendable_at(true, 10, 109, 100));
+        assert!(is_spendable_at(true, 10, 110, 100));
+
+        let record = UTXORecord {
+            outpoint: create_outpoint(Hash(hash_transaction(b"cb")), 0),
+            output: TxOutput {
+                value: 50,
+                script_pubkey: vec![],
+            },
+            block_height: 1,
+            is_coinbase: true,
+        };
+        assert!(!record.is_spendable_at(1, 1));
+        assert!(record.is_spendable_at(2, 1));
+        assert!(record.is_spendable_at(1, 0));
+    }
 }
@@ -31,6 +31,9 @@ pub enum BlockchainError {
 
     #[error("UTXO error: {0}")]
     UtxoError(String),
+
+    #[error("Mempool error: {0}")]
+    MempoolError(String),
 }
 
 // Implement From for UTXOError to BlockchainError
@@ -46,3 +49,10 @@ impl From<crate::network::ProtocolError> for BlockchainError {
         BlockchainError::NetworkError(format!("{}", error))
     }
 }
+
+// Implement From for MempoolError to BlockchainError
+impl From<crate::mempool::MempoolError> for BlockchainError {
+    fn from(error: crate::mempool::MempoolError) -> Self {
+        BlockchainError::MempoolError(format!("{}", error))
+    }
+}
@@ -5,7 +5,14 @@ pub mod config;
 pub mod crypto;
 pub mod database;
 pub mod error;
+pub mod mempool;
+pub mod mining;
 pub mod network;
+pub mod rpc;
+pub mod time;
+
+#[cfg(test)]
+pub(crate) mod test_util;
 
 // Remove broken re-exports that caused E0432:
 // pub use blockchain::QuantumResistantBlockchain;
@@ -2,18 +2,25 @@
 
 use std::env;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use tokio::signal;
 
 // ----- Crate imports -----
-use btpc_quantum_resistant_chain::database::utxo_set::MemoryUTXOStorage;
+use btpc_quantum_resistant_chain::config::{get_default_config_path, Config, NetworkType};
+use btpc_quantum_resistant_chain::database::utxo_set::{FileUTXOStorage, UTXO_FILE};
 use btpc_quantum_resistant_chain::database::{DatabaseConfig, DatabaseManager};
+use btpc_quantum_resistant_chain::mempool::{
+    FeeEstimator, Mempool, MempoolConfig, FEE_ESTIMATES_FILE,
+};
 use btpc_quantum_resistant_chain::network::{SyncManager, SyncScheduler, SyncState};
+use btpc_quantum_resistant_chain::time::now_secs;
 
 #[derive(Debug, Clone)]
 struct NodeConfig {
+    /// Which chain to run.
+    network: NetworkType,
     /// How often the sync scheduler ticks, in seconds.
     sync_interval_secs: u64,
 }
@@ -21,6 +28,7 @@ struct NodeConfig {
 impl Default for NodeConfig {
     fn default() -> Self {
         Self {
+            network: NetworkType::Mainnet,
             sync_interval_secs: 5,
         }
     }
@@ -33,6 +41,18 @@ impl NodeConfig {
 
         while let Some(arg) = args.next() {
             match arg.as_str() {
+                "--network" => match args.next().as_deref() {
+                    Some("mainnet") => cfg.network = NetworkType::Mainnet,
+                    Some("testnet") => cfg.network = NetworkType::Testnet,
+                    Some("regtest") => cfg.network = NetworkType::Regtest,
+                    other => {
+                        eprintln!(
+                            "Invalid --network: {} (expected mainnet, testnet or regtest)",
+                            other.unwrap_or("<missing>")
+                        );
+                        print_help_and_exit();
+                    }
+                },
                 "--sync-interval-secs" => {
                     if let Some(val) = args.next() {
                         match u64::from_str(&val) {
@@ -72,6 +92,7 @@ USAGE:
   btpc-quantum-resistant-chain [FLAGS]
 
 FLAGS:
+  --network <name>             mainnet, testnet or regtest (default mainnet)
   --sync-interval-secs <u64>   How often the sync scheduler ticks (default 5)
   -h, --help                   Show this help and exit
 "
@@ -92,20 +113,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         cfg.sync_interval_secs
     );
 
-    // --- DatabaseManager using MemoryUTXOStorage ---
-    let storage = Box::new(MemoryUTXOStorage::new());
+    // Each network keeps its data next to its default config file.
+    let data_dir = get_default_config_path(cfg.network.clone())
+        .parent()
+        .map(|dir| dir.to_path_buf());
+    let config = Config::new(cfg.network.clone(), data_dir);
 
+    // --- DatabaseManager using FileUTXOStorage ---
     let db_cfg = DatabaseConfig {
-        data_dir: "./data".to_string().into(),
-        // change path if needed
-        max_cache_size: 10_000,
-        // tune cache size for your workload
+        data_dir: config.get_chainstate_dir(),
+        max_cache_size: config.database.max_cache_size,
     };
+    let storage = Box::new(FileUTXOStorage::open(db_cfg.data_dir.join(UTXO_FILE))?);
 
+    let mempool_file = config.get_mempool_file();
+    let fee_estimates_file = config.data_dir.join(FEE_ESTIMATES_FILE);
     let db_manager = Arc::new(DatabaseManager::new(storage, db_cfg));
     // ------------------------------------------------
 
-    let sync_manager = Arc::new(SyncManager::new(db_manager));
+    let sync_manager = Arc::new(SyncManager::new(Arc::clone(&db_manager)));
+
+    // --- Mempool, reloaded from the previous run ---
+    let mut mempool = Mempool::new(MempoolConfig::from_config(&config));
+    mempool.set_tip_height(sync_manager.get_state().current_height);
+    let fee_estimator =
+        FeeEstimator::load(&fee_estimates_file, mempool.config()).unwrap_or_else(|e| {
+            log::warn!(
+                "Ignoring unreadable {}: {}",
+                fee_estimates_file.display(),
+                e
+            );
+            FeeEstimator::new(mempool.config())
+        });
+    mempool.set_fee_estimator(fee_estimator);
+    // Saved transactions are re-validated against the UTXO set; an in-memory
+    // set starts empty, so loading would reject them all and the dump at
+    // shutdown would then erase the file.
+    let persist_mempool = db_manager.storage().is_persistent();
+    if persist_mempool {
+        match mempool.load(&mempool_file, db_manager.storage(), now_secs()) {
+            Ok(stats) => log::info!(
+                "Loaded {} mempool transaction(s) ({} expired, {} no longer valid)",
+                stats.loaded,
+                stats.expired,
+                stats.failed
+            ),
+            Err(e) => log::warn!("Ignoring unreadable {}: {}", mempool_file.display(), e),
+        }
+    } else {
+        log::info!("UTXO set is not persistent; not loading or saving the mempool");
+    }
+    let mempool = Arc::new(RwLock::new(mempool));
+    // ------------------------------------------------
 
     let scheduler = SyncScheduler::new(
         Arc::clone(&sync_manager),
@@ -137,5 +196,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log::info!("Node running. Press Ctrl+C to stop.");
     signal::ctrl_c().await?;
     log::info!("Shutdown signal received. Exiting…");
+
+    if persist_mempool {
+        match mempool.read().unwrap().dump(&mempool_file) {
+            Ok(count) => log::info!("Saved {} mempool transaction(s)", count),
+            Err(e) => log::error!("Failed to save mempool: {}", e),
+        }
+    }
+    if let Some(estimator) = mempool.read().unwrap().fee_estimator() {
+        if let Err(e) = estimator.save(&fee_estimates_file) {
+            log::error!("Failed to save fee estimates: {}", e);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,410 @@
+//! Fee estimation from confirmed-block data.
+//!
+//! Every transaction entering the pool is tracked in an exponentially spaced
+//! feerate bucket together with the height it arrived at. When a block
+//! confirms it, the number of blocks it waited is recorded against every
+//! target it met; transactions that leave the pool unconfirmed count as
+//! failures for the targets they outlived. All counters decay each block so
+//! the estimate follows current conditions.
+//!
+//! `estimate_fee(target, mode)` walks the buckets from the highest feerate
+//! down and returns the lowest feerate whose transactions still confirmed
+//! within `target` blocks often enough for `mode`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::{feerate, MempoolConfig, MempoolEntry, MempoolError};
+use crate::config::WalletConfig;
+use crate::database::DatabaseManager;
+use crate::network::protocol::Hash;
+
+/// File name of the estimator state inside the data directory.
+pub const FEE_ESTIMATES_FILE: &str = "fee_estimates.dat";
+
+const FEE_ESTIMATES_VERSION: u32 = 1;
+
+/// Per-block decay applied to every counter (half-life of ~350 blocks).
+const DECAY: f64 = 0.998;
+
+/// Ratio between the lower bounds of neighbouring buckets.
+const BUCKET_SPACING: f64 = 1.1;
+
+/// Lower bound of the highest bucket (base units per kB).
+const MAX_BUCKET_FEERATE: u64 = 10_000_000;
+
+/// Decayed transaction count a bucket range needs before it is judged.
+const SUFFICIENT_TXS: f64 = 2.0;
+
+/// How sure an estimate has to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EstimateMode {
+    /// 95% of transactions at the feerate confirmed in time.
+    Conservative,
+    /// 85% of transactions at the feerate confirmed in time.
+    Economical,
+}
+
+impl EstimateMode {
+    fn success_threshold(self) -> f64 {
+        match self {
+            EstimateMode::Conservative => 0.95,
+            EstimateMode::Economical => 0.85,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    height: u64,
+    bucket: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EstimatorSnapshot {
+    version: u32,
+    best_height: u64,
+    buckets: Vec<u64>,
+    confirmed: Vec<Vec<f64>>,
+    failed: Vec<Vec<f64>>,
+    total: Vec<f64>,
+}
+
+/// Tracks confirmation times per feerate bucket.
+#[derive(Debug, Clone)]
+pub struct FeeEstimator {
+    min_relay_fee: u64,
+    /// Targets reported by `estimates`, from `MempoolConfig::fee_estimation_blocks`.
+    targets: Vec<u32>,
+    max_target: u32,
+    /// Lower bound of each bucket, ascending.
+    buckets: Vec<u64>,
+    /// `confirmed[t - 1][b]`: confirmed within `t` blocks.
+    confirmed: Vec<Vec<f64>>,
+    /// `failed[t - 1][b]`: left the pool unconfirmed after more than `t` blocks.
+    failed: Vec<Vec<f64>>,
+    /// `total[b]`: confirmed at all.
+    total: Vec<f64>,
+    tracked: HashMap<Hash, Tracked>,
+    best_height: u64,
+}
+
+impl FeeEstimator {
+    pub fn new(config: &MempoolConfig) -> Self {
+        let mut buckets = Vec::new();
+        let mut lower = config.min_relay_fee.max(1);
+        while lower < MAX_BUCKET_FEERATE {
+            buckets.push(lower);
+            lower = ((lower as f64) * BUCKET_SPACING).ceil() as u64;
+        }
+        buckets.push(MAX_BUCKET_FEERATE);
+
+        let max_target = config
+            .fee_estimation_blocks
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let rows = max_target as usize;
+
+        Self {
+            min_relay_fee: config.min_relay_fee,
+            targets: config.fee_estimation_blocks.clone(),
+            max_target,
+            confirmed: vec![vec![0.0; buckets.len()]; rows],
+            failed: vec![vec![0.0; buckets.len()]; rows],
+            total: vec![0.0; buckets.len()],
+            buckets,
+            tracked: HashMap::new(),
+            best_height: 0,
+        }
+    }
+
+    /// Height of the last block processed.
+    pub fn best_height(&self) -> u64 {
+        self.best_height
+    }
+
+    /// Largest target an estimate can be asked for.
+    pub fn max_target(&self) -> u32 {
+        self.max_target
+    }
+
+    /// Number of pooled transactions currently tracked.
+    pub fn tracked_count(&self) -> usize {
+        self.tracked.len()
+    }
+
+    fn bucket_for(&self, rate: u64) -> usize {
+        self.buckets.partition_point(|&lower| lower <= rate).max(1) - 1
+    }
+
+    /// Start tracking a transaction that just entered the pool at `height`.
+    pub fn process_transaction(&mut self, entry: &MempoolEntry, height: u64) {
+        let bucket = self.bucket_for(feerate(entry.fee, entry.size));
+        self.tracked
+            .entry(entry.txid)
+            .or_insert(Tracked { height, bucket });
+    }
+
+    /// Stop tracking a transaction that left the pool without confirming.
+    pub fn remove_transaction(&mut self, txid: &Hash) {
+        let Some(tracked) = self.tracked.remove(txid) else {
+            return;
+        };
+        let waited = self.best_height.saturating_sub(tracked.height);
+        let outlived = waited.min(u64::from(self.max_target) + 1) as usize;
+        for row in self.failed.iter_mut().take(outlived.saturating_sub(1)) {
+            row[tracked.bucket] += 1.0;
+        }
+    }
+
+    /// Record a connected block and the pool entries it confirmed
+    /// (see `Mempool::remove_for_block`).
+    ///
+    /// Blocks at or below the last processed height are ignored, so a reorg
+    /// never counts the same confirmations twice.
+    pub fn process_block(&mut self, height: u64, confirmed: &[MempoolEntry]) {
+        if height <= self.best_height {
+            return;
+        }
+        self.best_height = height;
+
+        for row in self.confirmed.iter_mut().chain(self.failed.iter_mut()) {
+            row.iter_mut().for_each(|v| *v *= DECAY);
+        }
+        self.total.iter_mut().for_each(|v| *v *= DECAY);
+
+        for entry in confirmed {
+            let Some(tracked) = self.tracked.remove(&entry.txid) else {
+                continue;
+            };
+            let blocks = height.saturating_sub(tracked.height).max(1);
+            self.total[tracked.bucket] += 1.0;
+            if blocks <= u64::from(self.max_target) {
+                for row in self.confirmed.iter_mut().skip(blocks as usize - 1) {
+                    row[tracked.bucket] += 1.0;
+                }
+            }
+        }
+    }
+
+    /// Feerate (base units per kB) likely to confirm within `target` blocks.
+    ///
+    /// Returns `None` until enough confirmations have been seen. Targets
+    /// above `max_target` are clamped.
+    pub fn estimate_fee(&self, target: u32, mode: EstimateMode) -> Option<u64> {
+        if target == 0 {
+            return None;
+        }
+        let row = target.min(self.max_target) as usize - 1;
+        let threshold = mode.success_threshold();
+
+        let mut best = None;
+        let (mut confirmed, mut seen) = (0.0, 0.0);
+        for bucket in (0..self.buckets.len()).rev() {
+            confirmed += self.confirmed[row][bucket];
+            seen += self.total[bucket] + self.failed[row][bucket];
+            if seen < SUFFICIENT_TXS {
+                continue;
+            }
+            if confirmed / seen < threshold {
+                break;
+            }
+            best = Some(self.buckets[bucket]);
+            confirmed = 0.0;
+            seen = 0.0;
+        }
+
+        best.map(|rate| rate.max(self.min_relay_fee))
+    }
+
+    /// Estimates for each configured target, for RPC callers.
+    pub fn estimates(&self, mode: EstimateMode) -> Vec<(u32, Option<u64>)> {
+        self.targets
+            .iter()
+            .map(|&target| (target, self.estimate_fee(target, mode)))
+            .collect()
+    }
+
+    /// Absolute fee for `size` bytes to confirm within `target` blocks.
+    pub fn fee_for_size(&self, size: usize, target: u32, mode: EstimateMode) -> Option<u64> {
+        self.estimate_fee(target, mode)
+            .map(|rate| (size as u64).saturating_mul(rate).div_ceil(1000))
+    }
+
+    /// Fee a wallet should attach, falling back to the fixed
+    /// `WalletConfig::transaction_fee` while there is no estimate.
+    pub fn wallet_fee(&self, wallet: &WalletConfig, size: usize, target: u32) -> u64 {
+        self.fee_for_size(size, target, EstimateMode::Conservative)
+            .unwrap_or(wallet.transaction_fee)
+    }
+
+    /// Write the decayed statistics to `path`.
+    ///
+    /// Tracked pool transactions are not saved; they are picked up again as
+    /// the mempool is reloaded.
+    pub fn save(&self, path: &Path) -> Result<(), MempoolError> {
+        let snapshot = EstimatorSnapshot {
+            version: FEE_ESTIMATES_VERSION,
+            best_height: self.best_height,
+            buckets: self.buckets.clone(),
+            confirmed: self.confirmed.clone(),
+            failed: self.failed.clone(),
+            total: self.total.clone(),
+        };
+        let bytes = DatabaseManager::serialize_with_checksum(&snapshot)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| MempoolError::IoError(e.to_string()))?;
+        }
+        let tmp = path.with_extension("dat.new");
+        fs::write(&tmp, &bytes).map_err(|e| MempoolError::IoError(e.to_string()))?;
+        fs::rename(&tmp, path).map_err(|e| MempoolError::IoError(e.to_string()))
+    }
+
+    /// Load state saved by `save`, or start empty if `path` does not exist.
+    ///
+    /// Saved state built for a different bucket layout or target range is
+    /// discarded rather than misread.
+    pub fn load(path: &Path, config: &MempoolConfig) -> Result<Self, MempoolError> {
+        let mut estimator = Self::new(config);
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(estimator),
+            Err(e) => return Err(MempoolError::IoError(e.to_string())),
+        };
+        let payload = DatabaseManager::verify_and_strip_checksum(&data)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?;
+        let snapshot: EstimatorSnapshot = bincode::deserialize(&payload)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?;
+        if snapshot.version != FEE_ESTIMATES_VERSION {
+            return Err(MempoolError::SerializationError(format!(
+                "unsupported fee estimates version {}",
+                snapshot.version
+            )));
+        }
+
+        if snapshot.buckets != estimator.buckets
+            || snapshot.confirmed.len() != estimator.confirmed.len()
+        {
+            log::info!("Fee estimation parameters changed; discarding saved estimates");
+            return Ok(estimator);
+        }
+
+        estimator.best_height = snapshot.best_height;
+        estimator.confirmed = snapshot.confirmed;
+        estimator.failed = snapshot.failed;
+        estimator.total = snapshot.total;
+        Ok(estimator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::hash_transaction;
+    use crate::mempool::PackageStats;
+    use crate::network::protocol::Transaction;
+
+    fn entry(tag: &str, fee: u64) -> MempoolEntry {
+        MempoolEntry {
+            tx: Transaction {
+                inputs: vec![],
+                outputs: vec![],
+                replaceable: false,
+            },
+            txid: Hash(hash_transaction(tag.as_bytes())),
+            fee,
+            size: 1_000,
+            entry_time: 0,
+            fee_delta: 0,
+            ancestors: PackageStats::single(fee, 1_000),
+            descendants: PackageStats::single(fee, 1_000),
+        }
+    }
+
+    /// Feed `count` transactions at `fee` that each confirm after `wait` blocks.
+    fn feed(estimator: &mut FeeEstimator, height: &mut u64, fee: u64, wait: u64, count: usize) {
+        for i in 0..count {
+            let e = entry(&format!("{}-{}-{}", fee, *height, i), fee);
+            estimator.process_transaction(&e, *height);
+            *height += wait;
+            estimator.process_block(*height, &[e]);
+        }
+    }
+
+    #[test]
+    fn estimates_follow_confirmation_times() {
+        let mut estimator = FeeEstimator::new(&MempoolConfig::default());
+        assert_eq!(estimator.estimate_fee(1, EstimateMode::Economical), None);
+
+        let mut height = 0;
+        feed(&mut estimator, &mut height, 50_000, 1, 20);
+        feed(&mut estimator, &mut height, 2_000, 6, 20);
+
+        let fast = estimator
+            .estimate_fee(1, EstimateMode::Conservative)
+            .unwrap();
+        assert!((45_000..=50_000).contains(&fast), "{}", fast);
+
+        let slow = estimator
+            .estimate_fee(6, EstimateMode::Conservative)
+            .unwrap();
+        assert!(slow <= 2_000, "{}", slow);
+
+        // Beyond the configured range the largest target is used.
+        assert_eq!(
+            estimator.estimate_fee(100, EstimateMode::Conservative),
+            estimator.estimate_fee(24, EstimateMode::Conservative)
+        );
+        assert_eq!(estimator.estimates(EstimateMode::Economical).len(), 6);
+    }
+
+    #[test]
+    fn evicted_transactions_count_as_failures() {
+        let mut estimator = FeeEstimator::new(&MempoolConfig::default());
+        let mut height = 0;
+        feed(&mut estimator, &mut height, 2_000, 1, 5);
+
+        for i in 0..20 {
+            let e = entry(&format!("stuck-{}", i), 2_000);
+            estimator.process_transaction(&e, height);
+            height += 3;
+            estimator.process_block(height, &[]);
+            estimator.remove_transaction(&e.txid);
+        }
+
+        assert_eq!(estimator.tracked_count(), 0);
+        assert_eq!(estimator.estimate_fee(1, EstimateMode::Economical), None);
+    }
+
+    #[test]
+    fn state_survives_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FEE_ESTIMATES_FILE);
+        let config = MempoolConfig::default();
+
+        let mut estimator = FeeEstimator::new(&config);
+        let mut height = 0;
+        feed(&mut estimator, &mut height, 20_000, 2, 10);
+        estimator.save(&path).unwrap();
+
+        let reloaded = FeeEstimator::load(&path, &config).unwrap();
+        assert_eq!(reloaded.best_height(), estimator.best_height());
+        assert_eq!(
+            reloaded.estimate_fee(2, EstimateMode::Economical),
+            estimator.estimate_fee(2, EstimateMode::Economical)
+        );
+
+        let wallet = WalletConfig::default();
+        assert_eq!(
+            FeeEstimator::new(&config).wallet_fee(&wallet, 250, 2),
+            wallet.transaction_fee
+        );
+    }
+}
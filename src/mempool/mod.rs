@@ -0,0 +1,983 @@
+//! Transaction memory pool.
+//!
+//! Holds validated, unconfirmed transactions until they are mined. Inputs are
+//! resolved against the UTXO set plus the outputs of other pooled
+//! transactions, so chains of unconfirmed transactions are accepted.
+//!
+//! Policy enforced on entry:
+//! - structural checks (non-empty, no duplicate inputs/outputs, size limit);
+//! - inputs must exist and must not already be spent by another pooled tx;
+//! - coinbase outputs may only be spent once they would be
+//!   `coinbase_maturity` confirmations deep in the next block;
+//! - inputs must cover outputs and the fee must meet `min_relay_fee`.
+//!
+//! When the pool exceeds its size or count limit, the package (a transaction
+//! plus its in-pool descendants) with the lowest feerate is evicted first.
+//!
+//! An attached `FeeEstimator` is told about every transaction that enters,
+//! every one `remove_for_block` confirms, and every one that leaves
+//! unconfirmed (eviction, expiry, replacement, conflicts).
+
+pub mod fee_estimator;
+pub mod orphan;
+pub mod package;
+pub mod persist;
+pub mod rbf;
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::database::utxo_set::{is_spendable_at, OutPoint, UTXOStorage};
+use crate::network::protocol::{Hash, Transaction};
+use crate::time::now_secs;
+
+pub use fee_estimator::{EstimateMode, FeeEstimator, FEE_ESTIMATES_FILE};
+pub use orphan::{OrphanEntry, OrphanPool, TxAcceptance};
+pub use package::{AncestorPackage, AncestorPackages, PackageStats};
+pub use persist::{LoadStats, MEMPOOL_FILE, MEMPOOL_FILE_VERSION};
+pub use rbf::{RbfError, Replacement, MAX_REPLACEMENT_EVICTIONS};
+
+/// Mempool policy knobs.
+///
+/// This is **not** the same type as the `[mempool]` TOML section; build it
+/// with `MempoolConfig::from_config` to pick up the node-wide limits.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MempoolConfig {
+    /// Upper bound on the summed serialized size of pooled transactions.
+    pub max_size_bytes: usize,
+    /// Upper bound on the number of pooled transactions.
+    pub max_transactions: usize,
+    /// Minimum relay feerate in base units per 1000 bytes.
+    pub min_relay_fee: u64,
+    /// Largest single transaction accepted (bytes).
+    pub max_transaction_size: usize,
+    /// How long a transaction may sit in the pool before it is dropped.
+    pub expiry: Duration,
+    /// Upper bound on transactions parked in the orphan pool.
+    pub max_orphan_transactions: usize,
+    /// Upper bound on orphan bytes attributed to a single peer.
+    pub max_orphan_bytes_per_peer: usize,
+    /// How long an orphan waits for its parents before it is dropped.
+    pub orphan_expiry: Duration,
+    /// Upper bound on a transaction plus its in-pool ancestors (count).
+    pub max_ancestor_count: usize,
+    /// Upper bound on a transaction plus its in-pool ancestors (bytes).
+    pub max_ancestor_size: usize,
+    /// Upper bound on a transaction plus its in-pool descendants (count).
+    pub max_descendant_count: usize,
+    /// Upper bound on a transaction plus its in-pool descendants (bytes).
+    pub max_descendant_size: usize,
+    /// Confirmation targets (in blocks) the fee estimator reports on.
+    pub fee_estimation_blocks: Vec<u32>,
+    /// Confirmations a coinbase output needs before it can be spent.
+    pub coinbase_maturity: u32,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size_bytes: 300 * 1024 * 1024, // 300MB
+            max_transactions: 50_000,
+            min_relay_fee: 1_000, // 0.00001 BTP per kB
+            max_transaction_size: 1_000_000,
+            expiry: Duration::from_secs(14 * 24 * 60 * 60), // 14 days
+            max_orphan_transactions: 1_000,
+            max_orphan_bytes_per_peer: 5 * 1024 * 1024, // 5MB
+            orphan_expiry: Duration::from_secs(20 * 60), // 20 minutes
+            max_ancestor_count: 25,
+            max_ancestor_size: 101_000,
+            max_descendant_count: 25,
+            max_descendant_size: 101_000,
+            fee_estimation_blocks: vec![1, 2, 3, 6, 12, 24],
+            coinbase_maturity: 100,
+        }
+    }
+}
+
+impl MempoolConfig {
+    /// Take the node-wide size and expiry limits from `Config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_size_bytes: config.max_mempool_size,
+            expiry: config.mempool_expiry,
+            coinbase_maturity: config.mining.coinbase_maturity,
+            ..Self::default()
+        }
+    }
+
+    /// Minimum absolute fee for a transaction of `size` bytes.
+    pub fn min_fee_for(&self, size: usize) -> u64 {
+        (size as u64)
+            .saturating_mul(self.min_relay_fee)
+            .div_ceil(1000)
+    }
+}
+
+/// Reasons a transaction is rejected by the mempool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    AlreadyInPool,
+    EmptyInputsOrOutputs,
+    DuplicateInput,
+    DuplicateOutput,
+    TooLarge(usize),
+    /// Inputs that are neither in the UTXO set nor created by a pooled tx.
+    MissingInputs(Vec<OutPoint>),
+    /// An input spends a coinbase output that is not yet mature.
+    PrematureCoinbaseSpend(OutPoint),
+    /// An input is already spent by the given pooled transaction.
+    Conflict(Hash),
+    InsufficientInputs {
+        inputs: u64,
+        outputs: u64,
+    },
+    FeeTooLow {
+        fee: u64,
+        required: u64,
+    },
+    ValueOverflow,
+    PoolFull,
+    /// A conflicting replacement failed the replace-by-fee rules.
+    Replacement(RbfError),
+    /// The relaying peer already has too many orphan bytes parked.
+    PeerOrphanLimit(String),
+    /// Accepting the transaction would exceed an ancestor/descendant limit.
+    PackageLimit(String),
+    UtxoError(String),
+    SerializationError(String),
+    IoError(String),
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::AlreadyInPool => write!(f, "Transaction already in mempool"),
+            MempoolError::EmptyInputsOrOutputs => {
+                write!(f, "Transaction has no inputs or no outputs")
+            }
+            MempoolError::DuplicateInput => write!(f, "Transaction spends the same input twice"),
+            MempoolError::DuplicateOutput => write!(f, "Transaction output already exists"),
+            MempoolError::TooLarge(size) => write!(f, "Transaction too large: {} bytes", size),
+            MempoolError::MissingInputs(missing) => {
+                write!(f, "Missing {} transaction input(s)", missing.len())
+            }
+            MempoolError::PrematureCoinbaseSpend(outpoint) => write!(
+                f,
+                "Input {}:{} spends an immature coinbase output",
+                outpoint.tx_hash, outpoint.index
+            ),
+            MempoolError::Conflict(txid) => {
+                write!(f, "Input already spent by mempool transaction {}", txid)
+            }
+            MempoolError::InsufficientInputs { inputs, outputs } => {
+                write!(f, "Inputs ({}) do not cover outputs ({})", inputs, outputs)
+            }
+            MempoolError::FeeTooLow { fee, required } => {
+                write!(f, "Fee too low: {} < {} required", fee, required)
+            }
+            MempoolError::ValueOverflow => write!(f, "Transaction value overflow"),
+            MempoolError::PoolFull => write!(f, "Mempool full"),
+            MempoolError::Replacement(e) => write!(f, "Replacement rejected: {}", e),
+            MempoolError::PeerOrphanLimit(peer) => {
+                write!(f, "Orphan limit reached for peer {}", peer)
+            }
+            MempoolError::PackageLimit(msg) => write!(f, "Package limit exceeded: {}", msg),
+            MempoolError::UtxoError(msg) => write!(f, "UTXO error: {}", msg),
+            MempoolError::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
+            MempoolError::IoError(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// A validated transaction held in the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub txid: Hash,
+    /// Absolute fee in base units.
+    pub fee: u64,
+    /// Serialized size in bytes.
+    pub size: usize,
+    /// Unix time (seconds) the transaction entered the pool.
+    pub entry_time: u64,
+    /// Operator fee adjustment (see `Mempool::prioritise_transaction`).
+    /// Counts toward block selection and eviction, not relay policy.
+    pub fee_delta: i64,
+    /// This entry plus its in-pool ancestors (using the modified fee).
+    pub ancestors: PackageStats,
+    /// This entry plus its in-pool descendants (using the modified fee).
+    pub descendants: PackageStats,
+}
+
+impl MempoolEntry {
+    /// Feerate in base units per 1000 bytes.
+    pub fn feerate(&self) -> u64 {
+        feerate(self.fee, self.size)
+    }
+
+    /// Fee with the operator delta applied, floored at zero.
+    pub fn modified_fee(&self) -> u64 {
+        if self.fee_delta >= 0 {
+            self.fee.saturating_add(self.fee_delta as u64)
+        } else {
+            self.fee.saturating_sub(self.fee_delta.unsigned_abs())
+        }
+    }
+}
+
+/// Feerate in base units per 1000 bytes.
+pub fn feerate(fee: u64, size: usize) -> u64 {
+    if size == 0 {
+        return 0;
+    }
+    ((fee as u128 * 1000) / size as u128) as u64
+}
+
+/// Compare `fee_a / size_a` with `fee_b / size_b` without rounding.
+pub(crate) fn cmp_feerate(fee_a: u64, size_a: usize, fee_b: u64, size_b: usize) -> Ordering {
+    (fee_a as u128 * size_b as u128).cmp(&(fee_b as u128 * size_a as u128))
+}
+
+/// Serialized size of a transaction, as used for fee and limit accounting.
+pub fn transaction_size(tx: &Transaction) -> Result<usize, MempoolError> {
+    bincode::serialized_size(tx)
+        .map(|n| n as usize)
+        .map_err(|e| MempoolError::SerializationError(e.to_string()))
+}
+
+/// Signature checks a transaction costs a block: one per input, since every
+/// input carries a single signature in this transaction form.
+pub fn transaction_sigops(tx: &Transaction) -> usize {
+    tx.inputs.len()
+}
+
+/// The transaction memory pool.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: HashMap<Hash, MempoolEntry>,
+    /// Outpoint -> txid of the pooled transaction that spends it.
+    spent_by: HashMap<OutPoint, Hash>,
+    /// Outpoint -> (txid of the pooled transaction that creates it, value).
+    created: HashMap<OutPoint, (Hash, u64)>,
+    /// Operator fee deltas by txid; kept for transactions not (yet) pooled.
+    fee_deltas: HashMap<Hash, i64>,
+    total_size: usize,
+    /// Bumped on every change to the pooled set or to a modified fee.
+    sequence: u64,
+    /// Height of the chain tip; pooled transactions target the next block.
+    tip_height: u64,
+    /// Fed with every entry, confirmation and unconfirmed removal.
+    fee_estimator: Option<FeeEstimator>,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    /// Attach a fee estimator. Attach it before loading saved transactions
+    /// so they are tracked too.
+    pub fn set_fee_estimator(&mut self, estimator: FeeEstimator) {
+        self.fee_estimator = Some(estimator);
+    }
+
+    pub fn fee_estimator(&self) -> Option<&FeeEstimator> {
+        self.fee_estimator.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Summed serialized size of all pooled transactions.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    /// Counter that changes whenever the pool contents or a modified fee
+    /// change; lets template consumers tell whether a refresh is worthwhile.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Hash) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.entries.values()
+    }
+
+    /// Pooled transactions spending any of the inputs of `tx`.
+    pub fn direct_conflicts(&self, tx: &Transaction) -> Vec<Hash> {
+        let mut conflicts = Vec::new();
+        for input in &tx.inputs {
+            if let Some(spender) = self.spent_by.get(input) {
+                if !conflicts.contains(spender) {
+                    conflicts.push(*spender);
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Height of the chain tip the pool was last told about.
+    pub fn tip_height(&self) -> u64 {
+        self.tip_height
+    }
+
+    /// Record a new chain tip. Call this before `remove_for_block` and
+    /// `readd_disconnected` so coinbase maturity is judged against the
+    /// block that would include the pooled transactions.
+    pub fn set_tip_height(&mut self, height: u64) {
+        self.tip_height = height;
+    }
+
+    /// Txid of the pooled transaction spending `outpoint`, if any.
+    pub fn spender_of(&self, outpoint: &OutPoint) -> Option<Hash> {
+        self.spent_by.get(outpoint).copied()
+    }
+
+    /// Validate `tx` and add it to the pool.
+    pub fn accept_transaction(
+        &mut self,
+        tx: Transaction,
+        utxos: &dyn UTXOStorage,
+    ) -> Result<Hash, MempoolError> {
+        self.accept_transaction_at(tx, utxos, now_secs())
+    }
+
+    /// Same as `accept_transaction`, with an explicit entry time.
+    pub fn accept_transaction_at(
+        &mut self,
+        tx: Transaction,
+        utxos: &dyn UTXOStorage,
+        now: u64,
+    ) -> Result<Hash, MempoolError> {
+        let conflicts = self.direct_conflicts(&tx);
+        if !conflicts.is_empty() {
+            return self
+                .accept_replacement(tx, conflicts, utxos, now)
+                .map(|replacement| replacement.txid);
+        }
+
+        let entry = self.check_transaction(tx, utxos, now)?;
+        self.insert_and_trim(entry)
+    }
+
+    /// Insert a checked entry, then evict down to the pool limits.
+    fn insert_and_trim(&mut self, entry: MempoolEntry) -> Result<Hash, MempoolError> {
+        let txid = entry.txid;
+        self.insert_entry(entry);
+        self.trim_to_limits();
+
+        if !self.entries.contains_key(&txid) {
+            return Err(MempoolError::PoolFull);
+        }
+        Ok(txid)
+    }
+
+    /// Run every entry policy check against `tx` without modifying the pool.
+    pub fn check_transaction(
+        &self,
+        tx: Transaction,
+        utxos: &dyn UTXOStorage,
+        now: u64,
+    ) -> Result<MempoolEntry, MempoolError> {
+        self.check_transaction_excluding(tx, utxos, now, &HashSet::new())
+    }
+
+    /// Like `check_transaction`, but treat the pooled transactions in
+    /// `excluded` as already gone (used when evaluating a replacement).
+    fn check_transaction_excluding(
+        &self,
+        tx: Transaction,
+        utxos: &dyn UTXOStorage,
+        now: u64,
+        excluded: &HashSet<Hash>,
+    ) -> Result<MempoolEntry, MempoolError> {
+        if tx.inputs.is_empty() || tx.outputs.is_empty() {
+            return Err(MempoolError::EmptyInputsOrOutputs);
+        }
+
+        let txid = tx.txid();
+        if self.entries.contains_key(&txid) {
+            return Err(MempoolError::AlreadyInPool);
+        }
+
+        let size = transaction_size(&tx)?;
+        if size > self.config.max_transaction_size {
+            return Err(MempoolError::TooLarge(size));
+        }
+
+        let mut seen_inputs = HashSet::with_capacity(tx.inputs.len());
+        for input in &tx.inputs {
+            if !seen_inputs.insert(input) {
+                return Err(MempoolError::DuplicateInput);
+            }
+        }
+
+        for input in &tx.inputs {
+            if let Some(spender) = self.spent_by.get(input) {
+                if !excluded.contains(spender) {
+                    return Err(MempoolError::Conflict(*spender));
+                }
+            }
+        }
+
+        let input_value = self.input_value(&tx, utxos)?;
+
+        let mut seen_outputs = HashSet::with_capacity(tx.outputs.len());
+        let mut output_value: u64 = 0;
+        for (outpoint, value) in &tx.outputs {
+            let created_in_pool = self
+                .created
+                .get(outpoint)
+                .is_some_and(|(creator, _)| !excluded.contains(creator));
+            if !seen_outputs.insert(outpoint)
+                || created_in_pool
+                || lookup_utxo(utxos, outpoint)?.is_some()
+            {
+                return Err(MempoolError::DuplicateOutput);
+            }
+            output_value = output_value
+                .checked_add(*value)
+                .ok_or(MempoolError::ValueOverflow)?;
+        }
+
+        if input_value < output_value {
+            return Err(MempoolError::InsufficientInputs {
+                inputs: input_value,
+                outputs: output_value,
+            });
+        }
+
+        let fee = input_value - output_value;
+        let required = self.config.min_fee_for(size);
+        if fee < required {
+            return Err(MempoolError::FeeTooLow { fee, required });
+        }
+
+        self.check_package_limits(&tx, size)?;
+
+        let own = PackageStats::single(fee, size);
+        Ok(MempoolEntry {
+            tx,
+            txid,
+            fee,
+            size,
+            entry_time: now,
+            fee_delta: 0,
+            ancestors: own,
+            descendants: own,
+        })
+    }
+
+    /// Sum of input values, resolving against pooled outputs first.
+    /// Coinbase outputs must be mature in the block after the tip.
+    fn input_value(&self, tx: &Transaction, utxos: &dyn UTXOStorage) -> Result<u64, MempoolError> {
+        let mut missing = Vec::new();
+        let mut total: u64 = 0;
+        let spend_height = self.tip_height + 1;
+
+        for input in &tx.inputs {
+            let value = match self.created.get(input) {
+                Some((_, value)) => Some(*value),
+                None => match utxos
+                    .get_output(input)
+                    .map_err(|e| MempoolError::UtxoError(e.to_string()))?
+                {
+                    Some((output, height, is_coinbase)) => {
+                        if !is_spendable_at(
+                            is_coinbase,
+                            height,
+                            spend_height,
+                            self.config.coinbase_maturity,
+                        ) {
+                            return Err(MempoolError::PrematureCoinbaseSpend(input.clone()));
+                        }
+                        Some(output.value)
+                    }
+                    None => None,
+                },
+            };
+            match value {
+                Some(v) => total = total.checked_add(v).ok_or(MempoolError::ValueOverflow)?,
+                None => missing.push(input.clone()),
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(MempoolError::MissingInputs(missing));
+        }
+        Ok(total)
+    }
+
+    fn insert_entry(&mut self, mut entry: MempoolEntry) {
+        entry.fee_delta = self.fee_deltas.get(&entry.txid).copied().unwrap_or(0);
+        for input in &entry.tx.inputs {
+            self.spent_by.insert(input.clone(), entry.txid);
+        }
+        for (outpoint, value) in &entry.tx.outputs {
+            self.created.insert(outpoint.clone(), (entry.txid, *value));
+        }
+        self.total_size += entry.size;
+        self.sequence += 1;
+        if let Some(estimator) = &mut self.fee_estimator {
+            estimator.process_transaction(&entry, self.tip_height);
+        }
+        let txid = entry.txid;
+        self.entries.insert(txid, entry);
+
+        // Re-linking can join existing chains (e.g. a parent returning after
+        // a disconnect), so refresh everyone connected to the new entry.
+        let mut affected = self.ancestors(&txid);
+        affected.extend(self.descendants(&txid));
+        affected.push(txid);
+        self.refresh_package_stats(&affected);
+    }
+
+    /// Remove a single entry, leaving any descendants in place. The fee
+    /// estimator is not told; see `forget_unconfirmed`.
+    fn remove_entry(&mut self, txid: &Hash) -> Option<MempoolEntry> {
+        if !self.entries.contains_key(txid) {
+            return None;
+        }
+        let mut affected = self.ancestors(txid);
+        affected.extend(self.descendants(txid));
+
+        let entry = self.entries.remove(txid)?;
+        for input in &entry.tx.inputs {
+            if self.spent_by.get(input) == Some(txid) {
+                self.spent_by.remove(input);
+            }
+        }
+        for (outpoint, _) in &entry.tx.outputs {
+            self.created.remove(outpoint);
+        }
+        self.total_size -= entry.size;
+        self.sequence += 1;
+        self.refresh_package_stats(&affected);
+        Some(entry)
+    }
+
+    /// Adjust the fee `txid` is treated as paying for mining and eviction.
+    ///
+    /// Deltas accumulate, and apply whenever the transaction is (or later
+    /// becomes) pooled, until it confirms.
+    pub fn prioritise_transaction(&mut self, txid: Hash, delta: i64) {
+        let total = self.fee_deltas.entry(txid).or_insert(0);
+        *total = total.saturating_add(delta);
+        let total = *total;
+
+        if let Some(entry) = self.entries.get_mut(&txid) {
+            entry.fee_delta = total;
+            self.sequence += 1;
+            let mut affected = self.ancestors(&txid);
+            affected.extend(self.descendants(&txid));
+            affected.push(txid);
+            self.refresh_package_stats(&affected);
+        }
+    }
+
+    /// Accumulated operator fee delta for `txid`.
+    pub fn fee_delta(&self, txid: &Hash) -> i64 {
+        self.fee_deltas.get(txid).copied().unwrap_or(0)
+    }
+
+    /// In-pool transactions spending outputs of `txid`, transitively.
+    pub fn descendants(&self, txid: &Hash) -> Vec<Hash> {
+        let mut out = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([*txid]);
+
+        while let Some(current) = queue.pop_front() {
+            let Some(entry) = self.entries.get(&current) else {
+                continue;
+            };
+            for (outpoint, _) in &entry.tx.outputs {
+                if let Some(child) = self.spent_by.get(outpoint) {
+                    if seen.insert(*child) {
+                        out.push(*child);
+                        queue.push_back(*child);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Remove `txid` together with everything that depends on it.
+    pub fn remove_with_descendants(&mut self, txid: &Hash) -> Vec<MempoolEntry> {
+        if !self.entries.contains_key(txid) {
+            return Vec::new();
+        }
+        let mut doomed = vec![*txid];
+        doomed.extend(self.descendants(txid));
+        let removed: Vec<MempoolEntry> = doomed
+            .iter()
+            .filter_map(|id| self.remove_entry(id))
+            .collect();
+        self.forget_unconfirmed(&removed);
+        removed
+    }
+
+    /// Tell the fee estimator that `removed` left the pool unconfirmed.
+    fn forget_unconfirmed(&mut self, removed: &[MempoolEntry]) {
+        if let Some(estimator) = &mut self.fee_estimator {
+            for entry in removed {
+                estimator.remove_transaction(&entry.txid);
+            }
+        }
+    }
+
+    fn over_limits(&self) -> bool {
+        self.entries.len() > self.config.max_transactions
+            || self.total_size > self.config.max_size_bytes
+    }
+
+    /// Evict the lowest descendant-feerate packages until the pool fits.
+    fn trim_to_limits(&mut self) -> Vec<MempoolEntry> {
+        let mut evicted = Vec::new();
+
+        while self.over_limits() {
+            let worst = self
+                .entries
+                .values()
+                .min_by(|a, b| {
+                    cmp_feerate(
+                        a.descendants.fee,
+                        a.descendants.size,
+                        b.descendants.fee,
+                        b.descendants.size,
+                    )
+                })
+                .map(|e| e.txid);
+
+            match worst {
+                Some(id) => evicted.extend(self.remove_with_descendants(&id)),
+                None => break,
+            }
+        }
+
+        evicted
+    }
+
+    /// Drop entries older than the configured expiry, with their descendants.
+    pub fn expire(&mut self, now: u64) -> Vec<MempoolEntry> {
+        let expiry = self.config.expiry.as_secs();
+        let stale: Vec<Hash> = self
+            .entries
+            .values()
+            .filter(|e| e.entry_time.saturating_add(expiry) <= now)
+            .map(|e| e.txid)
+            .collect();
+
+        let mut removed = Vec::new();
+        for txid in stale {
+            removed.extend(self.remove_with_descendants(&txid));
+        }
+        removed
+    }
+
+    /// Update the pool for a newly connected block.
+    ///
+    /// Confirmed transactions are removed (their pooled children stay, since
+    /// the outputs they spend now live in the UTXO set), and anything that
+    /// conflicts with the block is removed along with its descendants.
+    /// Returns the entries that were confirmed, which are also passed to the
+    /// fee estimator as confirmed at `tip_height`.
+    pub fn remove_for_block(&mut self, block_txs: &[Transaction]) -> Vec<MempoolEntry> {
+        let mut confirmed = Vec::new();
+
+        for tx in block_txs {
+            let txid = tx.txid();
+            self.fee_deltas.remove(&txid);
+            if let Some(entry) = self.remove_entry(&txid) {
+                confirmed.push(entry);
+            }
+            for input in &tx.inputs {
+                if let Some(spender) = self.spent_by.get(input).copied() {
+                    self.remove_with_descendants(&spender);
+                }
+            }
+        }
+
+        if let Some(estimator) = &mut self.fee_estimator {
+            estimator.process_block(self.tip_height, &confirmed);
+        }
+        confirmed
+    }
+
+    /// Return the transactions of a disconnected block to the pool.
+    ///
+    /// `utxos` and `set_tip_height` must already reflect the disconnect.
+    /// Coinbase transactions (no inputs) are skipped; anything that no longer
+    /// validates is dropped, as are pooled transactions whose inputs vanished
+    /// with the block or spend coinbase outputs that are immature again.
+    /// Returns how many transactions were re-added.
+    pub fn readd_disconnected(
+        &mut self,
+        block_txs: Vec<Transaction>,
+        utxos: &dyn UTXOStorage,
+    ) -> usize {
+        let now = now_secs();
+        let mut readded = 0;
+
+        for tx in block_txs.into_iter().filter(|tx| !tx.inputs.is_empty()) {
+            match self.accept_transaction_at(tx, utxos, now) {
+                Ok(_) => readded += 1,
+                Err(e) => log::debug!("Dropping disconnected transaction: {}", e),
+            }
+        }
+
+        self.remove_unresolvable(utxos);
+        readded
+    }
+
+    /// Remove entries whose inputs can no longer be resolved or are no
+    /// longer mature.
+    fn remove_unresolvable(&mut self, utxos: &dyn UTXOStorage) {
+        let broken: Vec<Hash> = self
+            .entries
+            .values()
+            .filter(|e| self.input_value(&e.tx, utxos).is_err())
+            .map(|e| e.txid)
+            .collect();
+
+        for txid in broken {
+            self.remove_with_descendants(&txid);
+        }
+    }
+}
+
+/// Value of an unspent output in the UTXO set, if present.
+fn lookup_utxo(utxos: &dyn UTXOStorage, outpoint: &OutPoint) -> Result<Option<u64>, MempoolError> {
+    utxos
+        .get_output(outpoint)
+        .map(|found| found.map(|(output, _, _)| output.value))
+        .map_err(|e| MempoolError::UtxoError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::{MemoryUTXOStorage, TxOutput};
+    use crate::test_util::{funded, op, tx};
+
+    #[test]
+    fn accepts_and_chains_unconfirmed() {
+        let utxos = funded(&["coin"], 10_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let parent = tx(vec![op("coin", 0)], vec![(op("parent", 0), 9_000)]);
+        let child = tx(vec![op("parent", 0)], vec![(op("child", 0), 8_000)]);
+
+        let parent_id = pool.accept_transaction(parent, &utxos).unwrap();
+        let child_id = pool.accept_transaction(child, &utxos).unwrap();
+
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.get(&parent_id).unwrap().fee, 1_000);
+        assert_eq!(pool.descendants(&parent_id), vec![child_id]);
+    }
+
+    #[test]
+    fn rejects_missing_conflicting_and_low_fee() {
+        let utxos = funded(&["coin"], 10_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let orphan = tx(vec![op("nowhere", 0)], vec![(op("o", 0), 1)]);
+        assert_eq!(
+            pool.accept_transaction(orphan, &utxos),
+            Err(MempoolError::MissingInputs(vec![op("nowhere", 0)]))
+        );
+
+        let free = tx(vec![op("coin", 0)], vec![(op("free", 0), 10_000)]);
+        assert!(matches!(
+            pool.accept_transaction(free, &utxos),
+            Err(MempoolError::FeeTooLow { fee: 0, .. })
+        ));
+
+        let first = tx(vec![op("coin", 0)], vec![(op("a", 0), 9_000)]);
+        let first_id = pool.accept_transaction(first, &utxos).unwrap();
+        let double = tx(vec![op("coin", 0)], vec![(op("b", 0), 9_000)]);
+        assert_eq!(
+            pool.accept_transaction(double, &utxos),
+            Err(MempoolError::Conflict(first_id))
+        );
+    }
+
+    #[test]
+    fn evicts_lowest_feerate_package_when_full() {
+        let utxos = funded(&["c1", "c2"], 10_000);
+        let config = MempoolConfig {
+            max_transactions: 1,
+            ..MempoolConfig::default()
+        };
+        let mut pool = Mempool::new(config);
+
+        let cheap = tx(vec![op("c1", 0)], vec![(op("cheap", 0), 9_500)]);
+        let rich = tx(vec![op("c2", 0)], vec![(op("rich", 0), 5_000)]);
+
+        let cheap_id = pool.accept_transaction(cheap, &utxos).unwrap();
+        let rich_id = pool.accept_transaction(rich, &utxos).unwrap();
+
+        assert!(!pool.contains(&cheap_id));
+        assert!(pool.contains(&rich_id));
+
+        let cheaper = tx(vec![op("c1", 0)], vec![(op("cheaper", 0), 9_700)]);
+        assert_eq!(
+            pool.accept_transaction(cheaper, &utxos),
+            Err(MempoolError::PoolFull)
+        );
+    }
+
+    #[test]
+    fn expires_old_entries_with_descendants() {
+        let utxos = funded(&["coin"], 10_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let expiry = pool.config().expiry.as_secs();
+
+        let parent = tx(vec![op("coin", 0)], vec![(op("parent", 0), 9_000)]);
+        let child = tx(vec![op("parent", 0)], vec![(op("child", 0), 8_000)]);
+        pool.accept_transaction_at(parent, &utxos, 100).unwrap();
+        pool.accept_transaction_at(child, &utxos, 100 + expiry)
+            .unwrap();
+
+        assert!(pool.expire(100 + expiry - 1).is_empty());
+        assert_eq!(pool.expire(100 + expiry).len(), 2);
+        assert!(pool.is_empty());
+        assert_eq!(pool.total_size(), 0);
+    }
+
+    #[test]
+    fn feeds_the_fee_estimator() {
+        let utxos = funded(&["coin", "other"], 10_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+        pool.set_fee_estimator(FeeEstimator::new(pool.config()));
+        let tracked = |pool: &Mempool| pool.fee_estimator().unwrap().tracked_count();
+
+        let confirmed = tx(vec![op("coin", 0)], vec![(op("a", 0), 9_000)]);
+        pool.accept_transaction_at(confirmed.clone(), &utxos, 0)
+            .unwrap();
+        pool.accept_transaction_at(
+            tx(vec![op("other", 0)], vec![(op("b", 0), 9_000)]),
+            &utxos,
+            0,
+        )
+        .unwrap();
+        assert_eq!(tracked(&pool), 2);
+
+        pool.set_tip_height(1);
+        assert_eq!(pool.remove_for_block(&[confirmed]).len(), 1);
+        assert_eq!(pool.fee_estimator().unwrap().best_height(), 1);
+        assert_eq!(tracked(&pool), 1);
+
+        assert_eq!(pool.expire(pool.config().expiry.as_secs()).len(), 1);
+        assert_eq!(tracked(&pool), 0);
+    }
+
+    #[test]
+    fn block_connect_and_disconnect() {
+        let mut utxos = funded(&["coin", "other"], 10_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let parent = tx(vec![op("coin", 0)], vec![(op("parent", 0), 9_000)]);
+        let child = tx(vec![op("parent", 0)], vec![(op("child", 0), 8_000)]);
+        let loser = tx(vec![op("other", 0)], vec![(op("loser", 0), 9_000)]);
+        let winner = tx(vec![op("other", 0)], vec![(op("winner", 0), 9_500)]);
+
+        let parent_id = pool.accept_transaction(parent.clone(), &utxos).unwrap();
+        let child_id = pool.accept_transaction(child, &utxos).unwrap();
+        let loser_id = pool.accept_transaction(loser, &utxos).unwrap();
+
+        // Connect a block confirming `parent` and a conflict of `loser`.
+        let block = vec![parent, winner];
+        for spent in ["coin", "other"] {
+            utxos.spend_output(&op(spent, 0), Hash::zero()).unwrap();
+        }
+        for (created, value) in [("parent", 9_000), ("winner", 9_500)] {
+            let out = TxOutput {
+                value,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(op(created, 0), out, 2, false).unwrap();
+        }
+
+        let confirmed = pool.remove_for_block(&block);
+        assert_eq!(confirmed.len(), 1);
+        assert!(!pool.contains(&parent_id));
+        assert!(!pool.contains(&loser_id));
+        assert!(pool.contains(&child_id));
+
+        // Disconnect it again: restore the spent coins, drop the block outputs.
+        for created in ["parent", "winner"] {
+            utxos.spend_output(&op(created, 0), Hash::zero()).unwrap();
+        }
+        for spent in ["coin", "other"] {
+            let out = TxOutput {
+                value: 10_000,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(op(spent, 0), out, 1, false).unwrap();
+        }
+
+        assert_eq!(pool.readd_disconnected(block, &utxos), 2);
+        assert!(pool.contains(&parent_id));
+        assert!(pool.contains(&child_id));
+        assert_eq!(pool.descendants(&parent_id), vec![child_id]);
+    }
+
+    #[test]
+    fn coinbase_outputs_wait_for_maturity() {
+        let mut utxos = MemoryUTXOStorage::new();
+        let out = TxOutput {
+            value: 10_000,
+            script_pubkey: vec![],
+        };
+        utxos.add_output(op("reward", 0), out, 10, true).unwrap();
+        let config = MempoolConfig {
+            coinbase_maturity: 100,
+            ..MempoolConfig::default()
+        };
+        let mut pool = Mempool::new(config);
+        let spend = tx(vec![op("reward", 0)], vec![(op("spend", 0), 9_000)]);
+
+        // The next block (tip + 1) must be at least 10 + 100.
+        pool.set_tip_height(108);
+        assert_eq!(
+            pool.accept_transaction(spend.clone(), &utxos),
+            Err(MempoolError::PrematureCoinbaseSpend(op("reward", 0)))
+        );
+        pool.set_tip_height(109);
+        let txid = pool.accept_transaction(spend, &utxos).unwrap();
+
+        // A reorg back below maturity evicts the spend.
+        pool.set_tip_height(108);
+        pool.readd_disconnected(Vec::new(), &utxos);
+        assert!(!pool.contains(&txid));
+    }
+}
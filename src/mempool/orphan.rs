@@ -0,0 +1,454 @@
+//! Orphan transaction pool.
+//!
+//! Transactions whose parents have not been seen yet are parked here, keyed
+//! by the outpoints they are missing. When a parent enters the mempool, the
+//! orphans waiting on its outputs are retried. Each relaying peer has a byte
+//! budget so a single peer cannot fill the pool, and orphans expire after
+//! `MempoolConfig::orphan_expiry`.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use super::{transaction_size, Mempool, MempoolConfig, MempoolError};
+use crate::database::utxo_set::{OutPoint, UTXOStorage};
+use crate::network::peer_manager::{ConnectedPeer, PeerEventListener};
+use crate::network::protocol::{
+    GetDataMessage, Hash, InvEntry, NetworkMessage, Transaction, INV_TX,
+};
+use crate::time::now_secs;
+
+/// Largest transaction we are willing to park as an orphan (bytes).
+pub const MAX_ORPHAN_TX_SIZE: usize = 100_000;
+
+/// A transaction waiting for one or more parents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrphanEntry {
+    pub tx: Transaction,
+    pub txid: Hash,
+    pub size: usize,
+    /// Id of the peer that relayed the transaction.
+    pub peer_id: String,
+    pub entry_time: u64,
+    /// Inputs that could not be resolved when the orphan was parked.
+    pub missing: Vec<OutPoint>,
+}
+
+/// Outcome of offering a relayed transaction to the mempool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxAcceptance {
+    /// Accepted, followed by any orphans it unblocked (in acceptance order).
+    Accepted(Vec<Hash>),
+    /// Parked as an orphan. `getdata` asks for parents nobody requested yet.
+    Orphaned {
+        getdata: Option<Box<NetworkMessage>>,
+    },
+}
+
+#[derive(Debug)]
+pub struct OrphanPool {
+    max_orphans: usize,
+    max_bytes_per_peer: usize,
+    expiry: Duration,
+    orphans: HashMap<Hash, OrphanEntry>,
+    /// Missing outpoint -> orphans waiting on it.
+    by_missing: HashMap<OutPoint, HashSet<Hash>>,
+    /// Peer id -> orphan bytes attributed to that peer.
+    peer_bytes: HashMap<String, usize>,
+}
+
+impl OrphanPool {
+    pub fn new(config: &MempoolConfig) -> Self {
+        Self {
+            max_orphans: config.max_orphan_transactions,
+            max_bytes_per_peer: config.max_orphan_bytes_per_peer,
+            expiry: config.orphan_expiry,
+            orphans: HashMap::new(),
+            by_missing: HashMap::new(),
+            peer_bytes: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.orphans.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Hash) -> Option<&OrphanEntry> {
+        self.orphans.get(txid)
+    }
+
+    /// Orphan bytes currently attributed to `peer_id`.
+    pub fn peer_usage(&self, peer_id: &str) -> usize {
+        self.peer_bytes.get(peer_id).copied().unwrap_or(0)
+    }
+
+    /// Park `tx` until the outpoints in `missing` become available.
+    ///
+    /// When the pool is at capacity the oldest orphan is evicted to make room.
+    pub fn add_orphan(
+        &mut self,
+        tx: Transaction,
+        peer_id: &str,
+        missing: Vec<OutPoint>,
+        now: u64,
+    ) -> Result<Hash, MempoolError> {
+        let txid = tx.txid();
+        if self.orphans.contains_key(&txid) {
+            return Err(MempoolError::AlreadyInPool);
+        }
+
+        let size = transaction_size(&tx)?;
+        if size > MAX_ORPHAN_TX_SIZE {
+            return Err(MempoolError::TooLarge(size));
+        }
+        if self.peer_usage(peer_id) + size > self.max_bytes_per_peer {
+            return Err(MempoolError::PeerOrphanLimit(peer_id.to_string()));
+        }
+        if self.max_orphans == 0 {
+            return Err(MempoolError::PoolFull);
+        }
+
+        while self.orphans.len() >= self.max_orphans {
+            let oldest = self
+                .orphans
+                .values()
+                .min_by_key(|o| o.entry_time)
+                .map(|o| o.txid);
+            match oldest {
+                Some(id) => {
+                    self.remove(&id);
+                }
+                None => break,
+            }
+        }
+
+        for outpoint in &missing {
+            self.by_missing
+                .entry(outpoint.clone())
+                .or_default()
+                .insert(txid);
+        }
+        *self.peer_bytes.entry(peer_id.to_string()).or_default() += size;
+        self.orphans.insert(
+            txid,
+            OrphanEntry {
+                tx,
+                txid,
+                size,
+                peer_id: peer_id.to_string(),
+                entry_time: now,
+                missing,
+            },
+        );
+
+        Ok(txid)
+    }
+
+    /// Remove a single orphan and all of its index entries.
+    pub fn remove(&mut self, txid: &Hash) -> Option<OrphanEntry> {
+        let entry = self.orphans.remove(txid)?;
+
+        for outpoint in &entry.missing {
+            if let Some(waiting) = self.by_missing.get_mut(outpoint) {
+                waiting.remove(txid);
+                if waiting.is_empty() {
+                    self.by_missing.remove(outpoint);
+                }
+            }
+        }
+
+        if let Some(used) = self.peer_bytes.get_mut(&entry.peer_id) {
+            *used = used.saturating_sub(entry.size);
+            if *used == 0 {
+                self.peer_bytes.remove(&entry.peer_id);
+            }
+        }
+
+        Some(entry)
+    }
+
+    /// Remove and return every orphan that spends an output of `parent`.
+    pub fn take_children_of(&mut self, parent: &Transaction) -> Vec<OrphanEntry> {
+        let mut ids = Vec::new();
+        for (outpoint, _) in &parent.outputs {
+            if let Some(waiting) = self.by_missing.get(outpoint) {
+                for id in waiting {
+                    if !ids.contains(id) {
+                        ids.push(*id);
+                    }
+                }
+            }
+        }
+        ids.iter().filter_map(|id| self.remove(id)).collect()
+    }
+
+    /// Drop orphans that have waited longer than the configured expiry.
+    pub fn expire(&mut self, now: u64) -> usize {
+        let expiry = self.expiry.as_secs();
+        let stale: Vec<Hash> = self
+            .orphans
+            .values()
+            .filter(|o| o.entry_time.saturating_add(expiry) <= now)
+            .map(|o| o.txid)
+            .collect();
+
+        stale.iter().filter_map(|id| self.remove(id)).count()
+    }
+
+    /// Drop everything relayed by a disconnected peer.
+    pub fn remove_for_peer(&mut self, peer_id: &str) -> usize {
+        let theirs: Vec<Hash> = self
+            .orphans
+            .values()
+            .filter(|o| o.peer_id == peer_id)
+            .map(|o| o.txid)
+            .collect();
+
+        theirs.iter().filter_map(|id| self.remove(id)).count()
+    }
+
+    /// Drop orphans that a connected block confirmed or double-spent.
+    pub fn remove_for_block(&mut self, block_txs: &[Transaction]) -> usize {
+        let mut spent = HashSet::new();
+        let mut confirmed = HashSet::new();
+        for tx in block_txs {
+            confirmed.insert(tx.txid());
+            spent.extend(tx.inputs.iter());
+        }
+
+        let doomed: Vec<Hash> = self
+            .orphans
+            .values()
+            .filter(|o| {
+                confirmed.contains(&o.txid) || o.tx.inputs.iter().any(|i| spent.contains(i))
+            })
+            .map(|o| o.txid)
+            .collect();
+
+        doomed.iter().filter_map(|id| self.remove(id)).count()
+    }
+
+    /// Build a `GetData` for parents of `missing` that no orphan is already
+    /// waiting on (those were requested when that orphan was parked).
+    pub fn missing_parents_request(&self, missing: &[OutPoint]) -> Option<NetworkMessage> {
+        let mut items: Vec<InvEntry> = Vec::new();
+        for outpoint in missing {
+            if self.by_missing.contains_key(outpoint)
+                || items.iter().any(|i| i.hash == outpoint.tx_hash)
+            {
+                continue;
+            }
+            items.push(InvEntry {
+                kind: INV_TX,
+                hash: outpoint.tx_hash,
+            });
+        }
+
+        if items.is_empty() {
+            None
+        } else {
+            Some(NetworkMessage::GetData(GetDataMessage { items }))
+        }
+    }
+
+    /// Offer a relayed transaction to `mempool`, parking it here if its
+    /// parents are missing and retrying any orphans it unblocks.
+    pub fn process_transaction(
+        &mut self,
+        mempool: &mut Mempool,
+        tx: Transaction,
+        peer_id: &str,
+        utxos: &dyn UTXOStorage,
+    ) -> Result<TxAcceptance, MempoolError> {
+        let now = now_secs();
+        match mempool.accept_transaction_at(tx.clone(), utxos, now) {
+            Ok(txid) => Ok(TxAcceptance::Accepted(
+                self.resolve_children(mempool, txid, utxos, now),
+            )),
+            Err(MempoolError::MissingInputs(missing)) => {
+                let getdata = self.missing_parents_request(&missing).map(Box::new);
+                self.add_orphan(tx, peer_id, missing, now)?;
+                Ok(TxAcceptance::Orphaned { getdata })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Retry orphans of a freshly accepted transaction, cascading through
+    /// grandchildren. Returns `txid` followed by every orphan accepted.
+    pub fn resolve_children(
+        &mut self,
+        mempool: &mut Mempool,
+        txid: Hash,
+        utxos: &dyn UTXOStorage,
+        now: u64,
+    ) -> Vec<Hash> {
+        let mut accepted = vec![txid];
+        let mut queue = VecDeque::from([txid]);
+
+        while let Some(parent_id) = queue.pop_front() {
+            let children = match mempool.get(&parent_id) {
+                Some(parent) => self.take_children_of(&parent.tx),
+                None => continue,
+            };
+
+            for orphan in children {
+                match mempool.accept_transaction_at(orphan.tx.clone(), utxos, now) {
+                    Ok(id) => {
+                        accepted.push(id);
+                        queue.push_back(id);
+                    }
+                    Err(MempoolError::MissingInputs(missing)) => {
+                        // Still waiting on another parent; park it again.
+                        let _ =
+                            self.add_orphan(orphan.tx, &orphan.peer_id, missing, orphan.entry_time);
+                    }
+                    Err(e) => log::debug!("Dropping orphan {}: {}", orphan.txid, e),
+                }
+            }
+        }
+
+        accepted
+    }
+}
+
+/// Orphans relayed by a peer are dropped when it disconnects.
+impl PeerEventListener for RwLock<OrphanPool> {
+    fn peer_disconnected(&self, peer: &ConnectedPeer, _reason: &str) {
+        self.write().unwrap().remove_for_peer(&peer.info.id());
+    }
+}
+
+/// Periodically expire orphans. The lock is never held across `.await`.
+pub async fn run_expiry(orphans: Arc<RwLock<OrphanPool>>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+
+        let removed = orphans.write().unwrap().expire(now_secs());
+        if removed > 0 {
+            log::debug!("Expired {} orphan transaction(s)", removed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::MemoryUTXOStorage;
+    use crate::database::DatabaseManager;
+    use crate::network::protocol::InvMessage;
+    use crate::network::SyncManager;
+    use crate::test_util::{funded, op, tx};
+
+    #[test]
+    fn orphan_resolves_when_parent_arrives() {
+        let utxos = funded(&["coin"], 10_000);
+        let config = MempoolConfig::default();
+        let mut mempool = Mempool::new(config.clone());
+        let mut orphans = OrphanPool::new(&config);
+
+        let parent = tx(vec![op("coin", 0)], vec![(op("parent", 0), 9_000)]);
+        let child = tx(vec![op("parent", 0)], vec![(op("child", 0), 8_000)]);
+        let grandchild = tx(vec![op("child", 0)], vec![(op("grand", 0), 7_000)]);
+
+        let outcome = orphans
+            .process_transaction(&mut mempool, grandchild, "peer-a", &utxos)
+            .unwrap();
+        let TxAcceptance::Orphaned { getdata: Some(msg) } = outcome else {
+            panic!("expected orphan with getdata, got {:?}", outcome);
+        };
+        let NetworkMessage::GetData(req) = *msg else {
+            panic!("expected getdata");
+        };
+        assert_eq!(req.items[0].hash, op("child", 0).tx_hash);
+        assert_eq!(req.items[0].kind, INV_TX);
+        // A node syncing blocks does not mistake the request for a block.
+        let db = DatabaseManager::new(Box::new(MemoryUTXOStorage::new()), Default::default());
+        let sync = SyncManager::new(Arc::new(db));
+        let inv = InvMessage { items: req.items };
+        sync.handle_inv_message(inv, "peer-b").unwrap();
+        assert!(sync.get_blocks_for_download(10).is_empty());
+
+        let outcome = orphans
+            .process_transaction(&mut mempool, child, "peer-a", &utxos)
+            .unwrap();
+        assert!(matches!(outcome, TxAcceptance::Orphaned { .. }));
+        assert_eq!(orphans.len(), 2);
+
+        let outcome = orphans
+            .process_transaction(&mut mempool, parent, "peer-b", &utxos)
+            .unwrap();
+        let TxAcceptance::Accepted(ids) = outcome else {
+            panic!("expected acceptance");
+        };
+        assert_eq!(ids.len(), 3);
+        assert!(orphans.is_empty());
+        assert_eq!(orphans.peer_usage("peer-a"), 0);
+        assert_eq!(mempool.len(), 3);
+    }
+
+    #[test]
+    fn per_peer_budget_is_enforced() {
+        let orphan = tx(vec![op("missing", 0)], vec![(op("o", 0), 1)]);
+        let size = transaction_size(&orphan).unwrap();
+        let config = MempoolConfig {
+            max_orphan_bytes_per_peer: size,
+            ..MempoolConfig::default()
+        };
+        let mut orphans = OrphanPool::new(&config);
+
+        orphans
+            .add_orphan(orphan, "greedy", vec![op("missing", 0)], 0)
+            .unwrap();
+
+        let another = tx(vec![op("missing", 1)], vec![(op("o", 1), 1)]);
+        assert_eq!(
+            orphans.add_orphan(another.clone(), "greedy", vec![op("missing", 1)], 0),
+            Err(MempoolError::PeerOrphanLimit("greedy".to_string()))
+        );
+        assert!(orphans
+            .add_orphan(another, "polite", vec![op("missing", 1)], 0)
+            .is_ok());
+
+        assert_eq!(orphans.remove_for_peer("greedy"), 1);
+        assert_eq!(orphans.len(), 1);
+    }
+
+    #[test]
+    fn capacity_and_expiry() {
+        let config = MempoolConfig {
+            max_orphan_transactions: 2,
+            ..MempoolConfig::default()
+        };
+        let expiry = config.orphan_expiry.as_secs();
+        let mut orphans = OrphanPool::new(&config);
+
+        let mut ids = Vec::new();
+        for i in 0..3u32 {
+            let orphan = tx(vec![op("missing", i)], vec![(op("o", i), 1)]);
+            let id = orphans
+                .add_orphan(orphan, "peer", vec![op("missing", i)], u64::from(i))
+                .unwrap();
+            ids.push(id);
+        }
+
+        // The oldest was evicted to make room.
+        assert_eq!(orphans.len(), 2);
+        assert!(!orphans.contains(&ids[0]));
+
+        assert_eq!(orphans.expire(1 + expiry), 1);
+        assert!(orphans.contains(&ids[2]));
+        assert_eq!(orphans.expire(2 + expiry), 1);
+        assert!(orphans.is_empty());
+    }
+}
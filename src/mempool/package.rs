@@ -0,0 +1,429 @@
+//! Ancestor/descendant package tracking.
+//!
+//! Every pooled entry carries aggregate stats over itself plus its in-pool
+//! ancestors and descendants. Ancestor stats drive block template selection
+//! (a low-fee parent rides along with a child that pays for it), descendant
+//! stats drive eviction, and both are capped by the package limits in
+//! `MempoolConfig`.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use super::{cmp_feerate, feerate, transaction_sigops, Mempool, MempoolEntry, MempoolError};
+use crate::network::protocol::{Hash, Transaction};
+
+/// Aggregate count, size and fee over a set of pooled transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PackageStats {
+    pub count: usize,
+    pub size: usize,
+    pub fee: u64,
+}
+
+impl PackageStats {
+    /// Stats for a single transaction.
+    pub fn single(fee: u64, size: usize) -> Self {
+        Self {
+            count: 1,
+            size,
+            fee,
+        }
+    }
+
+    /// Feerate in base units per 1000 bytes.
+    pub fn feerate(&self) -> u64 {
+        feerate(self.fee, self.size)
+    }
+
+    fn add(&mut self, fee: u64, size: usize) {
+        self.count += 1;
+        self.size += size;
+        self.fee = self.fee.saturating_add(fee);
+    }
+
+    fn subtract(&mut self, fee: u64, size: usize) {
+        self.count = self.count.saturating_sub(1);
+        self.size = self.size.saturating_sub(size);
+        self.fee = self.fee.saturating_sub(fee);
+    }
+}
+
+impl Mempool {
+    /// In-pool transactions whose outputs `txid` spends, transitively.
+    pub fn ancestors(&self, txid: &Hash) -> Vec<Hash> {
+        match self.entries.get(txid) {
+            Some(entry) => self.ancestors_of(&entry.tx),
+            None => Vec::new(),
+        }
+    }
+
+    /// In-pool ancestors of a (possibly not yet pooled) transaction.
+    pub fn ancestors_of(&self, tx: &Transaction) -> Vec<Hash> {
+        let mut out = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<&Transaction> = VecDeque::from([tx]);
+
+        while let Some(current) = queue.pop_front() {
+            for input in &current.inputs {
+                let Some((parent, _)) = self.created.get(input) else {
+                    continue;
+                };
+                if seen.insert(*parent) {
+                    out.push(*parent);
+                    if let Some(entry) = self.entries.get(parent) {
+                        queue.push_back(&entry.tx);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Recompute ancestor and descendant stats for the given entries.
+    pub(crate) fn refresh_package_stats(&mut self, txids: &[Hash]) {
+        for txid in txids {
+            let Some(entry) = self.entries.get(txid) else {
+                continue;
+            };
+            let mut ancestors = PackageStats::single(entry.modified_fee(), entry.size);
+            let mut descendants = ancestors;
+
+            for id in self.ancestors(txid) {
+                if let Some(a) = self.entries.get(&id) {
+                    ancestors.add(a.modified_fee(), a.size);
+                }
+            }
+            for id in self.descendants(txid) {
+                if let Some(d) = self.entries.get(&id) {
+                    descendants.add(d.modified_fee(), d.size);
+                }
+            }
+
+            if let Some(entry) = self.entries.get_mut(txid) {
+                entry.ancestors = ancestors;
+                entry.descendants = descendants;
+            }
+        }
+    }
+
+    /// Refuse `tx` if it would push itself or any ancestor past a limit.
+    pub(crate) fn check_package_limits(
+        &self,
+        tx: &Transaction,
+        size: usize,
+    ) -> Result<(), MempoolError> {
+        let config = &self.config;
+        let ancestors = self.ancestors_of(tx);
+
+        if ancestors.len() + 1 > config.max_ancestor_count {
+            return Err(MempoolError::PackageLimit(format!(
+                "too many ancestors ({} > {})",
+                ancestors.len() + 1,
+                config.max_ancestor_count
+            )));
+        }
+
+        let mut ancestor_size = size;
+        for id in &ancestors {
+            let Some(a) = self.entries.get(id) else {
+                continue;
+            };
+            ancestor_size += a.size;
+
+            if a.descendants.count + 1 > config.max_descendant_count {
+                return Err(MempoolError::PackageLimit(format!(
+                    "too many descendants for {} ({} > {})",
+                    id,
+                    a.descendants.count + 1,
+                    config.max_descendant_count
+                )));
+            }
+            if a.descendants.size + size > config.max_descendant_size {
+                return Err(MempoolError::PackageLimit(format!(
+                    "descendant size of {} too large ({} > {})",
+                    id,
+                    a.descendants.size + size,
+                    config.max_descendant_size
+                )));
+            }
+        }
+
+        if ancestor_size > config.max_ancestor_size {
+            return Err(MempoolError::PackageLimit(format!(
+                "ancestor size too large ({} > {})",
+                ancestor_size, config.max_ancestor_size
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Packages in descending ancestor-feerate order, fitting `max_size` bytes.
+    pub fn ancestor_packages(&self, max_size: usize) -> AncestorPackages<'_> {
+        AncestorPackages::new(self, max_size)
+    }
+}
+
+/// A transaction plus the not-yet-selected ancestors it needs.
+#[derive(Debug, Clone)]
+pub struct AncestorPackage<'a> {
+    /// Package members, parents before children.
+    pub entries: Vec<&'a MempoolEntry>,
+    pub fee: u64,
+    pub size: usize,
+}
+
+impl AncestorPackage<'_> {
+    /// Feerate in base units per 1000 bytes.
+    pub fn feerate(&self) -> u64 {
+        feerate(self.fee, self.size)
+    }
+}
+
+/// Heap key: a candidate and the package stats it was scored with.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    txid: Hash,
+    fee: u64,
+    size: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_feerate(self.fee, self.size, other.fee, other.size)
+            .then_with(|| other.txid.0.cmp(&self.txid.0))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+/// Iterator over pooled transactions grouped into ancestor packages, best
+/// ancestor feerate first.
+///
+/// Once a package is yielded its members count as selected: descendants are
+/// re-scored without them, so a child that pays for its parent is picked
+/// together with it. Packages that do not fit the remaining size (or sigop)
+/// budget are skipped, along with anything that depends on them.
+pub struct AncestorPackages<'a> {
+    pool: &'a Mempool,
+    remaining_size: usize,
+    remaining_sigops: usize,
+    heap: BinaryHeap<Candidate>,
+    /// Ancestor stats with already-selected ancestors taken out.
+    modified: HashMap<Hash, PackageStats>,
+    selected: HashSet<Hash>,
+    failed: HashSet<Hash>,
+}
+
+impl<'a> AncestorPackages<'a> {
+    pub fn new(pool: &'a Mempool, max_size: usize) -> Self {
+        let heap = pool
+            .entries
+            .values()
+            .map(|e| Candidate {
+                txid: e.txid,
+                fee: e.ancestors.fee,
+                size: e.ancestors.size,
+            })
+            .collect();
+
+        Self {
+            pool,
+            remaining_size: max_size,
+            remaining_sigops: usize::MAX,
+            heap,
+            modified: HashMap::new(),
+            selected: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    /// Also cap the summed `transaction_sigops` of yielded packages.
+    pub fn with_max_sigops(mut self, max_sigops: usize) -> Self {
+        self.remaining_sigops = max_sigops;
+        self
+    }
+
+    /// Bytes still available to further packages.
+    pub fn remaining_size(&self) -> usize {
+        self.remaining_size
+    }
+
+    /// Sigops still available to further packages.
+    pub fn remaining_sigops(&self) -> usize {
+        self.remaining_sigops
+    }
+
+    fn current_stats(&self, txid: &Hash) -> Option<PackageStats> {
+        self.modified
+            .get(txid)
+            .copied()
+            .or_else(|| self.pool.entries.get(txid).map(|e| e.ancestors))
+    }
+
+    /// Take selected members out of their unselected descendants' stats.
+    fn rescore_descendants(&mut self, members: &[&MempoolEntry]) {
+        for member in members {
+            for id in self.pool.descendants(&member.txid) {
+                if self.selected.contains(&id) {
+                    continue;
+                }
+                let Some(mut stats) = self.current_stats(&id) else {
+                    continue;
+                };
+                stats.subtract(member.modified_fee(), member.size);
+                self.modified.insert(id, stats);
+                self.heap.push(Candidate {
+                    txid: id,
+                    fee: stats.fee,
+                    size: stats.size,
+                });
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for AncestorPackages<'a> {
+    type Item = AncestorPackage<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(candidate) = self.heap.pop() {
+            if self.selected.contains(&candidate.txid) || self.failed.contains(&candidate.txid) {
+                continue;
+            }
+            let Some(stats) = self.current_stats(&candidate.txid) else {
+                continue;
+            };
+            if stats.fee != candidate.fee || stats.size != candidate.size {
+                // Stale heap entry; a fresher one was pushed on rescore.
+                continue;
+            }
+
+            let pool = self.pool;
+            let mut members: Vec<&'a MempoolEntry> = pool
+                .ancestors(&candidate.txid)
+                .iter()
+                .chain(std::iter::once(&candidate.txid))
+                .filter(|id| !self.selected.contains(*id))
+                .filter_map(|id| pool.entries.get(id))
+                .collect();
+
+            let sigops: usize = members.iter().map(|m| transaction_sigops(&m.tx)).sum();
+            if stats.size > self.remaining_size
+                || sigops > self.remaining_sigops
+                || members.iter().any(|m| self.failed.contains(&m.txid))
+            {
+                self.failed.insert(candidate.txid);
+                continue;
+            }
+
+            // A parent always has fewer ancestors than its child.
+            members.sort_by_key(|m| m.ancestors.count);
+            for member in &members {
+                self.selected.insert(member.txid);
+            }
+            self.remaining_size -= stats.size;
+            self.remaining_sigops -= sigops;
+            self.rescore_descendants(&members);
+
+            return Some(AncestorPackage {
+                entries: members,
+                fee: stats.fee,
+                size: stats.size,
+            });
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::MempoolConfig;
+    use crate::test_util::{funded, op, tx};
+
+    #[test]
+    fn tracks_ancestor_and_descendant_stats() {
+        let utxos = funded(&["coin"], 100_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let a = tx(vec![op("coin", 0)], vec![(op("a", 0), 99_000)]);
+        let b = tx(vec![op("a", 0)], vec![(op("b", 0), 97_000)]);
+        let a_id = pool.accept_transaction(a.clone(), &utxos).unwrap();
+        let b_id = pool.accept_transaction(b, &utxos).unwrap();
+
+        let a_entry = pool.get(&a_id).unwrap();
+        assert_eq!(a_entry.descendants.count, 2);
+        assert_eq!(a_entry.descendants.fee, 3_000);
+        assert_eq!(pool.get(&b_id).unwrap().ancestors.count, 2);
+
+        // Confirming the parent leaves the child standing alone.
+        pool.remove_for_block(&[a]);
+        let b_entry = pool.get(&b_id).unwrap();
+        assert_eq!(b_entry.ancestors, PackageStats::single(2_000, b_entry.size));
+    }
+
+    #[test]
+    fn enforces_ancestor_limit() {
+        let utxos = funded(&["coin"], 100_000);
+        let config = MempoolConfig {
+            max_ancestor_count: 2,
+            ..MempoolConfig::default()
+        };
+        let mut pool = Mempool::new(config);
+
+        let a = tx(vec![op("coin", 0)], vec![(op("a", 0), 99_000)]);
+        let b = tx(vec![op("a", 0)], vec![(op("b", 0), 98_000)]);
+        let c = tx(vec![op("b", 0)], vec![(op("c", 0), 97_000)]);
+        pool.accept_transaction(a, &utxos).unwrap();
+        pool.accept_transaction(b, &utxos).unwrap();
+        assert!(matches!(
+            pool.accept_transaction(c, &utxos),
+            Err(MempoolError::PackageLimit(_))
+        ));
+    }
+
+    #[test]
+    fn child_pays_for_parent() {
+        let utxos = funded(&["p", "x"], 100_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        // Parent barely pays relay; its child pays a lot.
+        let parent = tx(vec![op("p", 0)], vec![(op("parent", 0), 99_500)]);
+        let child = tx(vec![op("parent", 0)], vec![(op("child", 0), 79_500)]);
+        // An unrelated transaction that beats the parent alone, not the package.
+        let other = tx(vec![op("x", 0)], vec![(op("other", 0), 95_000)]);
+
+        let parent_id = pool.accept_transaction(parent, &utxos).unwrap();
+        let child_id = pool.accept_transaction(child, &utxos).unwrap();
+        let other_id = pool.accept_transaction(other, &utxos).unwrap();
+
+        let packages: Vec<_> = pool.ancestor_packages(usize::MAX).collect();
+        let order: Vec<Hash> = packages
+            .iter()
+            .flat_map(|p| p.entries.iter().map(|e| e.txid))
+            .collect();
+        assert_eq!(order, vec![parent_id, child_id, other_id]);
+        assert_eq!(packages[0].fee, 20_500);
+
+        // With room for only one transaction, the package cannot fit.
+        let one = pool.get(&other_id).unwrap().size;
+        let limited: Vec<_> = pool.ancestor_packages(one).collect();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].entries[0].txid, other_id);
+    }
+}
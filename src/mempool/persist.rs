@@ -0,0 +1,225 @@
+//! Mempool persistence across restarts.
+//!
+//! On shutdown the pool is written to `mempool.dat` in the data directory as a
+//! checksummed blob (see `DatabaseManager::serialize_with_checksum`). On
+//! startup every entry is re-validated against the current UTXO set; entries
+//! that now fail, or that have outlived the configured expiry, are dropped.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::{Mempool, MempoolError};
+use crate::database::utxo_set::UTXOStorage;
+use crate::database::DatabaseManager;
+use crate::network::protocol::{Hash, Transaction};
+
+/// File name of the mempool dump inside the data directory.
+pub const MEMPOOL_FILE: &str = "mempool.dat";
+
+/// Format version written to, and required of, the dump.
+pub const MEMPOOL_FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedEntry {
+    tx: Transaction,
+    entry_time: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MempoolSnapshot {
+    version: u32,
+    /// Parents before children, so reloading never sees an orphan.
+    entries: Vec<PersistedEntry>,
+    fee_deltas: Vec<(Hash, i64)>,
+}
+
+/// Outcome of `Mempool::load`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    pub loaded: usize,
+    pub expired: usize,
+    pub failed: usize,
+}
+
+impl Mempool {
+    /// Write the pool to `path`, returning the number of entries saved.
+    ///
+    /// The file is written next to `path` and renamed into place so a crash
+    /// mid-write never leaves a truncated dump behind.
+    pub fn dump(&self, path: &Path) -> Result<usize, MempoolError> {
+        let mut entries: Vec<_> = self.entries.values().collect();
+        entries.sort_by_key(|e| (e.ancestors.count, e.entry_time));
+
+        let snapshot = MempoolSnapshot {
+            version: MEMPOOL_FILE_VERSION,
+            entries: entries
+                .iter()
+                .map(|e| PersistedEntry {
+                    tx: e.tx.clone(),
+                    entry_time: e.entry_time,
+                })
+                .collect(),
+            fee_deltas: self.fee_deltas.iter().map(|(k, v)| (*k, *v)).collect(),
+        };
+
+        let bytes = DatabaseManager::serialize_with_checksum(&snapshot)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| MempoolError::IoError(e.to_string()))?;
+        }
+        let tmp = path.with_extension("dat.new");
+        fs::write(&tmp, &bytes).map_err(|e| MempoolError::IoError(e.to_string()))?;
+        fs::rename(&tmp, path).map_err(|e| MempoolError::IoError(e.to_string()))?;
+
+        Ok(snapshot.entries.len())
+    }
+
+    /// Reload a dump written by `dump`, re-validating each entry.
+    ///
+    /// A missing file is not an error. Entries keep their original entry
+    /// time, so expiry continues from where it left off.
+    pub fn load(
+        &mut self,
+        path: &Path,
+        utxos: &dyn UTXOStorage,
+        now: u64,
+    ) -> Result<LoadStats, MempoolError> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(LoadStats::default()),
+            Err(e) => return Err(MempoolError::IoError(e.to_string())),
+        };
+        let payload = DatabaseManager::verify_and_strip_checksum(&data)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?;
+        let snapshot: MempoolSnapshot = bincode::deserialize(&payload)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?;
+        if snapshot.version != MEMPOOL_FILE_VERSION {
+            return Err(MempoolError::SerializationError(format!(
+                "unsupported mempool file version {}",
+                snapshot.version
+            )));
+        }
+
+        for (txid, delta) in snapshot.fee_deltas {
+            self.prioritise_transaction(txid, delta);
+        }
+
+        let expiry = self.config.expiry.as_secs();
+        let mut stats = LoadStats::default();
+        for PersistedEntry { tx, entry_time } in snapshot.entries {
+            if entry_time.saturating_add(expiry) <= now {
+                stats.expired += 1;
+                continue;
+            }
+            match self.accept_transaction_at(tx, utxos, entry_time) {
+                Ok(_) => stats.loaded += 1,
+                Err(e) => {
+                    log::debug!("Dropping persisted mempool transaction: {}", e);
+                    stats.failed += 1;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::MempoolConfig;
+    use crate::test_util::{funded, op, tx};
+
+    #[test]
+    fn dump_and_load_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MEMPOOL_FILE);
+        let utxos = funded(&["a"], 100_000);
+
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let parent = tx(vec![op("a", 0)], vec![(op("p", 0), 90_000)]);
+        let child = tx(vec![op("p", 0)], vec![(op("c", 0), 80_000)]);
+        let parent_id = pool.accept_transaction_at(parent, &utxos, 100).unwrap();
+        let child_id = pool.accept_transaction_at(child, &utxos, 200).unwrap();
+        pool.prioritise_transaction(child_id, 5_000);
+        assert_eq!(pool.dump(&path).unwrap(), 2);
+
+        let mut reloaded = Mempool::new(MempoolConfig::default());
+        let stats = reloaded.load(&path, &utxos, 300).unwrap();
+        assert_eq!(
+            stats,
+            LoadStats {
+                loaded: 2,
+                expired: 0,
+                failed: 0
+            }
+        );
+        assert_eq!(reloaded.get(&parent_id).unwrap().entry_time, 100);
+        let child = reloaded.get(&child_id).unwrap();
+        assert_eq!(child.fee_delta, 5_000);
+        assert_eq!(child.modified_fee(), 15_000);
+    }
+
+    #[test]
+    fn load_drops_expired_and_invalid_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MEMPOOL_FILE);
+        let utxos = funded(&["a", "b"], 100_000);
+
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let expiry = pool.config().expiry.as_secs();
+        pool.accept_transaction_at(tx(vec![op("a", 0)], vec![(op("x", 0), 90_000)]), &utxos, 0)
+            .unwrap();
+        pool.accept_transaction_at(
+            tx(vec![op("b", 0)], vec![(op("y", 0), 90_000)]),
+            &utxos,
+            expiry,
+        )
+        .unwrap();
+        pool.dump(&path).unwrap();
+
+        // The tip moved on and "b" was spent by a block.
+        let tip = funded(&["a"], 100_000);
+        let mut reloaded = Mempool::new(MempoolConfig::default());
+        let stats = reloaded.load(&path, &tip, expiry + 1).unwrap();
+        assert_eq!(
+            stats,
+            LoadStats {
+                loaded: 0,
+                expired: 1,
+                failed: 1
+            }
+        );
+        assert!(reloaded.is_empty());
+    }
+
+    #[test]
+    fn corrupted_dump_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MEMPOOL_FILE);
+        let utxos = funded(&["a"], 100_000);
+
+        let mut pool = Mempool::new(MempoolConfig::default());
+        pool.accept_transaction(tx(vec![op("a", 0)], vec![(op("x", 0), 90_000)]), &utxos)
+            .unwrap();
+        pool.dump(&path).unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        let mut reloaded = Mempool::new(MempoolConfig::default());
+        assert!(matches!(
+            reloaded.load(&path, &utxos, 0),
+            Err(MempoolError::SerializationError(_))
+        ));
+
+        let missing = dir.path().join("absent.dat");
+        assert_eq!(
+            reloaded.load(&missing, &utxos, 0).unwrap(),
+            LoadStats::default()
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! Opt-in replace-by-fee.
+//!
+//! A transaction that conflicts with pooled transactions replaces them only if
+//! every direct conflict set `Transaction::replaceable` and:
+//! - it does not spend outputs of anything it would evict;
+//! - it evicts at most `MAX_REPLACEMENT_EVICTIONS` transactions (conflicts
+//!   plus their descendants);
+//! - its feerate beats each direct conflict's feerate;
+//! - its absolute fee beats the summed fee of everything evicted;
+//! - the extra fee pays `min_relay_fee` for its own bytes.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use super::{cmp_feerate, feerate, Mempool, MempoolEntry, MempoolError};
+use crate::database::utxo_set::UTXOStorage;
+use crate::network::protocol::{Hash, Transaction};
+
+/// Upper bound on transactions a single replacement may evict.
+pub const MAX_REPLACEMENT_EVICTIONS: usize = 100;
+
+/// Why a replacement was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RbfError {
+    TooManyReplacements {
+        count: usize,
+        max: usize,
+    },
+    /// The replacement spends an output of a transaction it would evict.
+    SpendsConflictingTransaction(Hash),
+    InsufficientFeerate {
+        conflict: Hash,
+        feerate: u64,
+        conflict_feerate: u64,
+    },
+    InsufficientFee {
+        fee: u64,
+        replaced_fee: u64,
+    },
+    InsufficientRelayFee {
+        additional: u64,
+        required: u64,
+    },
+}
+
+impl RbfError {
+    /// Short, stable reject code for RPC callers.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RbfError::TooManyReplacements { .. } => "too-many-replacements",
+            RbfError::SpendsConflictingTransaction(_) => "replacement-spends-conflicting-tx",
+            RbfError::InsufficientFeerate { .. } => "insufficient-feerate",
+            RbfError::InsufficientFee { .. } => "insufficient-fee",
+            RbfError::InsufficientRelayFee { .. } => "insufficient-relay-fee",
+        }
+    }
+}
+
+impl fmt::Display for RbfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RbfError::TooManyReplacements { count, max } => write!(
+                f,
+                "would evict {} transactions, at most {} allowed",
+                count, max
+            ),
+            RbfError::SpendsConflictingTransaction(txid) => {
+                write!(f, "spends output of conflicting transaction {}", txid)
+            }
+            RbfError::InsufficientFeerate {
+                conflict,
+                feerate,
+                conflict_feerate,
+            } => write!(
+                f,
+                "feerate {} does not exceed feerate {} of {}",
+                feerate, conflict_feerate, conflict
+            ),
+            RbfError::InsufficientFee { fee, replaced_fee } => write!(
+                f,
+                "fee {} does not exceed replaced fee {}",
+                fee, replaced_fee
+            ),
+            RbfError::InsufficientRelayFee {
+                additional,
+                required,
+            } => write!(
+                f,
+                "additional fee {} does not pay relay fee {}",
+                additional, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RbfError {}
+
+impl From<RbfError> for MempoolError {
+    fn from(error: RbfError) -> Self {
+        MempoolError::Replacement(error)
+    }
+}
+
+/// Result of a successful replacement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    pub txid: Hash,
+    /// Conflicts and their descendants, removed from the pool.
+    pub replaced: Vec<MempoolEntry>,
+}
+
+impl Mempool {
+    /// Accept `tx` in place of the pooled transactions in `conflicts`.
+    ///
+    /// `conflicts` are the direct conflicts (see `Mempool::direct_conflicts`).
+    /// A conflict that did not opt in is reported as `MempoolError::Conflict`.
+    /// If the pool is full and trimming evicts the replacement itself, the
+    /// conflicts are put back and `MempoolError::PoolFull` is returned.
+    pub fn accept_replacement(
+        &mut self,
+        tx: Transaction,
+        conflicts: Vec<Hash>,
+        utxos: &dyn UTXOStorage,
+        now: u64,
+    ) -> Result<Replacement, MempoolError> {
+        for id in &conflicts {
+            match self.entries.get(id) {
+                Some(entry) if entry.tx.replaceable => {}
+                _ => return Err(MempoolError::Conflict(*id)),
+            }
+        }
+
+        let mut evicted: Vec<Hash> = Vec::new();
+        for id in &conflicts {
+            for doomed in std::iter::once(*id).chain(self.descendants(id)) {
+                if !evicted.contains(&doomed) {
+                    evicted.push(doomed);
+                }
+            }
+        }
+        if evicted.len() > MAX_REPLACEMENT_EVICTIONS {
+            return Err(RbfError::TooManyReplacements {
+                count: evicted.len(),
+                max: MAX_REPLACEMENT_EVICTIONS,
+            }
+            .into());
+        }
+
+        let evicted_set: HashSet<Hash> = evicted.iter().copied().collect();
+        for input in &tx.inputs {
+            if let Some((creator, _)) = self.created.get(input) {
+                if evicted_set.contains(creator) {
+                    return Err(RbfError::SpendsConflictingTransaction(*creator).into());
+                }
+            }
+        }
+
+        let entry = self.check_transaction_excluding(tx, utxos, now, &evicted_set)?;
+
+        for id in &conflicts {
+            let conflict = &self.entries[id];
+            if cmp_feerate(entry.fee, entry.size, conflict.fee, conflict.size).is_le() {
+                return Err(RbfError::InsufficientFeerate {
+                    conflict: *id,
+                    feerate: entry.feerate(),
+                    conflict_feerate: conflict.feerate(),
+                }
+                .into());
+            }
+        }
+
+        let replaced_fee = evicted
+            .iter()
+            .filter_map(|id| self.entries.get(id))
+            .fold(0u64, |acc, e| acc.saturating_add(e.fee));
+        if entry.fee <= replaced_fee {
+            return Err(RbfError::InsufficientFee {
+                fee: entry.fee,
+                replaced_fee,
+            }
+            .into());
+        }
+
+        let additional = entry.fee - replaced_fee;
+        let required = self.config.min_fee_for(entry.size);
+        if additional < required {
+            return Err(RbfError::InsufficientRelayFee {
+                additional,
+                required,
+            }
+            .into());
+        }
+
+        let replaced: Vec<MempoolEntry> = evicted
+            .iter()
+            .filter_map(|id| self.remove_entry(id))
+            .collect();
+        log::debug!(
+            "Replacing {} transaction(s) with {} (feerate {})",
+            replaced.len(),
+            entry.txid,
+            feerate(entry.fee, entry.size)
+        );
+
+        match self.insert_and_trim(entry) {
+            Ok(txid) => {
+                self.forget_unconfirmed(&replaced);
+                Ok(Replacement { txid, replaced })
+            }
+            Err(e) => {
+                self.restore_replaced(replaced, utxos);
+                Err(e)
+            }
+        }
+    }
+
+    /// Put back the entries a failed replacement removed. Trimming may have
+    /// evicted pooled parents of some of them; those are dropped too.
+    fn restore_replaced(&mut self, mut pending: Vec<MempoolEntry>, utxos: &dyn UTXOStorage) {
+        loop {
+            let before = pending.len();
+            let mut waiting = Vec::new();
+            for entry in pending {
+                // Parents among `pending` may come later; retry until stuck.
+                if self.input_value(&entry.tx, utxos).is_ok() {
+                    self.insert_entry(entry);
+                } else {
+                    waiting.push(entry);
+                }
+            }
+            pending = waiting;
+            if pending.is_empty() || pending.len() == before {
+                break;
+            }
+        }
+        for entry in &pending {
+            log::debug!("Dropping {}: its parent was evicted", entry.txid);
+        }
+        self.forget_unconfirmed(&pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::{OutPoint, TxOutput};
+    use crate::mempool::{transaction_size, MempoolConfig};
+    use crate::test_util::{funded, op, tx};
+
+    fn rbf_tx(inputs: Vec<OutPoint>, outputs: Vec<(OutPoint, u64)>) -> Transaction {
+        Transaction {
+            replaceable: true,
+            ..tx(inputs, outputs)
+        }
+    }
+
+    #[test]
+    fn higher_fee_replacement_evicts_conflict_and_descendants() {
+        let utxos = funded(&["coin"], 100_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let original = rbf_tx(vec![op("coin", 0)], vec![(op("orig", 0), 99_000)]);
+        let child = rbf_tx(vec![op("orig", 0)], vec![(op("child", 0), 98_000)]);
+        let original_id = pool.accept_transaction(original, &utxos).unwrap();
+        let child_id = pool.accept_transaction(child, &utxos).unwrap();
+
+        let bump = rbf_tx(vec![op("coin", 0)], vec![(op("bump", 0), 95_000)]);
+        let bump_id = pool.accept_transaction(bump, &utxos).unwrap();
+
+        assert!(pool.contains(&bump_id));
+        assert!(!pool.contains(&original_id));
+        assert!(!pool.contains(&child_id));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn non_signalling_conflict_is_not_replaced() {
+        let utxos = funded(&["coin"], 100_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let mut original = rbf_tx(vec![op("coin", 0)], vec![(op("orig", 0), 99_000)]);
+        original.replaceable = false;
+        let original_id = pool.accept_transaction(original, &utxos).unwrap();
+
+        let bump = rbf_tx(vec![op("coin", 0)], vec![(op("bump", 0), 50_000)]);
+        assert_eq!(
+            pool.accept_transaction(bump, &utxos),
+            Err(MempoolError::Conflict(original_id))
+        );
+    }
+
+    #[test]
+    fn replacement_must_pay_for_what_it_evicts() {
+        let utxos = funded(&["coin"], 100_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let original = rbf_tx(vec![op("coin", 0)], vec![(op("orig", 0), 99_000)]);
+        let child = rbf_tx(vec![op("orig", 0)], vec![(op("child", 0), 90_000)]);
+        pool.accept_transaction(original, &utxos).unwrap();
+        pool.accept_transaction(child, &utxos).unwrap();
+
+        // Beats the parent's feerate, but not parent + child fees (10_000).
+        let bump = rbf_tx(vec![op("coin", 0)], vec![(op("bump", 0), 95_000)]);
+        let err = pool.accept_transaction(bump, &utxos).unwrap_err();
+        let MempoolError::Replacement(rbf) = err else {
+            panic!("expected replacement error, got {:?}", err);
+        };
+        assert_eq!(rbf.code(), "insufficient-fee");
+
+        // Pays 10_001: more than replaced, but not enough extra for relay.
+        let stingy = rbf_tx(vec![op("coin", 0)], vec![(op("stingy", 0), 89_999)]);
+        let err = pool.accept_transaction(stingy, &utxos).unwrap_err();
+        assert!(matches!(
+            err,
+            MempoolError::Replacement(RbfError::InsufficientRelayFee { additional: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn replacement_cannot_spend_what_it_evicts() {
+        let utxos = funded(&["coin"], 100_000);
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        let original = rbf_tx(vec![op("coin", 0)], vec![(op("orig", 0), 99_000)]);
+        let original_id = pool.accept_transaction(original, &utxos).unwrap();
+
+        let bad = rbf_tx(
+            vec![op("coin", 0), op("orig", 0)],
+            vec![(op("bad", 0), 1_000)],
+        );
+        assert_eq!(
+            pool.accept_transaction(bad, &utxos),
+            Err(MempoolError::Replacement(
+                RbfError::SpendsConflictingTransaction(original_id)
+            ))
+        );
+    }
+
+    #[test]
+    fn conflicts_survive_a_replacement_that_is_trimmed() {
+        let mut utxos = funded(&["coin"], 100_000);
+        let out = TxOutput {
+            value: 100_000,
+            script_pubkey: vec![],
+        };
+        utxos.add_output(op("other", 0), out, 1, false).unwrap();
+
+        let rich = rbf_tx(vec![op("other", 0)], vec![(op("rich", 0), 50_000)]);
+        let original = rbf_tx(vec![op("coin", 0)], vec![(op("orig", 0), 99_000)]);
+        // Bigger than the original, paying a better feerate than it but a
+        // worse one than `rich`, so it is the first to go once over the limit.
+        let outputs = (0..8).map(|i| (op("bump", i), 90_000 / 8)).collect();
+        let bump = rbf_tx(vec![op("coin", 0)], outputs);
+
+        let limit = transaction_size(&rich).unwrap() + transaction_size(&original).unwrap();
+        assert!(transaction_size(&bump).unwrap() > transaction_size(&original).unwrap());
+        let mut pool = Mempool::new(MempoolConfig {
+            max_size_bytes: limit,
+            ..MempoolConfig::default()
+        });
+        let rich_id = pool.accept_transaction(rich, &utxos).unwrap();
+        let original_id = pool.accept_transaction(original, &utxos).unwrap();
+
+        assert_eq!(
+            pool.accept_transaction(bump, &utxos),
+            Err(MempoolError::PoolFull)
+        );
+        assert!(pool.contains(&rich_id));
+        assert!(pool.contains(&original_id));
+        assert_eq!(pool.spender_of(&op("coin", 0)), Some(original_id));
+        assert_eq!(pool.total_size(), limit);
+    }
+}
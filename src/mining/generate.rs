@@ -0,0 +1,236 @@
+//! Instant block generation for regtest.
+//!
+//! `generate_to_address` builds a template paying the given address, grinds
+//! it on the calling thread and hands it to the chain engine before moving
+//! on to the next block, so callers see every block connected by the time
+//! it returns. Regtest's `0x207f_ffff` target is met within a couple of
+//! hashes, which is what makes this practical for tests; other networks are
+//! refused.
+
+use std::fmt;
+use std::sync::RwLock;
+
+use super::pow::check_proof_of_work;
+use super::submit::{BlockRejection, ChainEngine};
+use super::template::{BlockTemplate, BlockTemplateBuilder, TemplateError};
+use crate::config::{Config, NetworkType};
+use crate::mempool::Mempool;
+use crate::network::protocol::Hash;
+use crate::time::now_secs;
+
+/// Header hashes tried per block before giving up, as in Bitcoin Core.
+pub const DEFAULT_MAX_TRIES: u64 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenerateError {
+    /// Instant mining is only offered on regtest.
+    NotRegtest(NetworkType),
+    Template(TemplateError),
+    /// The chain engine refused a block we produced.
+    Rejected(BlockRejection),
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::NotRegtest(network) => {
+                write!(
+                    f,
+                    "Block generation is only available on regtest, not {:?}",
+                    network
+                )
+            }
+            GenerateError::Template(e) => write!(f, "Template error: {}", e),
+            GenerateError::Rejected(rejection) => {
+                write!(f, "Generated block rejected: {}", rejection.reason())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+impl From<TemplateError> for GenerateError {
+    fn from(error: TemplateError) -> Self {
+        GenerateError::Template(error)
+    }
+}
+
+/// Mine `blocks` blocks paying `address` on top of the chain's tip and
+/// return their hashes in order.
+///
+/// Each block gets at most `max_tries` header hashes; if one runs out, the
+/// hashes mined so far are returned, as `generatetoaddress` does.
+pub fn generate_to_address(
+    config: &Config,
+    chain: &dyn ChainEngine,
+    mempool: &RwLock<Mempool>,
+    blocks: u32,
+    address: &str,
+    max_tries: u64,
+) -> Result<Vec<Hash>, GenerateError> {
+    if config.network != NetworkType::Regtest {
+        return Err(GenerateError::NotRegtest(config.network.clone()));
+    }
+
+    let mut mining = config.mining.clone();
+    mining.mining_address = address.to_string();
+    mining.mine_empty_blocks = true;
+    let builder = BlockTemplateBuilder::new(&mining);
+
+    let mut hashes = Vec::with_capacity(blocks as usize);
+    for _ in 0..blocks {
+        let tip = chain.tip();
+        let template = {
+            let pool = mempool.read().unwrap();
+            builder.build(&tip, &pool, now_secs() as u32)?
+        };
+        let Some(solved) = solve(&template, max_tries) else {
+            log::warn!(
+                "Gave up on block {} after {} tries",
+                template.height,
+                max_tries
+            );
+            break;
+        };
+
+        let block = solved.submission();
+        chain
+            .process_block(&block)
+            .map_err(GenerateError::Rejected)?;
+        log::debug!(
+            "Generated block {} at height {}",
+            block.hash(),
+            solved.height
+        );
+        hashes.push(block.hash());
+    }
+    Ok(hashes)
+}
+
+/// Grind header nonces, rolling the extra nonce when a nonce range runs
+/// out, for at most `max_tries` hashes.
+fn solve(template: &BlockTemplate, max_tries: u64) -> Option<BlockTemplate> {
+    let mut tries = 0u64;
+    let mut extra_nonce = 0u64;
+    loop {
+        let mut candidate = template.with_extra_nonce(extra_nonce);
+        for nonce in 0..=u32::MAX {
+            if tries == max_tries {
+                return None;
+            }
+            tries += 1;
+            candidate.header.nonce = nonce;
+            if check_proof_of_work(&candidate.header) {
+                return Some(candidate);
+            }
+        }
+        extra_nonce += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::{create_outpoint, hash_transaction, TxOutput};
+    use crate::mining::submit::MEDIAN_TIME_SPAN;
+    use crate::mining::MemoryChain;
+    use crate::network::protocol::Transaction;
+    use std::sync::Arc;
+
+    #[test]
+    fn mines_and_connects_blocks_on_regtest() {
+        let config = Config::new(NetworkType::Regtest, None);
+        let chain = MemoryChain::from_config(&config);
+        let mempool = RwLock::new(Mempool::default());
+
+        let hashes =
+            generate_to_address(&config, &chain, &mempool, 5, "alice", DEFAULT_MAX_TRIES).unwrap();
+        assert_eq!(hashes.len(), 5);
+        assert_eq!(chain.tip().height, 5);
+        assert_eq!(chain.tip().hash, hashes[4]);
+
+        let blocks = chain.blocks();
+        for (block, hash) in blocks.iter().zip(&hashes) {
+            assert_eq!(&block.hash(), hash);
+            assert_eq!(block.transactions.len(), 1);
+        }
+        // Times never run backwards, even when mined within one second.
+        assert!(blocks
+            .windows(2)
+            .all(|w| w[1].header.time >= w[0].header.time));
+    }
+
+    #[test]
+    fn confirms_pooled_transactions_once() {
+        let config = Config::new(NetworkType::Regtest, None);
+        let mempool = Arc::new(RwLock::new(Mempool::default()));
+        let chain = MemoryChain::from_config(&config).with_mempool(Arc::clone(&mempool));
+
+        let coin = create_outpoint(Hash(hash_transaction(b"coin")), 0);
+        let utxos = chain.utxos();
+        let output = TxOutput {
+            value: 100_000,
+            script_pubkey: vec![],
+        };
+        utxos
+            .write()
+            .unwrap()
+            .add(coin.clone(), output, 0, false)
+            .unwrap();
+        let tx = Transaction {
+            inputs: vec![coin],
+            outputs: vec![(create_outpoint(Hash(hash_transaction(b"paid")), 0), 90_000)],
+            replaceable: false,
+        };
+        let txid = mempool
+            .write()
+            .unwrap()
+            .accept_transaction(tx, utxos.read().unwrap().storage())
+            .unwrap();
+
+        let hashes = generate_to_address(
+            &config,
+            &chain,
+            &mempool,
+            MEDIAN_TIME_SPAN as u32 + 2,
+            "alice",
+            DEFAULT_MAX_TRIES,
+        )
+        .unwrap();
+        let blocks = chain.blocks();
+        assert_eq!(blocks.len(), hashes.len());
+        assert_eq!(blocks[0].transactions[1].txid(), txid);
+        assert!(blocks[1..].iter().all(|b| b.transactions.len() == 1));
+        let pool = mempool.read().unwrap();
+        assert!(pool.is_empty());
+        assert_eq!(pool.tip_height(), chain.tip().height);
+
+        // The tip's median time is the middle of the last eleven blocks.
+        let mut times: Vec<u32> = blocks[blocks.len() - MEDIAN_TIME_SPAN..]
+            .iter()
+            .map(|b| b.header.time)
+            .collect();
+        times.sort_unstable();
+        assert_eq!(chain.tip().median_time_past, times[MEDIAN_TIME_SPAN / 2]);
+        assert!(chain.tip().median_time_past < blocks.last().unwrap().header.time);
+    }
+
+    #[test]
+    fn refuses_other_networks_and_respects_max_tries() {
+        let mempool = RwLock::new(Mempool::default());
+
+        let mainnet = Config::new(NetworkType::Mainnet, None);
+        let chain = MemoryChain::from_config(&mainnet);
+        assert_eq!(
+            generate_to_address(&mainnet, &chain, &mempool, 1, "alice", DEFAULT_MAX_TRIES),
+            Err(GenerateError::NotRegtest(NetworkType::Mainnet))
+        );
+
+        let regtest = Config::new(NetworkType::Regtest, None);
+        let hard = MemoryChain::with_bits(0x0300_0001);
+        let hashes = generate_to_address(&regtest, &hard, &mempool, 3, "alice", 100).unwrap();
+        assert!(hashes.is_empty());
+        assert_eq!(hard.tip().height, 0);
+    }
+}
@@ -0,0 +1,382 @@
+//! In-process CPU miner.
+//!
+//! `Miner` runs `MiningConfig::threads` worker threads (all cores when 0)
+//! over one shared template. Worker `i` of `n` takes the coinbase extra
+//! nonces `i, i + n, i + 2n, ...` and sweeps the full header nonce range for
+//! each, so no two workers ever hash the same header. Publishing a new
+//! template (new tip, or a refresh with better transactions) makes every
+//! worker drop its current work within `CHECK_INTERVAL` hashes.
+//!
+//! This replaces `consensus::PowMiner`, now deprecated; `Miner::hashrate`
+//! is the node's only hashrate measure.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use super::pow::{compact_to_target, hash_meets};
+use super::submit::ChainEngine;
+use super::template::BlockTemplate;
+use crate::config::MiningConfig;
+
+/// Hashes between checks for a new template or shutdown.
+const CHECK_INTERVAL: u32 = 4_096;
+
+/// Receives solved blocks.
+///
+/// Every `ChainEngine` is a sink: the solved block goes to `process_block`,
+/// and a rejection (say, the tip moved on meanwhile) is logged.
+pub trait BlockSink: Send + Sync {
+    /// Called once per solved template, from a worker thread.
+    fn submit_block(&self, template: BlockTemplate);
+}
+
+impl<T: ChainEngine + ?Sized> BlockSink for T {
+    fn submit_block(&self, template: BlockTemplate) {
+        let block = template.submission();
+        if let Err(rejection) = self.process_block(&block) {
+            log::warn!(
+                "Mined block {} at height {} rejected: {}",
+                block.hash(),
+                template.height,
+                rejection.reason()
+            );
+        }
+    }
+}
+
+struct Job {
+    generation: u64,
+    template: BlockTemplate,
+}
+
+struct Shared {
+    job: Mutex<Option<Arc<Job>>>,
+    changed: Condvar,
+    /// Generation of the newest published job.
+    generation: AtomicU64,
+    /// Newest generation a worker has solved.
+    solved: AtomicU64,
+    stop: AtomicBool,
+    hashes: Vec<AtomicU64>,
+}
+
+/// Multi-threaded miner over a shared block template.
+pub struct Miner {
+    shared: Arc<Shared>,
+    sink: Arc<dyn BlockSink>,
+    workers: Vec<JoinHandle<()>>,
+    started: Instant,
+}
+
+impl Miner {
+    pub fn new(config: &MiningConfig, sink: Arc<dyn BlockSink>) -> Self {
+        let threads = match config.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+
+        Self {
+            shared: Arc::new(Shared {
+                job: Mutex::new(None),
+                changed: Condvar::new(),
+                generation: AtomicU64::new(0),
+                solved: AtomicU64::new(0),
+                stop: AtomicBool::new(false),
+                hashes: (0..threads).map(|_| AtomicU64::new(0)).collect(),
+            }),
+            sink,
+            workers: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.shared.hashes.len()
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.workers.is_empty()
+    }
+
+    /// Spawn the workers. They idle until a template is published.
+    pub fn start(&mut self) {
+        if self.is_running() {
+            return;
+        }
+        self.shared.stop.store(false, Ordering::SeqCst);
+        for hashes in &self.shared.hashes {
+            hashes.store(0, Ordering::Relaxed);
+        }
+        self.started = Instant::now();
+
+        let stride = self.threads();
+        for index in 0..stride {
+            let shared = Arc::clone(&self.shared);
+            let sink = Arc::clone(&self.sink);
+            let handle = thread::Builder::new()
+                .name(format!("miner-{}", index))
+                .spawn(move || run_worker(&shared, sink.as_ref(), index, stride))
+                .expect("spawn miner thread");
+            self.workers.push(handle);
+        }
+        log::info!("Started {} mining thread(s)", stride);
+    }
+
+    /// Replace the work all threads are on, e.g. after a new tip or when the
+    /// mempool has better transactions to offer.
+    pub fn update_template(&self, template: BlockTemplate) {
+        let mut job = self.shared.job.lock().unwrap();
+        let generation = self.shared.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        log::debug!(
+            "New mining template for height {} (generation {})",
+            template.height,
+            generation
+        );
+        *job = Some(Arc::new(Job {
+            generation,
+            template,
+        }));
+        self.shared.changed.notify_all();
+    }
+
+    /// Stop and join all workers.
+    pub fn stop(&mut self) {
+        if !self.is_running() {
+            return;
+        }
+        {
+            let _job = self.shared.job.lock().unwrap();
+            self.shared.stop.store(true, Ordering::SeqCst);
+            self.shared.changed.notify_all();
+        }
+        for handle in self.workers.drain(..) {
+            if handle.join().is_err() {
+                log::error!("Mining thread panicked");
+            }
+        }
+        log::info!("Mining stopped");
+    }
+
+    /// Headers hashed across all threads since `start`.
+    pub fn total_hashes(&self) -> u64 {
+        self.shared
+            .hashes
+            .iter()
+            .map(|h| h.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Aggregate hashes per second since `start`.
+    pub fn hashrate(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.total_hashes() as f64 / elapsed
+    }
+}
+
+impl Drop for Miner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run_worker(shared: &Shared, sink: &dyn BlockSink, index: usize, stride: usize) {
+    // Generation this worker has nothing more to do for.
+    let mut done = 0;
+    loop {
+        let job = {
+            let mut guard = shared.job.lock().unwrap();
+            loop {
+                if shared.stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Some(job) = guard.as_ref() {
+                    if job.generation != done
+                        && job.generation > shared.solved.load(Ordering::SeqCst)
+                    {
+                        break Arc::clone(job);
+                    }
+                }
+                guard = shared.changed.wait(guard).unwrap();
+            }
+        };
+        mine_job(shared, sink, &job, index, stride);
+        done = job.generation;
+    }
+}
+
+fn mine_job(shared: &Shared, sink: &dyn BlockSink, job: &Job, index: usize, stride: usize) {
+    let Some(target) = compact_to_target(job.template.header.bits) else {
+        log::warn!(
+            "Cannot mine template with invalid bits {:#010x}",
+            job.template.header.bits
+        );
+        return;
+    };
+    let counter = &shared.hashes[index];
+
+    let mut extra_nonce = index as u64;
+    loop {
+        let mut template = job.template.with_extra_nonce(extra_nonce);
+        let mut nonce = 0u32;
+        loop {
+            template.header.nonce = nonce;
+            let hash = template.header.hash();
+
+            if hash_meets(&hash, &target) {
+                counter.fetch_add(u64::from(nonce % CHECK_INTERVAL) + 1, Ordering::Relaxed);
+                if shared.solved.fetch_max(job.generation, Ordering::SeqCst) < job.generation {
+                    log::info!("Mined block at height {}: {}", template.height, hash);
+                    sink.submit_block(template);
+                }
+                return;
+            }
+
+            if nonce % CHECK_INTERVAL == CHECK_INTERVAL - 1 {
+                counter.fetch_add(u64::from(CHECK_INTERVAL), Ordering::Relaxed);
+                if shared.stop.load(Ordering::Relaxed)
+                    || shared.generation.load(Ordering::SeqCst) != job.generation
+                    || shared.solved.load(Ordering::SeqCst) >= job.generation
+                {
+                    return;
+                }
+            }
+
+            if nonce == u32::MAX {
+                break;
+            }
+            nonce += 1;
+        }
+        extra_nonce = extra_nonce.wrapping_add(stride as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::Mempool;
+    use crate::mining::pow::check_proof_of_work;
+    use crate::mining::{BlockTemplateBuilder, ChainTip, MemoryChain};
+    use crate::network::protocol::Hash;
+    use crate::time::now_secs;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct CollectingSink {
+        blocks: Mutex<Vec<BlockTemplate>>,
+        found: Condvar,
+    }
+
+    impl BlockSink for CollectingSink {
+        fn submit_block(&self, template: BlockTemplate) {
+            self.blocks.lock().unwrap().push(template);
+            self.found.notify_all();
+        }
+    }
+
+    impl CollectingSink {
+        fn wait_for(&self, count: usize) -> Vec<BlockTemplate> {
+            let guard = self.blocks.lock().unwrap();
+            let (guard, timeout) = self
+                .found
+                .wait_timeout_while(guard, Duration::from_secs(30), |b| b.len() < count)
+                .unwrap();
+            assert!(!timeout.timed_out(), "no block mined in time");
+            guard.clone()
+        }
+    }
+
+    fn config(threads: usize) -> MiningConfig {
+        MiningConfig {
+            threads,
+            mine_empty_blocks: true,
+            mining_address: "miner".to_string(),
+            ..MiningConfig::default()
+        }
+    }
+
+    fn template(prev: u8, bits: u32) -> BlockTemplate {
+        let tip = ChainTip {
+            hash: Hash([prev; 64]),
+            height: 5,
+            median_time_past: 1_000,
+            bits,
+        };
+        BlockTemplateBuilder::new(&config(1))
+            .build(&tip, &Mempool::default(), 2_000)
+            .unwrap()
+    }
+
+    #[test]
+    fn mines_and_follows_new_templates() {
+        let sink = Arc::new(CollectingSink::default());
+        let mut miner = Miner::new(&config(2), sink.clone());
+        assert_eq!(miner.threads(), 2);
+        miner.start();
+
+        miner.update_template(template(1, 0x207f_ffff));
+        let first = sink.wait_for(1);
+        assert!(check_proof_of_work(&first[0].header));
+        assert_eq!(first[0].header.prev_block, Hash([1; 64]));
+        assert_eq!(first[0].header.merkle_root, first[0].merkle_root());
+
+        miner.update_template(template(2, 0x207f_ffff));
+        let second = sink.wait_for(2);
+        assert_eq!(second[1].header.prev_block, Hash([2; 64]));
+
+        miner.stop();
+        assert!(!miner.is_running());
+        assert!(miner.total_hashes() >= 2);
+    }
+
+    #[test]
+    fn stops_promptly_on_unsolvable_work() {
+        let sink = Arc::new(CollectingSink::default());
+        let mut miner = Miner::new(&config(0), sink.clone());
+        assert!(miner.threads() >= 1);
+        miner.start();
+
+        miner.update_template(template(1, 0x0300_0001));
+        while miner.total_hashes() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        miner.stop();
+
+        assert!(sink.blocks.lock().unwrap().is_empty());
+        assert!(miner.hashrate() > 0.0);
+    }
+
+    #[test]
+    fn feeds_solved_blocks_to_the_chain_engine() {
+        let chain = Arc::new(MemoryChain::with_bits(0x207f_ffff));
+        let mut miner = Miner::new(&config(2), chain.clone());
+        miner.start();
+
+        let builder = BlockTemplateBuilder::new(&config(1));
+        for height in 1..=2 {
+            let work = builder
+                .build(&chain.tip(), &Mempool::default(), now_secs() as u32)
+                .unwrap();
+            miner.update_template(work);
+            let started = Instant::now();
+            while chain.tip().height < height {
+                assert!(
+                    started.elapsed() < Duration::from_secs(30),
+                    "no block connected"
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        miner.stop();
+
+        let blocks = chain.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].header.prev_block, blocks[0].hash());
+        assert_eq!(chain.tip().hash, blocks[1].hash());
+    }
+}
@@ -0,0 +1,33 @@
+//! Block production.
+//!
+//! `template` turns the mempool and the chain tip into a block template:
+//! a coinbase paying the configured address, the best-paying transactions
+//! that fit the block limits, and a header ready for proof-of-work.
+//! `miner` grinds templates on local CPU threads, checking headers with the
+//! target rules in `pow`; `stratum` hands the same templates to external
+//! mining software instead. Solved blocks reach the chain through the
+//! `ChainEngine` trait in `submit`, which also has an in-memory engine
+//! (`MemoryChain`) for tests and demos; on regtest, `generate` mines
+//! blocks on demand for tests.
+
+pub mod generate;
+pub mod miner;
+pub mod pow;
+pub mod stratum;
+pub mod submit;
+pub mod template;
+
+pub use generate::{generate_to_address, GenerateError};
+pub use miner::{BlockSink, Miner};
+pub use pow::{
+    check_proof_of_work, compact_to_target, difficulty_from_bits, meets_target, network_hashrate,
+};
+pub use stratum::{StratumConfig, StratumError, StratumJob, StratumServer, VarDiff};
+pub use submit::{
+    check_block, check_block_inputs, check_relayed_block, check_utxo_commitment, connect_block,
+    BlockRejection, BlockSubmission, ChainEngine, MemoryChain,
+};
+pub use template::{
+    coinbase_height, coinbase_transaction, BlockTemplate, BlockTemplateBuilder, ChainTip,
+    TemplateError,
+};
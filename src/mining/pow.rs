@@ -0,0 +1,188 @@
+//! Proof-of-work target checks for SHA-512 block headers.
+//!
+//! Targets are 512-bit big-endian numbers compared against the whole header
+//! hash. `bits` keeps its Bitcoin compact-target meaning for the most
+//! significant 256 bits of the target; the low 256 bits are zero. That way
+//! the familiar nBits values (`0x207f_ffff` on regtest, `0x1f00_ffff`
+//! default) give the familiar difficulties, while share targets derived
+//! from a difficulty use the full 512-bit precision.
+
+use num_bigint::BigUint;
+
+use crate::network::protocol::{BlockHeader, Hash};
+
+/// Compact target of difficulty 1, as in Bitcoin.
+pub const DIFFICULTY_ONE_BITS: u32 = 0x1d00_ffff;
+
+/// Size in bytes of a target, the same as a header hash.
+pub const TARGET_LEN: usize = 64;
+
+/// Big-endian 512-bit proof-of-work target.
+pub type Target = [u8; TARGET_LEN];
+
+/// Expand compact `bits` to a 512-bit target.
+///
+/// Returns `None` for negative, zero or overflowing encodings.
+pub fn compact_to_target(bits: u32) -> Option<Target> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 || mantissa == 0 {
+        return None;
+    }
+
+    // The compact value is a 256-bit number occupying the top half.
+    let mut target = [0u8; TARGET_LEN];
+    if exponent <= 3 {
+        let value = mantissa >> (8 * (3 - exponent));
+        if value == 0 {
+            return None;
+        }
+        target[28..32].copy_from_slice(&value.to_be_bytes());
+    } else {
+        if exponent > 32 {
+            return None;
+        }
+        let start = 32 - exponent;
+        target[start..start + 3].copy_from_slice(&mantissa.to_be_bytes()[1..]);
+    }
+    Some(target)
+}
+
+/// 512-bit target for a (possibly fractional) difficulty relative to
+/// `DIFFICULTY_ONE_BITS`, saturating at the easiest possible target.
+pub fn target_for_difficulty(difficulty: f64) -> Target {
+    let one = compact_to_target(DIFFICULTY_ONE_BITS).expect("valid difficulty-one bits");
+    // Fixed point with 32 fractional bits keeps sub-1 difficulties exact enough.
+    let scaled = (difficulty * 4_294_967_296.0).max(1.0) as u128;
+    let target = (BigUint::from_bytes_be(&one) << 32u32) / BigUint::from(scaled);
+
+    let bytes = target.to_bytes_be();
+    if bytes.len() > TARGET_LEN {
+        return [0xff; TARGET_LEN];
+    }
+    let mut out = [0u8; TARGET_LEN];
+    out[TARGET_LEN - bytes.len()..].copy_from_slice(&bytes);
+    out
+}
+
+/// Difficulty of compact `bits` relative to `DIFFICULTY_ONE_BITS`, or 0 for
+/// an invalid encoding.
+pub fn difficulty_from_bits(bits: u32) -> f64 {
+    let as_f64 = |target: Target| {
+        target
+            .iter()
+            .fold(0.0f64, |acc, byte| acc * 256.0 + f64::from(*byte))
+    };
+    match compact_to_target(bits) {
+        Some(target) => {
+            let one = compact_to_target(DIFFICULTY_ONE_BITS).expect("valid difficulty-one bits");
+            as_f64(one) / as_f64(target)
+        }
+        None => 0.0,
+    }
+}
+
+/// Hashes per second needed to find blocks of `difficulty` every
+/// `block_time` seconds on average; the same estimate as
+/// `DifficultyManager::estimate_network_hashrate`.
+pub fn network_hashrate(difficulty: f64, block_time: f64) -> f64 {
+    if block_time <= 0.0 {
+        return 0.0;
+    }
+    difficulty * f64::from(u32::MAX) / block_time
+}
+
+/// Whether `hash`, read as a 512-bit big-endian number, is at or below
+/// `target`.
+pub fn hash_meets(hash: &Hash, target: &Target) -> bool {
+    hash.as_bytes()[..] <= target[..]
+}
+
+/// Whether `hash` meets the compact target `bits`.
+pub fn meets_target(hash: &Hash, bits: u32) -> bool {
+    match compact_to_target(bits) {
+        Some(target) => hash_meets(hash, &target),
+        None => false,
+    }
+}
+
+/// Whether `header` carries valid proof-of-work for its own `bits`.
+pub fn check_proof_of_work(header: &BlockHeader) -> bool {
+    meets_target(&header.hash(), header.bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_with_prefix(prefix: &[u8]) -> Hash {
+        let mut bytes = [0xffu8; 64];
+        bytes[..prefix.len()].copy_from_slice(prefix);
+        Hash(bytes)
+    }
+
+    #[test]
+    fn expands_compact_targets() {
+        let regtest = compact_to_target(0x207f_ffff).unwrap();
+        assert_eq!(&regtest[..3], &[0x7f, 0xff, 0xff]);
+        assert!(regtest[3..].iter().all(|b| *b == 0));
+
+        assert!(regtest[32..].iter().all(|b| *b == 0));
+
+        let small = compact_to_target(0x0300_1234).unwrap();
+        assert_eq!(&small[29..32], &[0x00, 0x12, 0x34]);
+
+        assert_eq!(compact_to_target(0x2100_ffff), None);
+        assert_eq!(compact_to_target(0x1f80_0000), None);
+        assert_eq!(compact_to_target(0x1f00_0000), None);
+    }
+
+    #[test]
+    fn difficulty_scales_target() {
+        assert_eq!(
+            target_for_difficulty(1.0),
+            compact_to_target(DIFFICULTY_ONE_BITS).unwrap()
+        );
+        let two = target_for_difficulty(2.0);
+        assert_eq!(&two[..6], &[0, 0, 0, 0, 0x7f, 0xff]);
+        let easy = target_for_difficulty(1.0 / 256.0);
+        assert_eq!(&easy[..5], &[0, 0, 0, 0xff, 0xff]);
+        assert_eq!(&target_for_difficulty(0.0)[..3], &[0xff, 0xff, 0]);
+    }
+
+    #[test]
+    fn reports_difficulty_of_bits() {
+        assert_eq!(difficulty_from_bits(DIFFICULTY_ONE_BITS), 1.0);
+        assert_eq!(difficulty_from_bits(0x1c00_ffff), 256.0);
+        assert!(difficulty_from_bits(0x207f_ffff) < 1e-9);
+        assert_eq!(difficulty_from_bits(0x1f80_0000), 0.0);
+        assert_eq!(network_hashrate(2.0, 0.0), 0.0);
+        assert_eq!(network_hashrate(600.0, 600.0), f64::from(u32::MAX));
+    }
+
+    #[test]
+    fn compares_the_whole_hash() {
+        assert!(meets_target(
+            &hash_with_prefix(&[0x7f, 0xff, 0xfe]),
+            0x207f_ffff
+        ));
+        assert!(!meets_target(&hash_with_prefix(&[0x80]), 0x207f_ffff));
+        assert!(meets_target(&hash_with_prefix(&[0, 0, 0]), 0x1f00_ffff));
+        assert!(!meets_target(&hash_with_prefix(&[0, 1]), 0x1f00_ffff));
+
+        // Equal top halves: the low half decides.
+        let mut exact = [0u8; 64];
+        exact[..3].copy_from_slice(&[0x7f, 0xff, 0xff]);
+        assert!(meets_target(&Hash(exact), 0x207f_ffff));
+        exact[63] = 1;
+        assert!(!meets_target(&Hash(exact), 0x207f_ffff));
+
+        // Share targets keep precision below the top half.
+        let target = target_for_difficulty(7.0);
+        assert!(target[32..].iter().any(|b| *b != 0));
+        assert!(hash_meets(&Hash(target), &target));
+        let mut above = target;
+        above[32..].fill(0xff);
+        assert!(!hash_meets(&Hash(above), &target));
+    }
+}
@@ -0,0 +1,1008 @@
+//! Stratum-style mining server for external miners.
+//!
+//! Miners connect over TCP and exchange line-delimited JSON-RPC messages:
+//!
+//! - `mining.subscribe` → `[[["mining.set_difficulty", id], ["mining.notify", id]], extranonce1, 4]`
+//! - `mining.authorize [worker, password]` → `true`
+//! - `mining.submit [worker, job_id, extranonce2, time, nonce]` → `true`
+//! - pushed by the server: `mining.set_difficulty [difficulty]` and
+//!   `mining.notify`.
+//!
+//! At most `StratumConfig::max_connections` miners are served at once, each
+//! with up to 64 authorized workers; a request line longer than 16 KiB
+//! closes the connection.
+//!
+//! The coinbase commits its payee through a hashed outpoint (see
+//! `coinbase_transaction`), so `mining.notify` carries two splices where
+//! Bitcoin has `coinb1`/`coinb2`:
+//!
+//! `[job_id, prev_block, tag_prefix, tag_suffix, tx_prefix, tx_suffix,
+//!   merkle_branch, version, bits, time, clean_jobs, utxo_root]`
+//!
+//! A miner computes `outpoint = SHA-512(tag_prefix || extranonce1 ||
+//! extranonce2 || tag_suffix)`, then `coinbase = SHA-512(tx_prefix ||
+//! outpoint || tx_suffix)`, then folds the branch in as
+//! `root = SHA-512(root || branch[i])`. The header is hashed as its bincode
+//! encoding. `version`, `bits`, `time` and `nonce` are `%08x` hex; the
+//! extranonces are hex of the raw bytes spliced into the tag. `utxo_root`
+//! only enters the header from `UTXO_COMMITMENT_VERSION` on.
+//!
+//! Shares are checked against the connection's share target, the 512-bit
+//! `pow::target_for_difficulty` of its difficulty compared against the whole
+//! header hash; a share that also meets the block's own target is forwarded
+//! to the `BlockSink`.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Semaphore};
+
+use super::miner::BlockSink;
+use super::pow::{compact_to_target, hash_meets, target_for_difficulty};
+use super::template::{coinbase_tag_parts, BlockTemplate};
+use crate::blockchain::merkle::MerkleTree;
+use crate::network::protocol::Hash;
+use crate::time::now_secs;
+
+/// Bytes of extranonce2 each miner rolls.
+pub const EXTRANONCE2_SIZE: usize = 4;
+
+/// How far past the local clock a share's `time` may be.
+const MAX_FUTURE_TIME: u64 = 2 * 60 * 60;
+
+/// Longest request line accepted before the connection is dropped.
+const MAX_LINE_LENGTH: usize = 16 * 1024;
+
+/// Workers one connection may authorize.
+const MAX_WORKERS_PER_CONNECTION: usize = 64;
+
+/// Pause after a failed `accept`, so running out of file descriptors does
+/// not turn into a busy loop.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Stratum server knobs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StratumConfig {
+    pub listen_addr: SocketAddr,
+    /// Share difficulty a new connection starts at.
+    pub initial_difficulty: f64,
+    /// Floor for vardiff.
+    pub min_difficulty: f64,
+    /// Seconds between shares vardiff aims for.
+    pub target_share_interval: u64,
+    /// Seconds between vardiff re-evaluations of a connection.
+    pub retarget_interval: u64,
+    /// Jobs kept for late submissions before they count as stale.
+    pub max_jobs: usize,
+    /// Concurrent miner connections; further ones are closed on accept.
+    pub max_connections: usize,
+}
+
+impl Default for StratumConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "0.0.0.0:3333".parse().unwrap(),
+            initial_difficulty: 1.0,
+            min_difficulty: 1.0 / 65_536.0,
+            target_share_interval: 10,
+            retarget_interval: 60,
+            max_jobs: 8,
+            max_connections: 64,
+        }
+    }
+}
+
+/// Stratum error codes returned in `[code, message, null]` form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StratumError {
+    Other(String),
+    JobNotFound,
+    DuplicateShare,
+    LowDifficulty,
+    Unauthorized,
+    NotSubscribed,
+}
+
+impl StratumError {
+    pub fn code(&self) -> i64 {
+        match self {
+            StratumError::Other(_) => 20,
+            StratumError::JobNotFound => 21,
+            StratumError::DuplicateShare => 22,
+            StratumError::LowDifficulty => 23,
+            StratumError::Unauthorized => 24,
+            StratumError::NotSubscribed => 25,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!([self.code(), self.to_string(), null])
+    }
+}
+
+impl fmt::Display for StratumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StratumError::Other(msg) => write!(f, "{}", msg),
+            StratumError::JobNotFound => write!(f, "Job not found"),
+            StratumError::DuplicateShare => write!(f, "Duplicate share"),
+            StratumError::LowDifficulty => write!(f, "Low difficulty share"),
+            StratumError::Unauthorized => write!(f, "Unauthorized worker"),
+            StratumError::NotSubscribed => write!(f, "Not subscribed"),
+        }
+    }
+}
+
+impl std::error::Error for StratumError {}
+
+/// Siblings of the coinbase (leaf 0) from the bottom level up: its
+/// `MerkleTree` inclusion proof.
+fn coinbase_merkle_branch(leaves: &[[u8; 64]]) -> Vec<[u8; 64]> {
+    MerkleTree::new(leaves)
+        .and_then(|tree| tree.proof(0))
+        .expect("a template has a coinbase")
+        .siblings
+        .into_iter()
+        .map(Hash::into_bytes)
+        .collect()
+}
+
+/// A template as handed out to miners.
+#[derive(Debug, Clone)]
+pub struct StratumJob {
+    pub id: String,
+    pub template: BlockTemplate,
+    /// Whether miners must drop work on earlier jobs (new tip).
+    pub clean: bool,
+    tag_prefix: Vec<u8>,
+    tag_suffix: Vec<u8>,
+    tx_prefix: Vec<u8>,
+    tx_suffix: Vec<u8>,
+    merkle_branch: Vec<[u8; 64]>,
+}
+
+impl StratumJob {
+    fn new(id: String, template: BlockTemplate, clean: bool) -> Self {
+        let (tag_prefix, tag_suffix) = coinbase_tag_parts(template.height, &template.payout_script);
+
+        let coinbase = bincode::serialize(&template.coinbase).expect("coinbase serialize");
+        let outpoint = template.coinbase.outputs[0].0.tx_hash.into_bytes();
+        let at = coinbase
+            .windows(64)
+            .position(|w| w == outpoint)
+            .expect("coinbase encodes its outpoint");
+
+        let leaves: Vec<[u8; 64]> = template
+            .all_transactions()
+            .map(|tx| tx.txid().into_bytes())
+            .collect();
+
+        Self {
+            id,
+            clean,
+            tag_prefix,
+            tag_suffix,
+            tx_prefix: coinbase[..at].to_vec(),
+            tx_suffix: coinbase[at + 64..].to_vec(),
+            merkle_branch: coinbase_merkle_branch(&leaves),
+            template,
+        }
+    }
+
+    /// The `mining.notify` message for this job.
+    pub fn notify(&self) -> Value {
+        let header = &self.template.header;
+        json!({
+            "id": null,
+            "method": "mining.notify",
+            "params": [
+                self.id,
+                hex::encode(header.prev_block.as_bytes()),
+                hex::encode(&self.tag_prefix),
+                hex::encode(&self.tag_suffix),
+                hex::encode(&self.tx_prefix),
+                hex::encode(&self.tx_suffix),
+                self.merkle_branch.iter().map(hex::encode).collect::<Vec<_>>(),
+                format!("{:08x}", header.version),
+                format!("{:08x}", header.bits),
+                format!("{:08x}", header.time),
+                self.clean,
+                hex::encode(header.utxo_root.as_bytes()),
+            ]
+        })
+    }
+
+    /// The template as solved by a miner.
+    pub fn solve(
+        &self,
+        extranonce1: u32,
+        extranonce2: u32,
+        time: u32,
+        nonce: u32,
+    ) -> BlockTemplate {
+        let extra_nonce = u64::from(extranonce1) | (u64::from(extranonce2) << 32);
+        let mut template = self.template.with_extra_nonce(extra_nonce);
+        template.header.time = time;
+        template.header.nonce = nonce;
+        template
+    }
+}
+
+/// Per-connection variable share difficulty.
+///
+/// Every `retarget_interval` seconds the difficulty is scaled by how far the
+/// observed share interval is from `target_share_interval`, by at most 4x
+/// either way.
+#[derive(Debug, Clone)]
+pub struct VarDiff {
+    difficulty: f64,
+    min_difficulty: f64,
+    target_interval: u64,
+    retarget_interval: u64,
+    window_start: u64,
+    shares: u32,
+}
+
+impl VarDiff {
+    pub fn new(config: &StratumConfig, now: u64) -> Self {
+        Self {
+            difficulty: config.initial_difficulty.max(config.min_difficulty),
+            min_difficulty: config.min_difficulty,
+            target_interval: config.target_share_interval.max(1),
+            retarget_interval: config.retarget_interval.max(1),
+            window_start: now,
+            shares: 0,
+        }
+    }
+
+    pub fn difficulty(&self) -> f64 {
+        self.difficulty
+    }
+
+    /// Record an accepted share; returns the new difficulty if it changed.
+    pub fn record_share(&mut self, now: u64) -> Option<f64> {
+        self.shares += 1;
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed < self.retarget_interval {
+            return None;
+        }
+
+        let actual = elapsed as f64 / f64::from(self.shares);
+        let factor = (self.target_interval as f64 / actual).clamp(0.25, 4.0);
+        self.window_start = now;
+        self.shares = 0;
+
+        let next = (self.difficulty * factor).max(self.min_difficulty);
+        if (next / self.difficulty - 1.0).abs() < 0.1 {
+            return None;
+        }
+        self.difficulty = next;
+        Some(next)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+struct Session {
+    extranonce1: u32,
+    subscribed: bool,
+    workers: HashSet<String>,
+    vardiff: VarDiff,
+    /// Difficulty before the last vardiff change, honoured until the next job.
+    grace_difficulty: Option<f64>,
+    /// `(job_id, extranonce2, time, nonce)` of accepted shares.
+    seen: HashSet<(String, u32, u32, u32)>,
+}
+
+impl Session {
+    fn accepting_difficulty(&self) -> f64 {
+        match self.grace_difficulty {
+            Some(previous) => previous.min(self.vardiff.difficulty()),
+            None => self.vardiff.difficulty(),
+        }
+    }
+}
+
+fn set_difficulty(difficulty: f64) -> Value {
+    json!({ "id": null, "method": "mining.set_difficulty", "params": [difficulty] })
+}
+
+fn param_str(params: &[Value], index: usize) -> Result<&str, StratumError> {
+    params
+        .get(index)
+        .and_then(Value::as_str)
+        .ok_or_else(|| StratumError::Other(format!("missing parameter {}", index)))
+}
+
+/// `%08x` hex integer.
+fn param_u32(params: &[Value], index: usize) -> Result<u32, StratumError> {
+    let text = param_str(params, index)?;
+    u32::from_str_radix(text, 16)
+        .map_err(|_| StratumError::Other(format!("invalid hex integer {:?}", text)))
+}
+
+/// Raw little-endian bytes, as spliced into the coinbase tag.
+fn param_extranonce(params: &[Value], index: usize) -> Result<u32, StratumError> {
+    let text = param_str(params, index)?;
+    let bytes: [u8; EXTRANONCE2_SIZE] = hex::decode(text)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| StratumError::Other(format!("invalid extranonce2 {:?}", text)))?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+struct JobBook {
+    jobs: VecDeque<Arc<StratumJob>>,
+    next_id: u64,
+}
+
+/// Serves block templates to external miners and collects their shares.
+pub struct StratumServer {
+    config: StratumConfig,
+    jobs: RwLock<JobBook>,
+    notify: broadcast::Sender<Arc<StratumJob>>,
+    next_extranonce1: AtomicU32,
+    sink: Arc<dyn BlockSink>,
+    permits: Arc<Semaphore>,
+    shares_accepted: AtomicU64,
+    blocks_found: AtomicU64,
+}
+
+impl StratumServer {
+    pub fn new(config: StratumConfig, sink: Arc<dyn BlockSink>) -> Self {
+        let (notify, _) = broadcast::channel(16);
+        let permits = Arc::new(Semaphore::new(config.max_connections.max(1)));
+        Self {
+            config,
+            jobs: RwLock::new(JobBook {
+                jobs: VecDeque::new(),
+                next_id: 1,
+            }),
+            notify,
+            next_extranonce1: AtomicU32::new(1),
+            sink,
+            permits,
+            shares_accepted: AtomicU64::new(0),
+            blocks_found: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &StratumConfig {
+        &self.config
+    }
+
+    pub fn shares_accepted(&self) -> u64 {
+        self.shares_accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn blocks_found(&self) -> u64 {
+        self.blocks_found.load(Ordering::Relaxed)
+    }
+
+    /// Publish a new template to every subscribed miner.
+    ///
+    /// `clean` marks a new tip: earlier jobs are forgotten and miners are
+    /// told to abandon them. Otherwise (a mempool refresh) shares for recent
+    /// jobs are still accepted.
+    pub fn update_template(&self, template: BlockTemplate, clean: bool) -> Arc<StratumJob> {
+        let job = {
+            let mut book = self.jobs.write().unwrap();
+            let job = Arc::new(StratumJob::new(
+                format!("{:x}", book.next_id),
+                template,
+                clean,
+            ));
+            book.next_id += 1;
+            if clean {
+                book.jobs.clear();
+            }
+            book.jobs.push_back(Arc::clone(&job));
+            while book.jobs.len() > self.config.max_jobs.max(1) {
+                book.jobs.pop_front();
+            }
+            job
+        };
+        // No subscribers is fine: nobody is connected yet.
+        let _ = self.notify.send(Arc::clone(&job));
+        job
+    }
+
+    pub fn current_job(&self) -> Option<Arc<StratumJob>> {
+        self.jobs.read().unwrap().jobs.back().cloned()
+    }
+
+    fn find_job(&self, id: &str) -> Option<Arc<StratumJob>> {
+        self.jobs
+            .read()
+            .unwrap()
+            .jobs
+            .iter()
+            .find(|job| job.id == id)
+            .cloned()
+    }
+
+    /// Bind `StratumConfig::listen_addr` and serve miners. Only binding can
+    /// fail; errors accepting a connection are logged and retried.
+    pub async fn run(self: Arc<Self>) -> io::Result<()> {
+        let listener = TcpListener::bind(self.config.listen_addr).await?;
+        log::info!("Stratum server listening on {}", listener.local_addr()?);
+        self.serve(listener).await
+    }
+
+    /// Serve miners on an already bound listener.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Stratum accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let Ok(permit) = Arc::clone(&self.permits).try_acquire_owned() else {
+                log::debug!("Refusing stratum connection {}: too many connections", addr);
+                continue;
+            };
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_connection(stream).await {
+                    log::debug!("Stratum connection {} closed: {}", addr, e);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    fn new_session(&self) -> Session {
+        Session {
+            extranonce1: self.next_extranonce1.fetch_add(1, Ordering::Relaxed),
+            subscribed: false,
+            workers: HashSet::new(),
+            vardiff: VarDiff::new(&self.config, now_secs()),
+            grace_difficulty: None,
+            seen: HashSet::new(),
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let mut line = Vec::new();
+        let mut jobs = self.notify.subscribe();
+        let mut session = self.new_session();
+
+        loop {
+            tokio::select! {
+                more = read_line(&mut reader, &mut line) => {
+                    if !more? {
+                        return Ok(());
+                    }
+                    let request = std::mem::take(&mut line);
+                    if request.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    let replies = match serde_json::from_slice::<Request>(&request) {
+                        Ok(request) => self.handle_request(&mut session, request),
+                        Err(e) => vec![json!({
+                            "id": null,
+                            "result": null,
+                            "error": StratumError::Other(e.to_string()).to_json(),
+                        })],
+                    };
+                    write_messages(&mut write, &replies).await?;
+                }
+                job = jobs.recv() => {
+                    let job = match job {
+                        Ok(job) => job,
+                        Err(broadcast::error::RecvError::Lagged(_)) => match self.current_job() {
+                            Some(job) => job,
+                            None => continue,
+                        },
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    };
+                    if session.subscribed {
+                        self.prepare_for_job(&mut session);
+                        write_messages(&mut write, &[job.notify()]).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget state tied to jobs that can no longer be submitted against.
+    fn prepare_for_job(&self, session: &mut Session) {
+        session.grace_difficulty = None;
+        let book = self.jobs.read().unwrap();
+        session
+            .seen
+            .retain(|(id, ..)| book.jobs.iter().any(|job| &job.id == id));
+    }
+
+    fn handle_request(&self, session: &mut Session, request: Request) -> Vec<Value> {
+        let mut pushes = Vec::new();
+        let result = match request.method.as_str() {
+            "mining.subscribe" => {
+                session.subscribed = true;
+                let id = format!("{:08x}", session.extranonce1);
+                pushes.push(set_difficulty(session.vardiff.difficulty()));
+                if let Some(job) = self.current_job() {
+                    self.prepare_for_job(session);
+                    pushes.push(job.notify());
+                }
+                Ok(json!([
+                    [["mining.set_difficulty", id], ["mining.notify", id]],
+                    hex::encode(session.extranonce1.to_le_bytes()),
+                    EXTRANONCE2_SIZE,
+                ]))
+            }
+            "mining.authorize" => param_str(&request.params, 0).and_then(|worker| {
+                if !session.workers.contains(worker)
+                    && session.workers.len() >= MAX_WORKERS_PER_CONNECTION
+                {
+                    return Err(StratumError::Other("too many workers".to_string()));
+                }
+                log::info!("Stratum worker {} authorized", worker);
+                session.workers.insert(worker.to_string());
+                Ok(json!(true))
+            }),
+            "mining.submit" => self.submit_share(session, &request.params).map(|changed| {
+                if let Some(difficulty) = changed {
+                    pushes.push(set_difficulty(difficulty));
+                }
+                json!(true)
+            }),
+            other => Err(StratumError::Other(format!("unknown method {}", other))),
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "id": request.id, "result": result, "error": null }),
+            Err(e) => json!({ "id": request.id, "result": null, "error": e.to_json() }),
+        };
+        std::iter::once(response).chain(pushes).collect()
+    }
+
+    /// Check a submitted share; returns a new share difficulty if vardiff
+    /// moved.
+    fn submit_share(
+        &self,
+        session: &mut Session,
+        params: &[Value],
+    ) -> Result<Option<f64>, StratumError> {
+        if !session.subscribed {
+            return Err(StratumError::NotSubscribed);
+        }
+        if !session.workers.contains(param_str(params, 0)?) {
+            return Err(StratumError::Unauthorized);
+        }
+        let job_id = param_str(params, 1)?;
+        let extranonce2 = param_extranonce(params, 2)?;
+        let time = param_u32(params, 3)?;
+        let nonce = param_u32(params, 4)?;
+
+        let job = self.find_job(job_id).ok_or(StratumError::JobNotFound)?;
+        let key = (job.id.clone(), extranonce2, time, nonce);
+        if session.seen.contains(&key) {
+            return Err(StratumError::DuplicateShare);
+        }
+        let now = now_secs();
+        if time < job.template.header.time || u64::from(time) > now + MAX_FUTURE_TIME {
+            return Err(StratumError::Other("time out of range".to_string()));
+        }
+
+        let solved = job.solve(session.extranonce1, extranonce2, time, nonce);
+        let hash = solved.header.hash();
+        let is_block =
+            compact_to_target(solved.header.bits).is_some_and(|target| hash_meets(&hash, &target));
+        if !is_block
+            && !hash_meets(
+                &hash,
+                &target_for_difficulty(session.accepting_difficulty()),
+            )
+        {
+            return Err(StratumError::LowDifficulty);
+        }
+
+        session.seen.insert(key);
+        self.shares_accepted.fetch_add(1, Ordering::Relaxed);
+        if is_block {
+            self.blocks_found.fetch_add(1, Ordering::Relaxed);
+            log::info!(
+                "Stratum miner found block at height {}: {}",
+                solved.height,
+                hash
+            );
+            self.sink.submit_block(solved);
+        }
+
+        let previous = session.vardiff.difficulty();
+        let changed = session.vardiff.record_share(now);
+        if changed.is_some() {
+            session.grace_difficulty = Some(previous);
+        }
+        Ok(changed)
+    }
+}
+
+/// Append the next newline-terminated line to `line`, which keeps whatever a
+/// cancelled earlier call had already read. `false` at end of stream; an
+/// error once the line outgrows `MAX_LINE_LENGTH`, before reading further.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+) -> io::Result<bool> {
+    let limit = (MAX_LINE_LENGTH + 1).saturating_sub(line.len()) as u64;
+    let read = (&mut *reader).take(limit).read_until(b'\n', line).await?;
+    if line.len() > MAX_LINE_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read > 0 || !line.is_empty())
+}
+
+async fn write_messages(write: &mut OwnedWriteHalf, messages: &[Value]) -> io::Result<()> {
+    for message in messages {
+        let mut line = message.to_string();
+        line.push('\n');
+        write.write_all(line.as_bytes()).await?;
+    }
+    write.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MiningConfig;
+    use crate::database::utxo_set::UTXOStorage;
+    use crate::mempool::{Mempool, MempoolConfig};
+    use crate::mining::pow::meets_target;
+    use crate::mining::{BlockTemplateBuilder, ChainTip};
+    use crate::network::protocol::{BlockHeader, Transaction};
+    use sha2::{Digest, Sha512};
+    use std::sync::Mutex;
+    use tokio::io::Lines;
+    use tokio::net::tcp::OwnedReadHalf;
+
+    /// What a miner computes from `mining.notify`.
+    fn sha512_concat(parts: &[&[u8]]) -> [u8; 64] {
+        let mut hasher = Sha512::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        let mut out = [0u8; 64];
+        out.copy_from_slice(&hasher.finalize());
+        out
+    }
+
+    #[derive(Default)]
+    struct CollectingSink(Mutex<Vec<BlockTemplate>>);
+
+    impl BlockSink for CollectingSink {
+        fn submit_block(&self, template: BlockTemplate) {
+            self.0.lock().unwrap().push(template);
+        }
+    }
+
+    fn template(bits: u32, txs: usize) -> BlockTemplate {
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let mut utxos = crate::database::MemoryUTXOStorage::new();
+        for i in 0..txs {
+            let coin = crate::database::create_outpoint(Hash([i as u8; 64]), 0);
+            let out = crate::database::TxOutput {
+                value: 10_000,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(coin.clone(), out, 1, false).unwrap();
+            let spend = Transaction {
+                inputs: vec![coin],
+                outputs: vec![(
+                    crate::database::create_outpoint(Hash([i as u8 + 100; 64]), 0),
+                    5_000,
+                )],
+                replaceable: false,
+            };
+            pool.accept_transaction(spend, &utxos).unwrap();
+        }
+
+        let config = MiningConfig {
+            mine_empty_blocks: true,
+            mining_address: "pool".to_string(),
+            ..MiningConfig::default()
+        };
+        let tip = ChainTip {
+            hash: Hash([9; 64]),
+            height: 41,
+            median_time_past: 1_000,
+            bits,
+        };
+        BlockTemplateBuilder::new(&config)
+            .build(&tip, &pool, now_secs() as u32)
+            .unwrap()
+    }
+
+    fn easy_config() -> StratumConfig {
+        StratumConfig {
+            initial_difficulty: 1.0 / 16_777_216.0,
+            min_difficulty: 1.0 / 16_777_216.0,
+            ..StratumConfig::default()
+        }
+    }
+
+    fn request(method: &str, params: Value) -> Request {
+        serde_json::from_value(json!({ "id": 1, "method": method, "params": params })).unwrap()
+    }
+
+    fn error_code(replies: &[Value]) -> Option<i64> {
+        replies[0]["error"][0].as_i64()
+    }
+
+    fn grind(job: &StratumJob, en1: u32, en2: u32, want: impl Fn(&Hash) -> bool) -> u32 {
+        let time = job.template.header.time;
+        (0..u32::MAX)
+            .find(|nonce| want(&job.solve(en1, en2, time, *nonce).header.hash()))
+            .unwrap()
+    }
+
+    fn submit(job: &StratumJob, en2: u32, nonce: u32) -> Request {
+        request(
+            "mining.submit",
+            json!([
+                "alice",
+                job.id,
+                hex::encode(en2.to_le_bytes()),
+                format!("{:08x}", job.template.header.time),
+                format!("{:08x}", nonce),
+            ]),
+        )
+    }
+
+    #[test]
+    fn notify_carries_enough_to_rebuild_the_header() {
+        let server = StratumServer::new(easy_config(), Arc::new(CollectingSink::default()));
+        let job = server.update_template(template(0x207f_ffff, 4), true);
+        let params = job.notify()["params"].clone();
+        let field = |i: usize| hex::decode(params[i].as_str().unwrap()).unwrap();
+        let int = |i: usize| u32::from_str_radix(params[i].as_str().unwrap(), 16).unwrap();
+
+        let (en1, en2) = (0x0102_0304u32, 0xaabb_ccddu32);
+        let outpoint = sha512_concat(&[
+            &field(2)[..],
+            &en1.to_le_bytes()[..],
+            &en2.to_le_bytes()[..],
+            &field(3)[..],
+        ]);
+        let mut root = sha512_concat(&[&field(4)[..], &outpoint[..], &field(5)[..]]);
+        for sibling in params[6].as_array().unwrap() {
+            let sibling = hex::decode(sibling.as_str().unwrap()).unwrap();
+            root = sha512_concat(&[&root[..], &sibling[..]]);
+        }
+        let header = BlockHeader {
+            version: int(7),
+            prev_block: Hash(field(1).try_into().unwrap()),
+            merkle_root: Hash(root),
+            time: int(9),
+            bits: int(8),
+            nonce: 77,
+            utxo_root: Hash(field(11).try_into().unwrap()),
+        };
+
+        let solved = job.solve(en1, en2, int(9), 77);
+        assert_eq!(solved.header, header);
+        assert_eq!(solved.header.merkle_root, solved.merkle_root());
+    }
+
+    #[test]
+    fn validates_shares_and_forwards_blocks() {
+        let sink = Arc::new(CollectingSink::default());
+        let server = StratumServer::new(easy_config(), sink.clone());
+        let job = server.update_template(template(0x1e00_ffff, 2), true);
+        let mut session = server.new_session();
+        let en1 = session.extranonce1;
+        let share_target = target_for_difficulty(easy_config().initial_difficulty);
+        let nonce = grind(&job, en1, 0, |h| hash_meets(h, &share_target));
+
+        let replies = server.handle_request(&mut session, submit(&job, 0, nonce));
+        assert_eq!(error_code(&replies), Some(25));
+
+        let replies = server.handle_request(&mut session, request("mining.subscribe", json!([])));
+        assert_eq!(
+            replies[0]["result"][1],
+            json!(hex::encode(en1.to_le_bytes()))
+        );
+        assert_eq!(replies[1]["method"], json!("mining.set_difficulty"));
+        assert_eq!(replies[2]["params"][0], json!(job.id));
+
+        let replies = server.handle_request(&mut session, submit(&job, 0, nonce));
+        assert_eq!(error_code(&replies), Some(24));
+        server.handle_request(
+            &mut session,
+            request("mining.authorize", json!(["alice", "x"])),
+        );
+
+        let mut stale = submit(&job, 0, nonce);
+        stale.params[1] = json!("nope");
+        assert_eq!(
+            error_code(&server.handle_request(&mut session, stale)),
+            Some(21)
+        );
+
+        let replies = server.handle_request(&mut session, submit(&job, 0, nonce));
+        assert_eq!(replies[0]["result"], json!(true));
+        let replies = server.handle_request(&mut session, submit(&job, 0, nonce));
+        assert_eq!(error_code(&replies), Some(22));
+
+        session.vardiff.difficulty = 1e9;
+        let weak = grind(&job, en1, 1, |h| !meets_target(h, 0x1e00_ffff));
+        let replies = server.handle_request(&mut session, submit(&job, 1, weak));
+        assert_eq!(error_code(&replies), Some(23));
+        assert!(sink.0.lock().unwrap().is_empty());
+
+        // A block-meeting share is accepted whatever the share difficulty.
+        let job = server.update_template(template(0x207f_ffff, 2), true);
+        let block = grind(&job, en1, 2, |h| meets_target(h, 0x207f_ffff));
+        let replies = server.handle_request(&mut session, submit(&job, 2, block));
+        assert_eq!(replies[0]["result"], json!(true));
+
+        let found = sink.0.lock().unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(crate::mining::check_proof_of_work(&found[0].header));
+        assert_eq!(found[0].extra_nonce, u64::from(en1) | (2 << 32));
+        assert_eq!(server.blocks_found(), 1);
+        assert_eq!(server.shares_accepted(), 2);
+    }
+
+    #[test]
+    fn caps_workers_per_connection() {
+        let server = StratumServer::new(easy_config(), Arc::new(CollectingSink::default()));
+        let mut session = server.new_session();
+        let authorize = |session: &mut Session, worker: &str| {
+            let request = request("mining.authorize", json!([worker, "x"]));
+            server.handle_request(session, request)
+        };
+        for i in 0..MAX_WORKERS_PER_CONNECTION {
+            let replies = authorize(&mut session, &format!("worker{}", i));
+            assert_eq!(replies[0]["result"], json!(true));
+        }
+        assert_eq!(
+            error_code(&authorize(&mut session, "one-too-many")),
+            Some(20)
+        );
+        // Re-authorizing a known worker still works.
+        assert_eq!(authorize(&mut session, "worker0")[0]["result"], json!(true));
+        assert_eq!(session.workers.len(), MAX_WORKERS_PER_CONNECTION);
+    }
+
+    #[test]
+    fn vardiff_tracks_share_rate() {
+        let config = StratumConfig {
+            initial_difficulty: 8.0,
+            min_difficulty: 1.0,
+            target_share_interval: 10,
+            retarget_interval: 60,
+            ..StratumConfig::default()
+        };
+
+        // 60 shares in 60s: 10x too fast, capped at 4x.
+        let mut vardiff = VarDiff::new(&config, 0);
+        let changes: Vec<_> = (1..=60).filter_map(|t| vardiff.record_share(t)).collect();
+        assert_eq!(changes, vec![32.0]);
+
+        // One share in 600s: far too slow, quartered down to the floor.
+        let mut vardiff = VarDiff::new(&config, 0);
+        assert_eq!(vardiff.record_share(600), Some(2.0));
+        assert_eq!(vardiff.record_share(1_200), Some(1.0));
+        assert_eq!(vardiff.record_share(1_800), None);
+
+        // On target: unchanged.
+        let mut vardiff = VarDiff::new(&config, 0);
+        assert!((1..=6).all(|i| vardiff.record_share(i * 10).is_none()));
+        assert_eq!(vardiff.difficulty(), 8.0);
+    }
+
+    async fn next(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Value {
+        let line = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_miners_over_tcp() {
+        let server = Arc::new(StratumServer::new(
+            easy_config(),
+            Arc::new(CollectingSink::default()),
+        ));
+        server.update_template(template(0x207f_ffff, 0), true);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::clone(&server).serve(listener));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        write
+            .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[]}\n")
+            .await
+            .unwrap();
+        assert_eq!(next(&mut lines).await["id"], json!(1));
+        assert_eq!(
+            next(&mut lines).await["method"],
+            json!("mining.set_difficulty")
+        );
+        assert_eq!(next(&mut lines).await["method"], json!("mining.notify"));
+
+        let job = server.update_template(template(0x207f_ffff, 1), false);
+        let notify = next(&mut lines).await;
+        assert_eq!(notify["params"][0], json!(job.id));
+        assert_eq!(notify["params"][10], json!(false));
+    }
+
+    #[tokio::test]
+    async fn drops_long_lines_and_excess_connections() {
+        let config = StratumConfig {
+            max_connections: 1,
+            ..easy_config()
+        };
+        let server = Arc::new(StratumServer::new(
+            config,
+            Arc::new(CollectingSink::default()),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::clone(&server).serve(listener));
+
+        let first = TcpStream::connect(addr).await.unwrap();
+        let (read, mut write) = first.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write
+            .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[]}\n")
+            .await
+            .unwrap();
+        assert_eq!(next(&mut lines).await["id"], json!(1));
+
+        // The only slot is taken: the second connection is closed at once.
+        let second = TcpStream::connect(addr).await.unwrap();
+        let mut rest = Vec::new();
+        let mut second = BufReader::new(second);
+        assert_eq!(second.read_to_end(&mut rest).await.unwrap(), 0);
+
+        // A line that never ends is cut off at MAX_LINE_LENGTH.
+        let _ = write.write_all(&vec![b'x'; MAX_LINE_LENGTH + 2]).await;
+        while let Ok(Some(_)) = lines.next_line().await {}
+
+        // Which frees the slot again, once the server has noticed.
+        let mut served = false;
+        for _ in 0..50 {
+            let third = TcpStream::connect(addr).await.unwrap();
+            let (read, mut write) = third.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let _ = write
+                .write_all(b"{\"id\":2,\"method\":\"mining.subscribe\",\"params\":[]}\n")
+                .await;
+            if let Ok(Some(line)) = lines.next_line().await {
+                assert_eq!(
+                    serde_json::from_str::<Value>(&line).unwrap()["id"],
+                    json!(2)
+                );
+                served = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(served);
+    }
+}
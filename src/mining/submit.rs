@@ -0,0 +1,901 @@
+//! Handing solved blocks to the chain engine.
+//!
+//! A `BlockSubmission` is a full block: header plus every transaction,
+//! coinbase first. `ChainEngine::process_block` either connects it or
+//! reports a `BlockRejection`, whose `reason` strings follow the BIP22
+//! `submitblock` vocabulary so mining software can act on them.
+//! `check_block` holds the checks that need nothing but the block and the
+//! tip it claims to extend; engines run it first, then `check_block_inputs`
+//! and `check_utxo_commitment` against the UTXO set the block would be
+//! connected to. `connect_block` applies a block to that set and records
+//! its unclaimed reward in the `BurnLedger` the supply audit reads.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::pow::{check_proof_of_work, difficulty_from_bits, network_hashrate};
+use super::template::{coinbase_height, transactions_merkle_root, ChainTip};
+use crate::blockchain::audit::BurnLedger;
+use crate::blockchain::merkle::MerkleTree;
+use crate::blockchain::reward::EmissionSchedule;
+use crate::config::{Config, MiningConfig};
+use crate::database::utxo_set::{
+    is_spendable_at, MemoryUTXOStorage, OutPoint, TxOutput, UTXOError, UTXOSet, UTXOStorage,
+};
+use crate::mempool::Mempool;
+use crate::network::protocol::{Block, BlockHeader, Hash, Transaction};
+use crate::time::now_secs;
+
+/// How far past the local clock a block's `time` may be.
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Number of recent block times the median time past is taken over.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Seconds between blocks the difficulty aims for.
+const TARGET_SPACING: f64 = 600.0;
+
+/// A complete block as exchanged with miners and the chain engine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockSubmission {
+    pub header: BlockHeader,
+    /// Coinbase first, then the rest in block order.
+    pub transactions: Vec<Transaction>,
+}
+
+impl BlockSubmission {
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        self.transactions.first()
+    }
+
+    /// Merkle root over the carried transactions; `None` if there are none.
+    pub fn merkle_root(&self) -> Option<Hash> {
+        transactions_merkle_root(&self.transactions)
+    }
+
+    /// The header-and-txid form stored by the chain.
+    pub fn block(&self) -> Block {
+        Block {
+            header: self.header.clone(),
+            tx_hashes: self.transactions.iter().map(Transaction::txid).collect(),
+        }
+    }
+}
+
+/// Why the chain engine refused a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockRejection {
+    /// The block is already known.
+    Duplicate,
+    /// The block builds on something other than the current tip.
+    NotBestPrevBlock,
+    /// The previous block is known to be invalid.
+    BadPrevBlock,
+    /// `bits` is not the target required at this height.
+    BadDiffBits,
+    /// The header hash does not meet its target.
+    HighHash,
+    /// `time` is not after the median time past.
+    TimeTooOld,
+    /// `time` is too far ahead of the local clock.
+    TimeTooNew,
+    /// The header's merkle root does not match the transactions.
+    BadMerkleRoot,
+    /// The first transaction is not a coinbase.
+    MissingCoinbase,
+    /// A coinbase appears after the first transaction.
+    MultipleCoinbase,
+    /// The coinbase does not commit to the block's height.
+    BadCoinbaseHeight,
+    /// The coinbase pays more than subsidy plus fees.
+    BadCoinbaseAmount,
+    /// The same transaction appears twice.
+    DuplicateTransaction,
+    /// An input is neither unspent in the UTXO set nor created earlier in
+    /// the block, or is spent twice.
+    MissingOrSpentInputs,
+    /// An output reuses an outpoint that is unspent in the UTXO set or
+    /// created earlier in the block.
+    DuplicateOutput,
+    /// An input spends a coinbase output before `coinbase_maturity`.
+    PrematureCoinbaseSpend,
+    /// A transaction's outputs exceed its inputs.
+    InputsBelowOutputs,
+    /// The header's `utxo_root` is not the root of the UTXO set it builds on.
+    BadUtxoCommitment,
+    /// Any other consensus failure, as a BIP22-style reason string.
+    Invalid(String),
+}
+
+impl BlockRejection {
+    /// BIP22 reason string, as returned by `submitblock`.
+    pub fn reason(&self) -> &str {
+        match self {
+            BlockRejection::Duplicate => "duplicate",
+            BlockRejection::NotBestPrevBlock => "inconclusive-not-best-prevblk",
+            BlockRejection::BadPrevBlock => "bad-prevblk",
+            BlockRejection::BadDiffBits => "bad-diffbits",
+            BlockRejection::HighHash => "high-hash",
+            BlockRejection::TimeTooOld => "time-too-old",
+            BlockRejection::TimeTooNew => "time-too-new",
+            BlockRejection::BadMerkleRoot => "bad-txnmrklroot",
+            BlockRejection::MissingCoinbase => "bad-cb-missing",
+            BlockRejection::MultipleCoinbase => "bad-cb-multiple",
+            BlockRejection::BadCoinbaseHeight => "bad-cb-height",
+            BlockRejection::BadCoinbaseAmount => "bad-cb-amount",
+            BlockRejection::DuplicateTransaction => "bad-txns-duplicate",
+            BlockRejection::MissingOrSpentInputs => "bad-txns-inputs-missingorspent",
+            BlockRejection::DuplicateOutput => "bad-txns-duplicate-output",
+            BlockRejection::PrematureCoinbaseSpend => "bad-txns-premature-spend-of-coinbase",
+            BlockRejection::InputsBelowOutputs => "bad-txns-in-belowout",
+            BlockRejection::BadUtxoCommitment => "bad-utxo-commitment",
+            BlockRejection::Invalid(reason) => reason,
+        }
+    }
+}
+
+impl fmt::Display for BlockRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Block rejected: {}", self.reason())
+    }
+}
+
+impl std::error::Error for BlockRejection {}
+
+/// The node's view of the chain, as far as block production needs it.
+pub trait ChainEngine: Send + Sync {
+    /// The block new work should extend.
+    fn tip(&self) -> ChainTip;
+
+    /// Fully validate `block` (`check_block`, then `check_utxo_commitment`
+    /// and `connect_block`, which runs `check_block_inputs`) and connect it
+    /// if it is valid, evicting its transactions (and any conflicts) from
+    /// the mempool.
+    fn process_block(&self, block: &BlockSubmission) -> Result<(), BlockRejection>;
+
+    /// Estimated network hashes per second over recent blocks (see
+    /// `pow::network_hashrate`).
+    fn estimate_network_hashrate(&self) -> f64;
+}
+
+/// Chain engine kept entirely in memory, for tests and demos.
+///
+/// Blocks that pass `check_block` against the current tip are connected to
+/// an in-memory UTXO set with `connect_block`, which records unclaimed
+/// rewards in the chain's `BurnLedger`; both are shared (`utxos`, `burns`)
+/// with whoever audits them. Headers committing to the UTXO set are not
+/// checked, as the set keeps no commitment. With `with_mempool`, each
+/// connected block advances the pool's tip and evicts the transactions it
+/// confirms.
+///
+/// The network hashrate is estimated as `ConsensusManager` does: the tip's
+/// difficulty over the average spacing of recent blocks (the last
+/// `MEDIAN_TIME_SPAN`), or the ten-minute target spacing until two blocks
+/// are connected. `consensus` is not part of the build, so the estimate is
+/// computed here rather than delegated.
+pub struct MemoryChain {
+    state: Mutex<MemoryChainState>,
+    emission: EmissionSchedule,
+    coinbase_maturity: u32,
+    utxos: Arc<RwLock<UTXOSet>>,
+    burns: Arc<RwLock<BurnLedger>>,
+    mempool: Option<Arc<RwLock<Mempool>>>,
+}
+
+struct MemoryChainState {
+    tip: ChainTip,
+    blocks: Vec<BlockSubmission>,
+    /// Times of the last `MEDIAN_TIME_SPAN` blocks, oldest first.
+    recent_times: VecDeque<u32>,
+}
+
+impl MemoryChain {
+    /// Chain whose tip is `tip`, with an empty UTXO set and the default
+    /// (mainnet) consensus rules of `MiningConfig`.
+    pub fn new(tip: ChainTip) -> Self {
+        let mining = MiningConfig::default();
+        Self {
+            state: Mutex::new(MemoryChainState {
+                tip,
+                blocks: Vec::new(),
+                recent_times: VecDeque::with_capacity(MEDIAN_TIME_SPAN),
+            }),
+            emission: mining.emission,
+            coinbase_maturity: mining.coinbase_maturity,
+            utxos: Arc::new(RwLock::new(UTXOSet::new(
+                Box::new(MemoryUTXOStorage::new()),
+            ))),
+            burns: Arc::new(RwLock::new(BurnLedger::new())),
+            mempool: None,
+        }
+    }
+
+    /// Empty chain following `config`'s target, emission schedule and
+    /// coinbase maturity.
+    pub fn from_config(config: &Config) -> Self {
+        let mut chain = Self::with_bits(config.mining.difficulty_target);
+        chain.emission = config.mining.emission;
+        chain.coinbase_maturity = config.mining.coinbase_maturity;
+        chain
+    }
+
+    /// Empty chain at height 0 with target `bits`.
+    pub fn with_bits(bits: u32) -> Self {
+        Self::new(ChainTip {
+            hash: Hash::zero(),
+            height: 0,
+            median_time_past: 0,
+            bits,
+        })
+    }
+
+    /// Keep `mempool` in step with the chain.
+    pub fn with_mempool(mut self, mempool: Arc<RwLock<Mempool>>) -> Self {
+        self.mempool = Some(mempool);
+        self
+    }
+
+    /// Blocks connected so far, oldest first.
+    pub fn blocks(&self) -> Vec<BlockSubmission> {
+        self.state.lock().unwrap().blocks.clone()
+    }
+
+    /// The UTXO set blocks are connected to.
+    pub fn utxos(&self) -> Arc<RwLock<UTXOSet>> {
+        Arc::clone(&self.utxos)
+    }
+
+    /// Unclaimed rewards of the connected blocks, by height.
+    pub fn burns(&self) -> Arc<RwLock<BurnLedger>> {
+        Arc::clone(&self.burns)
+    }
+}
+
+impl ChainEngine for MemoryChain {
+    fn tip(&self) -> ChainTip {
+        self.state.lock().unwrap().tip
+    }
+
+    fn process_block(&self, block: &BlockSubmission) -> Result<(), BlockRejection> {
+        let mut state = self.state.lock().unwrap();
+        check_block(block, &state.tip, now_secs() as u32)?;
+        let height = state.tip.height + 1;
+        connect_block(
+            block,
+            height,
+            self.utxos.write().unwrap().storage_mut(),
+            &self.emission,
+            self.coinbase_maturity,
+            &mut self.burns.write().unwrap(),
+        )?;
+
+        if state.recent_times.len() == MEDIAN_TIME_SPAN {
+            state.recent_times.pop_front();
+        }
+        state.recent_times.push_back(block.header.time);
+        state.tip = ChainTip {
+            hash: block.hash(),
+            height,
+            median_time_past: median_time(&state.recent_times),
+            bits: state.tip.bits,
+        };
+        state.blocks.push(block.clone());
+
+        if let Some(mempool) = &self.mempool {
+            let mut pool = mempool.write().unwrap();
+            pool.set_tip_height(state.tip.height);
+            pool.remove_for_block(&block.transactions);
+        }
+        Ok(())
+    }
+
+    fn estimate_network_hashrate(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        let times = &state.recent_times;
+        let spacing = match (times.front(), times.back()) {
+            (Some(&first), Some(&last)) if times.len() > 1 => {
+                f64::from(last.saturating_sub(first)) / (times.len() - 1) as f64
+            }
+            _ => TARGET_SPACING,
+        };
+        network_hashrate(difficulty_from_bits(state.tip.bits), spacing)
+    }
+}
+
+/// Median of `times`, as Bitcoin's `GetMedianTimePast`: the middle element
+/// once sorted, the upper one for an even count.
+fn median_time(times: &VecDeque<u32>) -> u32 {
+    let mut sorted: Vec<u32> = times.iter().copied().collect();
+    sorted.sort_unstable();
+    sorted.get(sorted.len() / 2).copied().unwrap_or(0)
+}
+
+fn is_coinbase(tx: &Transaction) -> bool {
+    tx.inputs.is_empty()
+}
+
+/// Checks on `block` that need only the tip it should extend and the local
+/// clock `now`, cheapest first.
+pub fn check_block(
+    block: &BlockSubmission,
+    tip: &ChainTip,
+    now: u32,
+) -> Result<(), BlockRejection> {
+    let header = &block.header;
+    if block.hash() == tip.hash {
+        return Err(BlockRejection::Duplicate);
+    }
+    if header.prev_block != tip.hash {
+        return Err(BlockRejection::NotBestPrevBlock);
+    }
+    if header.bits != tip.bits {
+        return Err(BlockRejection::BadDiffBits);
+    }
+    if !check_proof_of_work(header) {
+        return Err(BlockRejection::HighHash);
+    }
+    if header.time <= tip.median_time_past {
+        return Err(BlockRejection::TimeTooOld);
+    }
+    if header.time > now.saturating_add(MAX_FUTURE_BLOCK_TIME) {
+        return Err(BlockRejection::TimeTooNew);
+    }
+
+    let coinbase = match block.coinbase() {
+        Some(coinbase) if is_coinbase(coinbase) => coinbase,
+        _ => return Err(BlockRejection::MissingCoinbase),
+    };
+    if block.transactions[1..].iter().any(is_coinbase) {
+        return Err(BlockRejection::MultipleCoinbase);
+    }
+    if coinbase_height(coinbase) != Some(tip.height + 1) {
+        return Err(BlockRejection::BadCoinbaseHeight);
+    }
+
+    let txids: Vec<[u8; 64]> = block
+        .transactions
+        .iter()
+        .map(|tx| tx.txid().into_bytes())
+        .collect();
+    check_merkle_root(header, &txids)
+}
+
+/// Checks on a block relayed by a peer, which carries only txids and may
+/// not extend our tip: proof-of-work for its own `bits` and the merkle root
+/// over its txids. Failing either proves the sender relayed garbage; the
+/// clock- and chain-dependent checks are left to the chain engine, since a
+/// stale or skewed block is not misbehavior.
+pub fn check_relayed_block(block: &Block) -> Result<(), BlockRejection> {
+    if !check_proof_of_work(&block.header) {
+        return Err(BlockRejection::HighHash);
+    }
+    let txids: Vec<[u8; 64]> = block
+        .tx_hashes
+        .iter()
+        .map(|txid| txid.into_bytes())
+        .collect();
+    check_merkle_root(&block.header, &txids)
+}
+
+/// `header.merkle_root` against the `txids` it should commit to.
+fn check_merkle_root(header: &BlockHeader, txids: &[[u8; 64]]) -> Result<(), BlockRejection> {
+    let mut seen = HashSet::new();
+    if !txids.iter().all(|txid| seen.insert(txid)) {
+        return Err(BlockRejection::DuplicateTransaction);
+    }
+    let tree = MerkleTree::new(txids).map_err(|_| BlockRejection::MissingCoinbase)?;
+    // A mutated list repeats transactions under a valid root (CVE-2012-2459);
+    // distinct txids rule it out, but the tree is the authority on that.
+    if tree.is_mutated() {
+        return Err(BlockRejection::DuplicateTransaction);
+    }
+    if tree.root() != header.merkle_root.into_bytes() {
+        return Err(BlockRejection::BadMerkleRoot);
+    }
+    Ok(())
+}
+
+/// Checks on a block that passed `check_block`, against the UTXO set it
+/// would be connected to at `height`: every input is unspent (or created
+/// earlier in the block) and spent once, every output outpoint is new,
+/// coinbase outputs are only spent after `coinbase_maturity` confirmations,
+/// no transaction creates value, and the coinbase claims at most the
+/// subsidy plus fees.
+///
+/// Returns the block's total fees.
+pub fn check_block_inputs(
+    block: &BlockSubmission,
+    height: u64,
+    utxos: &dyn UTXOStorage,
+    emission: &EmissionSchedule,
+    coinbase_maturity: u32,
+) -> Result<u64, BlockRejection> {
+    let Some((coinbase, transactions)) = block.transactions.split_first() else {
+        return Err(BlockRejection::MissingCoinbase);
+    };
+
+    // Outputs created earlier in the block: (value, is_coinbase).
+    let mut created: HashMap<&OutPoint, (u64, bool)> = HashMap::new();
+    create_outputs(&mut created, coinbase, true, utxos)?;
+    let mut spent: HashSet<&OutPoint> = HashSet::new();
+    let mut fees: u64 = 0;
+
+    for tx in transactions {
+        let mut input_value: u64 = 0;
+        for input in &tx.inputs {
+            if !spent.insert(input) {
+                return Err(BlockRejection::MissingOrSpentInputs);
+            }
+            let (value, created_height, from_coinbase) = match created.get(input) {
+                Some(&(value, from_coinbase)) => (value, height, from_coinbase),
+                None => match utxos.get_output(input) {
+                    Ok(Some((output, created_height, from_coinbase))) => {
+                        (output.value, created_height, from_coinbase)
+                    }
+                    Ok(None) => return Err(BlockRejection::MissingOrSpentInputs),
+                    Err(e) => return Err(BlockRejection::Invalid(e.to_string())),
+                },
+            };
+            if !is_spendable_at(from_coinbase, created_height, height, coinbase_maturity) {
+                return Err(BlockRejection::PrematureCoinbaseSpend);
+            }
+            input_value = input_value
+                .checked_add(value)
+                .ok_or_else(value_out_of_range)?;
+        }
+
+        let output_value = total_output_value(tx)?;
+        if input_value < output_value {
+            return Err(BlockRejection::InputsBelowOutputs);
+        }
+        fees = fees
+            .checked_add(input_value - output_value)
+            .ok_or_else(value_out_of_range)?;
+        create_outputs(&mut created, tx, false, utxos)?;
+    }
+
+    let allowed = emission
+        .block_reward(height)
+        .checked_add(fees)
+        .ok_or_else(value_out_of_range)?;
+    if total_output_value(coinbase)? > allowed {
+        return Err(BlockRejection::BadCoinbaseAmount);
+    }
+    Ok(fees)
+}
+
+/// Add `tx`'s outputs to `created`, refusing any outpoint that already
+/// exists there or in `utxos`: connecting it would overwrite a live output.
+fn create_outputs<'a>(
+    created: &mut HashMap<&'a OutPoint, (u64, bool)>,
+    tx: &'a Transaction,
+    from_coinbase: bool,
+    utxos: &dyn UTXOStorage,
+) -> Result<(), BlockRejection> {
+    for (outpoint, value) in &tx.outputs {
+        let unspent = utxos
+            .get_output(outpoint)
+            .map_err(|e| BlockRejection::Invalid(e.to_string()))?
+            .is_some();
+        if unspent || created.insert(outpoint, (*value, from_coinbase)).is_some() {
+            return Err(BlockRejection::DuplicateOutput);
+        }
+    }
+    Ok(())
+}
+
+/// Run `check_block_inputs` and, if it passes, connect `block` at `height`:
+/// spend its inputs from `utxos`, add its outputs, and record in `burns`
+/// whatever part of the subsidy and fees the coinbase left unclaimed.
+///
+/// Returns the block's total fees. The checks run before anything is
+/// written, so a rejected block leaves `utxos` and `burns` untouched; a
+/// storage error while writing is reported as `BlockRejection::Invalid`.
+pub fn connect_block(
+    block: &BlockSubmission,
+    height: u64,
+    utxos: &mut dyn UTXOStorage,
+    emission: &EmissionSchedule,
+    coinbase_maturity: u32,
+    burns: &mut BurnLedger,
+) -> Result<u64, BlockRejection> {
+    let fees = check_block_inputs(block, height, utxos, emission, coinbase_maturity)?;
+    let storage_error = |e: UTXOError| BlockRejection::Invalid(e.to_string());
+
+    for tx in &block.transactions {
+        let txid = tx.txid();
+        for input in &tx.inputs {
+            utxos.spend_output(input, txid).map_err(storage_error)?;
+        }
+        for (outpoint, value) in &tx.outputs {
+            let output = TxOutput {
+                value: *value,
+                script_pubkey: Vec::new(),
+            };
+            utxos
+                .add_output(outpoint.clone(), output, height, is_coinbase(tx))
+                .map_err(storage_error)?;
+        }
+    }
+
+    // check_block_inputs bounds the claim by subsidy plus fees.
+    let claimed = block.coinbase().map_or(Ok(0), total_output_value)?;
+    let unclaimed = emission.block_reward(height) + fees - claimed;
+    if unclaimed > 0 {
+        let burned = burns.entry(height).or_default();
+        *burned = burned.saturating_add(unclaimed);
+    }
+    Ok(fees)
+}
+
+/// Check a committing header's `utxo_root` against `utxo_root`, the
+/// `UtxoCommitment` root of the set before the block is connected. Headers
+/// below `UTXO_COMMITMENT_VERSION` commit to nothing and always pass.
+pub fn check_utxo_commitment(header: &BlockHeader, utxo_root: &Hash) -> Result<(), BlockRejection> {
+    if header.commits_utxo_set() && header.utxo_root != *utxo_root {
+        return Err(BlockRejection::BadUtxoCommitment);
+    }
+    Ok(())
+}
+
+fn total_output_value(tx: &Transaction) -> Result<u64, BlockRejection> {
+    tx.outputs
+        .iter()
+        .try_fold(0u64, |total, (_, value)| total.checked_add(*value))
+        .ok_or_else(value_out_of_range)
+}
+
+fn value_out_of_range() -> BlockRejection {
+    BlockRejection::Invalid("bad-txns-txouttotal-toolarge".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MiningConfig;
+    use crate::database::utxo_commitment::CommittedUTXOStorage;
+    use crate::database::utxo_set::{
+        create_outpoint, hash_transaction, MemoryUTXOStorage, TxOutput,
+    };
+    use crate::mempool::Mempool;
+    use crate::mining::BlockTemplateBuilder;
+    use crate::network::protocol::UTXO_COMMITMENT_VERSION;
+
+    fn tip() -> ChainTip {
+        ChainTip {
+            hash: Hash([3u8; 64]),
+            height: 4,
+            median_time_past: 1_000,
+            bits: 0x207f_ffff,
+        }
+    }
+
+    fn solved(tip: &ChainTip) -> BlockSubmission {
+        let config = MiningConfig {
+            mining_address: "miner".to_string(),
+            mine_empty_blocks: true,
+            ..MiningConfig::default()
+        };
+        let mut template = BlockTemplateBuilder::new(&config)
+            .build(tip, &Mempool::default(), 2_000)
+            .unwrap();
+        while !check_proof_of_work(&template.header) {
+            template.header.nonce += 1;
+        }
+        template.submission()
+    }
+
+    /// Re-grind `block` after a header edit so only the edit is at fault.
+    fn regrind(mut block: BlockSubmission) -> BlockSubmission {
+        block.header.nonce = 0;
+        while !check_proof_of_work(&block.header) {
+            block.header.nonce += 1;
+        }
+        block
+    }
+
+    #[test]
+    fn accepts_a_solved_template() {
+        let block = solved(&tip());
+        assert_eq!(check_block(&block, &tip(), 2_000), Ok(()));
+        assert_eq!(block.block().tx_hashes.len(), 1);
+    }
+
+    #[test]
+    fn reports_precise_reasons() {
+        let tip = tip();
+        let good = solved(&tip);
+
+        let mut extended = tip;
+        extended.hash = good.hash();
+        assert_eq!(
+            check_block(&good, &extended, 2_000),
+            Err(BlockRejection::Duplicate)
+        );
+
+        let mut stale = tip;
+        stale.hash = Hash([9u8; 64]);
+        assert_eq!(
+            check_block(&good, &stale, 2_000).unwrap_err().reason(),
+            "inconclusive-not-best-prevblk"
+        );
+
+        let mut harder = tip;
+        harder.bits = 0x1f00_ffff;
+        assert_eq!(
+            check_block(&good, &harder, 2_000),
+            Err(BlockRejection::BadDiffBits)
+        );
+
+        let mut unsolved = good.clone();
+        while check_proof_of_work(&unsolved.header) {
+            unsolved.header.nonce += 1;
+        }
+        assert_eq!(
+            check_block(&unsolved, &tip, 2_000).unwrap_err().reason(),
+            "high-hash"
+        );
+
+        let mut old = good.clone();
+        old.header.time = tip.median_time_past;
+        assert_eq!(
+            check_block(&regrind(old), &tip, 2_000),
+            Err(BlockRejection::TimeTooOld)
+        );
+        let mut future = good.clone();
+        future.header.time = 2_000 + MAX_FUTURE_BLOCK_TIME + 1;
+        assert_eq!(
+            check_block(&regrind(future), &tip, 2_000),
+            Err(BlockRejection::TimeTooNew)
+        );
+
+        let mut extra = good.clone();
+        let spender = spend(&[&op("in")], &[]);
+        extra.transactions.push(spender.clone());
+        assert_eq!(
+            check_block(&regrind(extra.clone()), &tip, 2_000)
+                .unwrap_err()
+                .reason(),
+            "bad-txnmrklroot"
+        );
+
+        extra.transactions.push(spender);
+        extra.header.merkle_root = extra.merkle_root().unwrap();
+        assert_eq!(
+            check_block(&regrind(extra.clone()), &tip, 2_000),
+            Err(BlockRejection::DuplicateTransaction)
+        );
+
+        extra.transactions.swap(0, 1);
+        assert_eq!(
+            check_block(&regrind(extra), &tip, 2_000),
+            Err(BlockRejection::MissingCoinbase)
+        );
+
+        // CVE-2012-2459: repeating the odd last transaction keeps the root.
+        let mut three = good.clone();
+        three.transactions.push(spend(&[&op("x")], &[("x-out", 1)]));
+        three.transactions.push(spend(&[&op("y")], &[("y-out", 1)]));
+        three.header.merkle_root = three.merkle_root().unwrap();
+        let three = regrind(three);
+        assert_eq!(check_block(&three, &tip, 2_000), Ok(()));
+        let mut mutated = three.clone();
+        mutated.transactions.push(three.transactions[2].clone());
+        assert_eq!(mutated.merkle_root(), Some(three.header.merkle_root));
+        assert_eq!(mutated.hash(), three.hash());
+        assert_eq!(
+            check_block(&mutated, &tip, 2_000),
+            Err(BlockRejection::DuplicateTransaction)
+        );
+
+        let mut ahead = tip;
+        ahead.height += 1;
+        assert_eq!(
+            check_block(&solved(&ahead), &tip, 2_000)
+                .unwrap_err()
+                .reason(),
+            "bad-cb-height"
+        );
+    }
+
+    #[test]
+    fn memory_chain_estimates_hashrate_from_recent_spacing() {
+        let chain = MemoryChain::new(tip());
+        let difficulty = difficulty_from_bits(tip().bits);
+        assert_eq!(
+            chain.estimate_network_hashrate(),
+            network_hashrate(difficulty, 600.0)
+        );
+        for time in [2_000, 2_300, 2_600] {
+            let mut block = solved(&chain.tip());
+            block.header.time = time;
+            chain.process_block(&regrind(block)).unwrap();
+        }
+        assert_eq!(
+            chain.estimate_network_hashrate(),
+            network_hashrate(difficulty, 300.0)
+        );
+    }
+
+    #[test]
+    fn relayed_blocks_are_checked_without_chain_context() {
+        let good = solved(&tip()).block();
+        assert_eq!(check_relayed_block(&good), Ok(()));
+
+        let mut extra = good.clone();
+        extra.tx_hashes.push(Hash([7u8; 64]));
+        assert_eq!(
+            check_relayed_block(&extra),
+            Err(BlockRejection::BadMerkleRoot)
+        );
+
+        let mut unsolved = good;
+        while check_proof_of_work(&unsolved.header) {
+            unsolved.header.nonce += 1;
+        }
+        assert_eq!(
+            check_relayed_block(&unsolved),
+            Err(BlockRejection::HighHash)
+        );
+    }
+
+    fn op(tag: &str) -> OutPoint {
+        create_outpoint(Hash(hash_transaction(tag.as_bytes())), 0)
+    }
+
+    fn spend(inputs: &[&OutPoint], outputs: &[(&str, u64)]) -> Transaction {
+        Transaction {
+            inputs: inputs.iter().map(|&input| input.clone()).collect(),
+            outputs: outputs
+                .iter()
+                .map(|(tag, value)| (op(tag), *value))
+                .collect(),
+            replaceable: false,
+        }
+    }
+
+    #[test]
+    fn checks_inputs_maturity_and_coinbase_amount() {
+        let emission = EmissionSchedule::mainnet();
+        let tip = tip();
+        let height = tip.height + 1;
+        let mut utxos = MemoryUTXOStorage::new();
+        for (tag, coinbase) in [("plain", false), ("reward", true)] {
+            let output = TxOutput {
+                value: 1_000,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(op(tag), output, 1, coinbase).unwrap();
+        }
+
+        let with = |txs: Vec<Transaction>| {
+            let mut block = solved(&tip);
+            block.transactions.extend(txs);
+            block
+        };
+        let check = |block: &BlockSubmission, maturity| {
+            check_block_inputs(block, height, &utxos, &emission, maturity)
+        };
+
+        // A spend and its in-block child: fees are 100 + 50.
+        let parent = spend(&[&op("plain")], &[("a", 900)]);
+        let child = spend(&[&op("a")], &[("b", 850)]);
+        let mut block = with(vec![parent.clone(), child]);
+        assert_eq!(check(&block, 100), Ok(150));
+
+        block.transactions[0].outputs[0].1 += 150;
+        assert_eq!(check(&block, 100), Ok(150));
+        block.transactions[0].outputs[0].1 += 1;
+        assert_eq!(check(&block, 100), Err(BlockRejection::BadCoinbaseAmount));
+
+        let double = with(vec![parent, spend(&[&op("plain")], &[("c", 1)])]);
+        assert_eq!(
+            check(&double, 100),
+            Err(BlockRejection::MissingOrSpentInputs)
+        );
+        let missing = with(vec![spend(&[&op("nowhere")], &[("c", 1)])]);
+        assert_eq!(
+            check(&missing, 100),
+            Err(BlockRejection::MissingOrSpentInputs)
+        );
+        let inflating = with(vec![spend(&[&op("plain")], &[("c", 1_001)])]);
+        assert_eq!(
+            check(&inflating, 100).unwrap_err().reason(),
+            "bad-txns-in-belowout"
+        );
+
+        // The coinbase output was created at height 1; the block is at 5.
+        let early = with(vec![spend(&[&op("reward")], &[("c", 1_000)])]);
+        assert_eq!(
+            check(&early, 100).unwrap_err().reason(),
+            "bad-txns-premature-spend-of-coinbase"
+        );
+        assert_eq!(
+            check(&early, 5),
+            Err(BlockRejection::PrematureCoinbaseSpend)
+        );
+        assert_eq!(check(&early, 4), Ok(0));
+
+        // Nor may a block spend its own coinbase.
+        let own = block.transactions[0].outputs[0].0.clone();
+        let same_block = with(vec![spend(&[&own], &[("c", 1)])]);
+        assert_eq!(
+            check(&same_block, 1),
+            Err(BlockRejection::PrematureCoinbaseSpend)
+        );
+    }
+
+    #[test]
+    fn rejects_outputs_that_reuse_an_outpoint() {
+        let emission = EmissionSchedule::mainnet();
+        let tip = tip();
+        let height = tip.height + 1;
+        let mut utxos = MemoryUTXOStorage::new();
+        for tag in ["plain", "taken"] {
+            let output = TxOutput {
+                value: 1_000,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(op(tag), output, 1, false).unwrap();
+        }
+        let with = |txs: Vec<Transaction>| {
+            let mut block = solved(&tip);
+            block.transactions.extend(txs);
+            block
+        };
+
+        let live = with(vec![spend(&[&op("plain")], &[("taken", 900)])]);
+        let twice = with(vec![
+            spend(&[&op("plain")], &[("a", 900)]),
+            spend(&[&op("taken")], &[("a", 900)]),
+        ]);
+        for block in [live, twice] {
+            assert_eq!(
+                check_block_inputs(&block, height, &utxos, &emission, 100)
+                    .unwrap_err()
+                    .reason(),
+                "bad-txns-duplicate-output"
+            );
+            let mut burns = BurnLedger::new();
+            assert_eq!(
+                connect_block(&block, height, &mut utxos, &emission, 100, &mut burns),
+                Err(BlockRejection::DuplicateOutput)
+            );
+            assert_eq!(utxos.outputs.len(), 2);
+            assert!(utxos.get_output(&op("plain")).unwrap().is_some());
+            assert!(burns.is_empty());
+        }
+    }
+
+    #[test]
+    fn committed_utxo_root_must_match_the_parent_set() {
+        let mut utxos = CommittedUTXOStorage::new(MemoryUTXOStorage::new()).unwrap();
+        let output = TxOutput {
+            value: 1_000,
+            script_pubkey: vec![],
+        };
+        utxos.add_output(op("plain"), output, 1, false).unwrap();
+
+        let v1 = solved(&tip());
+        assert_eq!(check_utxo_commitment(&v1.header, &utxos.root()), Ok(()));
+
+        let mut block = v1.clone();
+        block.header.version = UTXO_COMMITMENT_VERSION;
+        block.header.utxo_root = utxos.root();
+        assert_eq!(check_utxo_commitment(&block.header, &utxos.root()), Ok(()));
+        assert_eq!(
+            check_utxo_commitment(&block.header, &Hash::zero()),
+            Err(BlockRejection::BadUtxoCommitment)
+        );
+        assert_eq!(
+            BlockRejection::BadUtxoCommitment.reason(),
+            "bad-utxo-commitment"
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Block template builder.
+//!
+//! Transactions are taken from the mempool as ancestor packages (see
+//! `Mempool::ancestor_packages`), best ancestor feerate first, until the
+//! block size or sigop budget runs out. Space for the header and coinbase is
+//! reserved up front.
+
+use std::fmt;
+
+use super::submit::BlockSubmission;
+use crate::blockchain::merkle::MerkleTree;
+use crate::config::MiningConfig;
+use crate::database::utxo_set::{create_outpoint, hash_transaction, TxOutput};
+use crate::mempool::{transaction_size, Mempool, MempoolError};
+use crate::network::protocol::{Block, BlockHeader, Hash, Transaction, UTXO_COMMITMENT_VERSION};
+
+/// Header version produced by the builder.
+pub const BLOCK_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    /// `MiningConfig::mining_address` is empty.
+    NoMiningAddress,
+    /// The mempool had nothing to include and empty blocks are disabled.
+    NoTransactions,
+    /// Header plus coinbase alone exceed `MiningConfig::block_size_limit`.
+    BlockSizeLimit(usize),
+    Mempool(MempoolError),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::NoMiningAddress => write!(f, "No mining address configured"),
+            TemplateError::NoTransactions => {
+                write!(f, "No transactions to mine and empty blocks are disabled")
+            }
+            TemplateError::BlockSizeLimit(limit) => {
+                write!(
+                    f,
+                    "Block size limit {} leaves no room for a coinbase",
+                    limit
+                )
+            }
+            TemplateError::Mempool(e) => write!(f, "Mempool error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<MempoolError> for TemplateError {
+    fn from(error: MempoolError) -> Self {
+        TemplateError::Mempool(error)
+    }
+}
+
+/// What the builder needs to know about the block being extended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainTip {
+    pub hash: Hash,
+    pub height: u64,
+    /// Median timestamp of the last blocks; a new block must be later.
+    pub median_time_past: u32,
+    /// Compact target the next block must meet.
+    pub bits: u32,
+}
+
+/// A block ready for proof-of-work: only `header.nonce` remains to be found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTemplate {
+    pub header: BlockHeader,
+    pub height: u64,
+    pub coinbase: Transaction,
+    /// Locking script of the coinbase output (the mining address).
+    pub payout_script: Vec<u8>,
+    /// Extra nonce committed in the coinbase (see `with_extra_nonce`).
+    pub extra_nonce: u64,
+    /// Non-coinbase transactions, parents before children.
+    pub transactions: Vec<Transaction>,
+    /// Subsidy at `height`.
+    pub reward: u64,
+    /// Summed fees of `transactions`.
+    pub fees: u64,
+    /// Serialized size of header, coinbase and transactions. The header is
+    /// counted with room for a UTXO commitment (see `with_utxo_commitment`).
+    pub size: usize,
+    pub sigops: usize,
+}
+
+impl BlockTemplate {
+    /// Coinbase first, then the selected transactions.
+    pub fn all_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        std::iter::once(&self.coinbase).chain(self.transactions.iter())
+    }
+
+    /// The coinbase output as it enters the UTXO set.
+    pub fn coinbase_output(&self) -> TxOutput {
+        TxOutput {
+            value: self.reward + self.fees,
+            script_pubkey: self.payout_script.clone(),
+        }
+    }
+
+    /// Merkle root over the coinbase and transactions.
+    pub fn merkle_root(&self) -> Hash {
+        transactions_merkle_root(self.all_transactions()).expect("coinbase is always a leaf")
+    }
+
+    /// The same template with a different coinbase extra nonce, giving a
+    /// fresh header nonce space once the current one is exhausted.
+    pub fn with_extra_nonce(&self, extra_nonce: u64) -> BlockTemplate {
+        let mut template = self.clone();
+        template.extra_nonce = extra_nonce;
+        template.coinbase = build_coinbase(
+            self.height,
+            &self.payout_script,
+            self.reward.saturating_add(self.fees),
+            extra_nonce,
+        );
+        template.header.merkle_root = template.merkle_root();
+        template.header.nonce = 0;
+        template
+    }
+
+    /// The same template committing to `utxo_root`, the `UtxoCommitment`
+    /// root of the set the block builds on. Raises the header version to
+    /// `UTXO_COMMITMENT_VERSION` if needed.
+    pub fn with_utxo_commitment(&self, utxo_root: Hash) -> BlockTemplate {
+        let mut template = self.clone();
+        template.header.version = template.header.version.max(UTXO_COMMITMENT_VERSION);
+        template.header.utxo_root = utxo_root;
+        template.header.nonce = 0;
+        template
+    }
+
+    /// The block with the current header.
+    pub fn block(&self) -> Block {
+        Block {
+            header: self.header.clone(),
+            tx_hashes: self.all_transactions().map(Transaction::txid).collect(),
+        }
+    }
+
+    /// The block with the current header and full transactions, as handed
+    /// to the chain engine.
+    pub fn submission(&self) -> BlockSubmission {
+        BlockSubmission {
+            header: self.header.clone(),
+            transactions: self.all_transactions().cloned().collect(),
+        }
+    }
+}
+
+/// Merkle root over `transactions` in order, `None` when there are none.
+pub fn transactions_merkle_root<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+) -> Option<Hash> {
+    let leaves: Vec<[u8; 64]> = transactions
+        .into_iter()
+        .map(|tx| tx.txid().into_bytes())
+        .collect();
+    MerkleTree::new(&leaves).ok().map(|tree| Hash(tree.root()))
+}
+
+/// Coinbase for `height` paying `value` to `address`.
+///
+/// This transaction form has no scripts, so the payee is committed through
+/// the output's outpoint:
+/// `SHA-512("coinbase" || height || extra_nonce || address)`. That also makes
+/// every coinbase unique per height. The outpoint's index carries the height
+/// in the clear (our analogue of BIP34), so validators can check it without
+/// knowing the payee; see `coinbase_height`.
+pub fn coinbase_transaction(height: u64, address: &str, value: u64) -> Transaction {
+    build_coinbase(height, address.as_bytes(), value, 0)
+}
+
+/// Height a coinbase commits to: the index of its first output's outpoint.
+/// `None` if it has no outputs.
+pub fn coinbase_height(coinbase: &Transaction) -> Option<u64> {
+    coinbase
+        .outputs
+        .first()
+        .map(|(outpoint, _)| u64::from(outpoint.index))
+}
+
+/// Bytes hashed into the coinbase outpoint on either side of the 8-byte
+/// little-endian extra nonce.
+pub fn coinbase_tag_parts(height: u64, payee: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut prefix = b"coinbase".to_vec();
+    prefix.extend_from_slice(&height.to_le_bytes());
+    (prefix, payee.to_vec())
+}
+
+fn build_coinbase(height: u64, payee: &[u8], value: u64, extra_nonce: u64) -> Transaction {
+    let (mut tag, suffix) = coinbase_tag_parts(height, payee);
+    tag.extend_from_slice(&extra_nonce.to_le_bytes());
+    tag.extend_from_slice(&suffix);
+    // Heights past u32::MAX cannot be committed; check_block rejects them.
+    let outpoint = create_outpoint(Hash(hash_transaction(&tag)), height as u32);
+
+    Transaction {
+        inputs: vec![],
+        outputs: vec![(outpoint, value)],
+        replaceable: false,
+    }
+}
+
+/// Builds block templates from `MiningConfig` limits.
+#[derive(Debug, Clone)]
+pub struct BlockTemplateBuilder {
+    config: MiningConfig,
+}
+
+impl BlockTemplateBuilder {
+    pub fn new(config: &MiningConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub fn config(&self) -> &MiningConfig {
+        &self.config
+    }
+
+    /// Build a template extending `tip` from the contents of `pool`.
+    ///
+    /// `now` is the current Unix time; the header time is bumped past the
+    /// tip's median time if the local clock is behind it.
+    pub fn build(
+        &self,
+        tip: &ChainTip,
+        pool: &Mempool,
+        now: u32,
+    ) -> Result<BlockTemplate, TemplateError> {
+        if self.config.mining_address.is_empty() {
+            return Err(TemplateError::NoMiningAddress);
+        }
+        let height = tip.height + 1;
+        let reward = self.config.emission.block_reward(height);
+
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            prev_block: tip.hash,
+            merkle_root: Hash([0u8; 64]),
+            time: now.max(tip.median_time_past.saturating_add(1)),
+            bits: tip.bits,
+            nonce: 0,
+            utxo_root: Hash::zero(),
+        };
+
+        // Fixed-width encoding: the coinbase size does not depend on its value.
+        let coinbase_size = transaction_size(&coinbase_transaction(
+            height,
+            &self.config.mining_address,
+            0,
+        ))?;
+        let committing = BlockHeader {
+            version: UTXO_COMMITMENT_VERSION,
+            ..header.clone()
+        };
+        let header_size = bincode::serialized_size(&committing)
+            .map_err(|e| MempoolError::SerializationError(e.to_string()))?
+            as usize;
+        let reserved = header_size + coinbase_size;
+        let budget = self
+            .config
+            .block_size_limit
+            .checked_sub(reserved)
+            .ok_or(TemplateError::BlockSizeLimit(self.config.block_size_limit))?;
+
+        let mut transactions = Vec::new();
+        let mut fees = 0u64;
+        let mut packages = pool
+            .ancestor_packages(budget)
+            .with_max_sigops(self.config.max_block_sigops);
+        for package in packages.by_ref() {
+            for entry in package.entries {
+                fees = fees.saturating_add(entry.fee);
+                transactions.push(entry.tx.clone());
+            }
+        }
+        let size = reserved + (budget - packages.remaining_size());
+        let sigops = self.config.max_block_sigops - packages.remaining_sigops();
+
+        if transactions.is_empty() && !self.config.mine_empty_blocks {
+            return Err(TemplateError::NoTransactions);
+        }
+
+        let coinbase = coinbase_transaction(
+            height,
+            &self.config.mining_address,
+            reward.saturating_add(fees),
+        );
+        let mut template = BlockTemplate {
+            header,
+            height,
+            coinbase,
+            payout_script: self.config.mining_address.as_bytes().to_vec(),
+            extra_nonce: 0,
+            transactions,
+            reward,
+            fees,
+            size,
+            sigops,
+        };
+        template.header.merkle_root = template.merkle_root();
+        Ok(template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::reward::{calculate_block_reward, EmissionSchedule};
+    use crate::config::NetworkType;
+    use crate::database::utxo_set::{MemoryUTXOStorage, UTXOStorage};
+    use crate::mempool::MempoolConfig;
+    use crate::test_util::{op, tx};
+
+    fn pool_with(spends: &[(&str, u64)]) -> Mempool {
+        let mut utxos = MemoryUTXOStorage::new();
+        let mut pool = Mempool::new(MempoolConfig::default());
+        for (tag, fee) in spends {
+            let out = TxOutput {
+                value: 100_000,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(op(tag, 0), out, 1, false).unwrap();
+            let spend = tx(
+                vec![op(tag, 0)],
+                vec![(op(&format!("{}-out", tag), 0), 100_000 - fee)],
+            );
+            pool.accept_transaction(spend, &utxos).unwrap();
+        }
+        pool
+    }
+
+    fn mining_config() -> MiningConfig {
+        MiningConfig {
+            mining_address: "miner".to_string(),
+            ..MiningConfig::default()
+        }
+    }
+
+    fn tip() -> ChainTip {
+        ChainTip {
+            hash: Hash([7u8; 64]),
+            height: 10,
+            median_time_past: 1_000,
+            bits: 0x207f_ffff,
+        }
+    }
+
+    #[test]
+    fn template_pays_reward_plus_fees_and_commits_transactions() {
+        let pool = pool_with(&[("a", 2_000), ("b", 5_000)]);
+        let builder = BlockTemplateBuilder::new(&mining_config());
+
+        let template = builder.build(&tip(), &pool, 900).unwrap();
+        assert_eq!(template.height, 11);
+        assert_eq!(template.header.prev_block, tip().hash);
+        assert_eq!(template.header.bits, tip().bits);
+        assert_eq!(template.header.time, 1_001);
+        assert_eq!(template.transactions.len(), 2);
+        // Higher feerate first.
+        assert_eq!(template.transactions[0].outputs[0].1, 95_000);
+        assert_eq!(template.fees, 7_000);
+        assert_eq!(template.reward, calculate_block_reward(11));
+        assert_eq!(template.coinbase.outputs[0].1, template.reward + 7_000);
+        assert_eq!(coinbase_height(&template.coinbase), Some(11));
+        assert_eq!(template.coinbase_output().script_pubkey, b"miner".to_vec());
+        assert_eq!(template.header.merkle_root, template.block().merkle_root());
+        assert_eq!(template.sigops, 2);
+    }
+
+    #[test]
+    fn respects_size_and_sigop_limits() {
+        let pool = pool_with(&[("a", 2_000), ("b", 5_000), ("c", 9_000)]);
+
+        let config = MiningConfig {
+            max_block_sigops: 2,
+            ..mining_config()
+        };
+        let template = BlockTemplateBuilder::new(&config)
+            .build(&tip(), &pool, 2_000)
+            .unwrap();
+        assert_eq!(template.transactions.len(), 2);
+        assert_eq!(template.fees, 14_000);
+
+        let empty = BlockTemplateBuilder::new(&mining_config())
+            .build(&tip(), &Mempool::default(), 2_000)
+            .unwrap_err();
+        assert_eq!(empty, TemplateError::NoTransactions);
+
+        let config = MiningConfig {
+            mine_empty_blocks: true,
+            block_size_limit: 1,
+            ..mining_config()
+        };
+        assert_eq!(
+            BlockTemplateBuilder::new(&config).build(&tip(), &pool, 2_000),
+            Err(TemplateError::BlockSizeLimit(1))
+        );
+    }
+
+    #[test]
+    fn empty_block_when_allowed() {
+        let config = MiningConfig {
+            mine_empty_blocks: true,
+            ..mining_config()
+        };
+        let template = BlockTemplateBuilder::new(&config)
+            .build(&tip(), &Mempool::default(), 2_000)
+            .unwrap();
+        assert!(template.transactions.is_empty());
+        assert_eq!(template.header.time, 2_000);
+        assert_eq!(template.block().tx_hashes, vec![template.coinbase.txid()]);
+        assert_eq!(
+            template.header.merkle_root,
+            template.coinbase.txid(),
+            "a single leaf is its own root"
+        );
+
+        let committed = template.with_utxo_commitment(Hash([9u8; 64]));
+        assert!(committed.header.commits_utxo_set());
+        assert_eq!(committed.header.utxo_root, Hash([9u8; 64]));
+        assert_eq!(committed.size, template.size);
+        let rolled = committed.with_extra_nonce(7);
+        assert_eq!(rolled.header.utxo_root, committed.header.utxo_root);
+
+        let rolled = template.with_extra_nonce(7);
+        assert_ne!(rolled.coinbase.txid(), template.coinbase.txid());
+        assert_eq!(coinbase_height(&rolled.coinbase), Some(11));
+        assert_eq!(rolled.header.merkle_root, rolled.coinbase.txid());
+        assert_eq!(rolled.coinbase_output(), template.coinbase_output());
+
+        let regtest = MiningConfig {
+            emission: EmissionSchedule::for_network(&NetworkType::Regtest),
+            ..config.clone()
+        };
+        let template = BlockTemplateBuilder::new(&regtest)
+            .build(&tip(), &Mempool::default(), 2_000)
+            .unwrap();
+        assert_eq!(template.reward, regtest.emission.block_reward(11));
+        assert!(template.reward > calculate_block_reward(11));
+
+        let config = MiningConfig {
+            mining_address: String::new(),
+            ..config
+        };
+        assert_eq!(
+            BlockTemplateBuilder::new(&config).build(&tip(), &Mempool::default(), 2_000),
+            Err(TemplateError::NoMiningAddress)
+        );
+    }
+}
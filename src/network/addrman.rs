@@ -0,0 +1,486 @@
+//! Address manager: the peer addresses we know of and how they fared.
+//!
+//! Addresses start in the *new* table and move to the *tried* table once a
+//! connection to them succeeds. Each table is split into buckets of
+//! `BUCKET_SIZE` slots, and an address can only ever occupy one slot,
+//! chosen by a keyed hash:
+//!
+//! - a new address's bucket depends on the network group of the peer that
+//!   told us about it, so one source group reaches at most 64 of the
+//!   `NEW_BUCKET_COUNT` buckets however many addresses it sends;
+//! - a tried address's bucket depends on its own network group, which gets
+//!   at most 8 of the `TRIED_BUCKET_COUNT` buckets.
+//!
+//! The key is random per node (and kept in `peers.json`), so an attacker
+//! cannot aim addresses at chosen slots. An address landing on an occupied
+//! new slot only replaces the occupant if that one is stale
+//! (`AddrInfo::is_terrible`); an address promoted to an occupied tried slot
+//! pushes the occupant back to the new table. Together this keeps a flood
+//! of addresses from one network from crowding out everything else.
+//!
+//! `select` picks from either table with equal odds, weighting entries
+//! against recent failures and, among tried ones, toward recent success.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use super::peer_manager::NetworkGroup;
+
+pub const NEW_BUCKET_COUNT: usize = 1_024;
+pub const TRIED_BUCKET_COUNT: usize = 256;
+pub const BUCKET_SIZE: usize = 64;
+
+/// Most addresses in one `addr` message, and in one `getaddr` answer.
+pub const MAX_ADDR_TO_SEND: usize = 1_000;
+
+/// Share of known addresses handed out per `getaddr`, in percent.
+const GETADDR_PERCENT: usize = 23;
+
+/// Addresses unseen for this long are stale (30 days).
+const HORIZON_SECS: u64 = 30 * 86_400;
+
+/// An address attempted this recently is unlikely to be picked again.
+const RETRY_SECS: u64 = 10 * 60;
+
+const PEERS_FILE_VERSION: u32 = 1;
+
+/// What we know about one address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddrInfo {
+    pub addr: SocketAddr,
+    pub services: u64,
+    /// Address of the peer that told us about it.
+    pub source: IpAddr,
+    /// Last time the address was announced or connected to (Unix time).
+    pub last_seen: u64,
+    pub last_attempt: u64,
+    pub last_success: u64,
+    /// Failed attempts since the last success.
+    pub attempts: u32,
+    /// Whether the address is in the tried table.
+    pub tried: bool,
+}
+
+impl AddrInfo {
+    /// Whether the address is not worth keeping: unseen for a month, dated
+    /// in the future, or failing without (recent) success.
+    pub fn is_terrible(&self, now: u64) -> bool {
+        if self.last_attempt.saturating_add(60) >= now {
+            return false;
+        }
+        if self.last_seen > now.saturating_add(10 * 60) {
+            return true;
+        }
+        if now.saturating_sub(self.last_seen) > HORIZON_SECS {
+            return true;
+        }
+        if self.last_success == 0 && self.attempts >= 3 {
+            return true;
+        }
+        now.saturating_sub(self.last_success) > 7 * 86_400 && self.attempts >= 10
+    }
+
+    /// Relative odds of being picked by `select`.
+    pub fn chance(&self, now: u64) -> f64 {
+        let mut chance = 0.66f64.powi(self.attempts.min(8) as i32);
+        if now.saturating_sub(self.last_attempt) < RETRY_SECS {
+            chance *= 0.01;
+        }
+        if self.tried {
+            let days = now.saturating_sub(self.last_success) / 86_400;
+            chance /= 1.0 + days as f64;
+        }
+        chance
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PeersFile {
+    version: u32,
+    key: u64,
+    addresses: Vec<AddrInfo>,
+}
+
+type Slot = (usize, usize);
+
+/// New and tried address tables; see the module docs.
+#[derive(Debug, Clone)]
+pub struct AddrMan {
+    key: u64,
+    entries: HashMap<SocketAddr, AddrInfo>,
+    new_table: HashMap<Slot, SocketAddr>,
+    tried_table: HashMap<Slot, SocketAddr>,
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AddrMan {
+    pub fn new() -> Self {
+        Self::with_key(rand::random())
+    }
+
+    /// Manager with a fixed bucketing key, for reproducible placement.
+    pub(super) fn with_key(key: u64) -> Self {
+        Self {
+            key,
+            entries: HashMap::new(),
+            new_table: HashMap::new(),
+            tried_table: HashMap::new(),
+        }
+    }
+
+    /// Load the tables saved at `path`, or start empty if there are none.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e),
+        };
+        let file: PeersFile = serde_json::from_slice(&data)?;
+        if file.version != PEERS_FILE_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported peers file version {}", file.version),
+            ));
+        }
+        let mut addrman = Self::with_key(file.key);
+        for info in file.addresses {
+            addrman.restore(info);
+        }
+        Ok(addrman)
+    }
+
+    /// Write the tables to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut addresses: Vec<AddrInfo> = self.entries.values().cloned().collect();
+        addresses.sort_by_key(|info| info.addr);
+        let file = PeersFile {
+            version: PEERS_FILE_VERSION,
+            key: self.key,
+            addresses,
+        };
+        let bytes = serde_json::to_vec(&file)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.new");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn new_count(&self) -> usize {
+        self.new_table.len()
+    }
+
+    pub fn tried_count(&self) -> usize {
+        self.tried_table.len()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddrInfo> {
+        self.entries.get(addr)
+    }
+
+    /// Learn `addr` from `source`. Returns whether it is new to us; a known
+    /// address only has its `last_seen` and services refreshed.
+    pub fn add(
+        &mut self,
+        addr: SocketAddr,
+        services: u64,
+        source: IpAddr,
+        last_seen: u64,
+        now: u64,
+    ) -> bool {
+        if addr.ip().is_unspecified() || addr.port() == 0 {
+            return false;
+        }
+        let last_seen = last_seen.min(now);
+        if let Some(info) = self.entries.get_mut(&addr) {
+            info.last_seen = info.last_seen.max(last_seen);
+            info.services |= services;
+            return false;
+        }
+
+        let slot = self.new_slot(&addr, &source);
+        if let Some(occupant) = self.new_table.get(&slot).copied() {
+            if !self.entries[&occupant].is_terrible(now) {
+                return false;
+            }
+            self.entries.remove(&occupant);
+        }
+        self.new_table.insert(slot, addr);
+        self.entries.insert(
+            addr,
+            AddrInfo {
+                addr,
+                services,
+                source,
+                last_seen,
+                last_attempt: 0,
+                last_success: 0,
+                attempts: 0,
+                tried: false,
+            },
+        );
+        true
+    }
+
+    /// Record a connection attempt to `addr`.
+    pub fn attempt(&mut self, addr: &SocketAddr, now: u64) {
+        if let Some(info) = self.entries.get_mut(addr) {
+            info.last_attempt = now;
+            info.attempts = info.attempts.saturating_add(1);
+        }
+    }
+
+    /// Record a successful connection to `addr`, moving it to the tried
+    /// table.
+    pub fn good(&mut self, addr: &SocketAddr, now: u64) {
+        let Some(info) = self.entries.get_mut(addr) else {
+            return;
+        };
+        info.last_seen = now;
+        info.last_attempt = now;
+        info.last_success = now;
+        info.attempts = 0;
+        if info.tried {
+            return;
+        }
+        info.tried = true;
+        let source = info.source;
+
+        let slot = self.new_slot(addr, &source);
+        if self.new_table.get(&slot) == Some(addr) {
+            self.new_table.remove(&slot);
+        }
+        let slot = self.tried_slot(addr);
+        if let Some(evicted) = self.tried_table.insert(slot, *addr) {
+            self.demote(evicted);
+        }
+    }
+
+    /// Move `addr` from the tried table back to the new one.
+    fn demote(&mut self, addr: SocketAddr) {
+        let Some(info) = self.entries.get_mut(&addr) else {
+            return;
+        };
+        info.tried = false;
+        let source = info.source;
+        let slot = self.new_slot(&addr, &source);
+        if let Some(occupant) = self.new_table.insert(slot, addr) {
+            self.entries.remove(&occupant);
+        }
+    }
+
+    /// Put a saved entry back in its slot, dropping it if the slot is taken.
+    fn restore(&mut self, info: AddrInfo) {
+        let addr = info.addr;
+        let (table, slot) = if info.tried {
+            let slot = self.tried_slot(&addr);
+            (&mut self.tried_table, slot)
+        } else {
+            let slot = self.new_slot(&addr, &info.source);
+            (&mut self.new_table, slot)
+        };
+        if table.contains_key(&slot) || self.entries.contains_key(&addr) {
+            return;
+        }
+        table.insert(slot, addr);
+        self.entries.insert(addr, info);
+    }
+
+    /// An address to try connecting to, if any are known.
+    pub fn select(&self, now: u64) -> Option<SocketAddr> {
+        self.select_with(&mut rand::thread_rng(), now)
+    }
+
+    fn select_with<R: Rng>(&self, rng: &mut R, now: u64) -> Option<SocketAddr> {
+        let use_tried = match (self.tried_table.is_empty(), self.new_table.is_empty()) {
+            (true, true) => return None,
+            (false, true) => true,
+            (true, false) => false,
+            (false, false) => rng.gen_bool(0.5),
+        };
+        let table: Vec<&SocketAddr> = if use_tried {
+            self.tried_table.values().collect()
+        } else {
+            self.new_table.values().collect()
+        };
+        // Each rejection makes the next pick likelier, so this ends quickly
+        // even when every entry has a low chance.
+        let mut factor = 1.0;
+        loop {
+            let addr = table[rng.gen_range(0..table.len())];
+            if rng.gen::<f64>() < factor * self.entries[addr].chance(now) {
+                return Some(*addr);
+            }
+            factor *= 1.2;
+        }
+    }
+
+    /// A random sample of addresses worth sharing, for a `getaddr` answer.
+    pub fn get_addr(&self, now: u64) -> Vec<AddrInfo> {
+        let mut addresses: Vec<&AddrInfo> = self
+            .entries
+            .values()
+            .filter(|info| !info.is_terrible(now))
+            .collect();
+        addresses.shuffle(&mut rand::thread_rng());
+        let count = (self.entries.len() * GETADDR_PERCENT)
+            .div_ceil(100)
+            .min(MAX_ADDR_TO_SEND);
+        addresses.into_iter().take(count).cloned().collect()
+    }
+
+    fn new_slot(&self, addr: &SocketAddr, source: &IpAddr) -> Slot {
+        let group = NetworkGroup::of(&addr.ip());
+        let source_group = NetworkGroup::of(source);
+        let spread = self.hash(&[group.as_bytes(), source_group.as_bytes()]) % 64;
+        let bucket =
+            self.hash(&[source_group.as_bytes(), &spread.to_le_bytes()]) % NEW_BUCKET_COUNT as u64;
+        (bucket as usize, self.position(b'N', bucket, addr))
+    }
+
+    fn tried_slot(&self, addr: &SocketAddr) -> Slot {
+        let group = NetworkGroup::of(&addr.ip());
+        let spread = self.hash(&[&addr_bytes(addr)]) % 8;
+        let bucket =
+            self.hash(&[group.as_bytes(), &spread.to_le_bytes()]) % TRIED_BUCKET_COUNT as u64;
+        (bucket as usize, self.position(b'T', bucket, addr))
+    }
+
+    fn position(&self, table: u8, bucket: u64, addr: &SocketAddr) -> usize {
+        let hash = self.hash(&[&[table], &bucket.to_le_bytes(), &addr_bytes(addr)]);
+        (hash % BUCKET_SIZE as u64) as usize
+    }
+
+    fn hash(&self, parts: &[&[u8]]) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key.to_le_bytes());
+        for part in parts {
+            hasher.update((part.len() as u32).to_le_bytes());
+            hasher.update(part);
+        }
+        let digest = hasher.finalize();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+fn addr_bytes(addr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip() {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    bytes.extend_from_slice(&addr.port().to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn one_source_group_fills_few_new_buckets() {
+        let mut addrman = AddrMan::with_key(7);
+        let source: IpAddr = "198.51.100.1".parse().unwrap();
+        let mut added = 0;
+        for i in 0..20_000u32 {
+            let [_, _, c, d] = i.to_be_bytes();
+            let target = SocketAddr::new(IpAddr::from([20 + c % 100, d, c, d]), 8333);
+            if addrman.add(target, 1, source, NOW, NOW) {
+                added += 1;
+            }
+        }
+        let buckets: std::collections::HashSet<usize> = addrman
+            .new_table
+            .keys()
+            .map(|(bucket, _)| *bucket)
+            .collect();
+        assert!(buckets.len() <= 64);
+        assert_eq!(addrman.new_count(), added);
+        assert!(added <= 64 * BUCKET_SIZE);
+
+        // A known address is refreshed, not added twice.
+        let known = *addrman.new_table.values().next().unwrap();
+        assert!(!addrman.add(known, 4, source, NOW + 5, NOW + 5));
+        assert_eq!(addrman.get(&known).unwrap().services, 5);
+    }
+
+    #[test]
+    fn good_addresses_move_to_tried_and_persist() {
+        let mut addrman = AddrMan::with_key(1);
+        let source: IpAddr = "198.51.100.1".parse().unwrap();
+        let (good, bad) = (addr("203.0.113.1:8333"), addr("192.0.2.1:8333"));
+        assert!(addrman.add(good, 1, source, NOW, NOW));
+        assert!(addrman.add(bad, 1, source, NOW, NOW));
+
+        addrman.attempt(&good, NOW);
+        addrman.good(&good, NOW);
+        assert!(addrman.get(&good).unwrap().tried);
+        assert_eq!((addrman.new_count(), addrman.tried_count()), (1, 1));
+
+        for _ in 0..3 {
+            addrman.attempt(&bad, NOW - 3600);
+        }
+        assert!(addrman.get(&bad).unwrap().is_terrible(NOW));
+        assert!(!addrman.get(&good).unwrap().is_terrible(NOW));
+        assert_eq!(
+            addrman.get_addr(NOW),
+            vec![addrman.get(&good).unwrap().clone()]
+        );
+
+        // Among tried addresses, recent success wins most picks.
+        let mut tried = AddrMan::with_key(2);
+        let old = addr("198.51.100.9:8333");
+        for (target, success) in [(good, NOW), (old, NOW - 30 * 86_400)] {
+            tried.add(target, 1, source, success, success);
+            tried.good(&target, success);
+        }
+        assert_eq!(tried.tried_count(), 2);
+        let mut rng = StdRng::seed_from_u64(3);
+        let picks = (0..200)
+            .filter(|_| tried.select_with(&mut rng, NOW + RETRY_SECS) == Some(good))
+            .count();
+        assert!(picks > 150, "{}", picks);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peers.json");
+        addrman.save(&path).unwrap();
+        let loaded = AddrMan::load(&path).unwrap();
+        assert_eq!(loaded.key, addrman.key);
+        assert_eq!(loaded.entries, addrman.entries);
+        assert_eq!(loaded.tried_table, addrman.tried_table);
+        assert!(AddrMan::load(&dir.path().join("missing.json"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(AddrMan::new().select(NOW), None);
+    }
+}
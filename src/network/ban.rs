@@ -0,0 +1,207 @@
+//! Peer misbehavior and the ban list.
+//!
+//! Protocol violations add a `Misbehavior::score` to the offending peer;
+//! once its total reaches `NetworkConfig::ban_threshold` the peer's IP is
+//! banned for `NetworkConfig::ban_duration`. Bans can also be set and lifted
+//! by hand. The list survives restarts in `banlist.json` in the data
+//! directory; expired entries are dropped on load and by `sweep_expired`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::time::now_secs;
+
+/// Ban list file name inside the data directory.
+pub const BANLIST_FILE: &str = "banlist.json";
+
+/// A protocol violation a peer can be scored for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Misbehavior {
+    /// A message whose payload does not match its checksum.
+    BadChecksum,
+    /// A message over the size limit for its command.
+    OversizedMessage,
+    /// A block that fails validation.
+    InvalidBlock,
+    /// A transaction that fails consensus checks.
+    InvalidTransaction,
+    /// Data we never asked for, or a repeated handshake message.
+    UnsolicitedData,
+}
+
+impl Misbehavior {
+    /// Points added to the peer's score; the default ban threshold is 100.
+    pub fn score(self) -> u32 {
+        match self {
+            Misbehavior::BadChecksum => 10,
+            Misbehavior::OversizedMessage => 20,
+            Misbehavior::InvalidBlock => 100,
+            Misbehavior::InvalidTransaction => 10,
+            Misbehavior::UnsolicitedData => 10,
+        }
+    }
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Misbehavior::BadChecksum => "bad checksum",
+            Misbehavior::OversizedMessage => "oversized message",
+            Misbehavior::InvalidBlock => "invalid block",
+            Misbehavior::InvalidTransaction => "invalid transaction",
+            Misbehavior::UnsolicitedData => "unsolicited data",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One banned address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub address: IpAddr,
+    /// Unix time the ban was set.
+    pub created: u64,
+    /// Unix time the ban lifts.
+    pub banned_until: u64,
+    pub reason: String,
+}
+
+/// Banned IP addresses with their expiry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BanList {
+    entries: HashMap<IpAddr, BanEntry>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the list saved at `path`, or start empty if there is none.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e),
+        };
+        let entries: Vec<BanEntry> = serde_json::from_slice(&data)?;
+        let mut list = Self {
+            entries: entries.into_iter().map(|e| (e.address, e)).collect(),
+        };
+        list.sweep_expired();
+        Ok(list)
+    }
+
+    /// Write the list to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(&self.entries())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.new");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Ban `address` for `duration` from now. An existing longer ban is
+    /// kept.
+    pub fn ban(&mut self, address: IpAddr, duration: Duration, reason: &str) {
+        let now = now_secs();
+        let banned_until = now.saturating_add(duration.as_secs());
+        let entry = self.entries.entry(address).or_insert_with(|| BanEntry {
+            address,
+            created: now,
+            banned_until,
+            reason: reason.to_string(),
+        });
+        if entry.banned_until < banned_until {
+            entry.created = now;
+            entry.banned_until = banned_until;
+            entry.reason = reason.to_string();
+        }
+    }
+
+    /// Lift the ban on `address`; returns whether there was one.
+    pub fn unban(&mut self, address: &IpAddr) -> bool {
+        self.entries.remove(address).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn is_banned(&self, address: &IpAddr) -> bool {
+        self.is_banned_at(address, now_secs())
+    }
+
+    pub fn is_banned_at(&self, address: &IpAddr, now: u64) -> bool {
+        self.entries
+            .get(address)
+            .is_some_and(|entry| entry.banned_until > now)
+    }
+
+    /// Drop bans that have run out; returns how many.
+    pub fn sweep_expired(&mut self) -> usize {
+        let now = now_secs();
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.banned_until > now);
+        before - self.entries.len()
+    }
+
+    /// Current bans, soonest to lift first.
+    pub fn entries(&self) -> Vec<BanEntry> {
+        let mut entries: Vec<BanEntry> = self.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| (entry.banned_until, entry.address));
+        entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_expire_and_persist() {
+        let mut list = BanList::new();
+        let bad: IpAddr = "203.0.113.7".parse().unwrap();
+        let stale: IpAddr = "203.0.113.8".parse().unwrap();
+        list.ban(bad, Duration::from_secs(3600), "invalid block");
+        list.ban(stale, Duration::ZERO, "test");
+        assert!(list.is_banned(&bad));
+        assert!(!list.is_banned(&stale));
+
+        // A shorter ban does not cut a longer one short.
+        let until = list.entries()[1].banned_until;
+        list.ban(bad, Duration::from_secs(60), "bad checksum");
+        assert_eq!(list.entries()[1].banned_until, until);
+        assert!(!list.is_banned_at(&bad, until));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(BANLIST_FILE);
+        list.save(&path).unwrap();
+        let loaded = BanList::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.entries()[0].reason, "invalid block");
+        assert!(BanList::load(&dir.path().join("missing.json"))
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(list.sweep_expired(), 1);
+        assert!(list.unban(&bad));
+        assert!(!list.unban(&bad));
+    }
+}
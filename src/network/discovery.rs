@@ -0,0 +1,463 @@
+//! Peer discovery: DNS seeds and bootnodes.
+//!
+//! A fresh node knows no one, so it asks the DNS seeds of its network
+//! (`NetworkType::dns_seeds`, or `NetworkConfig::dns_seeds` when
+//! overridden) for the addresses of reachable nodes, all of which listen on
+//! the network's `default_port`. The seeds are only queried while the
+//! `AddrMan` holds fewer than `MIN_KNOWN_ADDRESSES`; after that, address
+//! gossip takes over. `NetworkConfig::bootnodes` are added on every round
+//! regardless.
+//!
+//! Names are looked up through a `SeedResolver`: `DnsResolver` in the node,
+//! pointed at the system resolver or a given nameserver, or a stub in
+//! tests.
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::TokioAsyncResolver;
+
+use super::peer_manager::PeerManager;
+use crate::config::Config;
+
+/// The DNS seeds are queried while the `AddrMan` knows fewer addresses.
+pub const MIN_KNOWN_ADDRESSES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryError {
+    /// A bootnode that is neither a TCP multiaddr nor `host:port`.
+    InvalidBootnode(String),
+    Resolve {
+        host: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::InvalidBootnode(entry) => write!(f, "Invalid bootnode {:?}", entry),
+            DiscoveryError::Resolve { host, reason } => {
+                write!(f, "Failed to resolve {}: {}", host, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+/// A configured bootnode.
+///
+/// Written as a multiaddr, `/ip4/<ip>/tcp/<port>`, `/ip6/...` or
+/// `/dns[46]/<host>/tcp/<port>`, optionally followed by `/p2p/<id>`, which
+/// is ignored since our handshake does not authenticate peer ids. Plain
+/// `ip:port` and `host:port` are accepted too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bootnode {
+    Addr(SocketAddr),
+    /// Resolved like a DNS seed, on every round.
+    Dns {
+        host: String,
+        port: u16,
+    },
+}
+
+impl Bootnode {
+    fn parse_multiaddr(entry: &str) -> Option<Self> {
+        let mut parts = entry.split('/').skip(1);
+        let mut ip = None;
+        let mut host = None;
+        let mut port = None;
+        while let Some(protocol) = parts.next() {
+            let value = parts.next()?;
+            match protocol {
+                "ip4" => ip = Some(IpAddr::V4(value.parse().ok()?)),
+                "ip6" => ip = Some(IpAddr::V6(value.parse().ok()?)),
+                "dns" | "dns4" | "dns6" if !value.is_empty() => host = Some(value.to_string()),
+                "tcp" => port = Some(value.parse().ok()?),
+                "p2p" => {}
+                _ => return None,
+            }
+        }
+        match (ip, host, port?) {
+            (Some(ip), None, port) => Some(Bootnode::Addr(SocketAddr::new(ip, port))),
+            (None, Some(host), port) => Some(Bootnode::Dns { host, port }),
+            _ => None,
+        }
+    }
+
+    fn parse_host_port(entry: &str) -> Option<Self> {
+        if let Ok(addr) = entry.parse() {
+            return Some(Bootnode::Addr(addr));
+        }
+        let (host, port) = entry.rsplit_once(':')?;
+        if host.is_empty() || host.contains(':') {
+            return None;
+        }
+        Some(Bootnode::Dns {
+            host: host.to_string(),
+            port: port.parse().ok()?,
+        })
+    }
+}
+
+impl FromStr for Bootnode {
+    type Err = DiscoveryError;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let parsed = if entry.starts_with('/') {
+            Self::parse_multiaddr(entry)
+        } else {
+            Self::parse_host_port(entry)
+        };
+        parsed.ok_or_else(|| DiscoveryError::InvalidBootnode(entry.to_string()))
+    }
+}
+
+/// Looks up the IP addresses of a host name.
+#[async_trait]
+pub trait SeedResolver: Send + Sync {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, DiscoveryError>;
+}
+
+/// `SeedResolver` backed by trust-dns.
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsResolver {
+    /// Use the system's resolver configuration (`/etc/resolv.conf`).
+    pub fn from_system_conf() -> Result<Self, DiscoveryError> {
+        let resolver =
+            TokioAsyncResolver::tokio_from_system_conf().map_err(|e| DiscoveryError::Resolve {
+                host: "system resolver".to_string(),
+                reason: e.to_string(),
+            })?;
+        Ok(Self { resolver })
+    }
+
+    /// Send every query to `nameserver`, unencrypted.
+    pub fn with_nameserver(nameserver: SocketAddr) -> Result<Self, DiscoveryError> {
+        let servers =
+            NameServerConfigGroup::from_ips_clear(&[nameserver.ip()], nameserver.port(), true);
+        let config = ResolverConfig::from_parts(None, Vec::new(), servers);
+        let resolver = TokioAsyncResolver::tokio(config, ResolverOpts::default()).map_err(|e| {
+            DiscoveryError::Resolve {
+                host: nameserver.to_string(),
+                reason: e.to_string(),
+            }
+        })?;
+        Ok(Self { resolver })
+    }
+}
+
+#[async_trait]
+impl SeedResolver for DnsResolver {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, DiscoveryError> {
+        let lookup = self
+            .resolver
+            .lookup_ip(host)
+            .await
+            .map_err(|e| DiscoveryError::Resolve {
+                host: host.to_string(),
+                reason: e.to_string(),
+            })?;
+        Ok(lookup.iter().collect())
+    }
+}
+
+/// Feeds DNS seed results and bootnodes into the `PeerManager`'s `AddrMan`.
+pub struct Discovery {
+    seeds: Vec<String>,
+    bootnodes: Vec<Bootnode>,
+    /// Port of the addresses the seeds return.
+    port: u16,
+    interval: Duration,
+    resolver: Arc<dyn SeedResolver>,
+    peers: Arc<PeerManager>,
+}
+
+impl Discovery {
+    /// Fails if a configured bootnode does not parse.
+    pub fn new(
+        config: &Config,
+        peers: Arc<PeerManager>,
+        resolver: Arc<dyn SeedResolver>,
+    ) -> Result<Self, DiscoveryError> {
+        let network = &config.network_config;
+        let bootnodes = network
+            .bootnodes
+            .iter()
+            .map(|entry| entry.parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            seeds: network.dns_seeds.clone(),
+            bootnodes,
+            port: config.network.default_port(),
+            interval: network.peer_discovery_interval,
+            resolver,
+            peers,
+        })
+    }
+
+    pub fn bootnodes(&self) -> &[Bootnode] {
+        &self.bootnodes
+    }
+
+    /// Add the bootnodes to the `AddrMan`; returns how many were new.
+    pub async fn add_bootnodes(&self) -> usize {
+        let mut added = 0;
+        for bootnode in &self.bootnodes {
+            let addrs = match bootnode {
+                Bootnode::Addr(addr) => vec![*addr],
+                Bootnode::Dns { host, port } => self.lookup(host, *port).await,
+            };
+            added += self.add(addrs);
+        }
+        added
+    }
+
+    /// Query every DNS seed and add what they return to the `AddrMan`;
+    /// returns how many addresses were new. A seed that fails is logged
+    /// and skipped.
+    pub async fn query_seeds(&self) -> usize {
+        let lookups = self.seeds.iter().map(|seed| self.lookup(seed, self.port));
+        let results = join_all(lookups).await;
+        results.into_iter().map(|addrs| self.add(addrs)).sum()
+    }
+
+    /// One discovery round: the bootnodes, then the DNS seeds if we still
+    /// know too few addresses.
+    pub async fn discover(&self) -> usize {
+        let mut added = self.add_bootnodes().await;
+        if self.peers.address_count() < MIN_KNOWN_ADDRESSES {
+            added += self.query_seeds().await;
+        }
+        added
+    }
+
+    /// Run a discovery round every `peer_discovery_interval`, starting now.
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            let added = self.discover().await;
+            if added > 0 {
+                log::info!("Discovered {} new peer addresses", added);
+            }
+        }
+    }
+
+    async fn lookup(&self, host: &str, port: u16) -> Vec<SocketAddr> {
+        match self.resolver.resolve(host).await {
+            Ok(ips) => ips
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect(),
+            Err(e) => {
+                log::warn!("{}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn add(&self, addrs: Vec<SocketAddr>) -> usize {
+        addrs
+            .into_iter()
+            .filter(|addr| self.peers.add_address(*addr))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NetworkType;
+    use crate::network::addrman::AddrMan;
+    use crate::network::PeerManagerConfig;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use tokio::net::UdpSocket;
+
+    /// Answers from a fixed table.
+    struct StubResolver(HashMap<String, Vec<IpAddr>>);
+
+    #[async_trait]
+    impl SeedResolver for StubResolver {
+        async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, DiscoveryError> {
+            self.0
+                .get(host)
+                .cloned()
+                .ok_or_else(|| DiscoveryError::Resolve {
+                    host: host.to_string(),
+                    reason: "no such host".to_string(),
+                })
+        }
+    }
+
+    /// Peer manager whose address manager has a fixed key, so which seeds
+    /// share a bucket slot (and are dropped) does not vary between runs.
+    fn peer_manager(config: &Config, dir: &Path) -> Arc<PeerManager> {
+        let peers_file = dir.join("peers.json");
+        AddrMan::with_key(1).save(&peers_file).unwrap();
+        let mut config = PeerManagerConfig::from_config(config);
+        config.banlist_file = None;
+        config.peers_file = Some(peers_file);
+        PeerManager::new(config)
+    }
+
+    #[test]
+    fn parses_bootnodes() {
+        let addr = |s: &str| Bootnode::Addr(s.parse().unwrap());
+        let cases = [
+            (
+                "/ip4/18.144.1.23/tcp/8333/p2p/12D3KooWNode",
+                addr("18.144.1.23:8333"),
+            ),
+            ("/ip6/2001:db8::1/tcp/18333", addr("[2001:db8::1]:18333")),
+            ("203.0.113.5:8333", addr("203.0.113.5:8333")),
+            (
+                "/dns4/boot.example.org/tcp/8333",
+                Bootnode::Dns {
+                    host: "boot.example.org".to_string(),
+                    port: 8333,
+                },
+            ),
+            (
+                "boot.example.org:18444",
+                Bootnode::Dns {
+                    host: "boot.example.org".to_string(),
+                    port: 18444,
+                },
+            ),
+        ];
+        for (entry, expected) in cases {
+            assert_eq!(entry.parse::<Bootnode>().unwrap(), expected, "{}", entry);
+        }
+        for entry in [
+            "/ip4/0.0.0.0/tcp/8334/ws",
+            "/ip4/18.144.1.23/udp/8333",
+            "/ip4/18.144.1.23",
+            "/ip4/1.2.3/tcp/8333",
+            "/ip4/1.2.3.4/dns4/a.org/tcp/8333",
+            "boot.example.org",
+            "",
+        ] {
+            assert_eq!(
+                entry.parse::<Bootnode>(),
+                Err(DiscoveryError::InvalidBootnode(entry.to_string()))
+            );
+        }
+
+        // The bootnodes shipped in config/*.toml all parse.
+        for file in [
+            include_str!("../../config/mainnet.toml"),
+            include_str!("../../config/testnet.toml"),
+            include_str!("../../config/regtest.toml"),
+        ] {
+            let parsed: toml::Value = toml::from_str(file).unwrap();
+            for entry in parsed["network"]["bootnodes"].as_array().unwrap() {
+                entry.as_str().unwrap().parse::<Bootnode>().unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn feeds_seeds_and_bootnodes_into_the_address_manager() {
+        let mut config = Config::new(NetworkType::Regtest, None);
+        config.network_config.dns_seeds =
+            vec!["seed.test".to_string(), "dead-seed.test".to_string()];
+        config.network_config.bootnodes = vec![
+            "/ip4/198.51.100.1/tcp/18444/p2p/12D3KooWBoot".to_string(),
+            "/dns4/boot.test/tcp/18555".to_string(),
+        ];
+        let resolver = StubResolver(HashMap::from([
+            (
+                "seed.test".to_string(),
+                vec![
+                    "203.0.113.1".parse().unwrap(),
+                    "2001:db8::7".parse().unwrap(),
+                ],
+            ),
+            (
+                "boot.test".to_string(),
+                vec!["198.51.100.2".parse().unwrap()],
+            ),
+        ]));
+        let dir = tempfile::tempdir().unwrap();
+        let peers = peer_manager(&config, dir.path());
+        let discovery = Discovery::new(&config, peers.clone(), Arc::new(resolver)).unwrap();
+
+        assert_eq!(discovery.discover().await, 4);
+        assert_eq!(peers.address_count(), 4);
+        // Nothing new the second time round.
+        assert_eq!(discovery.discover().await, 0);
+
+        config
+            .network_config
+            .bootnodes
+            .push("/ip4/198.51.100.3/udp/1".to_string());
+        let resolver = Arc::new(StubResolver(HashMap::new()));
+        assert!(matches!(
+            Discovery::new(&config, peers, resolver),
+            Err(DiscoveryError::InvalidBootnode(_))
+        ));
+    }
+
+    /// Answers every A query with `ips` and every other query with nothing.
+    async fn stub_nameserver(ips: Vec<[u8; 4]>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let query = &buf[..len];
+                // Skip the header and the question's name labels.
+                let mut end = 12;
+                while end < len && query[end] != 0 {
+                    end += 1 + query[end] as usize;
+                }
+                end += 5;
+                if end > len {
+                    continue;
+                }
+                let is_a = query[end - 4..end - 2] == [0, 1];
+                let answers: &[[u8; 4]] = if is_a { &ips } else { &[] };
+
+                let mut reply = query[..2].to_vec();
+                reply.extend_from_slice(&[0x81, 0x80, 0, 1, 0, answers.len() as u8, 0, 0, 0, 0]);
+                reply.extend_from_slice(&query[12..end]);
+                for ip in answers {
+                    // Name pointer to the question, type A, class IN, TTL 60.
+                    reply.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                    reply.extend_from_slice(ip);
+                }
+                let _ = socket.send_to(&reply, from).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn dns_resolver_queries_the_given_nameserver() {
+        let nameserver = stub_nameserver(vec![[203, 0, 113, 10], [203, 0, 113, 11]]).await;
+        let resolver = DnsResolver::with_nameserver(nameserver).unwrap();
+        let mut ips = resolver.resolve("seed.btpc.test").await.unwrap();
+        ips.sort();
+        assert_eq!(
+            ips,
+            vec![
+                "203.0.113.10".parse::<IpAddr>().unwrap(),
+                "203.0.113.11".parse().unwrap(),
+            ]
+        );
+    }
+}
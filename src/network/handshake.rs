@@ -0,0 +1,431 @@
+//! Version/verack handshake.
+//!
+//! Both sides open with `version`. Each checks the other's: protocol
+//! version at least `HandshakeConfig::min_version`, every
+//! `required_services` bit set, and a nonce that is not one of our own (a
+//! connection to ourselves). A good `version` is answered with `verack`, and
+//! the handshake is complete once both a `version` and a `verack` have
+//! arrived, in that order. Any other message before then ends it.
+//!
+//! `Handshake` is the per-connection state machine and does no I/O;
+//! `perform_handshake` drives it over a `PeerStream` within
+//! `NetworkConfig::connection_timeout`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::protocol::{
+    NetAddr, NetworkMessage, PeerInfo, PublicKey, VerackMessage, VersionMessage, PROTOCOL_VERSION,
+};
+use super::transport::{PeerStream, TransportError};
+use crate::config::NetworkConfig;
+use crate::time::now_secs;
+
+/// Service bit: the node serves the full block chain.
+pub const NODE_NETWORK: u64 = 0x01;
+
+/// User agent sent in our `version`.
+pub const USER_AGENT: &str = concat!("/btpc:", env!("CARGO_PKG_VERSION"), "/");
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Transport(TransportError),
+    /// No complete handshake within the timeout.
+    Timeout,
+    /// The peer's `version` carried one of our own nonces.
+    SelfConnection,
+    /// The peer speaks an older protocol than we accept.
+    ObsoleteVersion(u32),
+    /// The peer lacks some of the required service bits.
+    MissingServices(u64),
+    /// A message other than the one the handshake expects, by command.
+    UnexpectedMessage(&'static str),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Transport(e) => write!(f, "{}", e),
+            HandshakeError::Timeout => write!(f, "Handshake timed out"),
+            HandshakeError::SelfConnection => write!(f, "Connected to self"),
+            HandshakeError::ObsoleteVersion(v) => write!(f, "Obsolete protocol version {}", v),
+            HandshakeError::MissingServices(s) => write!(f, "Missing services {:#x}", s),
+            HandshakeError::UnexpectedMessage(command) => {
+                write!(f, "Unexpected {} during handshake", command)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<TransportError> for HandshakeError {
+    fn from(error: TransportError) -> Self {
+        HandshakeError::Transport(error)
+    }
+}
+
+/// What we announce and what we require of peers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandshakeConfig {
+    /// Address we tell peers to reach us at.
+    pub local_addr: SocketAddr,
+    pub services: u64,
+    pub required_services: u64,
+    pub min_version: u32,
+    pub user_agent: String,
+    /// Whether the peer should relay transactions to us.
+    pub relay: bool,
+    pub timeout: Duration,
+}
+
+impl HandshakeConfig {
+    pub fn from_config(config: &NetworkConfig) -> Self {
+        Self {
+            local_addr: config.external_addr.unwrap_or(config.listen_addr),
+            services: NODE_NETWORK,
+            required_services: NODE_NETWORK,
+            min_version: PROTOCOL_VERSION,
+            user_agent: USER_AGENT.to_string(),
+            relay: true,
+            timeout: config.connection_timeout,
+        }
+    }
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self::from_config(&NetworkConfig::default())
+    }
+}
+
+/// Nonces of our own `version` messages still in flight, shared by every
+/// handshake of the node so a connection to ourselves is recognised from
+/// either end.
+#[derive(Debug, Default)]
+pub struct LocalNonces {
+    nonces: Mutex<HashSet<u64>>,
+}
+
+impl LocalNonces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A fresh nonce, registered until `release`.
+    pub fn issue(&self) -> u64 {
+        let mut nonces = self.nonces.lock().unwrap();
+        loop {
+            let nonce = rand::random::<u64>();
+            if nonces.insert(nonce) {
+                return nonce;
+            }
+        }
+    }
+
+    pub fn release(&self, nonce: u64) {
+        self.nonces.lock().unwrap().remove(&nonce);
+    }
+
+    pub fn contains(&self, nonce: u64) -> bool {
+        self.nonces.lock().unwrap().contains(&nonce)
+    }
+}
+
+/// Where a `Handshake` stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeState {
+    /// Our `version` is out; theirs has not arrived.
+    AwaitingVersion,
+    /// Their `version` is accepted; their `verack` has not arrived.
+    AwaitingVerack,
+    Complete,
+}
+
+/// Handshake state for one connection.
+#[derive(Debug)]
+pub struct Handshake {
+    config: HandshakeConfig,
+    peer_addr: SocketAddr,
+    local: VersionMessage,
+    remote: Option<VersionMessage>,
+    state: HandshakeState,
+    nonces: Arc<LocalNonces>,
+}
+
+impl Handshake {
+    /// Handshake with the peer at `peer_addr`, announcing `start_height`.
+    pub fn new(
+        config: &HandshakeConfig,
+        peer_addr: SocketAddr,
+        start_height: u32,
+        nonces: Arc<LocalNonces>,
+    ) -> Self {
+        let local = VersionMessage {
+            version: PROTOCOL_VERSION,
+            services: config.services,
+            timestamp: now_secs(),
+            receiver: NetAddr::new(peer_addr.ip(), peer_addr.port()),
+            sender: NetAddr {
+                services: config.services,
+                ip: config.local_addr.ip(),
+                port: config.local_addr.port(),
+            },
+            nonce: nonces.issue(),
+            user_agent: config.user_agent.clone(),
+            start_height,
+            relay: config.relay,
+        };
+        Self {
+            config: config.clone(),
+            peer_addr,
+            local,
+            remote: None,
+            state: HandshakeState::AwaitingVersion,
+            nonces,
+        }
+    }
+
+    pub fn state(&self) -> HandshakeState {
+        self.state
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == HandshakeState::Complete
+    }
+
+    /// The `version` to send first.
+    pub fn version_message(&self) -> NetworkMessage {
+        NetworkMessage::Version(self.local.clone())
+    }
+
+    /// The peer's accepted `version`.
+    pub fn remote_version(&self) -> Option<&VersionMessage> {
+        self.remote.as_ref()
+    }
+
+    /// Protocol version both sides speak.
+    pub fn negotiated_version(&self) -> Option<u32> {
+        self.remote
+            .as_ref()
+            .map(|remote| remote.version.min(self.local.version))
+    }
+
+    /// Advance on `message` from the peer; returns the replies to send.
+    pub fn on_message(
+        &mut self,
+        message: NetworkMessage,
+    ) -> Result<Vec<NetworkMessage>, HandshakeError> {
+        match (self.state, message) {
+            (HandshakeState::AwaitingVersion, NetworkMessage::Version(version)) => {
+                if self.nonces.contains(version.nonce) {
+                    return Err(HandshakeError::SelfConnection);
+                }
+                if version.version < self.config.min_version {
+                    return Err(HandshakeError::ObsoleteVersion(version.version));
+                }
+                let missing = self.config.required_services & !version.services;
+                if missing != 0 {
+                    return Err(HandshakeError::MissingServices(missing));
+                }
+                self.remote = Some(version);
+                self.state = HandshakeState::AwaitingVerack;
+                Ok(vec![NetworkMessage::Verack(VerackMessage)])
+            }
+            (HandshakeState::AwaitingVerack, NetworkMessage::Verack(_)) => {
+                self.state = HandshakeState::Complete;
+                Ok(Vec::new())
+            }
+            (_, other) => Err(HandshakeError::UnexpectedMessage(other.command())),
+        }
+    }
+
+    /// The peer as negotiated, once the handshake is complete. The
+    /// transport is unauthenticated, so `public_key` is left zeroed.
+    pub fn peer_info(&self) -> Option<PeerInfo> {
+        if !self.is_complete() {
+            return None;
+        }
+        let remote = self.remote.as_ref()?;
+        let mut info = PeerInfo::new(
+            self.peer_addr,
+            PublicKey { key: [0u8; 32] },
+            remote.user_agent.clone(),
+        );
+        info.version = self.negotiated_version()?;
+        info.services = remote.services;
+        Some(info)
+    }
+}
+
+impl Drop for Handshake {
+    fn drop(&mut self) {
+        self.nonces.release(self.local.nonce);
+    }
+}
+
+/// Run `handshake` over `stream` until it completes, fails, or
+/// `HandshakeConfig::timeout` passes. Messages with unknown commands are
+/// skipped.
+pub async fn perform_handshake<S: AsyncRead + AsyncWrite>(
+    stream: &mut PeerStream<S>,
+    handshake: &mut Handshake,
+) -> Result<PeerInfo, HandshakeError> {
+    let timeout = handshake.config.timeout;
+    let exchange = async {
+        stream.send(&handshake.version_message()).await?;
+        while !handshake.is_complete() {
+            let message = match stream.receive().await {
+                Ok(message) => message,
+                Err(e) if !e.is_fatal() => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for reply in handshake.on_message(message)? {
+                stream.send(&reply).await?;
+            }
+        }
+        Ok::<(), HandshakeError>(())
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| HandshakeError::Timeout)??;
+    handshake.peer_info().ok_or(HandshakeError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NetworkType;
+    use crate::network::protocol::PingMessage;
+    use crate::network::transport::MessageCodec;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn streams() -> (PeerStream<DuplexStream>, PeerStream<DuplexStream>) {
+        let (a, b) = duplex(1 << 16);
+        let codec = MessageCodec::new(&NetworkType::Regtest);
+        (PeerStream::new(a, codec.clone()), PeerStream::new(b, codec))
+    }
+
+    fn handshake(nonces: &Arc<LocalNonces>, peer: u16, height: u32) -> Handshake {
+        Handshake::new(
+            &HandshakeConfig::default(),
+            addr(peer),
+            height,
+            Arc::clone(nonces),
+        )
+    }
+
+    #[test]
+    fn state_machine_requires_version_then_verack() {
+        let nonces = Arc::new(LocalNonces::new());
+        let mut ours = handshake(&nonces, 1, 0);
+        let theirs = handshake(&Arc::new(LocalNonces::new()), 2, 0);
+
+        // verack or anything else before version is refused.
+        assert!(matches!(
+            handshake(&nonces, 1, 0).on_message(NetworkMessage::Verack(VerackMessage)),
+            Err(HandshakeError::UnexpectedMessage("verack"))
+        ));
+        assert_eq!(
+            ours.on_message(theirs.version_message()).unwrap(),
+            vec![NetworkMessage::Verack(VerackMessage)]
+        );
+        assert_eq!(ours.state(), HandshakeState::AwaitingVerack);
+        assert!(ours.peer_info().is_none());
+        assert!(matches!(
+            ours.on_message(NetworkMessage::Ping(PingMessage { nonce: 1 })),
+            Err(HandshakeError::UnexpectedMessage("ping"))
+        ));
+
+        let mut ours = handshake(&nonces, 1, 0);
+        ours.on_message(theirs.version_message()).unwrap();
+        assert!(matches!(
+            ours.on_message(theirs.version_message()),
+            Err(HandshakeError::UnexpectedMessage("version"))
+        ));
+
+        // Our own nonce means we dialled ourselves.
+        let mut loopback = handshake(&nonces, 1, 0);
+        let ours = handshake(&nonces, 1, 0);
+        assert!(matches!(
+            loopback.on_message(ours.version_message()),
+            Err(HandshakeError::SelfConnection)
+        ));
+        let nonce = match ours.version_message() {
+            NetworkMessage::Version(v) => v.nonce,
+            _ => unreachable!(),
+        };
+        drop(ours);
+        assert!(!nonces.contains(nonce));
+
+        let mut old = match theirs.version_message() {
+            NetworkMessage::Version(v) => v,
+            _ => unreachable!(),
+        };
+        old.version = 0;
+        assert!(matches!(
+            handshake(&nonces, 1, 0).on_message(NetworkMessage::Version(old.clone())),
+            Err(HandshakeError::ObsoleteVersion(0))
+        ));
+        old.version = PROTOCOL_VERSION;
+        old.services = 0;
+        assert!(matches!(
+            handshake(&nonces, 1, 0).on_message(NetworkMessage::Version(old)),
+            Err(HandshakeError::MissingServices(NODE_NETWORK))
+        ));
+    }
+
+    #[tokio::test]
+    async fn peers_complete_the_handshake() {
+        let (mut a, mut b) = streams();
+        let mut ours = handshake(&Arc::new(LocalNonces::new()), 2, 10);
+        let mut theirs = handshake(&Arc::new(LocalNonces::new()), 1, 42);
+
+        let (left, right) = tokio::join!(
+            perform_handshake(&mut a, &mut ours),
+            perform_handshake(&mut b, &mut theirs)
+        );
+        let info = left.unwrap();
+        assert_eq!(info.address, addr(2));
+        assert_eq!(info.version, PROTOCOL_VERSION);
+        assert_eq!(info.services, NODE_NETWORK);
+        assert_eq!(info.user_agent, USER_AGENT);
+        assert!(info.is_valid());
+        assert_eq!(ours.remote_version().unwrap().start_height, 42);
+        assert_eq!(right.unwrap().address, addr(1));
+    }
+
+    #[tokio::test]
+    async fn detects_self_connections_and_times_out() {
+        let nonces = Arc::new(LocalNonces::new());
+        let (mut a, mut b) = streams();
+        let mut outbound = handshake(&nonces, 2, 0);
+        let mut inbound = handshake(&nonces, 1, 0);
+        let (left, right) = tokio::join!(
+            perform_handshake(&mut a, &mut outbound),
+            perform_handshake(&mut b, &mut inbound)
+        );
+        assert!(matches!(left, Err(HandshakeError::SelfConnection)));
+        assert!(matches!(right, Err(HandshakeError::SelfConnection)));
+
+        // A peer that never answers.
+        let (mut a, _silent) = streams();
+        let config = HandshakeConfig {
+            timeout: Duration::from_millis(50),
+            ..HandshakeConfig::default()
+        };
+        let mut waiting = Handshake::new(&config, addr(2), 0, nonces);
+        assert!(matches!(
+            perform_handshake(&mut a, &mut waiting).await,
+            Err(HandshakeError::Timeout)
+        ));
+    }
+}
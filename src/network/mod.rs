@@ -1,15 +1,42 @@
-//! Network module: protocol types/messages and sync management.
+//! Network module: protocol types/messages, peer transport, handshake,
+//! connection management, bans, known addresses and their discovery, and
+//! sync management.
 
+pub mod addrman;
+pub mod ban;
+pub mod discovery;
+pub mod handshake;
+pub mod peer_manager;
 pub mod protocol;
 pub mod sync;
+pub mod transport;
 
 // ---- Re-exports: Protocol layer ----
 pub use self::protocol::{
-    AddrMessage, Block, BlockHeader, GetAddrMessage, GetBlocksMessage, GetDataMessage, Hash,
-    InvEntry, InvMessage, MessageHeader, NetAddr, NetworkMessage, PeerInfo, PingMessage,
-    PongMessage, ProtocolError, Transaction, VerackMessage, VersionMessage,
+    AddrMessage, Block, BlockHeader, FramedMessage, GetAddrMessage, GetBlocksMessage,
+    GetDataMessage, Hash, InvEntry, InvMessage, MessageHeader, NetAddr, NetworkMessage, PeerInfo,
+    PingMessage, PongMessage, ProtocolError, Transaction, VerackMessage, VersionMessage,
 };
 
+// ---- Re-exports: Handshake ----
+pub use self::handshake::{
+    perform_handshake, Handshake, HandshakeConfig, HandshakeError, HandshakeState, LocalNonces,
+};
+
+// ---- Re-exports: Peer management ----
+pub use self::addrman::{AddrInfo, AddrMan, MAX_ADDR_TO_SEND};
+pub use self::ban::{BanEntry, BanList, Misbehavior, BANLIST_FILE};
+pub use self::discovery::{
+    Bootnode, Discovery, DiscoveryError, DnsResolver, SeedResolver, MIN_KNOWN_ADDRESSES,
+};
+pub use self::peer_manager::{
+    reconnect_delay, ConnectedPeer, NetworkGroup, PeerError, PeerEventListener, PeerId,
+    PeerManager, PeerManagerConfig, ADDR_RATE, DEFAULT_TARGET_OUTBOUND,
+};
+
+// ---- Re-exports: Transport layer ----
+pub use self::transport::{MessageCodec, MessageReader, MessageWriter, PeerStream, TransportError};
+
 // ---- Re-exports: Sync layer ----
 pub use self::sync::{BlockLocator, SyncError, SyncManager, SyncScheduler, SyncState, SyncStatus};
 
@@ -0,0 +1,1354 @@
+//! Peer connections: slots, lifecycle and keepalive.
+//!
+//! The `PeerManager` keeps up to `target_outbound` outbound connections,
+//! each in a different `NetworkGroup` so no single network operator can
+//! surround the node, and accepts inbound connections into whatever is left
+//! of `NetworkConfig::max_connections`. Outbound addresses are picked from
+//! the `AddrMan`; those that fail or drop are retried after an exponential
+//! backoff (`reconnect_delay`).
+//!
+//! Every connection is handshaken first (`perform_handshake`), then served
+//! by a reader and a writer task. Pings go out every `ping_interval`; the
+//! matching pong gives the peer's latency, and a peer that does not answer
+//! within `ping_timeout` is dropped. Everything else a peer sends, and its
+//! arrival and departure, is published to the registered
+//! `PeerEventListener`s (sync, the orphan pool, ...).
+//!
+//! Protocol violations, whether caught here (bad checksums, oversized or
+//! repeated handshake messages) or reported by a listener, add to the
+//! peer's misbehavior score (`Misbehavior::score`). At `ban_threshold` the
+//! peer's IP is banned for `ban_duration` and disconnected. Banned
+//! addresses are neither accepted nor dialled; the ban list is saved to
+//! `banlist_file` whenever it changes.
+//!
+//! Addresses are exchanged as in Bitcoin: we send `getaddr` to each new
+//! outbound peer and answer one `getaddr` per inbound peer. Addresses a
+//! peer sends are rate limited by a token bucket (`ADDR_RATE` per second,
+//! topped up by `MAX_ADDR_TO_SEND` for each `getaddr` we send it), and
+//! small, fresh announcements are relayed to two other peers. The address
+//! tables are saved to `peers_file` every `addr_save_interval`.
+
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::addrman::{AddrInfo, AddrMan, MAX_ADDR_TO_SEND};
+use super::ban::{BanEntry, BanList, Misbehavior};
+use super::handshake::{
+    perform_handshake, Handshake, HandshakeConfig, HandshakeError, LocalNonces, NODE_NETWORK,
+};
+use super::protocol::{
+    AddrMessage, GetAddrMessage, NetworkMessage, PeerInfo, PingMessage, PongMessage, PublicKey,
+};
+use super::transport::{MessageCodec, MessageReader, MessageWriter, PeerStream, TransportError};
+use crate::config::{Config, NetworkType};
+use crate::time::now_secs;
+
+/// Outbound connections kept open by default.
+pub const DEFAULT_TARGET_OUTBOUND: usize = 8;
+
+/// Messages queued for a peer before it is considered stuck and dropped.
+const SEND_QUEUE_LEN: usize = 1_024;
+
+/// Addresses per second a peer may send us unasked.
+pub const ADDR_RATE: f64 = 0.1;
+
+/// Announcements of at most this many addresses are relayed.
+const MAX_ADDR_TO_RELAY: usize = 10;
+
+/// Peers each relayed announcement is forwarded to.
+const ADDR_RELAY_FANOUT: usize = 2;
+
+/// Addresses drawn from the `AddrMan` per `outbound_candidates` call.
+const SELECT_TRIES: usize = 100;
+
+/// Pause after a failed `accept`, so running out of file descriptors does
+/// not turn into a busy loop.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Identifies a connection for as long as it is open.
+pub type PeerId = u64;
+
+/// Addresses grouped by who is likely to control them: the /16 of an IPv4
+/// address or the /32 of an IPv6 one. Local and private addresses are each
+/// their own group, so test networks on one host are not limited to a
+/// single peer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NetworkGroup(Vec<u8>);
+
+impl NetworkGroup {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn of(ip: &IpAddr) -> Self {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(*ip, IpAddr::V4),
+            IpAddr::V4(_) => *ip,
+        };
+        match ip {
+            IpAddr::V4(v4) if v4.is_loopback() || v4.is_private() || v4.is_link_local() => {
+                NetworkGroup([&[0u8][..], &v4.octets()[..]].concat())
+            }
+            IpAddr::V4(v4) => NetworkGroup(vec![4, v4.octets()[0], v4.octets()[1]]),
+            IpAddr::V6(v6) if v6.is_loopback() || (v6.segments()[0] & 0xfe00) == 0xfc00 => {
+                NetworkGroup([&[0u8][..], &v6.octets()[..]].concat())
+            }
+            IpAddr::V6(v6) => NetworkGroup([&[6u8][..], &v6.octets()[..4]].concat()),
+        }
+    }
+}
+
+impl fmt::Display for NetworkGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+/// Delay before the next attempt on an address after `failures`
+/// consecutive failures: `base` doubled per failure, at most `max`.
+pub fn reconnect_delay(base: Duration, max: Duration, failures: u32) -> Duration {
+    let factor = 1u32 << failures.saturating_sub(1).min(16);
+    base.saturating_mul(factor).min(max)
+}
+
+#[derive(Debug)]
+pub enum PeerError {
+    Handshake(HandshakeError),
+    /// All inbound slots are taken.
+    NoSlots,
+    AlreadyConnected(SocketAddr),
+    UnknownPeer(PeerId),
+    /// The peer stopped reading; it has been disconnected.
+    SendQueueFull(PeerId),
+    Banned(IpAddr),
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerError::Handshake(e) => write!(f, "Handshake failed: {}", e),
+            PeerError::NoSlots => write!(f, "No free connection slots"),
+            PeerError::AlreadyConnected(addr) => write!(f, "Already connected to {}", addr),
+            PeerError::UnknownPeer(id) => write!(f, "Unknown peer {}", id),
+            PeerError::SendQueueFull(id) => write!(f, "Send queue of peer {} is full", id),
+            PeerError::Banned(ip) => write!(f, "{} is banned", ip),
+        }
+    }
+}
+
+impl std::error::Error for PeerError {}
+
+impl From<HandshakeError> for PeerError {
+    fn from(error: HandshakeError) -> Self {
+        PeerError::Handshake(error)
+    }
+}
+
+/// A connected, handshaken peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectedPeer {
+    pub id: PeerId,
+    pub info: PeerInfo,
+    pub inbound: bool,
+    pub group: NetworkGroup,
+    /// Chain height the peer announced in its `version`.
+    pub start_height: u32,
+    /// Round trip of the last answered ping.
+    pub latency: Option<Duration>,
+    /// Sum of the peer's `Misbehavior` scores.
+    pub misbehavior: u32,
+}
+
+/// Receives peer lifecycle events and messages. Called from the connection
+/// tasks, so implementations should return quickly.
+pub trait PeerEventListener: Send + Sync {
+    fn peer_connected(&self, _peer: &ConnectedPeer) {}
+
+    fn peer_disconnected(&self, _peer: &ConnectedPeer, _reason: &str) {}
+
+    /// Any message after the handshake other than ping and pong. An error
+    /// scores the peer for the violation.
+    fn peer_message(
+        &self,
+        _peer: &ConnectedPeer,
+        _message: &NetworkMessage,
+    ) -> Result<(), Misbehavior> {
+        Ok(())
+    }
+}
+
+/// `PeerManager` knobs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerManagerConfig {
+    pub network: NetworkType,
+    pub handshake: HandshakeConfig,
+    pub target_outbound: usize,
+    /// Inbound plus outbound connections.
+    pub max_connections: usize,
+    pub ping_interval: Duration,
+    pub ping_timeout: Duration,
+    /// Delay after the first failure of an outbound address.
+    pub reconnect_base: Duration,
+    pub reconnect_max: Duration,
+    /// How often `run` tops up outbound connections and sends pings.
+    pub maintenance_interval: Duration,
+    /// Misbehavior score at which a peer is banned.
+    pub ban_threshold: u32,
+    pub ban_duration: Duration,
+    /// Where bans are saved; `None` keeps them in memory only.
+    pub banlist_file: Option<PathBuf>,
+    /// Where known addresses are saved; `None` keeps them in memory only.
+    pub peers_file: Option<PathBuf>,
+    pub addr_save_interval: Duration,
+}
+
+impl PeerManagerConfig {
+    pub fn from_config(config: &Config) -> Self {
+        let network = &config.network_config;
+        Self {
+            network: config.network.clone(),
+            handshake: HandshakeConfig::from_config(network),
+            target_outbound: DEFAULT_TARGET_OUTBOUND.min(network.max_connections),
+            max_connections: network.max_connections,
+            ping_interval: Duration::from_secs(120),
+            ping_timeout: network.message_timeout,
+            reconnect_base: Duration::from_secs(1),
+            reconnect_max: Duration::from_secs(10 * 60),
+            maintenance_interval: Duration::from_secs(1),
+            ban_threshold: network.ban_threshold,
+            ban_duration: network.ban_duration,
+            banlist_file: Some(config.get_banlist_file()),
+            peers_file: Some(config.get_peers_file()),
+            addr_save_interval: Duration::from_secs(15 * 60),
+        }
+    }
+
+    /// Connections left for inbound peers once outbound slots are reserved.
+    pub fn inbound_slots(&self) -> usize {
+        self.max_connections.saturating_sub(self.target_outbound)
+    }
+}
+
+struct PeerSlot {
+    peer: ConnectedPeer,
+    sender: mpsc::Sender<NetworkMessage>,
+    reader: Option<JoinHandle<()>>,
+    pending_ping: Option<(u64, Instant)>,
+    ping_due: Instant,
+    /// Addresses the peer may still send; see `ADDR_RATE`.
+    addr_tokens: f64,
+    addr_refilled: Instant,
+    answered_getaddr: bool,
+}
+
+impl PeerSlot {
+    fn refill_addr_tokens(&mut self, now: Instant) {
+        let cap = MAX_ADDR_TO_SEND as f64;
+        if self.addr_tokens < cap {
+            let elapsed = now.duration_since(self.addr_refilled).as_secs_f64();
+            self.addr_tokens = (self.addr_tokens + elapsed * ADDR_RATE).min(cap);
+        }
+        self.addr_refilled = now;
+    }
+}
+
+/// Retry state of an outbound address.
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    failures: u32,
+    retry_at: Instant,
+    connecting: bool,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            failures: 0,
+            retry_at: Instant::now(),
+            connecting: false,
+        }
+    }
+}
+
+/// Owns every peer connection; see the module docs.
+pub struct PeerManager {
+    config: PeerManagerConfig,
+    nonces: Arc<LocalNonces>,
+    start_height: AtomicU32,
+    next_id: AtomicU64,
+    peers: RwLock<HashMap<PeerId, PeerSlot>>,
+    /// Inbound connections still handshaking; they hold a slot already.
+    pending_inbound: AtomicUsize,
+    addrman: Mutex<AddrMan>,
+    /// Backoff of addresses we have dialled.
+    dials: Mutex<HashMap<SocketAddr, Backoff>>,
+    bans: Mutex<BanList>,
+    listeners: RwLock<Vec<Arc<dyn PeerEventListener>>>,
+}
+
+impl PeerManager {
+    /// A manager with the bans and addresses saved in `config.banlist_file`
+    /// and `config.peers_file`, if any. Unreadable files are logged and
+    /// replaced.
+    pub fn new(config: PeerManagerConfig) -> Arc<Self> {
+        let bans = match &config.banlist_file {
+            Some(path) => BanList::load(path).unwrap_or_else(|e| {
+                log::warn!("Ignoring ban list {}: {}", path.display(), e);
+                BanList::new()
+            }),
+            None => BanList::new(),
+        };
+        let addrman = match &config.peers_file {
+            Some(path) => AddrMan::load(path).unwrap_or_else(|e| {
+                log::warn!("Ignoring peers file {}: {}", path.display(), e);
+                AddrMan::new()
+            }),
+            None => AddrMan::new(),
+        };
+        Arc::new(Self {
+            config,
+            nonces: Arc::new(LocalNonces::new()),
+            start_height: AtomicU32::new(0),
+            next_id: AtomicU64::new(1),
+            peers: RwLock::new(HashMap::new()),
+            pending_inbound: AtomicUsize::new(0),
+            addrman: Mutex::new(addrman),
+            dials: Mutex::new(HashMap::new()),
+            bans: Mutex::new(bans),
+            listeners: RwLock::new(Vec::new()),
+        })
+    }
+
+    pub fn config(&self) -> &PeerManagerConfig {
+        &self.config
+    }
+
+    pub fn add_listener(&self, listener: Arc<dyn PeerEventListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Height announced in our `version` to new peers.
+    pub fn set_start_height(&self, height: u32) {
+        self.start_height.store(height, Ordering::Relaxed);
+    }
+
+    /// Remember `addr` as an outbound candidate, as if it had told us
+    /// about itself. Returns whether it was new.
+    pub fn add_address(&self, addr: SocketAddr) -> bool {
+        let now = now_secs();
+        self.addrman
+            .lock()
+            .unwrap()
+            .add(addr, NODE_NETWORK, addr.ip(), now, now)
+    }
+
+    /// Number of addresses in the `AddrMan`.
+    pub fn address_count(&self) -> usize {
+        self.addrman.lock().unwrap().len()
+    }
+
+    /// Write the `AddrMan` to `peers_file`, if there is one.
+    pub fn save_addresses(&self) -> io::Result<()> {
+        let Some(path) = &self.config.peers_file else {
+            return Ok(());
+        };
+        let addrman = self.addrman.lock().unwrap().clone();
+        addrman.save(path)
+    }
+
+    pub fn peers(&self) -> Vec<ConnectedPeer> {
+        let mut peers: Vec<ConnectedPeer> = self
+            .peers
+            .read()
+            .unwrap()
+            .values()
+            .map(|slot| slot.peer.clone())
+            .collect();
+        peers.sort_by_key(|peer| peer.id);
+        peers
+    }
+
+    pub fn peer(&self, id: PeerId) -> Option<ConnectedPeer> {
+        self.peers
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|slot| slot.peer.clone())
+    }
+
+    pub fn outbound_count(&self) -> usize {
+        count(&self.peers.read().unwrap(), false)
+    }
+
+    pub fn inbound_count(&self) -> usize {
+        count(&self.peers.read().unwrap(), true)
+    }
+
+    /// Whether another inbound peer fits, counting inbound connections
+    /// that are still handshaking.
+    pub fn has_inbound_slot(&self) -> bool {
+        self.inbound_count() + self.pending_inbound.load(Ordering::SeqCst)
+            < self.config.inbound_slots()
+    }
+
+    /// Hold an inbound slot for a connection about to handshake; `None` if
+    /// there is none left.
+    fn reserve_inbound(&self) -> Option<InboundReservation<'_>> {
+        let slots = self.config.inbound_slots();
+        self.pending_inbound
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                (self.inbound_count() + pending < slots).then_some(pending + 1)
+            })
+            .ok()
+            .map(|_| InboundReservation(&self.pending_inbound))
+    }
+
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.peers
+            .read()
+            .unwrap()
+            .values()
+            .any(|slot| slot.peer.info.address == *addr)
+    }
+
+    /// Queue `message` for peer `id`. A peer whose queue is full is not
+    /// keeping up and is disconnected.
+    pub fn send(&self, id: PeerId, message: NetworkMessage) -> Result<(), PeerError> {
+        let sent = match self.peers.read().unwrap().get(&id) {
+            Some(slot) => slot.sender.try_send(message),
+            None => return Err(PeerError::UnknownPeer(id)),
+        };
+        match sent {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.disconnect(id, "send queue full");
+                Err(PeerError::SendQueueFull(id))
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(PeerError::UnknownPeer(id)),
+        }
+    }
+
+    /// Queue `message` for every peer; returns how many it was queued for.
+    pub fn broadcast(&self, message: &NetworkMessage) -> usize {
+        let ids: Vec<PeerId> = self.peers.read().unwrap().keys().copied().collect();
+        ids.into_iter()
+            .filter(|&id| self.send(id, message.clone()).is_ok())
+            .count()
+    }
+
+    /// Close the connection to `id`; returns whether it was open.
+    pub fn disconnect(&self, id: PeerId, reason: &str) -> bool {
+        let Some(slot) = self.peers.write().unwrap().remove(&id) else {
+            return false;
+        };
+        if let Some(reader) = &slot.reader {
+            reader.abort();
+        }
+        if !slot.peer.inbound {
+            self.record_failure(slot.peer.info.address);
+        }
+        log::debug!(
+            "Disconnected peer {} ({}): {}",
+            id,
+            slot.peer.info.address,
+            reason
+        );
+        self.notify(|listener| listener.peer_disconnected(&slot.peer, reason));
+        true
+    }
+
+    /// Score peer `id` for `kind`, banning it once its total reaches
+    /// `ban_threshold`. Returns whether the peer was banned.
+    pub fn misbehaving(&self, id: PeerId, kind: Misbehavior) -> bool {
+        let (address, score) = {
+            let mut peers = self.peers.write().unwrap();
+            let Some(slot) = peers.get_mut(&id) else {
+                return false;
+            };
+            slot.peer.misbehavior = slot.peer.misbehavior.saturating_add(kind.score());
+            (slot.peer.info.address, slot.peer.misbehavior)
+        };
+        log::debug!(
+            "Peer {} ({}) misbehaved: {}, score {}",
+            id,
+            address,
+            kind,
+            score
+        );
+        if score < self.config.ban_threshold {
+            return false;
+        }
+        self.ban(address.ip(), self.config.ban_duration, &kind.to_string());
+        true
+    }
+
+    /// Ban `ip` for `duration` and drop every connection from it.
+    pub fn ban(&self, ip: IpAddr, duration: Duration, reason: &str) {
+        self.bans.lock().unwrap().ban(ip, duration, reason);
+        self.save_bans();
+        log::info!("Banned {} for {}s: {}", ip, duration.as_secs(), reason);
+        let ids: Vec<PeerId> = self
+            .peers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|slot| slot.peer.info.address.ip() == ip)
+            .map(|slot| slot.peer.id)
+            .collect();
+        for id in ids {
+            self.disconnect(id, "banned");
+        }
+    }
+
+    /// Lift the ban on `ip`; returns whether there was one.
+    pub fn unban(&self, ip: &IpAddr) -> bool {
+        let lifted = self.bans.lock().unwrap().unban(ip);
+        if lifted {
+            self.save_bans();
+        }
+        lifted
+    }
+
+    pub fn clear_bans(&self) {
+        self.bans.lock().unwrap().clear();
+        self.save_bans();
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.bans.lock().unwrap().is_banned(ip)
+    }
+
+    /// Current bans, soonest to lift first.
+    pub fn banned(&self) -> Vec<BanEntry> {
+        self.bans.lock().unwrap().entries()
+    }
+
+    fn save_bans(&self) {
+        let Some(path) = &self.config.banlist_file else {
+            return;
+        };
+        if let Err(e) = self.bans.lock().unwrap().save(path) {
+            log::warn!("Failed to save ban list {}: {}", path.display(), e);
+        }
+    }
+
+    /// Dial `addr` and handshake with it as an outbound peer.
+    pub async fn connect(self: &Arc<Self>, addr: SocketAddr) -> Result<PeerId, PeerError> {
+        self.start_dial(addr);
+        self.dial(addr).await
+    }
+
+    /// `connect` for a dial `start_dial` already recorded.
+    async fn dial(self: &Arc<Self>, addr: SocketAddr) -> Result<PeerId, PeerError> {
+        let stream =
+            match PeerStream::connect(addr, &self.config.network, self.config.handshake.timeout)
+                .await
+            {
+                Ok(stream) => stream,
+                Err(e) => {
+                    self.record_failure(addr);
+                    return Err(HandshakeError::from(e).into());
+                }
+            };
+        self.add_connection(stream, addr, false).await
+    }
+
+    /// Handshake over an open `stream` to `addr` and, if that succeeds and
+    /// a slot is free, start serving it.
+    pub async fn add_connection<S>(
+        self: &Arc<Self>,
+        mut stream: PeerStream<S>,
+        addr: SocketAddr,
+        inbound: bool,
+    ) -> Result<PeerId, PeerError>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let reservation = if inbound {
+            Some(self.reserve_inbound().ok_or(PeerError::NoSlots)?)
+        } else {
+            None
+        };
+        let result = self.handshake(&mut stream, addr, inbound).await;
+        let peer = match result {
+            Ok(peer) => peer,
+            Err(e) => {
+                if !inbound {
+                    match e {
+                        PeerError::Handshake(_) => self.record_failure(addr),
+                        _ => self.finish_dial(addr),
+                    }
+                }
+                return Err(e);
+            }
+        };
+        let id = peer.id;
+
+        let (reader, writer) = stream.into_split();
+        let (sender, receiver) = mpsc::channel(SEND_QUEUE_LEN);
+        {
+            let mut peers = self.peers.write().unwrap();
+            // Re-checked: other connections may have landed during the handshake.
+            if inbound && count(&peers, true) >= self.config.inbound_slots() {
+                return Err(PeerError::NoSlots);
+            }
+            if peers.values().any(|slot| slot.peer.info.address == addr) {
+                drop(peers);
+                self.finish_dial(addr);
+                return Err(PeerError::AlreadyConnected(addr));
+            }
+            peers.insert(
+                id,
+                PeerSlot {
+                    peer: peer.clone(),
+                    sender,
+                    reader: None,
+                    pending_ping: None,
+                    ping_due: Instant::now(),
+                    addr_tokens: 1.0,
+                    addr_refilled: Instant::now(),
+                    answered_getaddr: false,
+                },
+            );
+        }
+        drop(reservation);
+        if !inbound {
+            self.record_success(addr);
+        }
+        log::info!(
+            "Connected {} peer {} ({}, {})",
+            if inbound { "inbound" } else { "outbound" },
+            id,
+            addr,
+            peer.info.user_agent
+        );
+        self.notify(|listener| listener.peer_connected(&peer));
+
+        tokio::spawn(Arc::clone(self).write_loop(id, writer, receiver));
+        let reader = tokio::spawn(Arc::clone(self).read_loop(id, reader));
+        if let Some(slot) = self.peers.write().unwrap().get_mut(&id) {
+            slot.reader = Some(reader);
+        }
+        if !inbound {
+            self.request_addresses(id);
+        }
+        Ok(id)
+    }
+
+    async fn handshake<S: AsyncRead + AsyncWrite>(
+        &self,
+        stream: &mut PeerStream<S>,
+        addr: SocketAddr,
+        inbound: bool,
+    ) -> Result<ConnectedPeer, PeerError> {
+        if self.is_banned(&addr.ip()) {
+            return Err(PeerError::Banned(addr.ip()));
+        }
+        if self.is_connected(&addr) {
+            return Err(PeerError::AlreadyConnected(addr));
+        }
+        let mut handshake = Handshake::new(
+            &self.config.handshake,
+            addr,
+            self.start_height.load(Ordering::Relaxed),
+            Arc::clone(&self.nonces),
+        );
+        let info = perform_handshake(stream, &mut handshake).await?;
+        Ok(ConnectedPeer {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            info,
+            inbound,
+            group: NetworkGroup::of(&addr.ip()),
+            start_height: handshake.remote_version().map_or(0, |v| v.start_height),
+            latency: None,
+            misbehavior: 0,
+        })
+    }
+
+    async fn write_loop<S: AsyncRead + AsyncWrite>(
+        self: Arc<Self>,
+        id: PeerId,
+        mut writer: MessageWriter<WriteHalf<S>>,
+        mut receiver: mpsc::Receiver<NetworkMessage>,
+    ) {
+        while let Some(message) = receiver.recv().await {
+            if let Err(e) = writer.send(&message).await {
+                self.disconnect(id, &e.to_string());
+                return;
+            }
+        }
+    }
+
+    async fn read_loop<S: AsyncRead + AsyncWrite>(
+        self: Arc<Self>,
+        id: PeerId,
+        mut reader: MessageReader<ReadHalf<S>>,
+    ) {
+        loop {
+            let result = reader.receive().await;
+            if let Err(e) = &result {
+                let kind = match e {
+                    TransportError::BadChecksum { .. } => Some(Misbehavior::BadChecksum),
+                    TransportError::Oversized { .. } => Some(Misbehavior::OversizedMessage),
+                    _ => None,
+                };
+                if let Some(kind) = kind {
+                    self.misbehaving(id, kind);
+                }
+            }
+            match result {
+                Ok(message) => self.handle_message(id, message),
+                Err(e) if !e.is_fatal() => log::debug!("Peer {}: {}", id, e),
+                Err(e) => {
+                    self.disconnect(id, &e.to_string());
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_message(&self, id: PeerId, message: NetworkMessage) {
+        match message {
+            NetworkMessage::Ping(PingMessage { nonce }) => {
+                let _ = self.send(id, NetworkMessage::Pong(PongMessage { nonce }));
+            }
+            NetworkMessage::Pong(PongMessage { nonce }) => {
+                let mut peers = self.peers.write().unwrap();
+                if let Some(slot) = peers.get_mut(&id) {
+                    if let Some((expected, sent)) = slot.pending_ping {
+                        if expected == nonce {
+                            slot.peer.latency = Some(sent.elapsed());
+                            slot.pending_ping = None;
+                            slot.ping_due = Instant::now() + self.config.ping_interval;
+                        }
+                    }
+                }
+            }
+            NetworkMessage::Version(_) | NetworkMessage::Verack(_) => {
+                self.misbehaving(id, Misbehavior::UnsolicitedData);
+            }
+            NetworkMessage::GetAddr(_) => self.answer_getaddr(id),
+            NetworkMessage::Addr(addr) => self.handle_addr(id, addr),
+            message => {
+                let Some(peer) = self.peer(id) else {
+                    return;
+                };
+                let mut violations = Vec::new();
+                self.notify(|listener| {
+                    if let Err(kind) = listener.peer_message(&peer, &message) {
+                        violations.push(kind);
+                    }
+                });
+                for kind in violations {
+                    self.misbehaving(id, kind);
+                }
+            }
+        }
+    }
+
+    /// Ask peer `id` for addresses, allowing it a full answer.
+    fn request_addresses(&self, id: PeerId) {
+        if let Some(slot) = self.peers.write().unwrap().get_mut(&id) {
+            slot.addr_tokens += MAX_ADDR_TO_SEND as f64;
+        }
+        let _ = self.send(id, NetworkMessage::GetAddr(GetAddrMessage));
+    }
+
+    /// Answer the first `getaddr` of an inbound peer; others are ignored so
+    /// a peer cannot map our address tables.
+    fn answer_getaddr(&self, id: PeerId) {
+        {
+            let mut peers = self.peers.write().unwrap();
+            let Some(slot) = peers.get_mut(&id) else {
+                return;
+            };
+            if !slot.peer.inbound || slot.answered_getaddr {
+                return;
+            }
+            slot.answered_getaddr = true;
+        }
+        let addresses: Vec<PeerInfo> = self
+            .addrman
+            .lock()
+            .unwrap()
+            .get_addr(now_secs())
+            .iter()
+            .map(announcement)
+            .collect();
+        if !addresses.is_empty() {
+            let _ = self.send(id, NetworkMessage::Addr(AddrMessage { addresses }));
+        }
+    }
+
+    /// Learn the addresses peer `id` sent, as far as its tokens allow, and
+    /// relay small fresh announcements.
+    fn handle_addr(&self, id: PeerId, message: AddrMessage) {
+        let total = message.addresses.len();
+        if total > MAX_ADDR_TO_SEND {
+            self.misbehaving(id, Misbehavior::OversizedMessage);
+            return;
+        }
+        let (source, allowed) = {
+            let mut peers = self.peers.write().unwrap();
+            let Some(slot) = peers.get_mut(&id) else {
+                return;
+            };
+            slot.refill_addr_tokens(Instant::now());
+            let allowed = (slot.addr_tokens as usize).min(total);
+            slot.addr_tokens -= allowed as f64;
+            (slot.peer.info.address.ip(), allowed)
+        };
+        if allowed < total {
+            log::debug!(
+                "Peer {} rate limited: dropped {} addresses",
+                id,
+                total - allowed
+            );
+        }
+
+        let now = now_secs();
+        let accepted: Vec<PeerInfo> = message
+            .addresses
+            .into_iter()
+            .take(allowed)
+            .filter(|info| !self.is_banned(&info.address.ip()))
+            .collect();
+        let mut relay = Vec::new();
+        {
+            let mut addrman = self.addrman.lock().unwrap();
+            for info in accepted {
+                addrman.add(info.address, info.services, source, info.last_seen, now);
+                if total <= MAX_ADDR_TO_RELAY && info.last_seen.saturating_add(10 * 60) >= now {
+                    relay.push(info);
+                }
+            }
+        }
+        if !relay.is_empty() {
+            self.relay_addresses(id, relay);
+        }
+    }
+
+    fn relay_addresses(&self, from: PeerId, addresses: Vec<PeerInfo>) {
+        let mut ids: Vec<PeerId> = self
+            .peers
+            .read()
+            .unwrap()
+            .keys()
+            .copied()
+            .filter(|&id| id != from)
+            .collect();
+        ids.shuffle(&mut rand::thread_rng());
+        for id in ids.into_iter().take(ADDR_RELAY_FANOUT) {
+            let message = AddrMessage {
+                addresses: addresses.clone(),
+            };
+            let _ = self.send(id, NetworkMessage::Addr(message));
+        }
+    }
+
+    /// Ping peers that are due, and drop those that left a ping unanswered
+    /// for `ping_timeout`.
+    pub fn ping_peers(&self) {
+        let now = Instant::now();
+        let mut pings = Vec::new();
+        let mut timed_out = Vec::new();
+        {
+            let mut peers = self.peers.write().unwrap();
+            for (&id, slot) in peers.iter_mut() {
+                match slot.pending_ping {
+                    Some((_, sent)) if now.duration_since(sent) >= self.config.ping_timeout => {
+                        timed_out.push(id);
+                    }
+                    None if slot.ping_due <= now => {
+                        let nonce = rand::random::<u64>();
+                        slot.pending_ping = Some((nonce, now));
+                        pings.push((id, nonce));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (id, nonce) in pings {
+            let _ = self.send(id, NetworkMessage::Ping(PingMessage { nonce }));
+        }
+        for id in timed_out {
+            self.disconnect(id, "ping timeout");
+        }
+    }
+
+    /// Addresses worth dialling now to fill the free outbound slots, drawn
+    /// from the `AddrMan`: not connected, banned or backing off, and each in
+    /// a network group no outbound peer or other candidate is in.
+    pub fn outbound_candidates(&self) -> Vec<SocketAddr> {
+        let now = Instant::now();
+        let (mut groups, connected, outbound) = {
+            let peers = self.peers.read().unwrap();
+            let groups: HashSet<NetworkGroup> = peers
+                .values()
+                .filter(|slot| !slot.peer.inbound)
+                .map(|slot| slot.peer.group.clone())
+                .collect();
+            let connected: HashSet<SocketAddr> =
+                peers.values().map(|slot| slot.peer.info.address).collect();
+            (groups, connected, count(&peers, false))
+        };
+
+        let dials = self.dials.lock().unwrap();
+        let dialling = dials.values().filter(|b| b.connecting).count();
+        let wanted = self
+            .config
+            .target_outbound
+            .saturating_sub(outbound + dialling);
+        let addrman = self.addrman.lock().unwrap();
+        let bans = self.bans.lock().unwrap();
+        let unix_now = now_secs();
+
+        let mut candidates = Vec::new();
+        for _ in 0..SELECT_TRIES {
+            if candidates.len() >= wanted {
+                break;
+            }
+            let Some(addr) = addrman.select(unix_now) else {
+                break;
+            };
+            let ready = dials
+                .get(&addr)
+                .is_none_or(|b| !b.connecting && b.retry_at <= now);
+            if !ready
+                || connected.contains(&addr)
+                || candidates.contains(&addr)
+                || bans.is_banned(&addr.ip())
+            {
+                continue;
+            }
+            if groups.insert(NetworkGroup::of(&addr.ip())) {
+                candidates.push(addr);
+            }
+        }
+        candidates
+    }
+
+    /// Dial `outbound_candidates` in the background. Each dial is recorded
+    /// before this returns, so the next round does not pick it again.
+    pub fn fill_outbound(self: &Arc<Self>) {
+        for addr in self.outbound_candidates() {
+            self.start_dial(addr);
+            let manager = Arc::clone(self);
+            tokio::spawn(async move {
+                if let Err(e) = manager.dial(addr).await {
+                    log::debug!("Outbound connection to {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Accept inbound connections on `listener`, forever. A failed accept
+    /// (say, out of file descriptors) is logged and retried after a pause.
+    pub async fn listen(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept inbound peer: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            if !self.has_inbound_slot() {
+                log::debug!("Refusing inbound {}: no free slots", addr);
+                continue;
+            }
+            if self.is_banned(&addr.ip()) {
+                log::debug!("Refusing inbound {}: banned", addr);
+                continue;
+            }
+            let _ = stream.set_nodelay(true);
+            let stream = PeerStream::new(stream, MessageCodec::new(&self.config.network));
+            let manager = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = manager.add_connection(stream, addr, true).await {
+                    log::debug!("Inbound connection from {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Keep outbound slots filled, peers pinged, the ban list free of
+    /// expired bans and `peers_file` current, forever.
+    pub async fn run(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(self.config.maintenance_interval);
+        let mut saved = Instant::now();
+        loop {
+            ticker.tick().await;
+            self.fill_outbound();
+            self.ping_peers();
+            if self.bans.lock().unwrap().sweep_expired() > 0 {
+                self.save_bans();
+            }
+            if saved.elapsed() >= self.config.addr_save_interval {
+                saved = Instant::now();
+                if let Err(e) = self.save_addresses() {
+                    log::warn!("Failed to save peer addresses: {}", e);
+                }
+            }
+        }
+    }
+
+    fn start_dial(&self, addr: SocketAddr) {
+        self.dials
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_insert_with(Backoff::new)
+            .connecting = true;
+        self.addrman.lock().unwrap().attempt(&addr, now_secs());
+    }
+
+    fn finish_dial(&self, addr: SocketAddr) {
+        if let Some(backoff) = self.dials.lock().unwrap().get_mut(&addr) {
+            backoff.connecting = false;
+        }
+    }
+
+    fn record_success(&self, addr: SocketAddr) {
+        if let Some(backoff) = self.dials.lock().unwrap().get_mut(&addr) {
+            backoff.failures = 0;
+            backoff.connecting = false;
+        }
+        self.addrman.lock().unwrap().good(&addr, now_secs());
+    }
+
+    fn record_failure(&self, addr: SocketAddr) {
+        let mut dials = self.dials.lock().unwrap();
+        let backoff = dials.entry(addr).or_insert_with(Backoff::new);
+        backoff.failures = backoff.failures.saturating_add(1);
+        backoff.connecting = false;
+        backoff.retry_at = Instant::now()
+            + reconnect_delay(
+                self.config.reconnect_base,
+                self.config.reconnect_max,
+                backoff.failures,
+            );
+    }
+
+    fn notify(&self, mut event: impl FnMut(&dyn PeerEventListener)) {
+        let listeners = self.listeners.read().unwrap().clone();
+        for listener in &listeners {
+            event(listener.as_ref());
+        }
+    }
+}
+
+/// `info` as an `addr` entry; only the address, services and time matter.
+fn announcement(info: &AddrInfo) -> PeerInfo {
+    let mut peer = PeerInfo::new(info.addr, PublicKey { key: [0u8; 32] }, String::new());
+    peer.services = info.services;
+    peer.last_seen = info.last_seen;
+    peer
+}
+
+/// An inbound slot held for the length of a handshake.
+struct InboundReservation<'a>(&'a AtomicUsize);
+
+impl Drop for InboundReservation<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn count(peers: &HashMap<PeerId, PeerSlot>, inbound: bool) -> usize {
+    peers
+        .values()
+        .filter(|slot| slot.peer.inbound == inbound)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::{InvMessage, VerackMessage};
+    use tokio::io::duplex;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl Recorder {
+        fn events(&self) -> Vec<String> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    impl PeerEventListener for Recorder {
+        fn peer_connected(&self, peer: &ConnectedPeer) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("connected {}", peer.id));
+        }
+
+        fn peer_disconnected(&self, peer: &ConnectedPeer, _reason: &str) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("disconnected {}", peer.id));
+        }
+
+        fn peer_message(
+            &self,
+            peer: &ConnectedPeer,
+            message: &NetworkMessage,
+        ) -> Result<(), Misbehavior> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("{} from {}", message.command(), peer.id));
+            Ok(())
+        }
+    }
+
+    fn config() -> PeerManagerConfig {
+        let mut config = PeerManagerConfig::from_config(&Config::new(NetworkType::Regtest, None));
+        config.target_outbound = 2;
+        config.max_connections = 3;
+        config.banlist_file = None;
+        config.peers_file = None;
+        config
+    }
+
+    fn manager() -> (Arc<PeerManager>, Arc<Recorder>) {
+        let manager = PeerManager::new(config());
+        let recorder = Arc::new(Recorder::default());
+        manager.add_listener(recorder.clone());
+        (manager, recorder)
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    /// Connect `a` (outbound, to `b_addr`) and `b` (inbound, from `a_addr`).
+    async fn link(
+        a: &Arc<PeerManager>,
+        a_addr: SocketAddr,
+        b: &Arc<PeerManager>,
+        b_addr: SocketAddr,
+    ) -> (Result<PeerId, PeerError>, Result<PeerId, PeerError>) {
+        let (left, right) = duplex(1 << 16);
+        let codec = MessageCodec::new(&NetworkType::Regtest);
+        tokio::join!(
+            a.add_connection(PeerStream::new(left, codec.clone()), b_addr, false),
+            b.add_connection(PeerStream::new(right, codec), a_addr, true)
+        )
+    }
+
+    async fn eventually(check: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if check() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("condition not reached");
+    }
+
+    #[test]
+    fn groups_and_backoff() {
+        let group = |s: &str| NetworkGroup::of(&s.parse().unwrap());
+        assert_eq!(group("8.8.4.4"), group("8.8.8.8"));
+        assert_ne!(group("8.8.8.8"), group("8.9.8.8"));
+        assert_eq!(group("::ffff:8.8.4.4"), group("8.8.8.8"));
+        assert_eq!(group("2001:db8:1::1"), group("2001:db8:2::1"));
+        assert_ne!(group("127.0.0.1"), group("127.0.0.2"));
+        assert_ne!(group("10.0.0.1"), group("10.0.0.2"));
+
+        let (base, max) = (Duration::from_secs(1), Duration::from_secs(60));
+        assert_eq!(reconnect_delay(base, max, 1), Duration::from_secs(1));
+        assert_eq!(reconnect_delay(base, max, 3), Duration::from_secs(4));
+        assert_eq!(reconnect_delay(base, max, 40), max);
+    }
+
+    #[test]
+    fn outbound_candidates_are_diverse() {
+        let manager = PeerManager::new(config());
+        for a in ["8.8.8.8:1", "8.8.4.4:1", "9.9.9.9:1", "1.1.1.1:1"] {
+            manager.add_address(addr(a));
+        }
+        let candidates = manager.outbound_candidates();
+        assert_eq!(candidates.len(), 2);
+        let groups: HashSet<NetworkGroup> = candidates
+            .iter()
+            .map(|a| NetworkGroup::of(&a.ip()))
+            .collect();
+        assert_eq!(groups.len(), 2);
+
+        // A failed address backs off.
+        manager.record_failure(addr("1.1.1.1:1"));
+        manager.record_failure(addr("9.9.9.9:1"));
+        assert_eq!(manager.outbound_candidates().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn each_outbound_dial_counts_one_attempt() {
+        let manager = PeerManager::new(config());
+        // A port nothing listens on, so the dial fails straight away.
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(manager.add_address(closed));
+
+        manager.fill_outbound();
+        eventually(|| manager.dials.lock().unwrap()[&closed].failures == 1).await;
+        let addrman = manager.addrman.lock().unwrap();
+        assert_eq!(addrman.get(&closed).unwrap().attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn connects_pings_relays_and_disconnects() {
+        let (a, a_events) = manager();
+        let (b, b_events) = manager();
+        let (a_addr, b_addr) = (addr("127.0.0.1:1001"), addr("127.0.0.2:1002"));
+        let (to_b, to_a) = link(&a, a_addr, &b, b_addr).await;
+        let (to_b, to_a) = (to_b.unwrap(), to_a.unwrap());
+        assert_eq!(a.outbound_count(), 1);
+        assert_eq!(b.inbound_count(), 1);
+        assert_eq!(a_events.events(), vec![format!("connected {}", to_b)]);
+
+        // Keepalive: the pong gives a latency and is not relayed.
+        a.ping_peers();
+        eventually(|| a.peer(to_b).unwrap().latency.is_some()).await;
+
+        let inv = NetworkMessage::Inv(InvMessage { items: vec![] });
+        b.send(to_a, inv).unwrap();
+        eventually(|| a_events.events().len() == 2).await;
+        assert_eq!(a_events.events()[1], format!("inv from {}", to_b));
+
+        // A second connection to the same address is refused.
+        let (again, _) = link(&a, a_addr, &b, b_addr).await;
+        assert!(matches!(again, Err(PeerError::AlreadyConnected(_))));
+
+        assert!(a.disconnect(to_b, "test"));
+        assert!(!a.disconnect(to_b, "test"));
+        eventually(|| b.peers().is_empty()).await;
+        assert_eq!(
+            b_events.events().last().unwrap(),
+            &format!("disconnected {}", to_a)
+        );
+        assert!(matches!(
+            a.send(to_b, NetworkMessage::Verack(VerackMessage)),
+            Err(PeerError::UnknownPeer(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn inbound_is_limited_to_the_free_slots() {
+        let (hub, _) = manager();
+        assert_eq!(hub.config().inbound_slots(), 1);
+        let (first, _) = manager();
+        let (second, _) = manager();
+        let hub_addr = addr("127.0.0.1:2000");
+
+        let (_, accepted) = link(&first, addr("127.0.0.3:1"), &hub, hub_addr).await;
+        accepted.unwrap();
+        assert!(!hub.has_inbound_slot());
+        let (_, refused) = link(&second, addr("127.0.0.4:1"), &hub, hub_addr).await;
+        assert!(matches!(refused, Err(PeerError::NoSlots)));
+
+        // A connection still handshaking holds its slot too.
+        let (hub, _) = manager();
+        let (silent, right) = duplex(1 << 16);
+        let stream = PeerStream::new(right, MessageCodec::new(&NetworkType::Regtest));
+        let pending = tokio::spawn({
+            let hub = Arc::clone(&hub);
+            async move { hub.add_connection(stream, addr("127.0.0.5:1"), true).await }
+        });
+        eventually(|| !hub.has_inbound_slot()).await;
+        let (_, refused) = link(&second, addr("127.0.0.4:1"), &hub, hub_addr).await;
+        assert!(matches!(refused, Err(PeerError::NoSlots)));
+
+        drop(silent);
+        assert!(pending.await.unwrap().is_err());
+        assert!(hub.has_inbound_slot());
+    }
+
+    #[tokio::test]
+    async fn misbehaving_peers_are_banned() {
+        let mut strict = config();
+        strict.ban_threshold = 20;
+        let a = PeerManager::new(strict);
+        let (b, _) = manager();
+        let (a_addr, b_addr) = (addr("127.0.0.1:3001"), addr("127.0.0.2:3002"));
+        let (to_b, to_a) = link(&a, a_addr, &b, b_addr).await;
+        let (to_b, to_a) = (to_b.unwrap(), to_a.unwrap());
+
+        // A repeated handshake message scores; the second reaches the threshold.
+        b.send(to_a, NetworkMessage::Verack(VerackMessage)).unwrap();
+        eventually(|| a.peer(to_b).is_some_and(|p| p.misbehavior == 10)).await;
+        b.send(to_a, NetworkMessage::Verack(VerackMessage)).unwrap();
+        eventually(|| a.peers().is_empty() && b.peers().is_empty()).await;
+        assert!(a.is_banned(&b_addr.ip()));
+        assert_eq!(a.banned()[0].reason, "unsolicited data");
+
+        let (refused, _) = link(&a, a_addr, &b, b_addr).await;
+        assert!(matches!(refused, Err(PeerError::Banned(ip)) if ip == b_addr.ip()));
+
+        assert!(a.unban(&b_addr.ip()));
+        let (to_b, _) = link(&a, a_addr, &b, b_addr).await;
+        to_b.unwrap();
+        a.ban(b_addr.ip(), Duration::from_secs(60), "manual");
+        assert!(a.peers().is_empty());
+        a.clear_bans();
+        assert!(a.banned().is_empty());
+    }
+
+    #[tokio::test]
+    async fn exchanges_addresses_within_rate_limits() {
+        let (a, _) = manager();
+        let (b, _) = manager();
+        for i in 1..=20u8 {
+            b.add_address(SocketAddr::new(IpAddr::from([30 + i, 1, 1, 1]), 8333));
+        }
+        let expected = (b.address_count() * 23).div_ceil(100);
+        let (a_addr, b_addr) = (addr("127.0.0.1:4001"), addr("127.0.0.2:4002"));
+        let (to_b, to_a) = link(&a, a_addr, &b, b_addr).await;
+        let (to_b, to_a) = (to_b.unwrap(), to_a.unwrap());
+
+        // `a` asked on connecting, and `b` answered with a share of its table.
+        eventually(|| a.address_count() > 0).await;
+        assert!(a.address_count() <= expected);
+        assert_eq!(a.peer(to_b).unwrap().misbehavior, 0);
+
+        // Unasked, `a` may only get one address in.
+        let before = b.address_count();
+        let flood: Vec<PeerInfo> = (1..=5u8)
+            .map(|i| {
+                let address = SocketAddr::new(IpAddr::from([60 + i, 2, 2, 2]), 8333);
+                PeerInfo::new(address, PublicKey { key: [0u8; 32] }, String::new())
+            })
+            .collect();
+        let addresses = flood.clone();
+        a.send(to_b, NetworkMessage::Addr(AddrMessage { addresses }))
+            .unwrap();
+        eventually(|| b.address_count() == before + 1).await;
+
+        let addresses = vec![flood[0].clone(); MAX_ADDR_TO_SEND + 1];
+        a.send(to_b, NetworkMessage::Addr(AddrMessage { addresses }))
+            .unwrap();
+        eventually(|| b.peer(to_a).is_some_and(|p| p.misbehavior == 20)).await;
+        assert_eq!(b.address_count(), before + 1);
+    }
+}
@@ -1,15 +1,17 @@
 //! P2P protocol types: messages, headers, peer info, and helper crypto.
 //! Heavy Clippy cleanup, consistent `Hash` newtype, explicit errors.
 
-use bincode;
+use bincode::{self, Options};
 use hex;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha512};
 use std::fmt;
 use std::net::{IpAddr, SocketAddr};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::blockchain::merkle::MerkleTree;
+use crate::blockchain::merkle::{MerkleProof, MerkleTree};
 use crate::database::utxo_set::{hash_transaction as hash512_tx, OutPoint};
 
 /// Public, 64-byte SHA-512 hash newtype (binary form).
@@ -103,6 +105,9 @@ impl std::error::Error for ProtocolError {}
 /// Maximum message size (bytes) for safety.
 pub const MAX_MESSAGE_SIZE: usize = 2 * 1024 * 1024; // 2 MiB
 
+/// Bytes of a `MessageHeader` on the wire (see `MessageHeader::to_bytes`).
+pub const MESSAGE_HEADER_SIZE: usize = 4 + 12 + 4 + 64;
+
 /// Wire protocol version.
 pub const PROTOCOL_VERSION: u32 = 1;
 
@@ -138,15 +143,21 @@ mod builtin_crypto {
     }
 }
 
-use builtin_crypto::{sha512_hash, PublicKeyBuiltin as PublicKey};
+use builtin_crypto::sha512_hash;
+pub use builtin_crypto::PublicKeyBuiltin as PublicKey;
 
 /// Compact transaction reference.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct InvEntry {
-    pub kind: u8, // 1 = block, 2 = tx
+    pub kind: u8, // INV_TX or INV_BLOCK
     pub hash: Hash,
 }
 
+/// `InvEntry::kind` for a transaction (Bitcoin's `MSG_TX`).
+pub const INV_TX: u8 = 1;
+/// `InvEntry::kind` for a block (Bitcoin's `MSG_BLOCK`).
+pub const INV_BLOCK: u8 = 2;
+
 /// Version handshake message.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct VersionMessage {
@@ -195,8 +206,16 @@ pub struct GetDataMessage {
     pub items: Vec<InvEntry>,
 }
 
+/// First header version that commits to the UTXO set.
+pub const UTXO_COMMITMENT_VERSION: u32 = 2;
+
 /// Block header (simplified).
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+///
+/// From `UTXO_COMMITMENT_VERSION` on, a header also carries `utxo_root`:
+/// the `database::utxo_commitment` root of the UTXO set the block builds on.
+/// Older headers encode exactly as before, so their hashes are unchanged,
+/// and their `utxo_root` is ignored (it decodes as zero).
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlockHeader {
     pub version: u32,
     pub prev_block: Hash,
@@ -204,6 +223,104 @@ pub struct BlockHeader {
     pub time: u32,
     pub bits: u32,
     pub nonce: u32,
+    pub utxo_root: Hash,
+}
+
+/// `BlockHeader` as a map, for human-readable formats.
+#[derive(Serialize, Deserialize)]
+struct BlockHeaderFields {
+    version: u32,
+    prev_block: Hash,
+    merkle_root: Hash,
+    time: u32,
+    bits: u32,
+    nonce: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    utxo_root: Option<Hash>,
+}
+
+impl Serialize for BlockHeader {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let utxo_root = self.commits_utxo_set().then_some(self.utxo_root);
+        if serializer.is_human_readable() {
+            return BlockHeaderFields {
+                version: self.version,
+                prev_block: self.prev_block,
+                merkle_root: self.merkle_root,
+                time: self.time,
+                bits: self.bits,
+                nonce: self.nonce,
+                utxo_root,
+            }
+            .serialize(serializer);
+        }
+
+        let len = if utxo_root.is_some() { 7 } else { 6 };
+        let mut tuple = serializer.serialize_tuple(len)?;
+        tuple.serialize_element(&self.version)?;
+        tuple.serialize_element(&self.prev_block)?;
+        tuple.serialize_element(&self.merkle_root)?;
+        tuple.serialize_element(&self.time)?;
+        tuple.serialize_element(&self.bits)?;
+        tuple.serialize_element(&self.nonce)?;
+        if let Some(utxo_root) = &utxo_root {
+            tuple.serialize_element(utxo_root)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHeader {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let fields = BlockHeaderFields::deserialize(deserializer)?;
+            let header = BlockHeader {
+                version: fields.version,
+                prev_block: fields.prev_block,
+                merkle_root: fields.merkle_root,
+                time: fields.time,
+                bits: fields.bits,
+                nonce: fields.nonce,
+                utxo_root: fields.utxo_root.unwrap_or_else(Hash::zero),
+            };
+            if header.commits_utxo_set() != fields.utxo_root.is_some() {
+                return Err(de::Error::custom(
+                    "utxo_root must be present exactly when the version commits to it",
+                ));
+            }
+            return Ok(header);
+        }
+
+        struct HeaderVisitor;
+
+        impl<'de> Visitor<'de> for HeaderVisitor {
+            type Value = BlockHeader;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a block header")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<BlockHeader, A::Error> {
+                let missing = |index| de::Error::invalid_length(index, &"a block header");
+                let mut header = BlockHeader {
+                    version: seq.next_element()?.ok_or_else(|| missing(0))?,
+                    prev_block: seq.next_element()?.ok_or_else(|| missing(1))?,
+                    merkle_root: seq.next_element()?.ok_or_else(|| missing(2))?,
+                    time: seq.next_element()?.ok_or_else(|| missing(3))?,
+                    bits: seq.next_element()?.ok_or_else(|| missing(4))?,
+                    nonce: seq.next_element()?.ok_or_else(|| missing(5))?,
+                    utxo_root: Hash::zero(),
+                };
+                if header.commits_utxo_set() {
+                    header.utxo_root = seq.next_element()?.ok_or_else(|| missing(6))?;
+                }
+                Ok(header)
+            }
+        }
+
+        // The length is an upper bound: version-1 headers stop after six.
+        deserializer.deserialize_tuple(7, HeaderVisitor)
+    }
 }
 
 impl BlockHeader {
@@ -211,6 +328,17 @@ impl BlockHeader {
         let serialized = bincode::serialize(self).expect("header serialize");
         Hash(hash512_tx(&serialized))
     }
+
+    /// Whether this header's version carries `utxo_root`.
+    pub fn commits_utxo_set(&self) -> bool {
+        self.version >= UTXO_COMMITMENT_VERSION
+    }
+
+    /// Whether `proof` shows `txid` is committed by this header; all a light
+    /// client holding only headers needs to check a payment.
+    pub fn verify_transaction(&self, txid: &Hash, proof: &MerkleProof) -> bool {
+        proof.leaf == *txid && proof.verify(self.merkle_root.as_bytes())
+    }
 }
 
 /// Block + transactions.
@@ -229,6 +357,12 @@ impl Block {
         let t = MerkleTree::new(&leaves).expect("merkle requires at least one leaf");
         Hash(t.root())
     }
+
+    /// Inclusion proof for `txid`, if the block contains it.
+    pub fn merkle_proof(&self, txid: &Hash) -> Option<MerkleProof> {
+        let leaves: Vec<[u8; 64]> = self.tx_hashes.iter().map(|h| *h.as_bytes()).collect();
+        MerkleTree::new(&leaves).ok()?.proof_for(txid.as_bytes())
+    }
 }
 
 /// Transaction (compact demo form).
@@ -236,6 +370,9 @@ impl Block {
 pub struct Transaction {
     pub inputs: Vec<OutPoint>,
     pub outputs: Vec<(OutPoint, u64)>, // (outpoint, value)
+    /// Opt-in replace-by-fee signal: while unconfirmed, this transaction may
+    /// be replaced by a conflicting one that pays more.
+    pub replaceable: bool,
 }
 
 impl Transaction {
@@ -334,6 +471,64 @@ impl NetworkMessage {
         let bytes = self.to_bytes()?;
         Ok(Hash(sha512_hash(&bytes)))
     }
+
+    /// Command name carried in the `MessageHeader` of this message.
+    pub fn command(&self) -> &'static str {
+        match self {
+            NetworkMessage::Version(_) => "version",
+            NetworkMessage::Verack(_) => "verack",
+            NetworkMessage::Ping(_) => "ping",
+            NetworkMessage::Pong(_) => "pong",
+            NetworkMessage::GetAddr(_) => "getaddr",
+            NetworkMessage::Addr(_) => "addr",
+            NetworkMessage::Inv(_) => "inv",
+            NetworkMessage::GetData(_) => "getdata",
+            NetworkMessage::Block(_) => "block",
+        }
+    }
+
+    /// Wire payload: the bincode encoding of the inner message. The variant
+    /// travels as the header's command.
+    pub fn payload_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        let encoded = match self {
+            NetworkMessage::Version(m) => bincode::serialize(m),
+            NetworkMessage::Verack(m) => bincode::serialize(m),
+            NetworkMessage::Ping(m) => bincode::serialize(m),
+            NetworkMessage::Pong(m) => bincode::serialize(m),
+            NetworkMessage::GetAddr(m) => bincode::serialize(m),
+            NetworkMessage::Addr(m) => bincode::serialize(m),
+            NetworkMessage::Inv(m) => bincode::serialize(m),
+            NetworkMessage::GetData(m) => bincode::serialize(m),
+            NetworkMessage::Block(m) => bincode::serialize(m),
+        };
+        encoded.map_err(|e| ProtocolError::SerializationError(e.to_string()))
+    }
+
+    /// Decode a wire payload for `command`. Returns `Ok(None)` for commands
+    /// this node does not know; the payload must be consumed exactly.
+    pub fn from_payload(command: &str, payload: &[u8]) -> Result<Option<Self>, ProtocolError> {
+        fn decode<T: serde::de::DeserializeOwned>(payload: &[u8]) -> Result<T, ProtocolError> {
+            // Bounded by the payload, so length prefixes cannot over-allocate.
+            bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .with_limit(payload.len() as u64)
+                .deserialize(payload)
+                .map_err(|e| ProtocolError::SerializationError(e.to_string()))
+        }
+        let message = match command {
+            "version" => NetworkMessage::Version(decode(payload)?),
+            "verack" => NetworkMessage::Verack(decode(payload)?),
+            "ping" => NetworkMessage::Ping(decode(payload)?),
+            "pong" => NetworkMessage::Pong(decode(payload)?),
+            "getaddr" => NetworkMessage::GetAddr(decode(payload)?),
+            "addr" => NetworkMessage::Addr(decode(payload)?),
+            "inv" => NetworkMessage::Inv(decode(payload)?),
+            "getdata" => NetworkMessage::GetData(decode(payload)?),
+            "block" => NetworkMessage::Block(decode(payload)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(message))
+    }
 }
 
 /// Message header with checksum to guard payload integrity.
@@ -346,7 +541,9 @@ pub struct MessageHeader {
 }
 
 impl MessageHeader {
-    pub fn new(command: &str, payload: &[u8]) -> Result<Self, ProtocolError> {
+    /// Header for `payload` on the network identified by `magic`
+    /// (`NetworkType::magic_bytes`).
+    pub fn new(magic: u32, command: &str, payload: &[u8]) -> Result<Self, ProtocolError> {
         if payload.len() > MAX_MESSAGE_SIZE {
             return Err(ProtocolError::InvalidMessage);
         }
@@ -358,7 +555,7 @@ impl MessageHeader {
         cmd[..b.len()].copy_from_slice(b);
         let checksum = Hash(sha512_hash(payload));
         Ok(Self {
-            magic: 0xD9B4BEF9, // bitcoin mainnet magic (demo)
+            magic,
             command: cmd,
             length: payload.len() as u32,
             checksum,
@@ -368,6 +565,48 @@ impl MessageHeader {
     pub fn verify_checksum(&self, payload: &[u8]) -> bool {
         Hash(sha512_hash(payload)) == self.checksum
     }
+
+    /// Command name without its NUL padding. Fails unless the name is
+    /// printable ASCII followed only by NULs.
+    pub fn command_name(&self) -> Result<&str, ProtocolError> {
+        let end = self
+            .command
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.command.len());
+        let (name, padding) = self.command.split_at(end);
+        if !name.iter().all(u8::is_ascii_graphic) || padding.iter().any(|&b| b != 0) {
+            return Err(ProtocolError::InvalidMessage);
+        }
+        std::str::from_utf8(name).map_err(|_| ProtocolError::InvalidMessage)
+    }
+
+    /// Wire form: `magic` (LE) || `command` || `length` (LE) || `checksum`.
+    pub fn to_bytes(&self) -> [u8; MESSAGE_HEADER_SIZE] {
+        let mut out = [0u8; MESSAGE_HEADER_SIZE];
+        out[..4].copy_from_slice(&self.magic.to_le_bytes());
+        out[4..16].copy_from_slice(&self.command);
+        out[16..20].copy_from_slice(&self.length.to_le_bytes());
+        out[20..].copy_from_slice(self.checksum.as_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8; MESSAGE_HEADER_SIZE]) -> Self {
+        let mut magic = [0u8; 4];
+        magic.copy_from_slice(&bytes[..4]);
+        let mut command = [0u8; 12];
+        command.copy_from_slice(&bytes[4..16]);
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&bytes[16..20]);
+        let mut checksum = [0u8; 64];
+        checksum.copy_from_slice(&bytes[20..]);
+        Self {
+            magic: u32::from_le_bytes(magic),
+            command,
+            length: u32::from_le_bytes(length),
+            checksum: Hash(checksum),
+        }
+    }
 }
 
 /// Framed wire message (header + payload).
@@ -378,23 +617,33 @@ pub struct FramedMessage {
 }
 
 impl FramedMessage {
-    pub fn new(command: &str, message: &NetworkMessage) -> Result<Self, ProtocolError> {
-        let payload = message.to_bytes()?;
-        let header = MessageHeader::new(command, &payload)?;
+    pub fn new(magic: u32, message: &NetworkMessage) -> Result<Self, ProtocolError> {
+        let payload = message.payload_bytes()?;
+        let header = MessageHeader::new(magic, message.command(), &payload)?;
         Ok(Self { header, payload })
     }
 
+    /// The message `payload` holds according to `header`'s command;
+    /// `Ok(None)` for unknown commands.
     pub fn parse_message(
         header: &MessageHeader,
         payload: &[u8],
-    ) -> Result<NetworkMessage, ProtocolError> {
-        if !header.verify_checksum(payload) {
+    ) -> Result<Option<NetworkMessage>, ProtocolError> {
+        if payload.len() > MAX_MESSAGE_SIZE || payload.len() != header.length as usize {
             return Err(ProtocolError::InvalidMessage);
         }
-        if payload.len() > MAX_MESSAGE_SIZE {
+        if !header.verify_checksum(payload) {
             return Err(ProtocolError::InvalidMessage);
         }
-        NetworkMessage::from_bytes(payload)
+        NetworkMessage::from_payload(header.command_name()?, payload)
+    }
+
+    /// Header then payload, as sent on the wire.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MESSAGE_HEADER_SIZE + self.payload.len());
+        out.extend_from_slice(&self.header.to_bytes());
+        out.extend_from_slice(&self.payload);
+        out
     }
 }
 
@@ -412,6 +661,106 @@ mod tests {
         let _id = peer_info.id();
     }
 
+    #[test]
+    fn header_verifies_transaction_proofs() {
+        let tx_hashes: Vec<Hash> = (0..5u8).map(|i| Hash(hash512_tx(&[i]))).collect();
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                prev_block: Hash::zero(),
+                merkle_root: Hash::zero(),
+                time: 0,
+                bits: 0x207f_ffff,
+                nonce: 0,
+                utxo_root: Hash::zero(),
+            },
+            tx_hashes: tx_hashes.clone(),
+        };
+        block.header.merkle_root = block.merkle_root();
+
+        for (i, txid) in tx_hashes.iter().enumerate() {
+            let proof = block.merkle_proof(txid).unwrap();
+            assert!(block.header.verify_transaction(txid, &proof));
+            let neighbour = &tx_hashes[(i + 1) % tx_hashes.len()];
+            assert!(!block.header.verify_transaction(neighbour, &proof));
+        }
+        assert!(block.merkle_proof(&Hash::zero()).is_none());
+
+        let proof = block.merkle_proof(&tx_hashes[3]).unwrap();
+        let mut other = block.header.clone();
+        other.merkle_root = Hash(hash512_tx(b"other"));
+        assert!(!other.verify_transaction(&tx_hashes[3], &proof));
+    }
+
+    #[test]
+    fn utxo_root_is_encoded_from_its_version_on() {
+        let v1 = BlockHeader {
+            version: 1,
+            prev_block: Hash([1u8; 64]),
+            merkle_root: Hash([2u8; 64]),
+            time: 3,
+            bits: 4,
+            nonce: 5,
+            utxo_root: Hash::zero(),
+        };
+        // Six fields, as before the commitment existed.
+        let encoded = bincode::serialize(&v1).unwrap();
+        assert_eq!(encoded.len(), 4 + 2 * (8 + 64) + 3 * 4);
+        assert_eq!(bincode::deserialize::<BlockHeader>(&encoded).unwrap(), v1);
+
+        let mut v2 = v1.clone();
+        v2.version = UTXO_COMMITMENT_VERSION;
+        v2.utxo_root = Hash([6u8; 64]);
+        let encoded = bincode::serialize(&v2).unwrap();
+        assert_eq!(encoded.len(), 4 + 3 * (8 + 64) + 3 * 4);
+        assert_eq!(bincode::deserialize::<BlockHeader>(&encoded).unwrap(), v2);
+
+        let mut other_root = v2.clone();
+        other_root.utxo_root = Hash([7u8; 64]);
+        assert_ne!(other_root.hash(), v2.hash());
+
+        let json = serde_json::to_value(&v2).unwrap();
+        assert_eq!(json["utxo_root"], hex::encode([6u8; 64]));
+        assert_eq!(serde_json::from_value::<BlockHeader>(json).unwrap(), v2);
+        let json = serde_json::to_value(&v1).unwrap();
+        assert!(json.get("utxo_root").is_none());
+        assert_eq!(serde_json::from_value::<BlockHeader>(json).unwrap(), v1);
+    }
+
+    #[test]
+    fn framed_messages_carry_their_command() {
+        let magic = crate::config::NetworkType::Testnet.magic_bytes();
+        let ping = NetworkMessage::Ping(PingMessage { nonce: 9 });
+        let framed = FramedMessage::new(magic, &ping).unwrap();
+        assert_eq!(framed.header.magic, magic);
+        assert_eq!(framed.header.command_name().unwrap(), "ping");
+
+        let bytes = framed.to_bytes();
+        let mut header = [0u8; MESSAGE_HEADER_SIZE];
+        header.copy_from_slice(&bytes[..MESSAGE_HEADER_SIZE]);
+        let header = MessageHeader::from_bytes(&header);
+        assert_eq!(header, framed.header);
+        let payload = &bytes[MESSAGE_HEADER_SIZE..];
+        assert_eq!(
+            FramedMessage::parse_message(&header, payload).unwrap(),
+            Some(ping)
+        );
+
+        // The command picks the variant; a mismatched payload fails.
+        let mut as_verack = header.clone();
+        as_verack.command = *b"verack\0\0\0\0\0\0";
+        assert!(FramedMessage::parse_message(&as_verack, payload).is_err());
+        let mut unknown = header.clone();
+        unknown.command = *b"sendcmpct\0\0\0";
+        assert_eq!(
+            FramedMessage::parse_message(&unknown, payload).unwrap(),
+            None
+        );
+        let mut garbled = header;
+        garbled.command = *b"ping\0x\0\0\0\0\0\0";
+        assert!(garbled.command_name().is_err());
+    }
+
     #[test]
     fn checksum_len() {
         let ping_msg = NetworkMessage::Ping(PingMessage { nonce: 12345 });
@@ -6,7 +6,11 @@ use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::database::DatabaseManager;
-use crate::network::{GetBlocksMessage, Hash, InvMessage, PeerInfo, ProtocolError};
+use crate::mining::check_relayed_block;
+use crate::network::ban::Misbehavior;
+use crate::network::peer_manager::{ConnectedPeer, PeerEventListener};
+use crate::network::protocol::{INV_BLOCK, INV_TX};
+use crate::network::{GetBlocksMessage, Hash, InvMessage, NetworkMessage, PeerInfo, ProtocolError};
 
 #[derive(Debug, Clone)]
 pub enum SyncError {
@@ -277,22 +281,26 @@ impl SyncManager {
         Ok(BlockLocator::new(vec![], Hash::from_bytes([0u8; 64])))
     }
 
+    fn update_peer_count(&self) {
+        let active = self.active_peers.read().unwrap().len();
+        self.update_state(|state| state.peers_connected = active);
+    }
+
     async fn get_blocks_to_download(&self) -> Result<Vec<Hash>, SyncError> {
         Ok(vec![])
     }
 
+    // One arm per inventory type, even where an arm could fold into a guard.
+    #[allow(clippy::collapsible_match)]
     pub fn handle_inv_message(&self, inv: InvMessage, _peer_id: &str) -> Result<(), SyncError> {
         for item in inv.items {
             match item.kind {
-                2 => {
-                    // MSG_BLOCK
+                INV_BLOCK => {
                     if !self.is_block_known(&item.hash) {
                         self.block_queue.write().unwrap().push_back(item.hash);
                     }
                 }
-                1 => {
-                    // MSG_TX
-                }
+                INV_TX => {}
                 _ => {}
             }
         }
@@ -318,6 +326,51 @@ impl SyncManager {
     }
 }
 
+/// Connected peers become sync candidates, and their announcements are
+/// queued for download. Blocks nobody asked for count against the sender.
+impl PeerEventListener for SyncManager {
+    fn peer_connected(&self, peer: &ConnectedPeer) {
+        let id = peer.info.id();
+        self.add_peer(peer.info.clone());
+        self.mark_peer_active(&id);
+        self.update_peer_count();
+    }
+
+    fn peer_disconnected(&self, peer: &ConnectedPeer, _reason: &str) {
+        self.remove_peer(&peer.info.id());
+        self.update_peer_count();
+    }
+
+    fn peer_message(
+        &self,
+        peer: &ConnectedPeer,
+        message: &NetworkMessage,
+    ) -> Result<(), Misbehavior> {
+        match message {
+            NetworkMessage::Inv(inv) => {
+                let _ = self.handle_inv_message(inv.clone(), &peer.info.id());
+            }
+            NetworkMessage::Block(block) => {
+                let hash = block.header.hash();
+                if !self.requested_blocks.read().unwrap().contains(&hash) {
+                    return Err(Misbehavior::UnsolicitedData);
+                }
+                if let Err(rejection) = check_relayed_block(block) {
+                    log::debug!(
+                        "Block {} from {} rejected: {}",
+                        hash,
+                        peer.info.id(),
+                        rejection
+                    );
+                    return Err(Misbehavior::InvalidBlock);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
 pub struct SyncScheduler {
     sync_manager: Arc<SyncManager>,
     interval: Duration,
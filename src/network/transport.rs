@@ -0,0 +1,359 @@
+//! Peer transport: `NetworkMessage`s framed on a byte stream.
+//!
+//! Every message is a `MessageHeader` in its fixed wire form
+//! (`MESSAGE_HEADER_SIZE` bytes) followed by `length` bytes of payload. The
+//! header's magic must be the local network's (`NetworkType::magic_bytes`),
+//! and its length is checked against the codec's limit before any payload
+//! buffer is allocated. The command selects the `NetworkMessage` variant.
+//!
+//! A message with an unknown command or a bad checksum has had its payload
+//! read in full, so the stream is still in step and the caller may carry on
+//! (`TransportError::is_fatal`). Any other error leaves the stream
+//! unusable.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+
+use super::protocol::{
+    FramedMessage, MessageHeader, NetworkMessage, ProtocolError, MAX_MESSAGE_SIZE,
+    MESSAGE_HEADER_SIZE,
+};
+use crate::config::NetworkType;
+
+#[derive(Debug)]
+pub enum TransportError {
+    Io(io::Error),
+    /// The peer closed the connection.
+    Closed,
+    /// The header carried another network's magic.
+    BadMagic(u32),
+    /// The header announced a payload over the codec's limit.
+    Oversized {
+        command: String,
+        length: u32,
+    },
+    BadChecksum {
+        command: String,
+    },
+    UnknownCommand(String),
+    Protocol(ProtocolError),
+}
+
+impl TransportError {
+    /// Whether the stream can no longer be used: everything but a skipped
+    /// unknown command or a dropped corrupt message.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            TransportError::UnknownCommand(_) | TransportError::BadChecksum { .. }
+        )
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Io(e) => write!(f, "I/O error: {}", e),
+            TransportError::Closed => write!(f, "Connection closed"),
+            TransportError::BadMagic(magic) => write!(f, "Wrong network magic {:08x}", magic),
+            TransportError::Oversized { command, length } => {
+                write!(f, "Oversized {} message: {} bytes", command, length)
+            }
+            TransportError::BadChecksum { command } => {
+                write!(f, "Bad checksum on {} message", command)
+            }
+            TransportError::UnknownCommand(command) => write!(f, "Unknown command {}", command),
+            TransportError::Protocol(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl From<io::Error> for TransportError {
+    fn from(error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            TransportError::Closed
+        } else {
+            TransportError::Io(error)
+        }
+    }
+}
+
+impl From<ProtocolError> for TransportError {
+    fn from(error: ProtocolError) -> Self {
+        TransportError::Protocol(error)
+    }
+}
+
+/// Frames messages for one network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageCodec {
+    magic: u32,
+    max_message_size: usize,
+}
+
+impl MessageCodec {
+    pub fn new(network: &NetworkType) -> Self {
+        Self {
+            magic: network.magic_bytes(),
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Lower the payload limit below `MAX_MESSAGE_SIZE`.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size.min(MAX_MESSAGE_SIZE);
+        self
+    }
+
+    pub fn magic(&self) -> u32 {
+        self.magic
+    }
+
+    /// Header and payload of `message`, ready to write.
+    pub fn encode(&self, message: &NetworkMessage) -> Result<Vec<u8>, TransportError> {
+        let framed = FramedMessage::new(self.magic, message)?;
+        if framed.payload.len() > self.max_message_size {
+            return Err(TransportError::Oversized {
+                command: message.command().to_string(),
+                length: framed.header.length,
+            });
+        }
+        Ok(framed.to_bytes())
+    }
+
+    /// Parse and check a header: magic, command name and announced length.
+    pub fn decode_header(
+        &self,
+        bytes: &[u8; MESSAGE_HEADER_SIZE],
+    ) -> Result<MessageHeader, TransportError> {
+        let header = MessageHeader::from_bytes(bytes);
+        if header.magic != self.magic {
+            return Err(TransportError::BadMagic(header.magic));
+        }
+        let command = header.command_name()?;
+        if header.length as usize > self.max_message_size {
+            return Err(TransportError::Oversized {
+                command: command.to_string(),
+                length: header.length,
+            });
+        }
+        Ok(header)
+    }
+
+    /// Read the next message from `reader`.
+    pub async fn read_message<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+    ) -> Result<NetworkMessage, TransportError> {
+        let mut header = [0u8; MESSAGE_HEADER_SIZE];
+        reader.read_exact(&mut header).await?;
+        let header = self.decode_header(&header)?;
+
+        let mut payload = vec![0u8; header.length as usize];
+        reader.read_exact(&mut payload).await?;
+
+        let command = header.command_name()?;
+        if !header.verify_checksum(&payload) {
+            return Err(TransportError::BadChecksum {
+                command: command.to_string(),
+            });
+        }
+        NetworkMessage::from_payload(command, &payload)?
+            .ok_or_else(|| TransportError::UnknownCommand(command.to_string()))
+    }
+
+    /// Write `message` to `writer` and flush it.
+    pub async fn write_message<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        message: &NetworkMessage,
+    ) -> Result<(), TransportError> {
+        let bytes = self.encode(message)?;
+        writer.write_all(&bytes).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Receiving half of a `PeerStream`.
+#[derive(Debug)]
+pub struct MessageReader<R> {
+    reader: R,
+    codec: MessageCodec,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    pub fn new(reader: R, codec: MessageCodec) -> Self {
+        Self { reader, codec }
+    }
+
+    pub async fn receive(&mut self) -> Result<NetworkMessage, TransportError> {
+        self.codec.read_message(&mut self.reader).await
+    }
+}
+
+/// Sending half of a `PeerStream`.
+#[derive(Debug)]
+pub struct MessageWriter<W> {
+    writer: W,
+    codec: MessageCodec,
+}
+
+impl<W: AsyncWrite + Unpin> MessageWriter<W> {
+    pub fn new(writer: W, codec: MessageCodec) -> Self {
+        Self { writer, codec }
+    }
+
+    pub async fn send(&mut self, message: &NetworkMessage) -> Result<(), TransportError> {
+        self.codec.write_message(&mut self.writer, message).await
+    }
+}
+
+/// A connection to one peer, sending and receiving whole messages.
+#[derive(Debug)]
+pub struct PeerStream<S> {
+    reader: MessageReader<ReadHalf<S>>,
+    writer: MessageWriter<WriteHalf<S>>,
+}
+
+impl PeerStream<TcpStream> {
+    /// Open a TCP connection to `addr`, giving up after `timeout`.
+    pub async fn connect(
+        addr: SocketAddr,
+        network: &NetworkType,
+        timeout: Duration,
+    ) -> Result<Self, TransportError> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| TransportError::Protocol(ProtocolError::Timeout))??;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream, MessageCodec::new(network)))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> PeerStream<S> {
+    pub fn new(stream: S, codec: MessageCodec) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            reader: MessageReader::new(reader, codec.clone()),
+            writer: MessageWriter::new(writer, codec),
+        }
+    }
+
+    pub async fn send(&mut self, message: &NetworkMessage) -> Result<(), TransportError> {
+        self.writer.send(message).await
+    }
+
+    pub async fn receive(&mut self) -> Result<NetworkMessage, TransportError> {
+        self.reader.receive().await
+    }
+
+    /// Separate halves, so reading and writing can run in different tasks.
+    pub fn into_split(self) -> (MessageReader<ReadHalf<S>>, MessageWriter<WriteHalf<S>>) {
+        (self.reader, self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::{GetAddrMessage, PingMessage, VerackMessage};
+    use tokio::io::duplex;
+
+    fn pair(
+        network: &NetworkType,
+    ) -> (PeerStream<tokio::io::DuplexStream>, tokio::io::DuplexStream) {
+        let (local, remote) = duplex(1 << 16);
+        (PeerStream::new(local, MessageCodec::new(network)), remote)
+    }
+
+    #[tokio::test]
+    async fn messages_roundtrip_over_a_stream() {
+        let (local, remote) = duplex(1 << 16);
+        let mut a = PeerStream::new(local, MessageCodec::new(&NetworkType::Regtest));
+        let mut b = PeerStream::new(remote, MessageCodec::new(&NetworkType::Regtest));
+
+        let messages = vec![
+            NetworkMessage::Verack(VerackMessage),
+            NetworkMessage::Ping(PingMessage { nonce: 7 }),
+            NetworkMessage::GetAddr(GetAddrMessage),
+        ];
+        for message in &messages {
+            a.send(message).await.unwrap();
+        }
+        for message in &messages {
+            assert_eq!(&b.receive().await.unwrap(), message);
+        }
+
+        drop(a);
+        assert!(matches!(b.receive().await, Err(TransportError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn rejects_other_networks_and_oversized_frames() {
+        let ping = NetworkMessage::Ping(PingMessage { nonce: 1 });
+
+        let (mut local, mut remote) = pair(&NetworkType::Regtest);
+        let mainnet = MessageCodec::new(&NetworkType::Mainnet);
+        remote
+            .write_all(&mainnet.encode(&ping).unwrap())
+            .await
+            .unwrap();
+        let err = local.receive().await.unwrap_err();
+        assert!(matches!(err, TransportError::BadMagic(m) if m == mainnet.magic()));
+        assert!(err.is_fatal());
+
+        // The length is refused from the header alone; no payload follows.
+        let codec = MessageCodec::new(&NetworkType::Regtest);
+        let mut header = MessageHeader::new(codec.magic(), "block", &[]).unwrap();
+        header.length = u32::MAX;
+        let (mut local, mut remote) = pair(&NetworkType::Regtest);
+        remote.write_all(&header.to_bytes()).await.unwrap();
+        assert!(matches!(
+            local.receive().await,
+            Err(TransportError::Oversized {
+                length: u32::MAX,
+                ..
+            })
+        ));
+
+        let small = codec.clone().with_max_message_size(4);
+        assert!(matches!(
+            small.encode(&ping),
+            Err(TransportError::Oversized { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn skips_unknown_commands_and_corrupt_payloads() {
+        let codec = MessageCodec::new(&NetworkType::Testnet);
+        let (local, mut remote) = duplex(1 << 16);
+        let mut stream = PeerStream::new(local, codec.clone());
+
+        let unknown = FramedMessage {
+            header: MessageHeader::new(codec.magic(), "sendcmpct", &[1, 2, 3]).unwrap(),
+            payload: vec![1, 2, 3],
+        };
+        remote.write_all(&unknown.to_bytes()).await.unwrap();
+        let mut corrupt = codec
+            .encode(&NetworkMessage::Ping(PingMessage { nonce: 2 }))
+            .unwrap();
+        *corrupt.last_mut().unwrap() ^= 1;
+        remote.write_all(&corrupt).await.unwrap();
+        let next = NetworkMessage::Ping(PingMessage { nonce: 3 });
+        codec.write_message(&mut remote, &next).await.unwrap();
+
+        let err = stream.receive().await.unwrap_err();
+        assert!(matches!(&err, TransportError::UnknownCommand(c) if c == "sendcmpct"));
+        assert!(!err.is_fatal());
+        let err = stream.receive().await.unwrap_err();
+        assert!(matches!(err, TransportError::BadChecksum { .. }));
+        assert_eq!(stream.receive().await.unwrap(), next);
+    }
+}
@@ -0,0 +1,150 @@
+//! Chain state RPC calls.
+//!
+//! - `auditsupply [bucket_blocks?]`: audit the UTXO set against the
+//!   emission schedule at the current tip (see `blockchain::audit`). The
+//!   reply carries the totals, any height ranges where more coins are held
+//!   than were issued, and a yearly supply and inflation table.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::sync::{Arc, RwLock};
+
+use super::{RpcError, RpcHandler, RPC_INTERNAL_ERROR};
+use crate::blockchain::audit::{
+    audit_supply, inflation_table, inflation_table_years, BurnLedger, DEFAULT_AUDIT_BUCKET_BLOCKS,
+};
+use crate::blockchain::reward::EmissionSchedule;
+use crate::database::utxo_set::UTXOSet;
+use crate::mining::ChainEngine;
+
+/// Serves `auditsupply`.
+pub struct BlockchainRpc {
+    emission: EmissionSchedule,
+    chain: Arc<dyn ChainEngine>,
+    utxos: Arc<RwLock<UTXOSet>>,
+    burns: Arc<RwLock<BurnLedger>>,
+}
+
+impl BlockchainRpc {
+    /// `utxos` and `burns` are the set and ledger the chain engine connects
+    /// blocks to (see `MemoryChain::utxos` and `MemoryChain::burns`);
+    /// unspendable outputs are read from `utxos` itself.
+    pub fn new(
+        emission: &EmissionSchedule,
+        chain: Arc<dyn ChainEngine>,
+        utxos: Arc<RwLock<UTXOSet>>,
+        burns: Arc<RwLock<BurnLedger>>,
+    ) -> Self {
+        Self {
+            emission: *emission,
+            chain,
+            utxos,
+            burns,
+        }
+    }
+
+    async fn audit_supply(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let bucket_blocks = match params.first() {
+            None | Some(Value::Null) => DEFAULT_AUDIT_BUCKET_BLOCKS,
+            Some(value) => value
+                .as_u64()
+                .filter(|&blocks| blocks > 0)
+                .ok_or_else(|| RpcError::invalid_params("bucket_blocks must be positive"))?,
+        };
+
+        // Walking a large UTXO set is slow; keep it off the RPC workers.
+        let emission = self.emission;
+        let height = self.chain.tip().height;
+        let utxos = Arc::clone(&self.utxos);
+        let burns = Arc::clone(&self.burns);
+        let audit = tokio::task::spawn_blocking(move || {
+            let utxos = utxos.read().unwrap();
+            let burns = burns.read().unwrap();
+            audit_supply(&utxos, height, &emission, &burns, bucket_blocks)
+        })
+        .await
+        .map_err(|e| RpcError::new(RPC_INTERNAL_ERROR, e.to_string()))?
+        .map_err(|e| RpcError::new(RPC_INTERNAL_ERROR, e.to_string()))?;
+
+        if !audit.is_sound() {
+            log::error!("{}", audit);
+        }
+
+        Ok(json!({
+            "height": audit.height,
+            "expected": audit.expected,
+            "unspent": audit.unspent_value,
+            "unspendable": audit.unspendable,
+            "burned": audit.burned,
+            "excess": audit.excess(),
+            "shortfall": audit.shortfall(),
+            "sound": audit.is_sound(),
+            "discrepancies": audit.discrepancies,
+            "inflation": inflation_table(&emission, inflation_table_years(&emission)),
+        }))
+    }
+}
+
+#[async_trait]
+impl RpcHandler for BlockchainRpc {
+    fn methods(&self) -> &'static [&'static str] {
+        &["auditsupply"]
+    }
+
+    async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "auditsupply" => self.audit_supply(params).await,
+            _ => Err(RpcError::method_not_found(method)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MiningConfig;
+    use crate::mempool::Mempool;
+    use crate::mining::pow::check_proof_of_work;
+    use crate::mining::{BlockTemplateBuilder, MemoryChain};
+
+    #[tokio::test]
+    async fn audits_the_blocks_the_chain_connected() {
+        let emission = EmissionSchedule::mainnet();
+        let chain = Arc::new(MemoryChain::with_bits(0x207f_ffff));
+        let config = MiningConfig {
+            mining_address: "miner".to_string(),
+            mine_empty_blocks: true,
+            ..MiningConfig::default()
+        };
+        let builder = BlockTemplateBuilder::new(&config);
+        for height in 1..=3u64 {
+            let template = builder
+                .build(&chain.tip(), &Mempool::default(), 2_000)
+                .unwrap();
+            let mut block = template.submission();
+            // Block 2's miner leaves 100 of the subsidy unclaimed.
+            if height == 2 {
+                block.transactions[0].outputs[0].1 -= 100;
+                block.header.merkle_root = block.merkle_root().unwrap();
+            }
+            while !check_proof_of_work(&block.header) {
+                block.header.nonce += 1;
+            }
+            chain.process_block(&block).unwrap();
+        }
+        let rpc = BlockchainRpc::new(&emission, chain.clone(), chain.utxos(), chain.burns());
+
+        let report = rpc.call("auditsupply", &[]).await.unwrap();
+        assert_eq!(report["height"], 3);
+        assert_eq!(report["expected"], emission.total_supply(3));
+        assert_eq!(report["burned"], 100);
+        // The chain starts above a genesis block it never connected.
+        assert_eq!(report["shortfall"], emission.block_reward(0));
+        assert_eq!(report["sound"], true);
+        assert_eq!(report["discrepancies"], json!([]));
+        assert_eq!(report["inflation"].as_array().unwrap().len(), 26);
+
+        let bad = rpc.call("auditsupply", &[json!(0)]).await;
+        assert_eq!(bad.unwrap_err().code, crate::rpc::RPC_INVALID_PARAMS);
+    }
+}
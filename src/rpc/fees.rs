@@ -0,0 +1,177 @@
+//! Fee estimation RPC calls.
+//!
+//! - `estimatesmartfee [conf_target, "CONSERVATIVE"|"ECONOMICAL"?]`: feerate
+//!   (base units per kB) likely to confirm within `conf_target` blocks, from
+//!   the mempool's `FeeEstimator`. Without enough data the reply carries
+//!   `errors` instead of `feerate`, as in Bitcoin Core.
+//! - `estimatetxfee [size, conf_target?]`: the absolute fee the wallet
+//!   attaches to a `size`-byte transaction; the estimate for `conf_target`
+//!   (default 6) while there is one, otherwise
+//!   `WalletConfig::transaction_fee`.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::sync::{Arc, RwLock};
+
+use super::{RpcError, RpcHandler};
+use crate::config::WalletConfig;
+use crate::mempool::{EstimateMode, Mempool};
+
+/// Target `estimatetxfee` uses when none is given.
+const DEFAULT_WALLET_TARGET: u32 = 6;
+
+/// Serves `estimatesmartfee` and `estimatetxfee`.
+pub struct FeeRpc {
+    wallet: WalletConfig,
+    mempool: Arc<RwLock<Mempool>>,
+}
+
+impl FeeRpc {
+    pub fn new(wallet: &WalletConfig, mempool: Arc<RwLock<Mempool>>) -> Self {
+        Self {
+            wallet: wallet.clone(),
+            mempool,
+        }
+    }
+
+    fn estimate_smart_fee(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let target = params
+            .first()
+            .and_then(Value::as_u64)
+            .and_then(|target| u32::try_from(target).ok())
+            .filter(|&target| target > 0)
+            .ok_or_else(|| RpcError::invalid_params("conf_target must be a positive integer"))?;
+        let mode = match params.get(1) {
+            None | Some(Value::Null) => EstimateMode::Conservative,
+            Some(value) => match value.as_str().map(str::to_ascii_uppercase).as_deref() {
+                Some("CONSERVATIVE") => EstimateMode::Conservative,
+                Some("ECONOMICAL") => EstimateMode::Economical,
+                _ => return Err(RpcError::invalid_params("Invalid estimate_mode parameter")),
+            },
+        };
+
+        let mempool = self.mempool.read().unwrap();
+        let Some(estimator) = mempool.fee_estimator() else {
+            return Ok(json!({ "errors": ["Fee estimation disabled"], "blocks": 0 }));
+        };
+        let blocks = target.min(estimator.max_target());
+        Ok(match estimator.estimate_fee(target, mode) {
+            Some(feerate) => json!({ "feerate": feerate, "blocks": blocks }),
+            None => {
+                json!({ "errors": ["Insufficient data or no feerate found"], "blocks": blocks })
+            }
+        })
+    }
+
+    fn estimate_tx_fee(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let size = params
+            .first()
+            .and_then(Value::as_u64)
+            .and_then(|size| usize::try_from(size).ok())
+            .filter(|&size| size > 0)
+            .ok_or_else(|| RpcError::invalid_params("size must be a positive integer"))?;
+        let target = match params.get(1) {
+            None | Some(Value::Null) => DEFAULT_WALLET_TARGET,
+            Some(value) => value
+                .as_u64()
+                .and_then(|target| u32::try_from(target).ok())
+                .filter(|&target| target > 0)
+                .ok_or_else(|| {
+                    RpcError::invalid_params("conf_target must be a positive integer")
+                })?,
+        };
+
+        let mempool = self.mempool.read().unwrap();
+        let (fee, estimated) = match mempool.fee_estimator() {
+            Some(estimator) => (
+                estimator.wallet_fee(&self.wallet, size, target),
+                estimator
+                    .estimate_fee(target, EstimateMode::Conservative)
+                    .is_some(),
+            ),
+            None => (self.wallet.transaction_fee, false),
+        };
+        Ok(json!({ "fee": fee, "estimated": estimated }))
+    }
+}
+
+#[async_trait]
+impl RpcHandler for FeeRpc {
+    fn methods(&self) -> &'static [&'static str] {
+        &["estimatesmartfee", "estimatetxfee"]
+    }
+
+    async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "estimatesmartfee" => self.estimate_smart_fee(params),
+            "estimatetxfee" => self.estimate_tx_fee(params),
+            _ => Err(RpcError::method_not_found(method)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::utxo_set::{MemoryUTXOStorage, TxOutput, UTXOStorage};
+    use crate::mempool::{FeeEstimator, MempoolConfig};
+    use crate::network::protocol::Transaction;
+    use crate::rpc::RPC_INVALID_PARAMS;
+    use crate::test_util::op;
+
+    #[tokio::test]
+    async fn estimates_from_confirmed_mempool_transactions() {
+        let mut pool = Mempool::new(MempoolConfig::default());
+        pool.set_fee_estimator(FeeEstimator::new(pool.config()));
+        let mempool = Arc::new(RwLock::new(pool));
+        let wallet = WalletConfig::default();
+        let rpc = FeeRpc::new(&wallet, Arc::clone(&mempool));
+
+        let none = rpc.call("estimatesmartfee", &[json!(2)]).await.unwrap();
+        assert!(none.get("feerate").is_none());
+        assert_eq!(none["blocks"], 2);
+        let fallback = rpc.call("estimatetxfee", &[json!(250)]).await.unwrap();
+        assert_eq!(
+            fallback,
+            json!({ "fee": wallet.transaction_fee, "estimated": false })
+        );
+
+        // Every transaction confirms in the block after it arrives.
+        let mut utxos = MemoryUTXOStorage::new();
+        for height in 1..=10u64 {
+            let coin = op("coin", height as u32);
+            let out = TxOutput {
+                value: 100_000,
+                script_pubkey: vec![],
+            };
+            utxos.add_output(coin.clone(), out, 0, false).unwrap();
+            let tx = Transaction {
+                inputs: vec![coin],
+                outputs: vec![(op("paid", height as u32), 50_000)],
+                replaceable: false,
+            };
+            let mut pool = mempool.write().unwrap();
+            pool.accept_transaction(tx.clone(), &utxos).unwrap();
+            pool.set_tip_height(height);
+            pool.remove_for_block(&[tx]);
+        }
+
+        let estimate = rpc
+            .call("estimatesmartfee", &[json!(2), json!("economical")])
+            .await
+            .unwrap();
+        let feerate = estimate["feerate"].as_u64().unwrap();
+        assert!(feerate > 0);
+        let fee = rpc
+            .call("estimatetxfee", &[json!(1_000), json!(2)])
+            .await
+            .unwrap();
+        assert_eq!(fee, json!({ "fee": feerate, "estimated": true }));
+
+        for bad in [json!([0]), json!([2, "fast"])] {
+            let params = bad.as_array().unwrap();
+            let err = rpc.call("estimatesmartfee", params).await.unwrap_err();
+            assert_eq!(err.code, RPC_INVALID_PARAMS);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Regtest block generation RPC.
+//!
+//! - `generatetoaddress [nblocks, address, maxtries?]`: mine `nblocks`
+//!   blocks paying `address` and return their hashes once all of them are
+//!   connected. `address` must be a wallet address (128 lowercase hex
+//!   digits, see `crypto::is_valid_address`). See
+//!   `mining::generate_to_address`.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+
+use super::{RpcError, RpcHandler, RPC_INTERNAL_ERROR, RPC_INVALID_ADDRESS_OR_KEY, RPC_MISC_ERROR};
+use crate::config::Config;
+use crate::crypto::is_valid_address;
+use crate::mempool::Mempool;
+use crate::mining::generate::DEFAULT_MAX_TRIES;
+use crate::mining::{generate_to_address, ChainEngine, GenerateError};
+
+/// Serves `generatetoaddress`.
+pub struct GeneratingRpc {
+    config: Config,
+    chain: Arc<dyn ChainEngine>,
+    mempool: Arc<RwLock<Mempool>>,
+}
+
+impl GeneratingRpc {
+    pub fn new(
+        config: &Config,
+        chain: Arc<dyn ChainEngine>,
+        mempool: Arc<RwLock<Mempool>>,
+    ) -> Self {
+        Self {
+            config: config.clone(),
+            chain,
+            mempool,
+        }
+    }
+
+    async fn generate_to_address(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let blocks = params
+            .first()
+            .and_then(Value::as_u64)
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or_else(|| RpcError::invalid_params("nblocks must be a non-negative integer"))?;
+        let address = params
+            .get(1)
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::invalid_params("Expected an address"))?;
+        if !is_valid_address(address) {
+            return Err(RpcError::new(
+                RPC_INVALID_ADDRESS_OR_KEY,
+                "Error: Invalid address",
+            ));
+        }
+        let address = address.to_string();
+        let max_tries = match params.get(2) {
+            None | Some(Value::Null) => DEFAULT_MAX_TRIES,
+            Some(value) => value.as_u64().ok_or_else(|| {
+                RpcError::invalid_params("maxtries must be a non-negative integer")
+            })?,
+        };
+
+        // Hashing runs on a blocking thread so the RPC server stays responsive.
+        let config = self.config.clone();
+        let chain = Arc::clone(&self.chain);
+        let mempool = Arc::clone(&self.mempool);
+        let hashes = tokio::task::spawn_blocking(move || {
+            generate_to_address(
+                &config,
+                chain.as_ref(),
+                &mempool,
+                blocks,
+                &address,
+                max_tries,
+            )
+        })
+        .await
+        .map_err(|e| RpcError::new(RPC_INTERNAL_ERROR, e.to_string()))?
+        .map_err(|e| match e {
+            GenerateError::NotRegtest(_) => RpcError::new(RPC_MISC_ERROR, e.to_string()),
+            e => RpcError::new(RPC_INTERNAL_ERROR, e.to_string()),
+        })?;
+
+        Ok(Value::Array(
+            hashes
+                .iter()
+                .map(|hash| Value::String(hash.to_string()))
+                .collect(),
+        ))
+    }
+}
+
+#[async_trait]
+impl RpcHandler for GeneratingRpc {
+    fn methods(&self) -> &'static [&'static str] {
+        &["generatetoaddress"]
+    }
+
+    async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "generatetoaddress" => self.generate_to_address(params).await,
+            _ => Err(RpcError::method_not_found(method)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NetworkType;
+    use crate::crypto::address_from_public_key;
+    use crate::mining::MemoryChain;
+    use serde_json::json;
+
+    fn rpc(network: NetworkType) -> GeneratingRpc {
+        let config = Config::new(network, None);
+        let chain = Arc::new(MemoryChain::from_config(&config));
+        GeneratingRpc::new(&config, chain, Arc::new(RwLock::new(Mempool::default())))
+    }
+
+    #[tokio::test]
+    async fn generates_on_regtest_only() {
+        let address = json!(address_from_public_key(b"miner"));
+        let regtest = rpc(NetworkType::Regtest);
+        let hashes = regtest
+            .call("generatetoaddress", &[json!(3), address.clone()])
+            .await
+            .unwrap();
+        assert_eq!(hashes.as_array().unwrap().len(), 3);
+        assert_eq!(regtest.chain.tip().height, 3);
+        assert_eq!(hashes[2], regtest.chain.tip().hash.to_string());
+
+        let missing = regtest.call("generatetoaddress", &[json!(1)]).await;
+        assert_eq!(missing.unwrap_err().code, crate::rpc::RPC_INVALID_PARAMS);
+
+        let invalid = regtest
+            .call("generatetoaddress", &[json!(1), json!("alice")])
+            .await;
+        assert_eq!(invalid.unwrap_err().code, RPC_INVALID_ADDRESS_OR_KEY);
+        assert_eq!(regtest.chain.tip().height, 3);
+
+        let mainnet = rpc(NetworkType::Mainnet);
+        let refused = mainnet
+            .call("generatetoaddress", &[json!(1), address])
+            .await;
+        assert_eq!(refused.unwrap_err().code, RPC_MISC_ERROR);
+    }
+}
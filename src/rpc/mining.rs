@@ -0,0 +1,489 @@
+//! Mining RPC calls.
+//!
+//! - `getblocktemplate [{"longpollid"?, "mode"?, "data"?}]`: a BIP22-style
+//!   template from `BlockTemplateBuilder`. Transactions and the coinbase
+//!   are hex of their bincode encoding. Passing a `longpollid` from an
+//!   earlier reply blocks until the tip changes, or until the mempool has
+//!   changed and `mempool_refresh` has passed. `"mode": "proposal"` checks
+//!   `data` against the tip without submitting it. `target` is the full
+//!   512-bit target in hex.
+//! - `submitblock [hex]`: hex of a bincode `BlockSubmission`. Returns `null`
+//!   once the chain engine connects it, otherwise its BIP22 reason string.
+//! - `getmininginfo []`: tip height, difficulty, the chain engine's
+//!   network hashrate estimate (`ChainEngine::estimate_network_hashrate`)
+//!   and the subsidy of the next block.
+
+use async_trait::async_trait;
+use bincode::Options;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::{RpcError, RpcHandler, RPC_DESERIALIZATION_ERROR, RPC_MISC_ERROR};
+use crate::config::MiningConfig;
+use crate::mempool::{transaction_sigops, Mempool};
+use crate::mining::pow::{compact_to_target, difficulty_from_bits, TARGET_LEN};
+use crate::mining::{check_block, BlockSubmission, BlockTemplateBuilder, ChainEngine, ChainTip};
+use crate::network::protocol::Transaction;
+use crate::time::now_secs;
+
+/// How often a waiting long poll looks for a new tip or mempool change.
+const LONGPOLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a mempool-only change is held back before answering a long
+/// poll, so templates are not reissued for every transaction.
+const LONGPOLL_MEMPOOL_REFRESH: Duration = Duration::from_secs(60);
+
+/// A long poll answers with the current template after this long anyway.
+const LONGPOLL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Serves `getblocktemplate`, `submitblock` and `getmininginfo`.
+pub struct MiningRpc {
+    builder: BlockTemplateBuilder,
+    chain: Arc<dyn ChainEngine>,
+    mempool: Arc<RwLock<Mempool>>,
+    longpoll_interval: Duration,
+    mempool_refresh: Duration,
+}
+
+impl MiningRpc {
+    pub fn new(
+        config: &MiningConfig,
+        chain: Arc<dyn ChainEngine>,
+        mempool: Arc<RwLock<Mempool>>,
+    ) -> Self {
+        Self {
+            builder: BlockTemplateBuilder::new(config),
+            chain,
+            mempool,
+            longpoll_interval: LONGPOLL_INTERVAL,
+            mempool_refresh: LONGPOLL_MEMPOOL_REFRESH,
+        }
+    }
+
+    /// Override how often long polls check for changes and how long a
+    /// mempool-only change is held back.
+    pub fn with_longpoll_timing(mut self, interval: Duration, mempool_refresh: Duration) -> Self {
+        self.longpoll_interval = interval;
+        self.mempool_refresh = mempool_refresh;
+        self
+    }
+
+    fn longpoll_state(&self) -> (ChainTip, u64) {
+        let tip = self.chain.tip();
+        let sequence = self.mempool.read().unwrap().sequence();
+        (tip, sequence)
+    }
+
+    /// Wait until the state named by `longpollid` is out of date.
+    async fn wait_for_change(&self, longpollid: &str) {
+        let Some((tip_hex, sequence)) = longpollid
+            .split_once(':')
+            .and_then(|(tip, seq)| Some((tip.to_string(), seq.parse::<u64>().ok()?)))
+        else {
+            return;
+        };
+
+        let started = Instant::now();
+        loop {
+            let (tip, current) = self.longpoll_state();
+            let elapsed = started.elapsed();
+            if tip.hash.to_string() != tip_hex
+                || (current != sequence && elapsed >= self.mempool_refresh)
+                || elapsed >= LONGPOLL_TIMEOUT
+            {
+                return;
+            }
+            tokio::time::sleep(self.longpoll_interval).await;
+        }
+    }
+
+    async fn get_block_template(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let request = match params.first() {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(request)) => request.clone(),
+            Some(_) => {
+                return Err(RpcError::invalid_params(
+                    "Template request must be an object",
+                ))
+            }
+        };
+
+        match request
+            .get("mode")
+            .and_then(Value::as_str)
+            .unwrap_or("template")
+        {
+            "template" => {}
+            "proposal" => {
+                let data = request
+                    .get("data")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| RpcError::invalid_params("Missing data for proposal"))?;
+                let block = decode_block(data)?;
+                return Ok(
+                    match check_block(&block, &self.chain.tip(), now_secs() as u32) {
+                        Ok(()) => Value::Null,
+                        Err(rejection) => Value::String(rejection.reason().to_string()),
+                    },
+                );
+            }
+            mode => return Err(RpcError::invalid_params(format!("Invalid mode: {}", mode))),
+        }
+
+        if let Some(longpollid) = request.get("longpollid") {
+            let longpollid = longpollid
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("longpollid must be a string"))?;
+            self.wait_for_change(longpollid).await;
+        }
+        self.build_template()
+    }
+
+    fn build_template(&self) -> Result<Value, RpcError> {
+        let tip = self.chain.tip();
+        let pool = self.mempool.read().unwrap();
+        let template = self
+            .builder
+            .build(&tip, &pool, now_secs() as u32)
+            .map_err(|e| RpcError::new(RPC_MISC_ERROR, e.to_string()))?;
+
+        // 1-based positions, as BIP22 `depends` counts from the first
+        // non-coinbase transaction.
+        let mut created_by = HashMap::new();
+        let mut transactions = Vec::with_capacity(template.transactions.len());
+        for (position, tx) in template.transactions.iter().enumerate() {
+            let txid = tx.txid();
+            let mut depends: Vec<usize> = tx
+                .inputs
+                .iter()
+                .filter_map(|input| created_by.get(input).copied())
+                .collect();
+            depends.sort_unstable();
+            depends.dedup();
+            for (outpoint, _) in &tx.outputs {
+                created_by.insert(outpoint.clone(), position + 1);
+            }
+            transactions.push(json!({
+                "data": encode_hex(tx)?,
+                "txid": txid.to_string(),
+                "fee": pool.get(&txid).map_or(0, |entry| entry.fee),
+                "sigops": transaction_sigops(tx),
+                "depends": depends,
+            }));
+        }
+
+        let target = compact_to_target(template.header.bits).unwrap_or([0u8; TARGET_LEN]);
+        let config = self.builder.config();
+        Ok(json!({
+            "version": template.header.version,
+            "previousblockhash": template.header.prev_block.to_string(),
+            "height": template.height,
+            "curtime": template.header.time,
+            "mintime": tip.median_time_past.saturating_add(1),
+            "bits": format!("{:08x}", template.header.bits),
+            "target": hex::encode(target),
+            "coinbasevalue": template.reward.saturating_add(template.fees),
+            "coinbasetxn": {
+                "data": encode_hex(&template.coinbase)?,
+                "txid": template.coinbase.txid().to_string(),
+            },
+            "transactions": transactions,
+            "sizelimit": config.block_size_limit,
+            "sigoplimit": config.max_block_sigops,
+            "noncerange": "00000000ffffffff",
+            "mutable": ["time", "transactions", "prevblock"],
+            "longpollid": format!("{}:{}", tip.hash, pool.sequence()),
+        }))
+    }
+
+    fn submit_block(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let data = params
+            .first()
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::invalid_params("Expected block hex"))?;
+        let block = decode_block(data)?;
+        let hash = block.hash();
+
+        match self.chain.process_block(&block) {
+            Ok(()) => {
+                log::info!("Accepted submitted block {}", hash);
+                Ok(Value::Null)
+            }
+            Err(rejection) => {
+                log::info!("Rejected submitted block {}: {}", hash, rejection.reason());
+                Ok(Value::String(rejection.reason().to_string()))
+            }
+        }
+    }
+
+    fn get_mining_info(&self) -> Result<Value, RpcError> {
+        let tip = self.chain.tip();
+        let pooled = self.mempool.read().unwrap().len();
+        Ok(json!({
+            "blocks": tip.height,
+            "bits": format!("{:08x}", tip.bits),
+            "difficulty": difficulty_from_bits(tip.bits),
+            "networkhashps": self.chain.estimate_network_hashrate(),
+            "pooledtx": pooled,
+            "currentblockreward": self.builder.config().emission.block_reward(tip.height + 1),
+        }))
+    }
+}
+
+fn encode_hex(tx: &Transaction) -> Result<String, RpcError> {
+    bincode::serialize(tx)
+        .map(hex::encode)
+        .map_err(|e| RpcError::new(RPC_MISC_ERROR, e.to_string()))
+}
+
+fn decode_block(data: &str) -> Result<BlockSubmission, RpcError> {
+    // Bounded by the input, so length prefixes cannot over-allocate.
+    hex::decode(data)
+        .ok()
+        .and_then(|bytes| {
+            bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .with_limit(bytes.len() as u64)
+                .deserialize(&bytes)
+                .ok()
+        })
+        .ok_or_else(|| RpcError::new(RPC_DESERIALIZATION_ERROR, "Block decode failed"))
+}
+
+#[async_trait]
+impl RpcHandler for MiningRpc {
+    fn methods(&self) -> &'static [&'static str] {
+        &["getblocktemplate", "submitblock", "getmininginfo"]
+    }
+
+    async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "getblocktemplate" => self.get_block_template(params).await,
+            "submitblock" => self.submit_block(params),
+            "getmininginfo" => self.get_mining_info(),
+            _ => Err(RpcError::method_not_found(method)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::reward::calculate_block_reward;
+    use crate::database::utxo_set::{create_outpoint, hash_transaction, TxOutput};
+    use crate::mempool::MempoolConfig;
+    use crate::mining::pow::check_proof_of_work;
+    use crate::mining::template::transactions_merkle_root;
+    use crate::mining::MemoryChain;
+    use crate::network::protocol::{BlockHeader, Hash};
+
+    fn rpc() -> (MiningRpc, Arc<MemoryChain>, Arc<RwLock<Mempool>>) {
+        let chain = Arc::new(MemoryChain::new(ChainTip {
+            hash: Hash([1u8; 64]),
+            height: 9,
+            median_time_past: 1_000,
+            bits: 0x207f_ffff,
+        }));
+        let mempool = Arc::new(RwLock::new(Mempool::new(MempoolConfig::default())));
+        let config = MiningConfig {
+            mining_address: "miner".to_string(),
+            mine_empty_blocks: true,
+            ..MiningConfig::default()
+        };
+        let rpc = MiningRpc::new(&config, chain.clone(), mempool.clone())
+            .with_longpoll_timing(Duration::from_millis(10), Duration::from_millis(200));
+        (rpc, chain, mempool)
+    }
+
+    /// Pool a transaction spending a fresh output of `chain`'s UTXO set.
+    fn pool_spend(chain: &MemoryChain, mempool: &RwLock<Mempool>, tag: &str, fee: u64) {
+        let input = create_outpoint(Hash(hash_transaction(tag.as_bytes())), 0);
+        let utxos = chain.utxos();
+        let mut utxos = utxos.write().unwrap();
+        utxos
+            .add(
+                input.clone(),
+                TxOutput {
+                    value: 50_000,
+                    script_pubkey: vec![],
+                },
+                1,
+                false,
+            )
+            .unwrap();
+        let output = create_outpoint(Hash(hash_transaction(format!("{}-out", tag).as_bytes())), 0);
+        let tx = Transaction {
+            inputs: vec![input],
+            outputs: vec![(output, 50_000 - fee)],
+            replaceable: false,
+        };
+        mempool
+            .write()
+            .unwrap()
+            .accept_transaction(tx, utxos.storage())
+            .unwrap();
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(value: &Value) -> T {
+        bincode::deserialize(&hex::decode(value.as_str().unwrap()).unwrap()).unwrap()
+    }
+
+    /// Assemble and solve a block from a `getblocktemplate` reply, the way
+    /// external mining software would.
+    fn solve(template: &Value) -> BlockSubmission {
+        let mut transactions: Vec<Transaction> = vec![decode(&template["coinbasetxn"]["data"])];
+        for tx in template["transactions"].as_array().unwrap() {
+            transactions.push(decode(&tx["data"]));
+        }
+        let mut header = BlockHeader {
+            version: template["version"].as_u64().unwrap() as u32,
+            prev_block: serde_json::from_value(template["previousblockhash"].clone()).unwrap(),
+            merkle_root: transactions_merkle_root(&transactions).unwrap(),
+            time: template["curtime"].as_u64().unwrap() as u32,
+            bits: u32::from_str_radix(template["bits"].as_str().unwrap(), 16).unwrap(),
+            nonce: 0,
+            utxo_root: Hash::zero(),
+        };
+        while !check_proof_of_work(&header) {
+            header.nonce += 1;
+        }
+        BlockSubmission {
+            header,
+            transactions,
+        }
+    }
+
+    fn block_hex(block: &BlockSubmission) -> Value {
+        Value::String(hex::encode(bincode::serialize(block).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn template_round_trips_through_submitblock() {
+        let (rpc, chain, mempool) = rpc();
+        pool_spend(&chain, &mempool, "a", 3_000);
+
+        let template = rpc.call("getblocktemplate", &[]).await.unwrap();
+        assert_eq!(template["height"], 10);
+        assert_eq!(template["previousblockhash"], Hash([1u8; 64]).to_string());
+        assert_eq!(template["bits"], "207fffff");
+        assert_eq!(template["transactions"][0]["fee"], 3_000);
+        assert_eq!(template["transactions"][0]["depends"], json!([]));
+        assert_eq!(
+            template["coinbasevalue"],
+            calculate_block_reward(10) + 3_000
+        );
+
+        let block = solve(&template);
+        let proposal = rpc
+            .call(
+                "getblocktemplate",
+                &[json!({ "mode": "proposal", "data": block_hex(&block) })],
+            )
+            .await
+            .unwrap();
+        assert_eq!(proposal, Value::Null);
+
+        let accepted = rpc.call("submitblock", &[block_hex(&block)]).await.unwrap();
+        assert_eq!(accepted, Value::Null);
+        assert_eq!(chain.tip().hash, block.hash());
+
+        let again = rpc.call("submitblock", &[block_hex(&block)]).await.unwrap();
+        assert_eq!(again, "duplicate");
+    }
+
+    #[tokio::test]
+    async fn submitblock_reports_rejection_reasons() {
+        let (rpc, _chain, _mempool) = rpc();
+        let template = rpc.call("getblocktemplate", &[]).await.unwrap();
+        let block = solve(&template);
+
+        let mut unsolved = block.clone();
+        while check_proof_of_work(&unsolved.header) {
+            unsolved.header.nonce += 1;
+        }
+        let reply = rpc
+            .call("submitblock", &[block_hex(&unsolved)])
+            .await
+            .unwrap();
+        assert_eq!(reply, "high-hash");
+
+        let mut stale = block;
+        stale.header.prev_block = Hash([2u8; 64]);
+        let reply = rpc.call("submitblock", &[block_hex(&stale)]).await.unwrap();
+        assert_eq!(reply, "inconclusive-not-best-prevblk");
+
+        let garbage = rpc.call("submitblock", &[json!("zz")]).await.unwrap_err();
+        assert_eq!(garbage.code, RPC_DESERIALIZATION_ERROR);
+        // A transaction count far beyond what the data holds.
+        let empty = BlockSubmission {
+            transactions: vec![],
+            ..stale
+        };
+        let mut bytes = bincode::serialize(&empty).unwrap();
+        let count = bytes.len() - 8;
+        bytes[count..].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        let oversized = rpc
+            .call("submitblock", &[json!(hex::encode(bytes))])
+            .await
+            .unwrap_err();
+        assert_eq!(oversized.code, RPC_DESERIALIZATION_ERROR);
+        assert!(rpc.call("submitblock", &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn long_poll_waits_for_tip_or_mempool_change() {
+        let (rpc, chain, mempool) = rpc();
+        let rpc = Arc::new(rpc);
+        let first = rpc.call("getblocktemplate", &[]).await.unwrap();
+        let request = [json!({ "longpollid": first["longpollid"] })];
+
+        // A new tip answers at once.
+        let waiting = tokio::spawn({
+            let rpc = Arc::clone(&rpc);
+            let request = request.clone();
+            async move { rpc.call("getblocktemplate", &request).await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        let block = solve(&first);
+        chain.process_block(&block).unwrap();
+        let next = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next["previousblockhash"], block.hash().to_string());
+        assert_ne!(next["longpollid"], first["longpollid"]);
+
+        // A mempool change answers only after the refresh delay.
+        let request = [json!({ "longpollid": next["longpollid"] })];
+        pool_spend(&chain, &mempool, "b", 1_000);
+        let started = Instant::now();
+        let refreshed = rpc.call("getblocktemplate", &request).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(refreshed["transactions"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_mining_info() {
+        let (rpc, chain, mempool) = rpc();
+        pool_spend(&chain, &mempool, "a", 1_000);
+
+        let info = rpc.call("getmininginfo", &[]).await.unwrap();
+        assert_eq!(info["blocks"], 9);
+        assert_eq!(info["pooledtx"], 1);
+        assert_eq!(info["bits"], "207fffff");
+        assert_eq!(info["difficulty"], difficulty_from_bits(0x207f_ffff));
+        assert!(info["networkhashps"].as_f64().unwrap() > 0.0);
+        assert_eq!(info["currentblockreward"], calculate_block_reward(10));
+
+        assert_eq!(
+            rpc.call("getblocktemplate", &[json!({ "mode": "bogus" })])
+                .await
+                .unwrap_err()
+                .code,
+            crate::rpc::RPC_INVALID_PARAMS
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! JSON-RPC interface.
+//!
+//! `server` accepts JSON-RPC requests (single or batched) as HTTP POST
+//! bodies and routes each call to the `RpcHandler` registered for its
+//! method name. Handlers group related calls: `mining` serves
+//! `getblocktemplate`, `submitblock` and `getmininginfo`; `fees` serves
+//! `estimatesmartfee` and the wallet's `estimatetxfee`; `generating`
+//! serves regtest's `generatetoaddress`; `blockchain` serves the
+//! `auditsupply` check of the UTXO set against the emission schedule;
+//! `network` serves the peer ban list (`setban`, `listbanned`,
+//! `clearbanned`).
+//!
+//! Replies use the Bitcoin Core shape `{"result", "error", "id"}` so
+//! existing mining software can talk to the node unchanged.
+
+pub mod blockchain;
+pub mod fees;
+pub mod generating;
+pub mod mining;
+pub mod network;
+pub mod server;
+
+pub use blockchain::BlockchainRpc;
+pub use fees::FeeRpc;
+pub use generating::GeneratingRpc;
+pub use mining::MiningRpc;
+pub use network::NetworkRpc;
+pub use server::RpcServer;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Malformed JSON-RPC request object.
+pub const RPC_INVALID_REQUEST: i64 = -32600;
+/// No handler registered for the method.
+pub const RPC_METHOD_NOT_FOUND: i64 = -32601;
+/// Wrong number or type of parameters.
+pub const RPC_INVALID_PARAMS: i64 = -32602;
+/// Unexpected failure inside a handler.
+pub const RPC_INTERNAL_ERROR: i64 = -32603;
+/// The request body is not JSON.
+pub const RPC_PARSE_ERROR: i64 = -32700;
+/// Generic application error.
+pub const RPC_MISC_ERROR: i64 = -1;
+/// A parameter could not be decoded (hex, bincode, ...).
+pub const RPC_DESERIALIZATION_ERROR: i64 = -22;
+/// Not a valid wallet address.
+pub const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+/// The address is already banned.
+pub const RPC_CLIENT_NODE_ALREADY_ADDED: i64 = -23;
+/// Not a valid IP address, or not a banned one.
+pub const RPC_CLIENT_INVALID_IP_OR_SUBNET: i64 = -30;
+
+/// Error member of a JSON-RPC reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(
+            RPC_METHOD_NOT_FOUND,
+            format!("Method not found: {}", method),
+        )
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(RPC_INVALID_PARAMS, message)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RPC error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// A group of RPC methods.
+#[async_trait]
+pub trait RpcHandler: Send + Sync {
+    /// Method names this handler answers.
+    fn methods(&self) -> &'static [&'static str];
+
+    /// Run `method` with positional `params`.
+    async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError>;
+}
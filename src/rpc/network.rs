@@ -0,0 +1,149 @@
+//! Peer ban RPC calls.
+//!
+//! - `setban [ip, "add"|"remove", bantime?]`: ban `ip` for `bantime`
+//!   seconds (default `NetworkConfig::ban_duration`), dropping any
+//!   connection from it, or lift its ban.
+//! - `listbanned`: current bans with their creation and expiry times.
+//! - `clearbanned`: lift every ban.
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{RpcError, RpcHandler, RPC_CLIENT_INVALID_IP_OR_SUBNET, RPC_CLIENT_NODE_ALREADY_ADDED};
+use crate::network::PeerManager;
+
+/// Serves `setban`, `listbanned` and `clearbanned`.
+pub struct NetworkRpc {
+    peers: Arc<PeerManager>,
+}
+
+impl NetworkRpc {
+    pub fn new(peers: Arc<PeerManager>) -> Self {
+        Self { peers }
+    }
+
+    fn set_ban(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let ip: IpAddr = params
+            .first()
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::invalid_params("Expected an IP address"))?
+            .parse()
+            .map_err(|_| RpcError::new(RPC_CLIENT_INVALID_IP_OR_SUBNET, "Invalid IP address"))?;
+        match params.get(1).and_then(Value::as_str) {
+            Some("add") => {
+                let duration = match params.get(2) {
+                    None | Some(Value::Null) => self.peers.config().ban_duration,
+                    Some(value) => value
+                        .as_u64()
+                        .filter(|&secs| secs > 0)
+                        .map(Duration::from_secs)
+                        .ok_or_else(|| RpcError::invalid_params("bantime must be positive"))?,
+                };
+                if self.peers.is_banned(&ip) {
+                    return Err(RpcError::new(
+                        RPC_CLIENT_NODE_ALREADY_ADDED,
+                        "IP address already banned",
+                    ));
+                }
+                self.peers.ban(ip, duration, "manually added");
+                Ok(Value::Null)
+            }
+            Some("remove") => {
+                if !self.peers.unban(&ip) {
+                    return Err(RpcError::new(
+                        RPC_CLIENT_INVALID_IP_OR_SUBNET,
+                        "Unban failed: IP address was not banned",
+                    ));
+                }
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::invalid_params(
+                "command must be \"add\" or \"remove\"",
+            )),
+        }
+    }
+
+    fn list_banned(&self) -> Value {
+        let bans: Vec<Value> = self
+            .peers
+            .banned()
+            .into_iter()
+            .map(|entry| {
+                json!({
+                    "address": entry.address.to_string(),
+                    "ban_created": entry.created,
+                    "banned_until": entry.banned_until,
+                    "reason": entry.reason,
+                })
+            })
+            .collect();
+        Value::Array(bans)
+    }
+}
+
+#[async_trait]
+impl RpcHandler for NetworkRpc {
+    fn methods(&self) -> &'static [&'static str] {
+        &["setban", "listbanned", "clearbanned"]
+    }
+
+    async fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "setban" => self.set_ban(params),
+            "listbanned" => Ok(self.list_banned()),
+            "clearbanned" => {
+                self.peers.clear_bans();
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::method_not_found(method)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, NetworkType};
+    use crate::network::PeerManagerConfig;
+
+    #[tokio::test]
+    async fn bans_and_unbans() {
+        let mut config = PeerManagerConfig::from_config(&Config::new(NetworkType::Regtest, None));
+        config.banlist_file = None;
+        let rpc = NetworkRpc::new(PeerManager::new(config));
+
+        rpc.call("setban", &[json!("203.0.113.9"), json!("add"), json!(600)])
+            .await
+            .unwrap();
+        let again = rpc
+            .call("setban", &[json!("203.0.113.9"), json!("add")])
+            .await;
+        assert_eq!(again.unwrap_err().code, RPC_CLIENT_NODE_ALREADY_ADDED);
+        let listed = rpc.call("listbanned", &[]).await.unwrap();
+        assert_eq!(listed[0]["address"], "203.0.113.9");
+        let until = listed[0]["banned_until"].as_u64().unwrap();
+        assert_eq!(until - listed[0]["ban_created"].as_u64().unwrap(), 600);
+
+        let bad = rpc
+            .call("setban", &[json!("not-an-ip"), json!("add")])
+            .await;
+        assert_eq!(bad.unwrap_err().code, RPC_CLIENT_INVALID_IP_OR_SUBNET);
+
+        rpc.call("setban", &[json!("203.0.113.9"), json!("remove")])
+            .await
+            .unwrap();
+        let missing = rpc
+            .call("setban", &[json!("203.0.113.9"), json!("remove")])
+            .await;
+        assert_eq!(missing.unwrap_err().code, RPC_CLIENT_INVALID_IP_OR_SUBNET);
+
+        rpc.call("setban", &[json!("2001:db8::1"), json!("add")])
+            .await
+            .unwrap();
+        rpc.call("clearbanned", &[]).await.unwrap();
+        assert_eq!(rpc.call("listbanned", &[]).await.unwrap(), json!([]));
+    }
+}
@@ -0,0 +1,401 @@
+//! Minimal HTTP/1.1 transport for JSON-RPC.
+//!
+//! Each connection carries one `POST` whose body is a request object or a
+//! batch array; the reply is written and the connection closed. When
+//! `RpcConfig::username` and `password` are both set, requests must carry
+//! matching HTTP Basic credentials. `RpcConfig::timeout` bounds reading the
+//! request, not running it, so long-polling calls can take their time.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+use super::{RpcError, RpcHandler, RPC_INVALID_PARAMS, RPC_INVALID_REQUEST, RPC_PARSE_ERROR};
+use crate::config::RpcConfig;
+
+/// Upper bound on the request line plus headers.
+const MAX_HEADER_SIZE: u64 = 16 * 1024;
+
+/// Upper bound on a request body; a hex-encoded maximum-size block fits.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+struct HttpRequest {
+    method: String,
+    /// Header names lowercased.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// JSON-RPC server dispatching to registered handlers.
+pub struct RpcServer {
+    config: RpcConfig,
+    handlers: HashMap<&'static str, Arc<dyn RpcHandler>>,
+    permits: Arc<Semaphore>,
+}
+
+impl RpcServer {
+    pub fn new(config: RpcConfig) -> Self {
+        let permits = Arc::new(Semaphore::new(config.max_connections.max(1)));
+        Self {
+            config,
+            handlers: HashMap::new(),
+            permits,
+        }
+    }
+
+    pub fn config(&self) -> &RpcConfig {
+        &self.config
+    }
+
+    /// Route every method `handler` names to it, replacing earlier owners.
+    pub fn register(&mut self, handler: Arc<dyn RpcHandler>) {
+        for method in handler.methods() {
+            if self
+                .handlers
+                .insert(*method, Arc::clone(&handler))
+                .is_some()
+            {
+                log::warn!("RPC method {} registered twice", method);
+            }
+        }
+    }
+
+    /// Answer one request body: a single call or a batch.
+    pub async fn dispatch(&self, body: &[u8]) -> Value {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(batch)) => {
+                let mut replies = Vec::with_capacity(batch.len());
+                for request in batch {
+                    replies.push(self.call(request).await);
+                }
+                Value::Array(replies)
+            }
+            Ok(request) => self.call(request).await,
+            Err(e) => reply(
+                Value::Null,
+                Err(RpcError::new(
+                    RPC_PARSE_ERROR,
+                    format!("Parse error: {}", e),
+                )),
+            ),
+        }
+    }
+
+    async fn call(&self, request: Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return reply(
+                id,
+                Err(RpcError::new(RPC_INVALID_REQUEST, "Missing method")),
+            );
+        };
+        let params = match request.get("params") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(params)) => params.clone(),
+            Some(_) => {
+                return reply(
+                    id,
+                    Err(RpcError::new(RPC_INVALID_PARAMS, "Params must be an array")),
+                )
+            }
+        };
+
+        let result = match self.handlers.get(method) {
+            Some(handler) => handler.call(method, &params).await,
+            None => Err(RpcError::method_not_found(method)),
+        };
+        if let Err(e) = &result {
+            log::debug!("RPC {} failed: {}", method, e);
+        }
+        reply(id, result)
+    }
+
+    /// Bind `RpcConfig::listen_addr` and serve until an accept error.
+    pub async fn run(self: Arc<Self>) -> io::Result<()> {
+        let listener = TcpListener::bind(self.config.listen_addr).await?;
+        log::info!("RPC server listening on {}", listener.local_addr()?);
+        self.serve(listener).await
+    }
+
+    /// Serve clients on an already bound listener.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (mut stream, addr) = listener.accept().await?;
+            let Ok(permit) = Arc::clone(&self.permits).try_acquire_owned() else {
+                log::debug!("Refusing RPC connection {}: too many connections", addr);
+                tokio::spawn(async move {
+                    let _ = write_response(&mut stream, "503 Service Unavailable", &[], b"").await;
+                });
+                continue;
+            };
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_connection(stream).await {
+                    log::debug!("RPC connection {} closed: {}", addr, e);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        let request =
+            match tokio::time::timeout(self.config.timeout, read_request(&mut stream)).await {
+                Ok(request) => request?,
+                Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out")),
+            };
+        let Some(request) = request else {
+            return Ok(());
+        };
+        let stream = stream.get_mut();
+
+        let mut headers = Vec::new();
+        if let Some(origin) = request.headers.get("origin") {
+            if self.cors_allows(origin) {
+                headers.push(("Access-Control-Allow-Origin", origin.clone()));
+                headers.push((
+                    "Access-Control-Allow-Headers",
+                    "Authorization, Content-Type".to_string(),
+                ));
+                headers.push(("Access-Control-Allow-Methods", "POST".to_string()));
+            }
+        }
+
+        if request.method == "OPTIONS" {
+            return write_response(stream, "204 No Content", &headers, b"").await;
+        }
+        if !self.authorized(&request) {
+            headers.push(("WWW-Authenticate", "Basic realm=\"jsonrpc\"".to_string()));
+            return write_response(stream, "401 Unauthorized", &headers, b"").await;
+        }
+        if request.method != "POST" {
+            return write_response(stream, "405 Method Not Allowed", &headers, b"").await;
+        }
+
+        let body = self.dispatch(&request.body).await.to_string();
+        headers.push(("Content-Type", "application/json".to_string()));
+        write_response(stream, "200 OK", &headers, body.as_bytes()).await
+    }
+
+    fn cors_allows(&self, origin: &str) -> bool {
+        self.config.enable_cors
+            && self
+                .config
+                .cors_origin
+                .iter()
+                .any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    fn authorized(&self, request: &HttpRequest) -> bool {
+        let (Some(user), Some(password)) = (&self.config.username, &self.config.password) else {
+            return true;
+        };
+        let expected = format!(
+            "Basic {}",
+            base64_encode(format!("{}:{}", user, password).as_bytes())
+        );
+        request.headers.get("authorization") == Some(&expected)
+    }
+}
+
+fn reply(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "result": result, "error": null, "id": id }),
+        Err(e) => json!({ "result": null, "error": e.to_json(), "id": id }),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read one request; `None` if the peer closed before sending anything.
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<HttpRequest>> {
+    let mut head = (&mut *reader).take(MAX_HEADER_SIZE);
+
+    let mut line = String::new();
+    if head.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let method = line
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| invalid_data("empty request line"))?
+        .to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await? == 0 {
+            return Err(invalid_data("headers too long or truncated"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid_data("malformed header"))?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let length = match headers.get("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| invalid_data("bad Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(invalid_data("request body too large"));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(HttpRequest {
+        method,
+        headers,
+        body,
+    }))
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
+
+/// Standard padded base64, for comparing Basic credentials.
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((triple >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::RPC_METHOD_NOT_FOUND;
+    use async_trait::async_trait;
+
+    struct Echo;
+
+    #[async_trait]
+    impl RpcHandler for Echo {
+        fn methods(&self) -> &'static [&'static str] {
+            &["echo"]
+        }
+
+        async fn call(&self, _method: &str, params: &[Value]) -> Result<Value, RpcError> {
+            Ok(Value::Array(params.to_vec()))
+        }
+    }
+
+    fn server(username: Option<&str>) -> RpcServer {
+        let mut server = RpcServer::new(RpcConfig {
+            username: username.map(str::to_string),
+            password: username.map(|_| "secret".to_string()),
+            ..RpcConfig::default()
+        });
+        server.register(Arc::new(Echo));
+        server
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"user:secret"), "dXNlcjpzZWNyZXQ=");
+    }
+
+    #[tokio::test]
+    async fn dispatches_single_and_batched_calls() {
+        let server = server(None);
+
+        let reply = server
+            .dispatch(br#"{"id":1,"method":"echo","params":[2]}"#)
+            .await;
+        assert_eq!(reply, json!({ "result": [2], "error": null, "id": 1 }));
+
+        let batch = server
+            .dispatch(br#"[{"id":"a","method":"nope"},{"id":"b","params":[]}]"#)
+            .await;
+        assert_eq!(batch[0]["error"]["code"], RPC_METHOD_NOT_FOUND);
+        assert_eq!(batch[1]["error"]["code"], RPC_INVALID_REQUEST);
+
+        let garbage = server.dispatch(b"{").await;
+        assert_eq!(garbage["error"]["code"], RPC_PARSE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn serves_http_with_basic_auth() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(server(Some("user"))).serve(listener));
+
+        async fn post(addr: std::net::SocketAddr, auth: Option<&str>) -> String {
+            let body = r#"{"id":7,"method":"echo","params":["hi"]}"#;
+            let mut request = format!(
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n",
+                body.len()
+            );
+            if let Some(auth) = auth {
+                request.push_str(&format!("Authorization: Basic {}\r\n", auth));
+            }
+            request.push_str("\r\n");
+            request.push_str(body);
+
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        }
+
+        let denied = post(addr, None).await;
+        assert!(denied.starts_with("HTTP/1.1 401"));
+        let wrong = post(addr, Some(&base64_encode(b"user:guess"))).await;
+        assert!(wrong.starts_with("HTTP/1.1 401"));
+
+        let ok = post(addr, Some(&base64_encode(b"user:secret"))).await;
+        assert!(ok.starts_with("HTTP/1.1 200"));
+        let (_, body) = ok.split_once("\r\n\r\n").unwrap();
+        let reply: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(reply["result"], json!(["hi"]));
+        assert_eq!(reply["id"], 7);
+    }
+}
@@ -0,0 +1,35 @@
+//! Fixtures shared by unit tests: tagged outpoints, funded UTXO stores and
+//! bare transactions.
+
+use crate::database::utxo_set::{
+    create_outpoint, hash_transaction, MemoryUTXOStorage, OutPoint, TxOutput, UTXOStorage,
+};
+use crate::network::protocol::{Hash, Transaction};
+
+/// Output `index` of the made-up transaction named `tag`.
+pub(crate) fn op(tag: &str, index: u32) -> OutPoint {
+    create_outpoint(Hash(hash_transaction(tag.as_bytes())), index)
+}
+
+/// A store holding `op(tag, 0)` worth `value` for each of `tags`,
+/// created at height 1.
+pub(crate) fn funded(tags: &[&str], value: u64) -> MemoryUTXOStorage {
+    let mut store = MemoryUTXOStorage::new();
+    for tag in tags {
+        let out = TxOutput {
+            value,
+            script_pubkey: vec![],
+        };
+        store.add_output(op(tag, 0), out, 1, false).unwrap();
+    }
+    store
+}
+
+/// A non-replaceable transaction.
+pub(crate) fn tx(inputs: Vec<OutPoint>, outputs: Vec<(OutPoint, u64)>) -> Transaction {
+    Transaction {
+        inputs,
+        outputs,
+        replaceable: false,
+    }
+}